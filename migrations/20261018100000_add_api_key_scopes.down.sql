@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE user_api_keys DROP COLUMN last_used_ip;
+ALTER TABLE user_api_keys DROP COLUMN last_used_at;
+ALTER TABLE user_api_keys DROP COLUMN scopes;
+UPDATE user_api_keys SET valid_until = NOW() WHERE valid_until IS NULL;
+ALTER TABLE user_api_keys ALTER COLUMN valid_until SET NOT NULL;
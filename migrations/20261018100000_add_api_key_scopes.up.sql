@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE user_api_keys ALTER COLUMN valid_until DROP NOT NULL;
+-- Scopes granted to the key, see ApiKeyScope
+ALTER TABLE user_api_keys ADD COLUMN scopes varchar[] NOT NULL DEFAULT '{read}';
+ALTER TABLE user_api_keys ADD COLUMN last_used_at timestamptz;
+ALTER TABLE user_api_keys ADD COLUMN last_used_ip inet;
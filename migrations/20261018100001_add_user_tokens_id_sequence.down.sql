@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE user_tokens ALTER COLUMN id DROP DEFAULT;
+DROP SEQUENCE IF EXISTS user_tokens_id_seq;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+CREATE SEQUENCE IF NOT EXISTS user_tokens_id_seq OWNED BY user_tokens.id;
+SELECT setval('user_tokens_id_seq', COALESCE((SELECT MAX(id) FROM user_tokens), 0) + 1, false);
+ALTER TABLE user_tokens ALTER COLUMN id SET DEFAULT nextval('user_tokens_id_seq');
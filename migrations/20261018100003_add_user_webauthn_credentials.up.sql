@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TABLE user_webauthn_credentials (
+    id bigserial PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name character varying NOT NULL,
+    credential_id bytea NOT NULL UNIQUE,
+    passkey jsonb NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    last_used_at timestamp without time zone
+);
+
+CREATE INDEX index_user_webauthn_credentials_on_user_id ON user_webauthn_credentials (user_id);
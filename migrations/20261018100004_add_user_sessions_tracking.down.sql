@@ -0,0 +1,9 @@
+-- Add down migration script here
+DROP INDEX index_user_sessions_on_user_id;
+
+ALTER TABLE user_sessions DROP COLUMN last_seen_at;
+ALTER TABLE user_sessions DROP COLUMN created_at;
+ALTER TABLE user_sessions DROP COLUMN ip;
+ALTER TABLE user_sessions DROP COLUMN user_agent;
+ALTER TABLE user_sessions DROP COLUMN user_id;
+ALTER TABLE user_sessions ALTER COLUMN session DROP DEFAULT;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+ALTER TABLE user_sessions ALTER COLUMN session SET DEFAULT '';
+ALTER TABLE user_sessions ADD COLUMN user_id integer REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE user_sessions ADD COLUMN user_agent character varying;
+ALTER TABLE user_sessions ADD COLUMN ip inet;
+ALTER TABLE user_sessions ADD COLUMN created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE user_sessions ADD COLUMN last_seen_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
+
+CREATE INDEX index_user_sessions_on_user_id ON user_sessions (user_id);
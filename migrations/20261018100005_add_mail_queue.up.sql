@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE mail_queue (
+    id bigserial PRIMARY KEY,
+    envelope_from character varying,
+    envelope_to character varying[] NOT NULL,
+    message bytea NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    last_error character varying,
+    created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    next_attempt_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    failed_at timestamp without time zone
+);
+
+CREATE INDEX index_mail_queue_on_next_attempt_at ON mail_queue (next_attempt_at) WHERE failed_at IS NULL;
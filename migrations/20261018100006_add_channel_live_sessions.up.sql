@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE channel_live_sessions (
+    id bigserial PRIMARY KEY,
+    channel_id integer NOT NULL REFERENCES channels(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    title character varying NOT NULL DEFAULT '',
+    started_at timestamp without time zone NOT NULL,
+    last_seen_at timestamp without time zone NOT NULL,
+    ended_at timestamp without time zone,
+    peak_viewers integer NOT NULL DEFAULT 0,
+    viewer_minutes bigint NOT NULL DEFAULT 0
+);
+
+CREATE INDEX index_channel_live_sessions_on_channel_id_and_started_at ON channel_live_sessions (channel_id, started_at DESC);
+CREATE UNIQUE INDEX index_channel_live_sessions_open ON channel_live_sessions (channel_id) WHERE ended_at IS NULL;
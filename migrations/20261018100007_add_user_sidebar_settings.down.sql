@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE users
+    DROP COLUMN show_sidebar_trending_images,
+    DROP COLUMN show_sidebar_streams,
+    DROP COLUMN show_sidebar_forum_activity,
+    DROP COLUMN show_sidebar_recent_comments;
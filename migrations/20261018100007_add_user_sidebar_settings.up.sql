@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE users
+    ADD COLUMN show_sidebar_trending_images boolean DEFAULT true NOT NULL,
+    ADD COLUMN show_sidebar_streams boolean DEFAULT true NOT NULL,
+    ADD COLUMN show_sidebar_forum_activity boolean DEFAULT true NOT NULL,
+    ADD COLUMN show_sidebar_recent_comments boolean DEFAULT true NOT NULL;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DELETE FROM image_features WHERE user_id IS NULL;
+ALTER TABLE image_features ALTER COLUMN user_id SET NOT NULL;
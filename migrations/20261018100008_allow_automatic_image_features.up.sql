@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- features created by the rotation job have no user
+ALTER TABLE image_features ALTER COLUMN user_id DROP NOT NULL;
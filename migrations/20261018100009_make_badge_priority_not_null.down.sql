@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE badges ALTER COLUMN priority DROP NOT NULL;
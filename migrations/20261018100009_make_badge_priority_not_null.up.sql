@@ -0,0 +1,3 @@
+-- Add up migration script here
+UPDATE badges SET priority = false WHERE priority IS NULL;
+ALTER TABLE badges ALTER COLUMN priority SET NOT NULL;
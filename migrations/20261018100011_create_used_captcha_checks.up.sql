@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Check codes of submitted captchas, kept until they expire so they cannot be used again
+CREATE TABLE used_captcha_checks (
+    id character varying PRIMARY KEY,
+    expires_at timestamp without time zone NOT NULL
+);
+
+CREATE INDEX index_used_captcha_checks_on_expires_at ON used_captcha_checks (expires_at);
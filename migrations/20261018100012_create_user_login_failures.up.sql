@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Failed logins are counted per account and client address, so repeated failures only lock
+-- that address out of the account instead of the account owner
+CREATE TABLE user_login_failures (
+    user_id integer NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    ip inet NOT NULL,
+    failed_attempts integer NOT NULL,
+    locked_at timestamp without time zone,
+    updated_at timestamp without time zone NOT NULL,
+    PRIMARY KEY (user_id, ip)
+);
+
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX index_channels_on_user_id;
+ALTER TABLE channels DROP COLUMN user_id;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE channels ADD COLUMN user_id integer REFERENCES users(id) ON DELETE SET NULL;
+CREATE INDEX index_channels_on_user_id ON channels (user_id);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE channels ALTER COLUMN remote_stream_id TYPE integer USING remote_stream_id::integer;
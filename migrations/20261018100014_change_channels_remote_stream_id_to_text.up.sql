@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Stream ids are opaque strings on Twitch and may not fit into an integer
+ALTER TABLE channels ALTER COLUMN remote_stream_id TYPE character varying USING remote_stream_id::text;
@@ -77,6 +77,8 @@ function pickAndResize(elem) {
     imageFormat = 'mp4';
   }
 
+  const autoplay = window.booru.autoplay ? 'autoplay' : '';
+
   // Check if we need to change to avoid flickering
   if (imageFormat === 'mp4' || imageFormat === 'webm') {
     for (const sourceEl of elem.querySelectorAll('video source')) {
@@ -90,7 +92,7 @@ function pickAndResize(elem) {
   if (imageFormat === 'mp4') {
     elem.classList.add('full-height');
     elem.insertAdjacentHTML('afterbegin',
-      `<video controls autoplay loop muted playsinline preload="auto" id="image-display"
+      `<video controls ${autoplay} loop muted playsinline preload="auto" id="image-display"
            width="${imageWidth}" height="${imageHeight}">
         <source src="${uris.webm}" type="video/webm">
         <source src="${uris.mp4}" type="video/mp4">
@@ -103,7 +105,7 @@ function pickAndResize(elem) {
   }
   else if (imageFormat === 'webm') {
     elem.insertAdjacentHTML('afterbegin',
-      `<video controls autoplay loop muted playsinline id="image-display">
+      `<video controls ${autoplay} loop muted playsinline id="image-display">
         <source src="${uri}" type="video/webm">
         <source src="${uri.replace(/webm$/, 'mp4')}" type="video/mp4">
         <p class="block block--fixed block--warning">
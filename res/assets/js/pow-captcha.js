@@ -0,0 +1,76 @@
+/**
+ * Proof of work captchas.
+ *
+ * Elements with the js-pow-captcha class carry a challenge in data-pow-challenge.
+ * A nonce is searched so that the SHA-256 hash of "challenge:nonce" starts with
+ * data-pow-difficulty zero bits, and put into the "captcha" field of the form.
+ * Submitting the form before that waits for the nonce to be found.
+ */
+import { $$ } from './utils/dom';
+
+function leadingZeroBits(hash) {
+  let zeros = 0;
+  for (let i = 0; i < hash.length; i++) {
+    if (hash[i] !== 0) return zeros + Math.clz32(hash[i]) - 24;
+    zeros += 8;
+  }
+  return zeros;
+}
+
+function findNonce(challenge, difficulty) {
+  const encoder = new TextEncoder();
+  return new Promise((resolve, reject) => {
+    const tryNonce = nonce => {
+      crypto.subtle.digest('SHA-256', encoder.encode(`${challenge}:${nonce}`))
+        .then(hash => {
+          if (leadingZeroBits(new Uint8Array(hash)) >= difficulty) resolve(nonce.toString());
+          else tryNonce(nonce + 1);
+        })
+        .catch(reject);
+    };
+    tryNonce(0);
+  });
+}
+
+function solveCaptcha(captcha) {
+  const form = captcha.closest('form');
+  const input = captcha.querySelector('input[name="captcha"]');
+  const status = captcha.querySelector('.js-pow-captcha-status');
+  let solved = false;
+  let submitWhenSolved = false;
+
+  form.addEventListener('submit', event => {
+    if (solved) return;
+    event.preventDefault();
+    submitWhenSolved = true;
+    status.textContent = 'Almost done, the form will be submitted in a moment...';
+  });
+
+  findNonce(captcha.dataset.powChallenge, parseInt(captcha.dataset.powDifficulty, 10))
+    .then(nonce => {
+      input.value = nonce;
+      solved = true;
+      status.textContent = 'Your browser was checked successfully.';
+      if (submitWhenSolved) form.submit();
+    })
+    .catch(() => {
+      status.textContent = 'Your browser could not be checked, please reload the page.';
+    });
+}
+
+function setupPowCaptchas() {
+  const captchas = $$('.js-pow-captcha');
+
+  // crypto.subtle is only available to pages served over HTTPS
+  if (!window.crypto || !window.crypto.subtle) {
+    captchas.forEach(captcha => {
+      captcha.querySelector('.js-pow-captcha-status').textContent =
+        'Your browser cannot be checked, please use a browser that supports the Web Crypto API and make sure the site is loaded over HTTPS.';
+    });
+    return;
+  }
+
+  captchas.forEach(solveCaptcha);
+}
+
+export { setupPowCaptchas };
@@ -19,7 +19,7 @@ function showVideoThumb(img) {
     <source src="${thumbUri.replace(/webm$/, 'mp4')}" type="video/mp4"/>
   `;
   vidEl.classList.remove('hidden');
-  vidEl.play();
+  if (window.booru.autoplay) vidEl.play();
 
   img.querySelector('.js-spoiler-info-overlay').classList.add('hidden');
 
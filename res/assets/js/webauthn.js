@@ -0,0 +1,96 @@
+/**
+ * WebAuthn ceremonies for passkeys and security keys.
+ *
+ * Buttons with the js-webauthn class carry the challenge issued by the server
+ * in data-webauthn-options and either "create" or "get" in data-webauthn-mode.
+ * The credential returned by the browser is put into the "credential" field of
+ * the surrounding form, which is then submitted.
+ */
+
+function decodeBase64Url(value) {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  const binary = atob(base64.padEnd(base64.length + ((4 - (base64.length % 4)) % 4), '='));
+  return Uint8Array.from(binary, c => c.charCodeAt(0)).buffer;
+}
+
+function encodeBase64Url(buffer) {
+  const binary = String.fromCharCode(...new Uint8Array(buffer));
+  return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+function creationOptions(options) {
+  const publicKey = options.publicKey;
+  publicKey.challenge = decodeBase64Url(publicKey.challenge);
+  publicKey.user.id = decodeBase64Url(publicKey.user.id);
+  (publicKey.excludeCredentials || []).forEach(c => { c.id = decodeBase64Url(c.id); });
+  return { publicKey };
+}
+
+function requestOptions(options) {
+  const publicKey = options.publicKey;
+  publicKey.challenge = decodeBase64Url(publicKey.challenge);
+  (publicKey.allowCredentials || []).forEach(c => { c.id = decodeBase64Url(c.id); });
+  return { publicKey };
+}
+
+function encodeCreated(credential) {
+  return {
+    id: credential.id,
+    rawId: encodeBase64Url(credential.rawId),
+    type: credential.type,
+    extensions: credential.getClientExtensionResults(),
+    response: {
+      attestationObject: encodeBase64Url(credential.response.attestationObject),
+      clientDataJSON: encodeBase64Url(credential.response.clientDataJSON),
+    },
+  };
+}
+
+function encodeAssertion(credential) {
+  const userHandle = credential.response.userHandle;
+  return {
+    id: credential.id,
+    rawId: encodeBase64Url(credential.rawId),
+    type: credential.type,
+    extensions: credential.getClientExtensionResults(),
+    response: {
+      authenticatorData: encodeBase64Url(credential.response.authenticatorData),
+      clientDataJSON: encodeBase64Url(credential.response.clientDataJSON),
+      signature: encodeBase64Url(credential.response.signature),
+      userHandle: userHandle ? encodeBase64Url(userHandle) : null,
+    },
+  };
+}
+
+function runCeremony(button) {
+  const form = button.closest('form');
+  if (!form.reportValidity()) return;
+
+  const options = JSON.parse(button.dataset.webauthnOptions);
+  const ceremony = button.dataset.webauthnMode === 'create' ?
+    navigator.credentials.create(creationOptions(options)).then(encodeCreated) :
+    navigator.credentials.get(requestOptions(options)).then(encodeAssertion);
+
+  button.disabled = true;
+  ceremony.then(credential => {
+    form.querySelector('input[name="credential"]').value = JSON.stringify(credential);
+    form.submit();
+  }).catch(() => {
+    button.disabled = false;
+    button.insertAdjacentHTML('afterend', '<p class="block block--danger">The passkey could not be used, please try again.</p>');
+  });
+}
+
+function setupWebauthn() {
+  if (!window.PublicKeyCredential) return;
+
+  document.addEventListener('click', event => {
+    const button = event.target && event.target.closest('.js-webauthn');
+    if (button) {
+      event.preventDefault();
+      runCeremony(button);
+    }
+  });
+}
+
+export { setupWebauthn };
@@ -20,6 +20,7 @@ import { initImagesClientside } from './imagesclientside';
 import { bindImageTarget }      from './image_expansion';
 import { setupEvents }          from './misc';
 import { setupNotifications }   from './notifications';
+import { setupPowCaptchas }     from './pow-captcha';
 import { setupPreviews }        from './preview';
 import { setupQuickTag }        from './quick-tag';
 import { initializeListener }   from './resizablemedia';
@@ -34,6 +35,7 @@ import { setupSearch }          from './search';
 import { setupToolbar }         from './textiletoolbar';
 import { hideStaffTools }       from './staffhider';
 import { pollOptionCreator }    from './poll';
+import { setupWebauthn }        from './webauthn';
 
 whenReady(() => {
 
@@ -52,6 +54,7 @@ whenReady(() => {
   bindImageTarget();
   setupEvents();
   setupNotifications();
+  setupPowCaptchas();
   setupPreviews();
   setupQuickTag();
   initializeListener();
@@ -66,5 +69,6 @@ whenReady(() => {
   setupToolbar();
   hideStaffTools();
   pollOptionCreator();
+  setupWebauthn();
 
 });
@@ -1,4 +1,8 @@
+use tiberius_dependencies::chrono::NaiveDateTime;
+
 pub mod pony;
+pub mod pow;
+mod raster;
 
 pub struct Captcha<Check>
 where
@@ -29,6 +33,61 @@ pub trait CaptchaProvider {
     /// Generates a new random captcha with it's challenge code
     fn generate_captcha(&self) -> Captcha<Self::Check>;
 
-    /// Returns true if the captcha is correctly solved, false otherwise
-    fn verify_captcha(&self, code: Self::Check, solution: Self::Solution) -> bool;
+    /// Checks the solution against the check code
+    ///
+    /// Returns None if the check code is invalid or expired.
+    fn verify_captcha(&self, code: Self::Check, solution: Self::Solution) -> Option<Verification>;
+}
+
+/// The outcome of checking a solution against a valid check code
+///
+/// Providers do not remember check codes. The caller must record the check code as used until
+/// it expires and reject the solution if it was used before, so each captcha can only be
+/// answered once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// Random identifier of the check code
+    pub id: String,
+    /// Time after which the check code is no longer accepted
+    pub expires_at: NaiveDateTime,
+    /// True if the solution is correct
+    pub solved: bool,
+}
+
+/// Object safe version of [CaptchaProvider] that transports the check code as JSON
+///
+/// Implemented for all captcha providers, the check code returned by `generate` must be
+/// protected from tampering by the caller as well.
+pub trait AnyCaptchaProvider: Send + Sync {
+    /// Generates a new captcha, returning the serialized check code and the HTML of the captcha
+    fn generate(&self) -> (String, maud::PreEscaped<String>);
+
+    /// Verifies the solution in the `captcha` parameter of the URL encoded form body against the check code
+    ///
+    /// Returns None if the check code is invalid or expired or the solution is missing.
+    fn verify(&self, check: &str, form_body: &str) -> Option<Verification>;
+}
+
+#[derive(serde::Deserialize)]
+struct CaptchaForm<Solution> {
+    captcha: Solution,
+}
+
+impl<T> AnyCaptchaProvider for T
+where
+    T: CaptchaProvider + Send + Sync,
+{
+    fn generate(&self) -> (String, maud::PreEscaped<String>) {
+        let captcha = self.generate_captcha();
+        let check = serde_json::to_string(&captcha.check)
+            .expect("captcha check codes must be serializable");
+        (check, captcha.html)
+    }
+
+    fn verify(&self, check: &str, form_body: &str) -> Option<Verification> {
+        let check: T::Check = serde_json::from_str(check).ok()?;
+        let config = tiberius_dependencies::serde_qs::Config::new(5, false);
+        let form: CaptchaForm<T::Solution> = config.deserialize_str(form_body).ok()?;
+        self.verify_captcha(check, form.captcha)
+    }
 }
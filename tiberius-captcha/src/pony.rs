@@ -1,8 +1,32 @@
-use crate::{Captcha, CaptchaProvider};
+use maud::html;
+use rand::{seq::SliceRandom, Rng};
+use ring::{aead, hkdf, rand::SecureRandom};
+use tiberius_dependencies::{
+    base64::{
+        engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+        Engine,
+    },
+    chrono::{Duration, NaiveDateTime, Utc},
+};
 
-pub struct PonyCaptcha {}
+use crate::{
+    raster::{cubic, ellipse, place, quadratic, rect, render_png, Layer, Shape, MARK_SIZE},
+    Captcha, CaptchaProvider, Verification,
+};
 
-#[derive(serde::Deserialize, serde::Serialize)]
+/// Number of cutie marks shown in a single challenge
+const PONIES_PER_CAPTCHA: usize = 6;
+
+/// A captcha asking the user to match the cutie marks of the Mane Six to their owners
+///
+/// The challenge is sealed with ChaCha20-Poly1305 into the check code, so the answer cannot be
+/// read or changed by the user. A check code expires after [PonyCaptcha::VALIDITY_MINUTES].
+pub struct PonyCaptcha {
+    key: aead::LessSafeKey,
+    validity: Duration,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Pony {
     TwilightSparkle,
     Rarity,
@@ -12,21 +36,518 @@ pub enum Pony {
     Applejack,
 }
 
+impl Pony {
+    pub const ALL: [Pony; 6] = [
+        Pony::TwilightSparkle,
+        Pony::Rarity,
+        Pony::Fluttershy,
+        Pony::RainbowDash,
+        Pony::PinkiePie,
+        Pony::Applejack,
+    ];
+
+    /// The value submitted by the form, matching the serialized name of the pony
+    pub fn value(&self) -> &'static str {
+        match self {
+            Pony::TwilightSparkle => "TwilightSparkle",
+            Pony::Rarity => "Rarity",
+            Pony::Fluttershy => "Fluttershy",
+            Pony::RainbowDash => "RainbowDash",
+            Pony::PinkiePie => "PinkiePie",
+            Pony::Applejack => "Applejack",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pony::TwilightSparkle => "Twilight Sparkle",
+            Pony::Rarity => "Rarity",
+            Pony::Fluttershy => "Fluttershy",
+            Pony::RainbowDash => "Rainbow Dash",
+            Pony::PinkiePie => "Pinkie Pie",
+            Pony::Applejack => "Applejack",
+        }
+    }
+
+    /// The cutie mark of the pony, drawn in a 100 by 100 coordinate space
+    fn cutie_mark(&self) -> Vec<Layer> {
+        match self {
+            Pony::TwilightSparkle => {
+                let small_star = |(x, y): (f32, f32)| {
+                    let points = [
+                        (0.0, -6.0),
+                        (2.0, 0.0),
+                        (8.0, 0.0),
+                        (3.0, 4.0),
+                        (5.0, 10.0),
+                        (0.0, 6.0),
+                        (-5.0, 10.0),
+                        (-3.0, 4.0),
+                        (-8.0, 0.0),
+                        (-2.0, 0.0),
+                    ];
+                    Layer::filled(
+                        Shape::Polygon(place(points.to_vec(), (x, y), 0.0)),
+                        0xffffff,
+                    )
+                };
+                vec![
+                    Layer::new(
+                        Shape::Polygon(vec![
+                            (50.0, 8.0),
+                            (61.0, 38.0),
+                            (92.0, 38.0),
+                            (67.0, 57.0),
+                            (76.0, 88.0),
+                            (50.0, 70.0),
+                            (24.0, 88.0),
+                            (33.0, 57.0),
+                            (8.0, 38.0),
+                            (39.0, 38.0),
+                        ]),
+                        Some(0xe0388f),
+                        Some((0xffffff, 2.0)),
+                    ),
+                    small_star((14.0, 18.0)),
+                    small_star((86.0, 18.0)),
+                    small_star((8.0, 64.0)),
+                    small_star((92.0, 64.0)),
+                    small_star((50.0, 88.0)),
+                ]
+            }
+            Pony::Rarity => {
+                let diamond = |(x, y): (f32, f32)| {
+                    let points = vec![(0.0, -14.0), (14.0, 0.0), (0.0, 22.0), (-14.0, 0.0)];
+                    [
+                        Layer::new(
+                            Shape::Polygon(place(points, (x, y), 0.0)),
+                            Some(0x5fc8f2),
+                            Some((0x2b78b8, 2.0)),
+                        ),
+                        Layer::stroked(
+                            Shape::Polyline(vec![(x - 14.0, y), (x + 14.0, y)]),
+                            0x2b78b8,
+                            1.0,
+                        ),
+                    ]
+                };
+                [
+                    diamond((30.0, 24.0)),
+                    diamond((70.0, 24.0)),
+                    diamond((50.0, 64.0)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect()
+            }
+            Pony::Fluttershy => {
+                let butterfly = |offset: (f32, f32), degrees: f32| {
+                    let wing = |center, rx, ry| {
+                        Layer::filled(
+                            Shape::Polygon(place(ellipse(center, rx, ry), offset, degrees)),
+                            0xf4a6c8,
+                        )
+                    };
+                    [
+                        wing((-9.0, -6.0), 9.0, 11.0),
+                        wing((9.0, -6.0), 9.0, 11.0),
+                        wing((-7.0, 9.0), 6.0, 7.0),
+                        wing((7.0, 9.0), 6.0, 7.0),
+                        Layer::filled(
+                            Shape::Polygon(place(rect((-2.0, -14.0), 4.0, 28.0), offset, degrees)),
+                            0x3fb8a8,
+                        ),
+                    ]
+                };
+                [
+                    butterfly((30.0, 30.0), -20.0),
+                    butterfly((72.0, 36.0), 15.0),
+                    butterfly((45.0, 72.0), 5.0),
+                ]
+                .into_iter()
+                .flatten()
+                .collect()
+            }
+            Pony::RainbowDash => {
+                let cloud = |center, radius| {
+                    Layer::new(
+                        Shape::Polygon(ellipse(center, radius, radius)),
+                        Some(0xffffff),
+                        Some((0x5fc8f2, 2.0)),
+                    )
+                };
+                let bolt = |points: [(f32, f32); 7], color| {
+                    Layer::filled(Shape::Polygon(points.to_vec()), color)
+                };
+                vec![
+                    cloud((35.0, 40.0), 16.0),
+                    cloud((55.0, 32.0), 18.0),
+                    cloud((72.0, 44.0), 14.0),
+                    Layer::filled(Shape::Polygon(rect((30.0, 40.0), 45.0, 18.0)), 0xffffff),
+                    bolt(
+                        [
+                            (52.0, 50.0),
+                            (60.0, 50.0),
+                            (52.0, 66.0),
+                            (58.0, 66.0),
+                            (42.0, 94.0),
+                            (48.0, 72.0),
+                            (42.0, 72.0),
+                        ],
+                        0xe03a3e,
+                    ),
+                    bolt(
+                        [
+                            (60.0, 50.0),
+                            (66.0, 50.0),
+                            (58.0, 66.0),
+                            (64.0, 66.0),
+                            (48.0, 94.0),
+                            (58.0, 66.0),
+                            (52.0, 66.0),
+                        ],
+                        0xf7d038,
+                    ),
+                    bolt(
+                        [
+                            (66.0, 50.0),
+                            (72.0, 50.0),
+                            (64.0, 66.0),
+                            (70.0, 66.0),
+                            (54.0, 94.0),
+                            (64.0, 66.0),
+                            (58.0, 66.0),
+                        ],
+                        0x3a7ce0,
+                    ),
+                ]
+            }
+            Pony::PinkiePie => {
+                let string = |from, control| {
+                    Layer::stroked(
+                        Shape::Polyline(quadratic(from, control, (48.0, 94.0))),
+                        0x555555,
+                        1.5,
+                    )
+                };
+                let balloon = |center, color| {
+                    Layer::filled(Shape::Polygon(ellipse(center, 13.0, 16.0)), color)
+                };
+                let shine = |(x, y): (f32, f32)| {
+                    Layer::filled(
+                        Shape::Polygon(ellipse((x - 5.0, y - 6.0), 3.0, 5.0)),
+                        0xffffff,
+                    )
+                    .with_opacity(0.6)
+                };
+                vec![
+                    string((28.0, 44.0), (32.0, 70.0)),
+                    string((52.0, 36.0), (50.0, 66.0)),
+                    string((74.0, 46.0), (66.0, 72.0)),
+                    balloon((28.0, 30.0), 0x5fc8f2),
+                    balloon((52.0, 22.0), 0xf7d038),
+                    balloon((74.0, 32.0), 0x5fc8f2),
+                    shine((28.0, 30.0)),
+                    shine((52.0, 22.0)),
+                    shine((74.0, 32.0)),
+                ]
+            }
+            Pony::Applejack => {
+                let apple = |offset: (f32, f32)| {
+                    let mut outline = vec![(0.0, -10.0)];
+                    outline.extend(cubic(
+                        (0.0, -10.0),
+                        (-8.0, -18.0),
+                        (-20.0, -12.0),
+                        (-18.0, 2.0),
+                    ));
+                    outline.extend(cubic(
+                        (-18.0, 2.0),
+                        (-16.0, 14.0),
+                        (-8.0, 20.0),
+                        (0.0, 16.0),
+                    ));
+                    outline.extend(cubic((0.0, 16.0), (8.0, 20.0), (16.0, 14.0), (18.0, 2.0)));
+                    outline.extend(cubic(
+                        (18.0, 2.0),
+                        (20.0, -12.0),
+                        (8.0, -18.0),
+                        (0.0, -10.0),
+                    ));
+                    [
+                        Layer::filled(Shape::Polygon(place(outline, offset, 0.0)), 0xe03a3e),
+                        Layer::filled(
+                            Shape::Polygon(place(rect((-1.5, -18.0), 3.0, 9.0), offset, 0.0)),
+                            0x7a4a1e,
+                        ),
+                        Layer::filled(
+                            Shape::Polygon(place(ellipse((7.0, -16.0), 6.0, 3.0), offset, 0.0)),
+                            0x5fb83a,
+                        ),
+                    ]
+                };
+                [
+                    apple((30.0, 34.0)),
+                    apple((70.0, 34.0)),
+                    apple((50.0, 72.0)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect()
+            }
+        }
+    }
+
+    /// Rasterizes the cutie mark with a random placement, colour shift and noise into a PNG
+    ///
+    /// The image is drawn anew for every challenge, so it cannot be matched against earlier ones
+    /// byte by byte.
+    fn render<R: Rng>(&self, rng: &mut R) -> Vec<u8> {
+        render_png(&self.cutie_mark(), rng)
+    }
+}
+
+/// The sealed check code of a challenge
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct PonyCaptchaInstance {
+    nonce: String,
+    sealed: String,
+}
+
+/// The challenge contained in the check code
 #[derive(serde::Deserialize, serde::Serialize)]
-pub struct PonyCaptchaInstance {}
+struct PonyChallenge {
+    /// Random identifier of the challenge to prevent reuse of the check code
+    id: String,
+    ponies: [Pony; PONIES_PER_CAPTCHA],
+    /// Unix timestamp after which the challenge is no longer accepted
+    expires_at: i64,
+}
 
+/// The owners of the cutie marks chosen by the user, in order of the images
 #[derive(serde::Deserialize)]
-pub struct PonyCaptchaSolution([Pony; 6]);
+#[serde(transparent)]
+pub struct PonyCaptchaSolution([Pony; PONIES_PER_CAPTCHA]);
+
+impl PonyCaptcha {
+    pub const VALIDITY_MINUTES: i64 = 10;
+
+    /// Creates a captcha provider using a key derived from the given secret
+    pub fn new(secret: &[u8]) -> Self {
+        let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, b"tiberius-pony-captcha");
+        let key: aead::UnboundKey = salt
+            .extract(secret)
+            .expand(&[b"check code".as_slice()], &aead::CHACHA20_POLY1305)
+            .expect("valid key length for captcha check code")
+            .into();
+        Self {
+            key: aead::LessSafeKey::new(key),
+            validity: Duration::minutes(Self::VALIDITY_MINUTES),
+        }
+    }
+
+    fn seal(&self, challenge: &PonyChallenge) -> PonyCaptchaInstance {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("could not generate captcha nonce");
+        let mut data =
+            serde_json::to_vec(challenge).expect("captcha challenges must be serializable");
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut data,
+            )
+            .expect("could not seal captcha challenge");
+        PonyCaptchaInstance {
+            nonce: URL_SAFE_NO_PAD.encode(nonce),
+            sealed: URL_SAFE_NO_PAD.encode(data),
+        }
+    }
+
+    fn open(&self, check: &PonyCaptchaInstance) -> Option<PonyChallenge> {
+        let nonce = URL_SAFE_NO_PAD.decode(&check.nonce).ok()?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).ok()?;
+        let mut data = URL_SAFE_NO_PAD.decode(&check.sealed).ok()?;
+        let data = self
+            .key
+            .open_in_place(nonce, aead::Aad::empty(), &mut data)
+            .ok()?;
+        serde_json::from_slice(data).ok()
+    }
+}
 
 impl CaptchaProvider for PonyCaptcha {
     type Check = PonyCaptchaInstance;
     type Solution = PonyCaptchaSolution;
 
     fn generate_captcha(&self) -> Captcha<Self::Check> {
-        todo!()
+        let mut rng = rand::thread_rng();
+        let ponies: [Pony; PONIES_PER_CAPTCHA] =
+            std::array::from_fn(|_| *Pony::ALL.choose(&mut rng).expect("ponies exist"));
+        let challenge = PonyChallenge {
+            id: URL_SAFE_NO_PAD.encode(rng.gen::<[u8; 16]>()),
+            ponies,
+            expires_at: (Utc::now() + self.validity).timestamp(),
+        };
+        let images: Vec<String> = ponies
+            .iter()
+            .map(|pony| {
+                format!(
+                    "data:image/png;base64,{}",
+                    STANDARD.encode(pony.render(&mut rng))
+                )
+            })
+            .collect();
+        let html = html! {
+            fieldset.pony-captcha {
+                legend { "Which pony does each cutie mark belong to?" }
+                p.block__content {
+                    "Choose the owner of each of the cutie marks below."
+                }
+                .flex.flex--wrap {
+                    @for (i, image) in images.iter().enumerate() {
+                        .field style="margin: 0 1em 1em 0; text-align: center" {
+                            label for=(format!("captcha_{}", i)) {
+                                img src=(image) alt=(format!("Cutie mark {}", i + 1)) width=(MARK_SIZE) height=(MARK_SIZE);
+                                br;
+                                (format!("Cutie mark {}", i + 1))
+                            }
+                            br;
+                            select.input id=(format!("captcha_{}", i)) name=(format!("captcha[{}]", i)) required {
+                                option value="" { "Choose a pony" }
+                                @for option in Pony::ALL {
+                                    option value=(option.value()) { (option.name()) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        Captcha {
+            check: self.seal(&challenge),
+            html,
+        }
+    }
+
+    fn verify_captcha(&self, code: Self::Check, solution: Self::Solution) -> Option<Verification> {
+        let challenge = self.open(&code)?;
+        if challenge.expires_at < Utc::now().timestamp() {
+            return None;
+        }
+        Some(Verification {
+            expires_at: NaiveDateTime::from_timestamp_opt(challenge.expires_at, 0)?,
+            solved: challenge.ponies == solution.0,
+            id: challenge.id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::Duration;
+
+    use super::{Pony, PonyCaptcha, PonyCaptchaSolution};
+    use crate::{AnyCaptchaProvider, CaptchaProvider};
+
+    fn solve(captcha: &PonyCaptcha, check: &super::PonyCaptchaInstance) -> [Pony; 6] {
+        captcha
+            .open(check)
+            .expect("check code can be opened")
+            .ponies
+    }
+
+    fn form_body(ponies: &[Pony; 6]) -> String {
+        ponies
+            .iter()
+            .enumerate()
+            .map(|(i, pony)| format!("captcha%5B{}%5D={}", i, pony.value()))
+            .collect::<Vec<_>>()
+            .join("&")
     }
 
-    fn verify_captcha(&self, _code: Self::Check, _solution: Self::Solution) -> bool {
-        todo!()
+    #[test]
+    fn test_pony_captcha_solve() {
+        let captcha = PonyCaptcha::new(b"secret");
+        let generated = captcha.generate_captcha();
+        let html = generated.html.into_string();
+        for i in 0..6 {
+            assert!(html.contains(&format!("name=\"captcha[{}]\"", i)));
+        }
+        let challenge = captcha.open(&generated.check).unwrap();
+        let answer = solve(&captcha, &generated.check);
+        let verification = captcha
+            .verify_captcha(generated.check, PonyCaptchaSolution(answer))
+            .expect("valid check code");
+        assert!(verification.solved);
+        assert_eq!(challenge.id, verification.id);
+
+        let (check, _) = captcha.generate();
+        let check_code: super::PonyCaptchaInstance = serde_json::from_str(&check).unwrap();
+        let answer = solve(&captcha, &check_code);
+        assert!(captcha.verify(&check, &form_body(&answer)).unwrap().solved);
+    }
+
+    #[test]
+    fn test_pony_captcha_does_not_reveal_answer() {
+        let mut rng = rand::thread_rng();
+        for pony in Pony::ALL {
+            let first = pony.render(&mut rng);
+            assert!(first.starts_with(b"\x89PNG"));
+            assert_ne!(first, pony.render(&mut rng));
+        }
+
+        let captcha = PonyCaptcha::new(b"secret");
+        let html = captcha.generate_captcha().html.into_string();
+        assert!(html.contains("data:image/png;base64,"));
+        for pony in Pony::ALL {
+            assert!(!html.contains(&format!("alt=\"{}", pony.name())));
+        }
+    }
+
+    #[test]
+    fn test_pony_captcha_reject() {
+        let captcha = PonyCaptcha::new(b"secret");
+
+        // wrong answer
+        let (check, _) = captcha.generate();
+        let check_code: super::PonyCaptchaInstance = serde_json::from_str(&check).unwrap();
+        let mut answer = solve(&captcha, &check_code);
+        answer[0] = Pony::ALL.into_iter().find(|x| *x != answer[0]).unwrap();
+        assert!(!captcha.verify(&check, &form_body(&answer)).unwrap().solved);
+
+        // incomplete answer
+        let (check, _) = captcha.generate();
+        assert!(captcha.verify(&check, "captcha%5B0%5D=Rarity").is_none());
+
+        // check code sealed with another key
+        let other = PonyCaptcha::new(b"other secret");
+        let (check, _) = other.generate();
+        let check_code: super::PonyCaptchaInstance = serde_json::from_str(&check).unwrap();
+        let answer = form_body(&solve(&other, &check_code));
+        assert!(captcha.verify(&check, &answer).is_none());
+
+        // tampered check code
+        let (check, _) = captcha.generate();
+        let mut check_code: super::PonyCaptchaInstance = serde_json::from_str(&check).unwrap();
+        let answer = form_body(&solve(&captcha, &check_code));
+        let first = if check_code.sealed.starts_with('A') {
+            "B"
+        } else {
+            "A"
+        };
+        check_code.sealed.replace_range(0..1, first);
+        let tampered = serde_json::to_string(&check_code).unwrap();
+        assert!(captcha.verify(&tampered, &answer).is_none());
+
+        // expired check code
+        let mut expired = PonyCaptcha::new(b"secret");
+        expired.validity = Duration::seconds(-1);
+        let (check, _) = expired.generate();
+        let check_code: super::PonyCaptchaInstance = serde_json::from_str(&check).unwrap();
+        let answer = form_body(&solve(&expired, &check_code));
+        assert!(expired.verify(&check, &answer).is_none());
     }
 }
@@ -0,0 +1,207 @@
+use maud::html;
+use rand::Rng;
+use ring::{digest, hmac};
+use tiberius_dependencies::{
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    chrono::{Duration, NaiveDateTime, Utc},
+};
+
+use crate::{Captcha, CaptchaProvider, Verification};
+
+/// Maximum length of a submitted nonce, longer nonces are rejected without hashing them
+const MAX_NONCE_LENGTH: usize = 64;
+
+/// A captcha that makes the browser spend some CPU time before submitting a form
+///
+/// The browser must find a nonce so that the SHA-256 hash of `challenge:nonce` starts with
+/// at least `difficulty` zero bits. Every additional bit of difficulty doubles the average work.
+/// The challenge is signed with HMAC-SHA256 and expires after
+/// [ProofOfWorkCaptcha::VALIDITY_MINUTES].
+pub struct ProofOfWorkCaptcha {
+    key: hmac::Key,
+    difficulty: u8,
+    validity: Duration,
+}
+
+/// The signed challenge of a proof of work captcha
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ProofOfWorkChallenge {
+    challenge: String,
+    difficulty: u8,
+    /// Unix timestamp after which the challenge is no longer accepted
+    expires_at: i64,
+    signature: String,
+}
+
+impl ProofOfWorkChallenge {
+    fn signed_data(&self) -> String {
+        format!("{}:{}:{}", self.challenge, self.difficulty, self.expires_at)
+    }
+}
+
+/// The nonce found by the browser
+#[derive(serde::Deserialize)]
+#[serde(transparent)]
+pub struct ProofOfWorkSolution(String);
+
+impl ProofOfWorkCaptcha {
+    pub const VALIDITY_MINUTES: i64 = 10;
+    /// Highest supported difficulty, beyond this browsers would take hours to solve a challenge
+    pub const MAX_DIFFICULTY: u8 = 32;
+
+    /// Creates a captcha provider signing challenges with a key derived from the given secret
+    ///
+    /// The difficulty is the number of leading zero bits the hash of the solution must have,
+    /// it is capped at [ProofOfWorkCaptcha::MAX_DIFFICULTY].
+    pub fn new(secret: &[u8], difficulty: u8) -> Self {
+        let key = digest::digest(
+            &digest::SHA256,
+            &[b"tiberius-pow-captcha:".as_slice(), secret].concat(),
+        );
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key.as_ref()),
+            difficulty: difficulty.min(Self::MAX_DIFFICULTY),
+            validity: Duration::minutes(Self::VALIDITY_MINUTES),
+        }
+    }
+
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+}
+
+/// Counts the leading zero bits of the hash of the challenge and nonce
+///
+/// All bytes of the hash are inspected, so the time taken does not depend on the result.
+fn leading_zero_bits(challenge: &str, nonce: &str) -> u32 {
+    let hash = digest::digest(
+        &digest::SHA256,
+        format!("{}:{}", challenge, nonce).as_bytes(),
+    );
+    let mut zeros = 0;
+    let mut counting = 1;
+    for byte in hash.as_ref() {
+        let byte_zeros = byte.leading_zeros();
+        zeros += byte_zeros * counting;
+        counting &= (byte_zeros == 8) as u32;
+    }
+    zeros
+}
+
+impl CaptchaProvider for ProofOfWorkCaptcha {
+    type Check = ProofOfWorkChallenge;
+    type Solution = ProofOfWorkSolution;
+
+    fn generate_captcha(&self) -> Captcha<Self::Check> {
+        let mut challenge = ProofOfWorkChallenge {
+            challenge: URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 16]>()),
+            difficulty: self.difficulty,
+            expires_at: (Utc::now() + self.validity).timestamp(),
+            signature: String::new(),
+        };
+        challenge.signature = URL_SAFE_NO_PAD
+            .encode(hmac::sign(&self.key, challenge.signed_data().as_bytes()).as_ref());
+        let html = html! {
+            .js-pow-captcha data-pow-challenge=(challenge.challenge) data-pow-difficulty=(challenge.difficulty) {
+                input type="hidden" name="captcha" value="";
+                p role="status" aria-live="polite" {
+                    span.js-pow-captcha-status { "Checking your browser before you continue..." }
+                }
+                noscript {
+                    p.block.block--fixed.block--warning {
+                        "This form requires JavaScript to check that you are not a bot."
+                    }
+                }
+            }
+        };
+        Captcha {
+            check: challenge,
+            html,
+        }
+    }
+
+    fn verify_captcha(&self, code: Self::Check, solution: Self::Solution) -> Option<Verification> {
+        let signature = URL_SAFE_NO_PAD.decode(&code.signature).ok()?;
+        hmac::verify(&self.key, code.signed_data().as_bytes(), &signature).ok()?;
+        if code.expires_at < Utc::now().timestamp() {
+            return None;
+        }
+        let solved = !solution.0.is_empty()
+            && solution.0.len() <= MAX_NONCE_LENGTH
+            && leading_zero_bits(&code.challenge, &solution.0) >= code.difficulty as u32;
+        Some(Verification {
+            expires_at: NaiveDateTime::from_timestamp_opt(code.expires_at, 0)?,
+            id: code.challenge,
+            solved,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::Duration;
+
+    use super::{leading_zero_bits, ProofOfWorkCaptcha, ProofOfWorkChallenge};
+    use crate::AnyCaptchaProvider;
+
+    fn solve(check: &str) -> String {
+        let check: ProofOfWorkChallenge = serde_json::from_str(check).unwrap();
+        (0u64..)
+            .map(|x| x.to_string())
+            .find(|nonce| leading_zero_bits(&check.challenge, nonce) >= check.difficulty as u32)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pow_captcha_solve() {
+        let captcha = ProofOfWorkCaptcha::new(b"secret", 8);
+        let (check, html) = captcha.generate();
+        assert!(html.into_string().contains("data-pow-difficulty=\"8\""));
+        let nonce = solve(&check);
+        let verification = captcha
+            .verify(&check, &format!("captcha={}", nonce))
+            .expect("valid check code");
+        assert!(verification.solved);
+        let code: ProofOfWorkChallenge = serde_json::from_str(&check).unwrap();
+        assert_eq!(code.challenge, verification.id);
+        assert_eq!(code.expires_at, verification.expires_at.timestamp());
+    }
+
+    #[test]
+    fn test_pow_captcha_reject() {
+        let captcha = ProofOfWorkCaptcha::new(b"secret", 8);
+
+        // insufficient work
+        let (check, _) = captcha.generate();
+        let code: ProofOfWorkChallenge = serde_json::from_str(&check).unwrap();
+        let wrong = (0u64..)
+            .map(|x| x.to_string())
+            .find(|nonce| leading_zero_bits(&code.challenge, nonce) < 8)
+            .unwrap();
+        let verification = captcha.verify(&check, &format!("captcha={}", wrong));
+        assert!(!verification.unwrap().solved);
+        assert!(!captcha.verify(&check, "captcha=").unwrap().solved);
+
+        // lowered difficulty
+        let (check, _) = captcha.generate();
+        let mut code: ProofOfWorkChallenge = serde_json::from_str(&check).unwrap();
+        code.difficulty = 0;
+        let tampered = serde_json::to_string(&code).unwrap();
+        assert!(captcha.verify(&tampered, "captcha=0").is_none());
+
+        // challenge signed with another key
+        let other = ProofOfWorkCaptcha::new(b"other secret", 8);
+        let (check, _) = other.generate();
+        assert!(captcha
+            .verify(&check, &format!("captcha={}", solve(&check)))
+            .is_none());
+
+        // expired challenge
+        let mut expired = ProofOfWorkCaptcha::new(b"secret", 8);
+        expired.validity = Duration::seconds(-1);
+        let (check, _) = expired.generate();
+        assert!(expired
+            .verify(&check, &format!("captcha={}", solve(&check)))
+            .is_none());
+    }
+}
@@ -0,0 +1,272 @@
+//! A small rasterizer for the cutie marks of the pony captcha
+//!
+//! Marks are described as filled or stroked outlines in a 100 by 100 coordinate space. Each
+//! rendering uses a random placement, shifted colours, background shapes and pixel noise, so
+//! neither the markup nor the bytes of the resulting PNG identify the mark.
+
+use std::io::Cursor;
+
+use rand::Rng;
+use tiberius_dependencies::image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+
+/// Width and height of a rendered mark in pixels
+pub const MARK_SIZE: u32 = 100;
+/// Samples taken per pixel along each axis to smooth the edges
+const SUPERSAMPLING: u32 = 2;
+/// Number of points used to approximate ellipses and curve segments
+const CURVE_STEPS: usize = 32;
+
+pub type Point = (f32, f32);
+
+pub enum Shape {
+    /// A closed outline
+    Polygon(Vec<Point>),
+    /// An open line, it can only be stroked
+    Polyline(Vec<Point>),
+}
+
+pub struct Layer {
+    shape: Shape,
+    fill: Option<[u8; 3]>,
+    stroke: Option<([u8; 3], f32)>,
+    opacity: f32,
+    bounds: (Point, Point),
+}
+
+impl Layer {
+    pub fn filled(shape: Shape, fill: u32) -> Self {
+        Self::new(shape, Some(fill), None)
+    }
+
+    pub fn stroked(shape: Shape, stroke: u32, width: f32) -> Self {
+        Self::new(shape, None, Some((stroke, width)))
+    }
+
+    pub fn new(shape: Shape, fill: Option<u32>, stroke: Option<(u32, f32)>) -> Self {
+        let points = match &shape {
+            Shape::Polygon(v) | Shape::Polyline(v) => v,
+        };
+        let margin = stroke.map(|x| x.1 / 2.0).unwrap_or(0.0);
+        let bounds = points.iter().fold(
+            ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+            |((x0, y0), (x1, y1)), (x, y)| ((x0.min(*x), y0.min(*y)), (x1.max(*x), y1.max(*y))),
+        );
+        Self {
+            shape,
+            fill: fill.map(rgb),
+            stroke: stroke.map(|(color, width)| (rgb(color), width)),
+            opacity: 1.0,
+            bounds: (
+                (bounds.0 .0 - margin, bounds.0 .1 - margin),
+                (bounds.1 .0 + margin, bounds.1 .1 + margin),
+            ),
+        }
+    }
+
+    pub fn with_opacity(self, opacity: f32) -> Self {
+        Self { opacity, ..self }
+    }
+
+    /// Returns the colour of the layer at the point, if the layer covers it
+    fn color_at(&self, (x, y): Point) -> Option<[u8; 3]> {
+        let ((x0, y0), (x1, y1)) = self.bounds;
+        if x < x0 || x > x1 || y < y0 || y > y1 {
+            return None;
+        }
+        let (points, closed) = match &self.shape {
+            Shape::Polygon(v) => (v, true),
+            Shape::Polyline(v) => (v, false),
+        };
+        if let Some((color, width)) = self.stroke {
+            if distance_to_outline(points, closed, (x, y)) <= width / 2.0 {
+                return Some(color);
+            }
+        }
+        match self.fill {
+            Some(color) if closed && contains(points, (x, y)) => Some(color),
+            _ => None,
+        }
+    }
+}
+
+fn rgb(color: u32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+fn contains(points: &[Point], (x, y): Point) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn distance_to_outline(points: &[Point], closed: bool, p: Point) -> f32 {
+    let segments = points.windows(2).map(|x| (x[0], x[1]));
+    let closing = match (closed, points.first(), points.last()) {
+        (true, Some(first), Some(last)) => Some((*last, *first)),
+        _ => None,
+    };
+    segments
+        .chain(closing)
+        .map(|(a, b)| distance_to_segment(a, b, p))
+        .fold(f32::MAX, f32::min)
+}
+
+fn distance_to_segment((ax, ay): Point, (bx, by): Point, (px, py): Point) -> f32 {
+    let (dx, dy) = (bx - ax, by - ay);
+    let length = dx * dx + dy * dy;
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / length).clamp(0.0, 1.0)
+    };
+    let (cx, cy) = (ax + t * dx, ay + t * dy);
+    ((px - cx).powi(2) + (py - cy).powi(2)).sqrt()
+}
+
+pub fn ellipse((cx, cy): Point, rx: f32, ry: f32) -> Vec<Point> {
+    (0..CURVE_STEPS)
+        .map(|i| {
+            let angle = i as f32 / CURVE_STEPS as f32 * std::f32::consts::TAU;
+            (cx + rx * angle.cos(), cy + ry * angle.sin())
+        })
+        .collect()
+}
+
+pub fn rect((x, y): Point, width: f32, height: f32) -> Vec<Point> {
+    vec![
+        (x, y),
+        (x + width, y),
+        (x + width, y + height),
+        (x, y + height),
+    ]
+}
+
+/// Approximates a cubic bezier curve, the start point is not included
+pub fn cubic(from: Point, c1: Point, c2: Point, to: Point) -> Vec<Point> {
+    (1..=CURVE_STEPS / 4)
+        .map(|i| {
+            let t = i as f32 / (CURVE_STEPS / 4) as f32;
+            let u = 1.0 - t;
+            let point = |a: f32, b: f32, c: f32, d: f32| {
+                u * u * u * a + 3.0 * u * u * t * b + 3.0 * u * t * t * c + t * t * t * d
+            };
+            (
+                point(from.0, c1.0, c2.0, to.0),
+                point(from.1, c1.1, c2.1, to.1),
+            )
+        })
+        .collect()
+}
+
+/// Approximates a quadratic bezier curve including the start point
+pub fn quadratic(from: Point, control: Point, to: Point) -> Vec<Point> {
+    (0..=CURVE_STEPS / 2)
+        .map(|i| {
+            let t = i as f32 / (CURVE_STEPS / 2) as f32;
+            let u = 1.0 - t;
+            (
+                u * u * from.0 + 2.0 * u * t * control.0 + t * t * to.0,
+                u * u * from.1 + 2.0 * u * t * control.1 + t * t * to.1,
+            )
+        })
+        .collect()
+}
+
+/// Rotates the points by the angle in degrees around the origin and moves them by the offset
+pub fn place(points: Vec<Point>, (dx, dy): Point, degrees: f32) -> Vec<Point> {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    points
+        .into_iter()
+        .map(|(x, y)| (x * cos - y * sin + dx, x * sin + y * cos + dy))
+        .collect()
+}
+
+fn hsl(hue: f32, saturation: f32, lightness: f32) -> u32 {
+    let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = hue / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = lightness - c / 2.0;
+    let channel = |v: f32| (((v + m) * 255.0).round() as u32).min(255);
+    (channel(r) << 16) | (channel(g) << 8) | channel(b)
+}
+
+fn blend(base: [f32; 3], color: [u8; 3], opacity: f32) -> [f32; 3] {
+    std::array::from_fn(|i| base[i] * (1.0 - opacity) + color[i] as f32 * opacity)
+}
+
+/// Renders the mark at a random angle, size, position and mirroring into a PNG
+pub fn render_png<R: Rng>(mark: &[Layer], rng: &mut R) -> Vec<u8> {
+    let size = MARK_SIZE as f32;
+    let background = rgb(hsl(rng.gen_range(0.0..360.0), 0.4, 0.8));
+    let noise: Vec<Layer> = (0..rng.gen_range(4..9))
+        .map(|_| {
+            let center = (rng.gen_range(0.0..size), rng.gen_range(0.0..size));
+            let radius = rng.gen_range(3.0..15.0);
+            let color = hsl(rng.gen_range(0.0..360.0), 0.3, 0.75);
+            Layer::filled(Shape::Polygon(ellipse(center, radius, radius)), color).with_opacity(0.5)
+        })
+        .collect();
+    let angle = rng.gen_range(-35.0f32..35.0).to_radians();
+    let (sin, cos) = angle.sin_cos();
+    let mirror = if rng.gen_bool(0.5) { -1.0 } else { 1.0 };
+    let scale = rng.gen_range(0.75..0.95);
+    let offset = (rng.gen_range(-5.0..5.0), rng.gen_range(-5.0..5.0));
+    let shift: [f32; 3] = std::array::from_fn(|_| rng.gen_range(-24.0..24.0));
+    // maps a point of the image back into the coordinates of the mark
+    let to_mark = |(x, y): Point| {
+        let (x, y) = (x - size / 2.0 - offset.0, y - size / 2.0 - offset.1);
+        let (x, y) = (x * cos + y * sin, -x * sin + y * cos);
+        (x / scale * mirror + size / 2.0, y / scale + size / 2.0)
+    };
+    let mut image = RgbImage::new(MARK_SIZE, MARK_SIZE);
+    for (px, py, pixel) in image.enumerate_pixels_mut() {
+        let mut sum = [0.0f32; 3];
+        for sample in 0..SUPERSAMPLING * SUPERSAMPLING {
+            let point = (
+                px as f32 + ((sample % SUPERSAMPLING) as f32 + 0.5) / SUPERSAMPLING as f32,
+                py as f32 + ((sample / SUPERSAMPLING) as f32 + 0.5) / SUPERSAMPLING as f32,
+            );
+            let mut color = background.map(|x| x as f32);
+            for layer in &noise {
+                if let Some(c) = layer.color_at(point) {
+                    color = blend(color, c, layer.opacity);
+                }
+            }
+            let mark_point = to_mark(point);
+            for layer in mark {
+                if let Some(c) = layer.color_at(mark_point) {
+                    let c =
+                        std::array::from_fn(|i| (c[i] as f32 + shift[i]).clamp(0.0, 255.0) as u8);
+                    color = blend(color, c, layer.opacity);
+                }
+            }
+            for i in 0..3 {
+                sum[i] += color[i];
+            }
+        }
+        let samples = (SUPERSAMPLING * SUPERSAMPLING) as f32;
+        *pixel =
+            Rgb(sum.map(|x| (x / samples + rng.gen_range(-12.0..12.0)).clamp(0.0, 255.0) as u8));
+    }
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .expect("encoding a png in memory cannot fail");
+    png
+}
@@ -49,6 +49,16 @@ pub enum ACLObject {
     StaffCategory,
     /// Staff Entry into the Staff Page
     StaffUserEntry,
+    /// A user account
+    User,
+    /// A livestream channel
+    Channel,
+    /// A badge that can be awarded to users
+    Badge,
+    /// A link from a user to their presence on another site
+    UserLink,
+    /// A tag that can be added to images
+    Tag,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -71,6 +81,8 @@ pub enum ACLActionImage {
     MergeDuplicate,
     IncrementView,
     RepairImage,
+    /// Make the image the featured image of the front page
+    Feature,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -80,6 +92,40 @@ pub enum ACLActionAPIKey {
     Admin,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACLActionUser {
+    /// View and revoke the login sessions of other users
+    ManageSessions,
+    /// Remove inappropriate avatars of other users
+    RemoveAvatar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACLActionChannel {
+    /// Register, edit and delete channels
+    Manage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACLActionBadge {
+    /// Create and edit badges
+    Manage,
+    /// Award badges to users and revoke them
+    Award,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACLActionUserLink {
+    /// Approve, reject and contact the owners of user links
+    Verify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACLActionTag {
+    /// Edit the description of any tag, verified artists can always edit their own tag
+    EditDescription,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ACLActionStaffCategory {
     Manage,
@@ -114,6 +160,11 @@ impl ACLObjectTrait for ACLObject {
             ACLObject::StaffCategory => "staff_category",
             ACLObject::StaffUserEntry => "staff_user_entry",
             ACLObject::Filter => "filter",
+            ACLObject::User => "user",
+            ACLObject::Channel => "channel",
+            ACLObject::Badge => "badge",
+            ACLObject::UserLink => "user_link",
+            ACLObject::Tag => "tag",
         }
         .to_string()
     }
@@ -153,6 +204,7 @@ impl ACLActionTrait for ACLActionImage {
             ACLActionImage::MergeDuplicate => "merge_duplicate".to_string(),
             ACLActionImage::IncrementView => "increment_view".to_string(),
             ACLActionImage::RepairImage => "repair_image".to_string(),
+            ACLActionImage::Feature => "feature".to_string(),
         }
     }
     fn action_of(&self, a: &ACLObject) -> bool {
@@ -212,6 +264,73 @@ impl ACLActionTrait for ACLActionFilter {
     }
 }
 
+impl ACLActionTrait for ACLActionUser {
+    fn action(&self) -> String {
+        match self {
+            ACLActionUser::ManageSessions => "manage_sessions",
+            ACLActionUser::RemoveAvatar => "remove_avatar",
+        }
+        .to_string()
+    }
+
+    fn action_of(&self, a: &ACLObject) -> bool {
+        *a == ACLObject::User
+    }
+}
+
+impl ACLActionTrait for ACLActionChannel {
+    fn action(&self) -> String {
+        match self {
+            ACLActionChannel::Manage => "manage",
+        }
+        .to_string()
+    }
+
+    fn action_of(&self, a: &ACLObject) -> bool {
+        *a == ACLObject::Channel
+    }
+}
+
+impl ACLActionTrait for ACLActionBadge {
+    fn action(&self) -> String {
+        match self {
+            ACLActionBadge::Manage => "manage",
+            ACLActionBadge::Award => "award",
+        }
+        .to_string()
+    }
+
+    fn action_of(&self, a: &ACLObject) -> bool {
+        *a == ACLObject::Badge
+    }
+}
+
+impl ACLActionTrait for ACLActionUserLink {
+    fn action(&self) -> String {
+        match self {
+            ACLActionUserLink::Verify => "verify",
+        }
+        .to_string()
+    }
+
+    fn action_of(&self, a: &ACLObject) -> bool {
+        *a == ACLObject::UserLink
+    }
+}
+
+impl ACLActionTrait for ACLActionTag {
+    fn action(&self) -> String {
+        match self {
+            ACLActionTag::EditDescription => "edit_description",
+        }
+        .to_string()
+    }
+
+    fn action_of(&self, a: &ACLObject) -> bool {
+        *a == ACLObject::Tag
+    }
+}
+
 #[instrument(skip(state, rstate), fields(user = rstate.session().raw_user()))]
 pub async fn verify_acl<T: SessionMode>(
     state: &TiberiusState,
@@ -226,6 +345,18 @@ pub async fn verify_acl<T: SessionMode>(
         action
     );
     let subject = rstate.user(state).await?;
+    if let Some(user) = &subject {
+        if state.site_config().require_staff_two_factor()
+            && user.is_staff()
+            && !user.has_second_factor(&mut state.get_db_client()).await?
+        {
+            warn!(
+                "Denying {:?} to staff user {} without two factor authentication",
+                action, user.id
+            );
+            return Ok(false);
+        }
+    }
     let subject = match subject {
         None => ACLSubject::None,
         Some(v) => ACLSubject::User(Box::new(v)),
@@ -175,6 +175,9 @@ pub struct SiteConfig {
     source_name: String,
     activity_filter: String,
     tag_url_root: String,
+    /// Staff members must enable two factor authentication before they can use their permissions
+    #[serde(default)]
+    require_staff_two_factor: bool,
 }
 
 impl SiteConfig {
@@ -193,6 +196,9 @@ impl SiteConfig {
     pub fn tag_url_root(&self) -> &String {
         &self.tag_url_root
     }
+    pub fn require_staff_two_factor(&self) -> bool {
+        self.require_staff_two_factor
+    }
 }
 
 #[derive(serde::Deserialize, Clone, Debug, Default)]
@@ -86,6 +86,14 @@ pub enum TiberiusError {
     TotpUrlError(totp_rs::TotpUrlError),
     #[error("Invalid Log Level {0:?}")]
     InvalidLogLevel(String),
+    #[error("Invalid Mail Transport {0:?}")]
+    InvalidMailTransport(String),
+    #[error("Invalid Captcha Provider {0:?}")]
+    InvalidCaptchaProvider(String),
+    #[error("Invalid Captcha Form {0:?}")]
+    InvalidCaptchaForm(String),
+    #[error("WebAuthn Error: {0:?}")]
+    Webauthn(#[from] tiberius_dependencies::webauthn_rs::prelude::WebauthnError),
     #[error("Could not read request: {0:?}")]
     MultipartError(#[from] axum::extract::multipart::MultipartError),
     #[error("Could not parse field: {0:?}")]
@@ -115,6 +123,8 @@ pub enum TiberiusError {
     InvalidJobId(Uuid),
     #[error("Attempted to immediate-schedule job {0:?} with a schedule: {1:?}")]
     ImmediateJobSchedule(Option<Uuid>, tiberius_dependencies::cron::Schedule),
+    #[error("Refusing to connect to non-public address of {0:?}")]
+    ForbiddenAddress(String),
 }
 
 pub type TiberiusResult<T> = std::result::Result<T, TiberiusError>;
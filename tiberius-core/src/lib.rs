@@ -38,6 +38,7 @@ pub mod error;
 pub mod footer;
 pub mod links;
 pub mod nodeid;
+pub mod remote;
 pub mod request_helper;
 pub mod session;
 pub mod state;
@@ -73,9 +74,25 @@ pub const COMMENT_CACHE_SIZE: u64 = 100;
 /// Comment PreRender cache initial size
 pub const COMMENT_CACHE_START_SIZE: usize = 10;
 
+/// Sidebar Block Cache TTL, kept short so new comments and topics show up quickly
+pub const SIDEBAR_CACHE_TTL: Duration = Duration::from_secs(30);
+/// Sidebar Block cache max size
+pub const SIDEBAR_CACHE_SIZE: u64 = 1_000;
+/// Sidebar Block cache initial size
+pub const SIDEBAR_CACHE_START_SIZE: usize = 10;
+
+/// Session Record Cache TTL, revoking a session on another server takes effect after this long
+pub const SESSION_RECORD_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Session Record cache max size
+pub const SESSION_RECORD_CACHE_SIZE: u64 = 10_000;
+
 pub use nodeid::NodeId;
 
 pub fn http_client(config: &Configuration) -> TiberiusResult<reqwest::Client> {
+    Ok(http_client_builder(config)?.build()?)
+}
+
+fn http_client_builder(config: &Configuration) -> TiberiusResult<reqwest::ClientBuilder> {
     let client = reqwest::Client::builder()
         .connect_timeout(std::time::Duration::from_millis(500))
         .timeout(std::time::Duration::from_secs(5))
@@ -85,7 +102,7 @@ pub fn http_client(config: &Configuration) -> TiberiusResult<reqwest::Client> {
     } else {
         client
     };
-    Ok(client.default_headers(common_headers()).build()?)
+    Ok(client.default_headers(common_headers()))
 }
 
 fn common_headers() -> HeaderMap {
@@ -120,7 +137,7 @@ impl CSPHeader {
     fn header_name(&self) -> &'static str {
         "Content-Security-Policy"
     }
-    fn header_value(&self) -> HeaderValue {
+    fn header_value(&self, allow_framing: bool) -> HeaderValue {
         use csp::*;
         let default_src = match &self.static_host {
             Some(static_host) => Sources::new_with(Source::Self_).add(Source::Host(static_host)),
@@ -151,7 +168,11 @@ impl CSPHeader {
         let csp = CSP::new()
             .add(Directive::DefaultSrc(default_src))
             .add(Directive::ObjectSrc(Sources::new()))
-            .add(Directive::FrameAncestors(Sources::new()))
+            .add(Directive::FrameAncestors(if allow_framing {
+                Sources::new_with(Source::Host("*"))
+            } else {
+                Sources::new()
+            }))
             .add(Directive::FrameSrc(Sources::new()))
             .add(Directive::FormAction(Sources::new_with(Source::Self_)))
             .add(Directive::ManifestSrc(Sources::new_with(Source::Self_)))
@@ -162,6 +183,10 @@ impl CSPHeader {
     }
 }
 
+/// Response extension that permits other sites to embed the page in a frame
+#[derive(Clone, Copy, Debug)]
+pub struct AllowFraming;
+
 pub async fn csp_header<B>(
     State(state): State<TiberiusState>,
     req: Request<B>,
@@ -169,8 +194,11 @@ pub async fn csp_header<B>(
 ) -> Response {
     let csp_header = state.csp();
     let mut resp = next.run(req).await;
-    resp.headers_mut()
-        .insert(csp_header.header_name(), csp_header.header_value());
+    let allow_framing = resp.extensions().get::<AllowFraming>().is_some();
+    resp.headers_mut().insert(
+        csp_header.header_name(),
+        csp_header.header_value(allow_framing),
+    );
     resp
 }
 
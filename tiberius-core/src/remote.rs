@@ -0,0 +1,179 @@
+//! Fetching of URLs supplied by users
+//!
+//! Without a proxy the server would connect to whatever the URL points at, including the
+//! loopback interface, the local network and cloud metadata endpoints. Clients created here
+//! refuse such addresses unless a proxy is configured, which is then responsible for it.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tiberius_dependencies::reqwest;
+
+use crate::{
+    config::Configuration,
+    error::{TiberiusError, TiberiusResult},
+    http_client_builder,
+};
+
+/// Returns true if the address is reachable over the public internet
+pub fn is_global_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_ipv4(ip),
+        IpAddr::V6(ip) => is_global_ipv6(ip),
+    }
+}
+
+fn is_global_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network"
+        || a == 0
+        // shared address space used for carrier grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // reserved for future use
+        || a >= 240)
+}
+
+fn is_global_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_global_ipv4(ip);
+    }
+    let segments = ip.segments();
+    // NAT64 addresses reach the embedded IPv4 address
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_global_ipv4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local addresses
+        || (segments[0] & 0xfe00) == 0xfc00
+        // link local addresses
+        || (segments[0] & 0xffc0) == 0xfe80
+        // documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // deprecated IPv4 compatible addresses
+        || segments[..6] == [0, 0, 0, 0, 0, 0])
+}
+
+/// Creates a HTTP client to fetch a URL supplied by a user
+///
+/// Requests are sent through the configured proxy if there is one. Otherwise the host is
+/// resolved here and [TiberiusError::ForbiddenAddress] is returned unless all of its addresses
+/// are global. The client is pinned to the checked addresses, so the host cannot resolve to
+/// another address once the request is made. Redirects are never followed.
+pub async fn http_client_for_url(
+    config: &Configuration,
+    url: &url::Url,
+) -> TiberiusResult<reqwest::Client> {
+    let client = http_client_builder(config)?;
+    if config.proxy.is_some() {
+        return Ok(client.build()?);
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| TiberiusError::ForbiddenAddress(url.to_string()))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| TiberiusError::ForbiddenAddress(url.to_string()))?;
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(url::Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(url::Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        _ => tokio::net::lookup_host((host, port)).await?.collect(),
+    };
+    if addrs.is_empty() || !addrs.iter().all(|x| is_global_ip(x.ip())) {
+        return Err(TiberiusError::ForbiddenAddress(host.to_string()));
+    }
+    Ok(client.resolve_to_addrs(host, &addrs).build()?)
+}
+
+/// Reads the body of the response, returns None if it is larger than `limit` bytes
+///
+/// The body is read in chunks, so an oversized body is not kept in memory.
+pub async fn read_body_limited(
+    mut resp: reqwest::Response,
+    limit: u64,
+) -> TiberiusResult<Option<Vec<u8>>> {
+    if resp.content_length().unwrap_or_default() > limit {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::{http_client_for_url, is_global_ip};
+    use crate::{config::Configuration, error::TiberiusError};
+
+    #[test]
+    fn test_is_global_ip() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_global_ip(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_global_ip(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_client_for_url_refuses_local_hosts() {
+        let config = Configuration::default();
+        for url in [
+            "http://127.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]:8080/",
+            "http://localhost/",
+        ] {
+            let url = url::Url::parse(url).unwrap();
+            assert!(
+                matches!(
+                    http_client_for_url(&config, &url).await,
+                    Err(TiberiusError::ForbiddenAddress(_))
+                ),
+                "{}",
+                url
+            );
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use std::{borrow::Cow, convert::TryInto};
+use std::{
+    borrow::Cow,
+    convert::TryInto,
+    net::{IpAddr, SocketAddr},
+};
 
 use axum::{headers::ContentType, middleware::Next};
 use axum_extra::routing::TypedPath;
 use either::Either;
 use serde::{de::DeserializeOwned, Deserialize};
-use sqlx::{pool::PoolConnection, Pool, Postgres};
+use sqlx::{pool::PoolConnection, types::ipnetwork::IpNetwork, Pool, Postgres};
 use tiberius_dependencies::{
     axum,
     axum::{
@@ -16,7 +20,10 @@ use tiberius_dependencies::{
 };
 use tiberius_models::{ApiKey, Client, DirectSafeSerialize, Image, SafeSerialize};
 
-use axum::http::{HeaderMap, Request};
+use axum::{
+    extract::ConnectInfo,
+    http::{request::Parts, HeaderMap, Request},
+};
 
 use crate::{
     acl::{verify_acl, ACLActionSite, ACLObject},
@@ -98,6 +105,59 @@ impl<T: std::fmt::Debug> ApiFormData<T> {
     }
 }
 
+/// Returns the address of the client that sent the request
+///
+/// If the request comes from one of the [Configuration::trusted_proxies], the address reported
+/// by the proxy is used, otherwise the address of the peer. Returns None if the server was not
+/// started with connection info.
+pub fn client_ip(config: &Configuration, parts: &Parts) -> Option<IpAddr> {
+    let ConnectInfo(peer) = parts.extensions.get::<ConnectInfo<SocketAddr>>()?;
+    Some(forwarded_client_ip(
+        &config.trusted_proxies,
+        peer.ip(),
+        &parts.headers,
+    ))
+}
+
+/// Resolves the client address of a request received from `peer`
+///
+/// X-Forwarded-For is read from the right and the first address that is not a trusted proxy
+/// is used, entries further left were supplied by the client itself. X-Real-IP is used if
+/// there is no X-Forwarded-For header.
+fn forwarded_client_ip(trusted: &[IpNetwork], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|x| x.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let forwarded_for: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .map(str::trim)
+        .collect();
+    if !forwarded_for.is_empty() {
+        let mut client = peer;
+        for entry in forwarded_for.iter().rev() {
+            match entry.parse() {
+                Ok(ip) => {
+                    client = ip;
+                    if !is_trusted(ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        return client;
+    }
+    headers
+        .get("X-Real-IP")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
 pub struct SqlxMiddleware {
     pool: Pool<Postgres>,
 }
@@ -270,3 +330,67 @@ where
         (self.headers, self.content).into_response()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use axum::http::HeaderMap;
+
+    use super::forwarded_client_ip;
+
+    fn ip(v: &str) -> IpAddr {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_client_ip() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", "1.2.3.4, 5.6.7.8".parse().unwrap());
+        headers.insert("X-Real-IP", "9.9.9.9".parse().unwrap());
+
+        // headers of untrusted peers are ignored
+        assert_eq!(
+            ip("203.0.113.1"),
+            forwarded_client_ip(&trusted, ip("203.0.113.1"), &headers)
+        );
+        assert_eq!(
+            ip("10.0.0.1"),
+            forwarded_client_ip(&[], ip("10.0.0.1"), &headers)
+        );
+
+        // the entry added by the trusted proxy wins over the ones sent by the client
+        assert_eq!(
+            ip("5.6.7.8"),
+            forwarded_client_ip(&trusted, ip("10.0.0.1"), &headers)
+        );
+        headers.insert(
+            "X-Forwarded-For",
+            "1.2.3.4, 5.6.7.8, 10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(
+            ip("5.6.7.8"),
+            forwarded_client_ip(&trusted, ip("::1"), &headers)
+        );
+        headers.insert(
+            "X-Forwarded-For",
+            "5.6.7.8, garbage, 10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(
+            ip("10.0.0.2"),
+            forwarded_client_ip(&trusted, ip("10.0.0.1"), &headers)
+        );
+
+        headers.remove("X-Forwarded-For");
+        assert_eq!(
+            ip("9.9.9.9"),
+            forwarded_client_ip(&trusted, ip("10.0.0.1"), &headers)
+        );
+        headers.remove("X-Real-IP");
+        assert_eq!(
+            ip("10.0.0.1"),
+            forwarded_client_ip(&trusted, ip("10.0.0.1"), &headers)
+        );
+    }
+}
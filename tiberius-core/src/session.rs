@@ -8,19 +8,19 @@ use std::{
 use async_std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use async_trait::async_trait;
 use axum::headers::{self, Header};
-use sqlx::{pool::PoolConnection, PgPool, Postgres};
+use sqlx::{pool::PoolConnection, types::ipnetwork::IpNetwork, PgPool, Postgres};
 use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
 use tiberius_dependencies::uuid;
 use tiberius_dependencies::{
     async_once_cell::OnceCell,
     axum,
     axum::{
-        extract::FromRequest,
+        extract::{FromRequest, FromRequestParts},
         headers::{
             authorization::{Basic, Bearer},
             Authorization, HeaderMapExt,
         },
-        http::StatusCode,
+        http::{request::Parts, HeaderMap, StatusCode},
         middleware::Next,
     },
     axum_extra::extract::{cookie::Cookie, CookieJar},
@@ -28,13 +28,14 @@ use tiberius_dependencies::{
     base64::Engine,
     http::Request,
 };
-use tiberius_models::{Client, User};
+use tiberius_models::{ApiKey, ApiKeyScope, Client, User, UserSession};
 use tracing::{info, trace, warn};
 use uuid::Uuid;
 
 use crate::{
     app::DBPool,
     error::{TiberiusError, TiberiusResult},
+    request_helper::client_ip,
     state::TiberiusState, TIBERIUS_SESSION_KEY,
 };
 
@@ -42,6 +43,12 @@ use crate::session::philomena_plug::handover_session;
 
 pub mod philomena_plug;
 
+/// How long a login lasts before the user has to sign in again
+pub const LOGIN_MAX_AGE_DAYS: i64 = 365;
+
+/// How long after the password step the second factor of a login is accepted
+pub const SECOND_FACTOR_MAX_AGE_MINUTES: i64 = 5;
+
 #[derive(Clone, Debug)]
 pub struct PostgresSessionStore {
     client: PgPool,
@@ -67,6 +74,7 @@ impl SessionMode for Testing {}
 
 pub enum AuthMethod {
     TOTP,
+    WebAuthn,
 }
 
 /// Session contains and maintains a user session as well as metadata for the session,
@@ -79,6 +87,9 @@ pub struct Session<MODE: SessionMode> {
     expires: NaiveDateTime,
     csrf_token: String,
     user_id: Option<i64>,
+    /// When the user logged into this session, sessions of the user can be revoked by this time
+    #[serde(default)]
+    logged_in_at: Option<NaiveDateTime>,
     data: BTreeMap<String, serde_json::Value>,
     /// Indicates if the session structure has been altered, meaning it must be saved to the database
     /// Is set automatically if the session is borrowed from SessionPtr as writeable
@@ -89,6 +100,18 @@ pub struct Session<MODE: SessionMode> {
     #[serde(skip)]
     ephemeral: bool,
     waiting_on_totp: bool,
+    #[serde(default)]
+    waiting_on_webauthn: bool,
+    /// When the password step of a pending login passed
+    #[serde(default)]
+    password_passed_at: Option<NaiveDateTime>,
+    /// When the user last re-authenticated to perform sensitive actions
+    #[serde(default)]
+    stepped_up_at: Option<NaiveDateTime>,
+    /// Set once the login has been recorded in user_sessions, if the record disappears
+    /// afterwards the session was revoked
+    #[serde(default)]
+    tracked: bool,
 
     #[serde(skip, default = "OnceCell::new")]
     cache_user: OnceCell<Option<User>>,
@@ -103,10 +126,15 @@ impl<T: SessionMode> Clone for Session<T> {
             expires: self.expires,
             csrf_token: self.csrf_token.clone(),
             user_id: self.user_id,
+            logged_in_at: self.logged_in_at,
             data: self.data.clone(),
             dirty: self.dirty,
             ephemeral: self.ephemeral,
             waiting_on_totp: self.waiting_on_totp,
+            waiting_on_webauthn: self.waiting_on_webauthn,
+            password_passed_at: self.password_passed_at,
+            stepped_up_at: self.stepped_up_at,
+            tracked: self.tracked,
             cache_user: OnceCell::new_with(self.cache_user.get().cloned().expect("could not get user from cache")),
         }
     }
@@ -121,10 +149,15 @@ impl From<Session<Authenticated>> for Session<Unauthenticated> {
             expires: value.expires,
             csrf_token: value.csrf_token,
             user_id: None,
+            logged_in_at: None,
             data: value.data,
             dirty: value.dirty,
             ephemeral: value.ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -141,10 +174,15 @@ impl From<Session<Unauthenticated>> for Session<Testing> {
             expires: value.expires,
             csrf_token: value.csrf_token,
             user_id: None,
+            logged_in_at: None,
             data: value.data,
             dirty: value.dirty,
             ephemeral: value.ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -161,10 +199,15 @@ impl From<Session<Authenticated>> for Session<Testing> {
             expires: value.expires,
             csrf_token: value.csrf_token,
             user_id: None,
+            logged_in_at: None,
             data: value.data,
             dirty: value.dirty,
             ephemeral: value.ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -214,6 +257,9 @@ impl<T: SessionMode> Session<T> {
     pub fn get_json_data(&self, key: String) -> Option<&serde_json::Value> {
         self.data.get(&key)
     }
+    pub fn remove_data(&mut self, key: &str) -> Option<serde_json::Value> {
+        self.data.remove(key)
+    }
     /// Returns true if the session is not persisted into cookies or the database backend
     ///
     /// To set a session as ephemeral, it must be created by passing `true` to the `Session::new()` constructor.
@@ -231,38 +277,203 @@ impl<T: SessionMode> Session<T> {
     }
 
     pub async fn get_user(&self, client: &mut Client) -> TiberiusResult<Option<User>> {
+        if self.more_auth() {
+            // the user has not finished logging in yet
+            return Ok(None);
+        }
         match self.user_id {
             None => Ok(None),
             /*Some(user_id) => Ok(self
             .cache_user
             .get_or_try_init(User::get_id(client, user_id))
             .await?.clone()),*/
-            Some(user_id) => Ok(User::get_id(client, user_id).await?),
+            Some(user_id) => Ok(User::get_id(client, user_id)
+                .await?
+                .filter(|user| user.session_valid(self.logged_in_at.unwrap_or(self.created)))),
         }
     }
 
     pub fn set_user(&mut self, user: &User) {
         self.user_id = Some(user.id as i64);
+        self.logged_in_at = Some(tiberius_dependencies::chrono::Utc::now().naive_utc());
+        // a new login starts over, second factors of an earlier attempt no longer apply
+        self.waiting_on_totp = false;
+        self.waiting_on_webauthn = false;
+        self.password_passed_at = None;
+        self.tracked = false;
+    }
+
+    /// Moves the login time of the session to now, so it survives [User::revoke_sessions]
+    pub fn renew_login(&mut self) {
+        if self.user_id.is_some() {
+            self.logged_in_at = Some(tiberius_dependencies::chrono::Utc::now().naive_utc());
+            self.mark_dirty();
+        }
+    }
+
+    /// Checks the session against it's record in user_sessions, creating the record on the
+    /// first request after logging in and updating the last seen time afterwards
+    ///
+    /// If the record was revoked, the user is logged out of this session.
+    /// Returns true if the session was changed and must be saved.
+    pub async fn verify_record(
+        &mut self,
+        client: &mut Client,
+        headers: &HeaderMap,
+        ip: Option<std::net::IpAddr>,
+    ) -> TiberiusResult<bool> {
+        if self.ephemeral || self.more_auth() {
+            return Ok(false);
+        }
+        let user_id = match self.user_id {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let user_agent: Option<String> = headers
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.chars().take(255).collect());
+        let ip = ip.map(IpNetwork::from);
+        let record = UserSession::get(client, self.id).await?;
+        match record {
+            Some(mut record) if self.tracked && record.user_id == Some(user_id as i32) => {
+                if record.last_seen_at + UserSession::touch_interval() < Utc::now().naive_utc() {
+                    record.touch(client, user_agent.as_deref(), ip).await?;
+                }
+                Ok(false)
+            }
+            _ if self.tracked => {
+                info!("Session {} was revoked, logging out", self.id);
+                self.unset_user();
+                self.mark_dirty();
+                Ok(true)
+            }
+            _ => {
+                UserSession::create(
+                    client,
+                    self.id,
+                    user_id,
+                    user_agent.as_deref(),
+                    ip,
+                    Utc::now() + Duration::days(LOGIN_MAX_AGE_DAYS),
+                )
+                .await?;
+                self.tracked = true;
+                self.mark_dirty();
+                Ok(true)
+            }
+        }
     }
 
     pub fn unset_user(&mut self) {
         self.user_id = None;
+        self.logged_in_at = None;
+        self.waiting_on_totp = false;
+        self.waiting_on_webauthn = false;
+        self.password_passed_at = None;
+        self.stepped_up_at = None;
+        self.tracked = false;
     }
 
     /// Indicates that more authentication methods are still being waited on, the session is not yet valid
     pub fn more_auth(&self) -> bool {
-        self.waiting_on_totp
+        self.waiting_on_totp || self.waiting_on_webauthn
+    }
+
+    /// Returns true if the given method may be used to complete the login
+    pub fn waiting_on(&self, r: AuthMethod) -> bool {
+        if self.second_factor_expired() {
+            return false;
+        }
+        match r {
+            AuthMethod::TOTP => self.waiting_on_totp,
+            AuthMethod::WebAuthn => self.waiting_on_webauthn,
+        }
+    }
+
+    /// Returns true if the pending login waited too long on the second factor,
+    /// the user has to enter their password again
+    pub fn second_factor_expired(&self) -> bool {
+        if !self.more_auth() {
+            return false;
+        }
+        match self.password_passed_at {
+            None => true,
+            Some(v) => {
+                v + Duration::minutes(SECOND_FACTOR_MAX_AGE_MINUTES)
+                    <= tiberius_dependencies::chrono::Utc::now().naive_utc()
+            }
+        }
     }
 
+    /// Marks an authentication method as acceptable second factor for the pending login
+    ///
+    /// The second factor is accepted for [SECOND_FACTOR_MAX_AGE_MINUTES] from the first call.
     pub fn set_waiting_auths(&mut self, r: AuthMethod) {
+        if !self.more_auth() {
+            self.password_passed_at = Some(tiberius_dependencies::chrono::Utc::now().naive_utc());
+        }
         match r {
             AuthMethod::TOTP => self.waiting_on_totp = true,
+            AuthMethod::WebAuthn => self.waiting_on_webauthn = true,
+        }
+    }
+
+    /// Marks an authentication method as completed
+    ///
+    /// Any one second factor completes the login, so the remaining alternatives are no longer
+    /// waited on. Completing a second factor also counts as step-up authentication.
+    pub fn complete_auth(&mut self, r: AuthMethod) {
+        match r {
+            AuthMethod::TOTP | AuthMethod::WebAuthn => {
+                self.waiting_on_totp = false;
+                self.waiting_on_webauthn = false;
+            }
+        }
+        self.password_passed_at = None;
+        self.step_up();
+    }
+
+    /// Records that the user re-authenticated for sensitive actions
+    pub fn step_up(&mut self) {
+        self.stepped_up_at = Some(tiberius_dependencies::chrono::Utc::now().naive_utc());
+        self.mark_dirty();
+    }
+
+    /// Returns true if the user re-authenticated within the given duration
+    ///
+    /// Sensitive moderator actions require a recent step-up on top of a valid login.
+    pub fn stepped_up(&self, max_age: Duration) -> bool {
+        if self.more_auth() || self.user_id.is_none() {
+            return false;
+        }
+        match self.stepped_up_at {
+            None => false,
+            Some(v) => v + max_age > tiberius_dependencies::chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    /// Returns the user that has logged in with their password but still has to complete
+    /// further authentication methods
+    pub async fn pending_user(&self, client: &mut Client) -> TiberiusResult<Option<User>> {
+        if !self.more_auth() || self.second_factor_expired() {
+            return Ok(None);
+        }
+        match self.user_id {
+            None => Ok(None),
+            Some(user_id) => Ok(User::get_id(client, user_id)
+                .await?
+                .filter(|user| user.session_valid(self.logged_in_at.unwrap_or(self.created)))),
         }
     }
 
     pub fn raw_user(&self) -> Option<i64> {
         self.user_id
     }
+    /// True once the login has been recorded in user_sessions, see [Session::verify_record]
+    pub fn tracked(&self) -> bool {
+        self.tracked
+    }
 }
 
 impl Session<Authenticated> {
@@ -277,6 +488,7 @@ impl Session<Authenticated> {
                 .expect("must be valid"),
             data: BTreeMap::new(),
             user_id: Some(user_id),
+            logged_in_at: Some(tiberius_dependencies::chrono::Utc::now().naive_utc()),
             csrf_token: base64::encode(
                 ring::rand::generate::<[u8; 32]>(&ring::rand::SystemRandom::new())
                     .unwrap()
@@ -285,6 +497,10 @@ impl Session<Authenticated> {
             dirty: false,
             ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -297,10 +513,15 @@ impl Session<Authenticated> {
             expires: self.expires,
             csrf_token: self.csrf_token,
             user_id: self.user_id,
+            logged_in_at: self.logged_in_at,
             data: self.data,
             dirty: self.dirty,
             ephemeral: self.ephemeral,
-            waiting_on_totp: false,
+            waiting_on_totp: self.waiting_on_totp,
+            waiting_on_webauthn: self.waiting_on_webauthn,
+            password_passed_at: self.password_passed_at,
+            stepped_up_at: self.stepped_up_at,
+            tracked: self.tracked,
 
             cache_user: OnceCell::new(),
         }
@@ -319,6 +540,7 @@ impl Session<Unauthenticated> {
                 .expect("must be valid"),
             data: BTreeMap::new(),
             user_id: None,
+            logged_in_at: None,
             csrf_token: base64::engine::general_purpose::STANDARD.encode(
                 ring::rand::generate::<[u8; 32]>(&ring::rand::SystemRandom::new())
                     .unwrap()
@@ -327,6 +549,10 @@ impl Session<Unauthenticated> {
             dirty: false,
             ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -339,10 +565,18 @@ impl Session<Unauthenticated> {
             expires: self.expires,
             csrf_token: self.csrf_token,
             user_id: Some(user_id),
+            logged_in_at: self
+                .logged_in_at
+                .filter(|_| self.user_id == Some(user_id))
+                .or_else(|| Some(tiberius_dependencies::chrono::Utc::now().naive_utc())),
             data: self.data,
             dirty: self.dirty,
             ephemeral: self.ephemeral,
             waiting_on_totp: false,
+            waiting_on_webauthn: false,
+            password_passed_at: None,
+            stepped_up_at: None,
+            tracked: false,
 
             cache_user: OnceCell::new(),
         }
@@ -361,9 +595,7 @@ impl SessionID {
 }
 
 // Returns authorization from HTTP Header
-fn authorization<B: Send>(req: &Request<B>) -> Option<String> {
-    static HEADER: &str = "Authorization";
-    let headers = req.headers();
+fn authorization(headers: &HeaderMap) -> Option<String> {
     if headers.get(Authorization::<Bearer>::name()).is_some() {
         let auth_headers: Option<Authorization<Bearer>> =
             headers.typed_get::<headers::Authorization<Bearer>>();
@@ -375,12 +607,86 @@ fn authorization<B: Send>(req: &Request<B>) -> Option<String> {
 }
 
 // Turns an unauthorized session into an authorized session
-fn session_from_api_key<B: Send>(
-    session: &mut Session<Unauthenticated>,
-    key: &str,
-    req: &Request<B>,
-) -> TiberiusResult<Session<Authenticated>> {
-    todo!()
+fn session_from_api_key(
+    session: Session<Unauthenticated>,
+    key: &ApiKey,
+) -> Session<Authenticated> {
+    let mut session = session.into_authenticated(key.user_id());
+    // API sessions are never persisted
+    session.ephemeral = true;
+    session
+}
+
+/// Marks the scope an API endpoint requires from the API Key used to access it
+pub trait ApiScope: Send + Sync {
+    const SCOPE: ApiKeyScope;
+}
+
+macro_rules! api_scope {
+    ($name:ident, $scope:expr) => {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        pub struct $name {}
+        impl ApiScope for $name {
+            const SCOPE: ApiKeyScope = $scope;
+        }
+    };
+}
+
+api_scope!(ScopeRead, ApiKeyScope::Read);
+api_scope!(ScopeUpload, ApiKeyScope::Upload);
+
+/// Authenticates a request using the API Key given as Bearer token in the Authorization header
+///
+/// Requests without a valid and unexpired key or lacking the scope `S` are rejected.
+/// On success, the last use of the key is recorded.
+pub struct ApiKeyAuth<S: ApiScope> {
+    pub key: ApiKey,
+    pub user: User,
+    pub session: Session<Authenticated>,
+    _scope: PhantomData<S>,
+}
+
+#[async_trait]
+impl<S: ApiScope> FromRequestParts<TiberiusState> for ApiKeyAuth<S> {
+    type Rejection = TiberiusError;
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &TiberiusState,
+    ) -> Result<Self, Self::Rejection> {
+        let secret = match authorization(&req.headers) {
+            Some(v) => v,
+            None => {
+                debug!("API request without authorization header");
+                return Err(TiberiusError::AccessDenied);
+            }
+        };
+        let mut client = state.get_db_client();
+        let mut key = match ApiKey::get_by_secret(&mut client, &secret).await? {
+            Some(v) => v,
+            None => {
+                debug!("API request with unknown or expired key");
+                return Err(TiberiusError::AccessDenied);
+            }
+        };
+        if !key.has_scope(S::SCOPE) {
+            debug!("API key {} lacks scope {:?}", key.id(), S::SCOPE);
+            return Err(TiberiusError::AccessDenied);
+        }
+        let user = match key.user(&mut client).await? {
+            Some(v) => v,
+            None => return Err(TiberiusError::AccessDenied),
+        };
+        let ip = client_ip(state.config(), req).map(IpNetwork::from);
+        key.touch(&mut client, ip).await?;
+        let session = session_from_api_key(Session::<Unauthenticated>::new(true), &key);
+        Ok(Self {
+            key,
+            user,
+            session,
+            _scope: PhantomData,
+        })
+    }
 }
 
 pub trait DbSessionExt {
@@ -396,3 +702,32 @@ impl DbSessionExt for tiberius_dependencies::tower_sessions::Session {
         Ok(self.insert(TIBERIUS_SESSION_KEY, session)?)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use super::{AuthMethod, Session, Unauthenticated, SECOND_FACTOR_MAX_AGE_MINUTES};
+
+    #[test]
+    fn test_second_factor_expiry() {
+        let mut session = Session::<Unauthenticated>::new(true);
+        assert!(!session.second_factor_expired());
+
+        session.set_waiting_auths(AuthMethod::TOTP);
+        session.set_waiting_auths(AuthMethod::WebAuthn);
+        assert!(session.waiting_on(AuthMethod::TOTP));
+        assert!(session.waiting_on(AuthMethod::WebAuthn));
+        assert!(!session.second_factor_expired());
+
+        session.password_passed_at =
+            Some(Utc::now().naive_utc() - Duration::minutes(SECOND_FACTOR_MAX_AGE_MINUTES + 1));
+        assert!(session.second_factor_expired());
+        assert!(!session.waiting_on(AuthMethod::TOTP));
+        assert!(!session.waiting_on(AuthMethod::WebAuthn));
+
+        session.unset_user();
+        assert!(!session.second_factor_expired());
+        assert!(!session.more_auth());
+    }
+}
@@ -27,7 +27,10 @@ use tiberius_dependencies::{
     axum_csrf::CsrfToken,
     axum_flash::{Flash, IncomingFlashes},
 };
-use tiberius_models::{ApiKey, Client, Conversation, Filter, Notification, SiteNotice, User};
+use tiberius_models::{
+    ApiKey, Client, Conversation, Filter, ImageInteraction, Notification, SiteNotice, User,
+    UserSettings,
+};
 use tokio::sync::Mutex;
 
 use crate::acl::{verify_acl, ACLActionSite, ACLObject};
@@ -37,7 +40,7 @@ use crate::{
     config::Configuration,
     error::{TiberiusError, TiberiusResult},
     footer::FooterData,
-    request_helper::DbRef,
+    request_helper::{client_ip, DbRef},
     session::{Authenticated, DbSessionExt, Session, SessionMode, Unauthenticated},
     *,
 };
@@ -60,6 +63,11 @@ pub struct TiberiusState {
     pub page_subtext_cache: moka::future::Cache<PageSubtextCacheTag, PreEscaped<String>>,
     pub csd_cache: moka::future::Cache<u64, Markup>,
     pub comment_cache: moka::future::Cache<u64, Markup>,
+    /// short lived cache for the front page sidebar blocks
+    pub sidebar_cache: moka::future::Cache<PageSubtextCacheTag, PreEscaped<String>>,
+    /// sessions whose user_sessions record was checked recently and the user they belong to,
+    /// see [TiberiusRequestState::verify_session_record]
+    pub session_record_cache: moka::future::Cache<uuid::Uuid, i64>,
     pub csrf: axum_csrf::CsrfConfig,
     pub flash: axum_flash::Config,
     pub csp: CSPHeader,
@@ -69,6 +77,10 @@ pub struct TiberiusState {
 pub enum PageSubtextCacheTag {
     /// Cache Key for Staff Page with User Id
     StaffPageContent { logged_in: bool, user: i32 },
+    /// Cache Key for the Recent Comments block with Filter Id and the User Id whose image hides apply
+    RecentComments { filter: i32, user: i32 },
+    /// Cache Key for the Forum Activity block
+    ForumActivity,
 }
 
 impl PageSubtextCacheTag {
@@ -168,10 +180,14 @@ pub struct TiberiusRequestState<T: SessionMode> {
     session: Session<T>,
     db_session: tower_sessions::Session,
     pub headers: axum::http::HeaderMap,
+    /// Address of the client, see [crate::request_helper::client_ip]
+    pub client_ip: Option<std::net::IpAddr>,
     pub incoming_flashes: IncomingFlashes,
     pub started_at: Instant,
 
     cache_filter: OnceCell<Filter>,
+    /// Images rendered on the page, see [TiberiusRequestState::interactions_for]
+    interaction_images: std::sync::Mutex<Vec<i64>>,
 
     csrf_token: axum_csrf::CsrfToken,
 }
@@ -274,9 +290,11 @@ impl TiberiusRequestState<Unauthenticated> {
             session: self.session.into(),
             db_session: self.db_session,
             headers: self.headers,
+            client_ip: self.client_ip,
             incoming_flashes: self.incoming_flashes,
             started_at: self.started_at,
             cache_filter: self.cache_filter,
+            interaction_images: self.interaction_images,
             csrf_token: self.csrf_token,
         }
     }
@@ -291,9 +309,11 @@ impl TiberiusRequestState<Authenticated> {
             session: self.session.into(),
             db_session: self.db_session,
             headers: self.headers,
+            client_ip: self.client_ip,
             incoming_flashes: self.incoming_flashes,
             started_at: self.started_at,
             cache_filter: self.cache_filter,
+            interaction_images: self.interaction_images,
             csrf_token: self.csrf_token,
         }
     }
@@ -317,7 +337,8 @@ impl FromRequestParts<TiberiusState> for TiberiusRequestState<Authenticated> {
             .map_err(|e| todo!())?
             .unwrap_or_else(|| todo!());
         let headers = req.headers.clone();
-        let rstate = Self {
+        let client_ip = client_ip(state.config(), req);
+        let mut rstate = Self {
             cookie_jar: req
                 .extract()
                 .await
@@ -336,15 +357,21 @@ impl FromRequestParts<TiberiusState> for TiberiusRequestState<Authenticated> {
             session,
             db_session,
             headers,
+            client_ip,
             started_at: Instant::now(),
             incoming_flashes: IncomingFlashes::from_request_parts(req, &state)
                 .await
                 .map_err(|e: (StatusCode, &'static str)| e.into_response())?,
             cache_filter: OnceCell::new(),
+            interaction_images: std::sync::Mutex::new(Vec::new()),
             csrf_token: CsrfToken::from_request_parts(req, &state)
                 .await
                 .map_err(|e: (StatusCode, &'static str)| e.into_response())?,
         };
+        rstate
+            .verify_session_record(state)
+            .await
+            .map_err(|e| e.into_response())?;
         if state.config().enable_lock_down
             && !verify_acl(state, &rstate, ACLObject::Site, ACLActionSite::Use)
                 .await
@@ -403,7 +430,8 @@ impl FromRequestParts<TiberiusState> for TiberiusRequestState<Unauthenticated> {
             }
         };
         let headers = req.headers.clone();
-        let rstate = Self {
+        let client_ip = client_ip(state.config(), req);
+        let mut rstate = Self {
             cookie_jar: req.extract().await.map_err(|e| {
                 (
                     flash.clone(),
@@ -423,15 +451,21 @@ impl FromRequestParts<TiberiusState> for TiberiusRequestState<Unauthenticated> {
             session,
             db_session,
             headers,
+            client_ip,
             started_at: Instant::now(),
             incoming_flashes: IncomingFlashes::from_request_parts(req, &state)
                 .await
                 .map_err(|e: (StatusCode, &'static str)| (flash.clone(), e.into_response()))?,
             cache_filter: OnceCell::new(),
+            interaction_images: std::sync::Mutex::new(Vec::new()),
             csrf_token: CsrfToken::from_request_parts(req, &state)
                 .await
                 .map_err(|e: (StatusCode, &'static str)| (flash.clone(), e.into_response()))?,
         };
+        rstate
+            .verify_session_record(state)
+            .await
+            .map_err(|e| (flash.clone(), e.into_response()))?;
         if state.config().enable_lock_down {
             let uri = state.url_directions.login_page.clone();
             if req.uri != uri {
@@ -486,6 +520,15 @@ impl TiberiusState {
                 .time_to_live(COMMENT_CACHE_TTL)
                 .time_to_idle(COMMENT_CACHE_TTI)
                 .build(),
+            sidebar_cache: moka::future::Cache::builder()
+                .max_capacity(SIDEBAR_CACHE_SIZE)
+                .initial_capacity(SIDEBAR_CACHE_START_SIZE)
+                .time_to_live(SIDEBAR_CACHE_TTL)
+                .build(),
+            session_record_cache: moka::future::Cache::builder()
+                .max_capacity(SESSION_RECORD_CACHE_SIZE)
+                .time_to_live(SESSION_RECORD_CACHE_TTL)
+                .build(),
             csrf,
             flash,
             csp,
@@ -540,6 +583,35 @@ impl<T: SessionMode> TiberiusRequestState<T> {
         Ok(self.db_session.insert(TIBERIUS_SESSION_KEY, self.session.clone())?)
     }
 
+    /// Logs the session out if it has been revoked, see [Session::verify_record]
+    ///
+    /// Verified sessions are remembered for [SESSION_RECORD_CACHE_TTL] so the record isn't
+    /// looked up on every request.
+    pub async fn verify_session_record(&mut self, state: &TiberiusState) -> TiberiusResult<()> {
+        let session_id = self.session.id();
+        let user_id = self.session.raw_user();
+        if self.session.tracked()
+            && user_id.is_some()
+            && state.session_record_cache.get(&session_id).await == user_id
+        {
+            return Ok(());
+        }
+        if self
+            .session
+            .verify_record(&mut state.get_db_client(), &self.headers, self.client_ip)
+            .await?
+        {
+            self.push_session_update()?;
+        }
+        match self.session.raw_user() {
+            Some(user_id) if self.session.tracked() => {
+                state.session_record_cache.insert(session_id, user_id).await
+            }
+            _ => state.session_record_cache.invalidate(&session_id).await,
+        }
+        Ok(())
+    }
+
     pub fn db_session_mut(
         &mut self,
     ) -> &mut tower_sessions::Session {
@@ -548,11 +620,14 @@ impl<T: SessionMode> TiberiusRequestState<T> {
 
     #[instrument(skip(self, state))]
     pub async fn theme_name(&self, state: &TiberiusState) -> TiberiusResult<String> {
-        let user = self.user(state).await?;
-        Ok(if let Some(user) = user {
-            user.user_settings.theme
-        } else {
-            "default".to_string()
+        Ok(self.user_settings(state).await?.theme().to_string())
+    }
+    /// Returns the settings of the user, anonymous visitors get the default settings
+    #[instrument(skip(self, state))]
+    pub async fn user_settings(&self, state: &TiberiusState) -> TiberiusResult<UserSettings> {
+        Ok(match self.user(state).await? {
+            Some(user) => user.user_settings,
+            None => UserSettings::default(),
         })
     }
     #[instrument(skip(self, state))]
@@ -587,25 +662,52 @@ impl<T: SessionMode> TiberiusRequestState<T> {
     pub async fn conversations(&self) -> TiberiusResult<Vec<Conversation>> {
         Ok(Vec::new()) //TODO: grab user notifications
     }
-    pub async fn notifications(&self) -> TiberiusResult<Vec<Notification>> {
-        Ok(Vec::new()) //TODO: grab user notifications
+    pub async fn notifications(&self, state: &TiberiusState) -> TiberiusResult<Vec<Notification>> {
+        match self.user(state).await? {
+            Some(user) => Ok(Notification::unread_for_user(&mut state.get_db_client(), user.id).await?),
+            None => Ok(Vec::new()),
+        }
     }
+    /// Width of the main content of the page
+    ///
+    /// This is not a user setting, the centered layout chosen in the user settings is applied
+    /// to the surrounding container instead, so it works with every content width.
     pub async fn layout_class(&self) -> LayoutClass {
-        // TODO: let user set LayoutClass
         LayoutClass::Wide
     }
     pub async fn csd_extra(&self) -> TiberiusResult<ClientSideExtra> {
         // TODO: set Extra Client Side Data here
         Ok(ClientSideExtra::new())
     }
-    pub async fn interactions(&self) -> TiberiusResult<Interactions> {
-        // TODO: load user interactions
-        Ok(Vec::new())
+    /// Records images shown on the page, the faves, hides and votes of the user on them are
+    /// sent to the frontend with the client side data
+    pub fn interactions_for(&self, image_ids: &[i64]) {
+        let mut images = self
+            .interaction_images
+            .lock()
+            .expect("interaction images lock poisoned");
+        images.extend_from_slice(image_ids);
+    }
+    pub async fn interactions(&self, state: &TiberiusState) -> TiberiusResult<Interactions> {
+        let image_ids = self
+            .interaction_images
+            .lock()
+            .expect("interaction images lock poisoned")
+            .clone();
+        if image_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let user = match self.user(state).await? {
+            Some(user) => user,
+            None => return Ok(Vec::new()),
+        };
+        let mut client = state.get_db_client();
+        Ok(ImageInteraction::for_images(&mut client, user.id as i64, &image_ids).await?)
     }
 }
 
 pub type ClientSideExtra = std::collections::BTreeMap<String, serde_json::Value>;
-pub type Interactions = Vec<()>;
+pub type Interactions = Vec<ImageInteraction>;
 
 #[derive(Default)]
 pub struct SiteNotices(pub Vec<SiteNotice>);
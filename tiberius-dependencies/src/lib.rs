@@ -27,6 +27,7 @@ pub use lazy_static;
 pub use mime;
 pub use moka;
 pub use once_cell;
+pub use qrcode;
 pub use regex;
 pub use reqwest;
 pub use rust_embed;
@@ -49,6 +50,7 @@ pub use tower;
 pub use tower_sessions;
 pub use tracing_futures;
 pub use uuid;
+pub use webauthn_rs;
 
 pub mod prelude {
     pub use tracing;
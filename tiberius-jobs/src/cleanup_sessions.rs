@@ -1,8 +1,10 @@
-use tiberius_core::{config::Configuration, error::TiberiusResult, session::PostgresSessionStore};
+use tiberius_core::{config::Configuration, error::TiberiusResult};
 use tiberius_dependencies::prelude::*;
 use tiberius_dependencies::sentry;
 use tiberius_dependencies::serde;
 use tiberius_dependencies::serde_json;
+use tiberius_dependencies::tower_sessions::PostgresStore;
+use tiberius_models::UserSession;
 
 use crate::SharedCtx;
 use crate::scheduler::CurrentJob;
@@ -33,9 +35,16 @@ pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult
 
 #[instrument(skip(current_job, sctx))]
 async fn tx_run_job(mut current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
-    let pool = sctx.client();
-    //let store = todo!();
-    //store.cleanup().await?;
-    debug!("Job {}: Completed session pruning", current_job.id());
+    let mut client = sctx.client();
+    // expired session data, the server also does this periodically while it runs
+    PostgresStore::new(client.pool().clone())
+        .delete_expired()
+        .await?;
+    let pruned = UserSession::cleanup(&mut client).await?;
+    debug!(
+        "Job {}: Completed session pruning, removed {} expired sessions",
+        current_job.id(),
+        pruned
+    );
     Ok(())
 }
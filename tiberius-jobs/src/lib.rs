@@ -7,13 +7,18 @@
 
 pub mod cleanup_sessions;
 pub mod generate_thumbnails;
+pub mod process_avatars;
 #[cfg(feature = "job_process_image")]
 pub mod process_image;
 pub mod refresh_cachelines;
 pub mod refresh_channels;
 pub mod reindex_images;
 pub mod reindex_tags;
+pub mod reset_channel_stats;
+pub mod rotate_featured_image;
 pub mod scheduler;
+pub mod send_mail;
+pub mod verify_user_links;
 
 use std::error::Error;
 use std::str::FromStr;
@@ -68,8 +73,8 @@ pub async fn scheduler(db: DBPool, config: Configuration) -> ! {
             max_delay: Duration::seconds(10),
             last: Utc::now(),
             fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
-                info!("Starting picarto_tv job on scheduler instant {:?}", i);
-                let config = refresh_channels::PicartoConfig::default();
+                info!("Starting refresh_channels job on scheduler instant {:?}", i);
+                let config = refresh_channels::RefreshChannelsConfig::default();
                 tokio::spawn(async move {
                     refresh_channels::run_job(current_job, sctx).await?;
                     TiberiusResult::<()>::Ok(())
@@ -78,6 +83,70 @@ pub async fn scheduler(db: DBPool, config: Configuration) -> ! {
             }),
         });
     }
+    {
+        info!("Setting up Channel Statistics Reset Job");
+        sched.add(Job {
+            interval: Some(Schedule::from_str("0 0 0 * * * *").unwrap()),
+            max_delay: Duration::seconds(10),
+            last: Utc::now(),
+            fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
+                info!("Starting reset_channel_stats job");
+                tokio::spawn(async move {
+                    reset_channel_stats::run_job(current_job, sctx).await?;
+                    TiberiusResult::<()>::Ok(())
+                });
+                Ok(())
+            }),
+        });
+    }
+    {
+        info!("Setting up Featured Image Rotation Job");
+        sched.add(Job {
+            interval: Some(Schedule::from_str("0 0 * * * * *").unwrap()),
+            max_delay: Duration::seconds(10),
+            last: Utc::now(),
+            fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
+                info!("Starting rotate_featured_image job");
+                tokio::spawn(async move {
+                    rotate_featured_image::run_job(current_job, sctx).await?;
+                    TiberiusResult::<()>::Ok(())
+                });
+                Ok(())
+            }),
+        });
+    }
+    {
+        info!("Setting up Avatar Processing Job");
+        sched.add(Job {
+            interval: Some(Schedule::from_str("30 * * * * * *").unwrap()),
+            max_delay: Duration::seconds(10),
+            last: Utc::now(),
+            fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
+                info!("Starting process_avatars job");
+                tokio::spawn(async move {
+                    process_avatars::run_job(current_job, sctx).await?;
+                    TiberiusResult::<()>::Ok(())
+                });
+                Ok(())
+            }),
+        });
+    }
+    {
+        info!("Setting up User Link Verification Job");
+        sched.add(Job {
+            interval: Some(Schedule::from_str("0 */5 * * * * *").unwrap()),
+            max_delay: Duration::seconds(10),
+            last: Utc::now(),
+            fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
+                info!("Starting verify_user_links job");
+                tokio::spawn(async move {
+                    verify_user_links::run_job(current_job, sctx).await?;
+                    TiberiusResult::<()>::Ok(())
+                });
+                Ok(())
+            }),
+        });
+    }
     {
         info!("Setting up Image Reindex Job");
         sched.add(Job {
@@ -115,6 +184,22 @@ pub async fn scheduler(db: DBPool, config: Configuration) -> ! {
             }),
         });
     }
+    {
+        info!("Setting up Mail Delivery Job");
+        sched.add(Job {
+            interval: Some(Schedule::from_str("0/10 * * * * * *").unwrap()),
+            max_delay: Duration::seconds(10),
+            last: Utc::now(),
+            fun: Box::new(move |i: Instant, current_job: CurrentJob, sctx: SharedCtx| -> TiberiusResult<()> {
+                debug!("Starting send_mail job");
+                tokio::spawn(async move {
+                    send_mail::run_job(current_job, sctx).await?;
+                    TiberiusResult::<()>::Ok(())
+                });
+                Ok(())
+            }),
+        });
+    }
     {
         info!("Setting up Session Cleanup Job");
         sched.add(Job {
@@ -0,0 +1,216 @@
+use std::path::{Component, Path, PathBuf};
+
+use tiberius_core::config::Configuration;
+use tiberius_core::error::TiberiusResult;
+use tiberius_dependencies::image;
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::sentry;
+use tiberius_dependencies::serde_json;
+use tiberius_dependencies::tokio;
+use tiberius_dependencies::uuid::Uuid;
+use tiberius_models::{Client, User};
+
+use crate::SharedCtx;
+use crate::scheduler::CurrentJob;
+
+/// Width and height of processed avatars, twice the largest size they are displayed at
+pub const AVATAR_SIZE: u32 = 250;
+
+/// Directory uploads wait in until this job processes them
+///
+/// Each upload is named after the ID of the user, so a new upload replaces a waiting one.
+pub fn pending_avatar_dir(config: &Configuration) -> PathBuf {
+    config.avatar_base().join("pending")
+}
+
+/// Returns the location of a stored avatar, if the avatar is a plain relative path
+///
+/// Avatars are taken from the database and the URL, so they must not escape the avatar directory.
+pub fn avatar_file_path(config: &Configuration, avatar: &str) -> Option<PathBuf> {
+    relative_avatar_path(avatar).map(|path| config.avatar_base().join(path))
+}
+
+fn relative_avatar_path(avatar: &str) -> Option<&Path> {
+    let path = Path::new(avatar);
+    let mut components = path.components().peekable();
+    components.peek()?;
+    if components.all(|x| matches!(x, Component::Normal(_))) && !avatar.starts_with("pending/") {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Suffix of uploads a run of the job has taken from the pending directory
+const PROCESSING_SUFFIX: &str = ".processing";
+
+/// Returns the user an upload in the pending directory belongs to
+fn pending_avatar_user_id(path: &Path) -> Option<i64> {
+    path.file_name()?.to_str()?.split('.').next()?.parse().ok()
+}
+
+/// Deletes the file of an avatar that is no longer used
+pub async fn remove_avatar_file(config: &Configuration, avatar: &str) -> TiberiusResult<()> {
+    if let Some(path) = avatar_file_path(config, avatar) {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[instrument(skip(current_job, sctx))]
+pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    sentry::configure_scope(|scope| {
+        scope.clear();
+    });
+    let tx = sentry::start_transaction(sentry::TransactionContext::new(
+        "process_avatars",
+        "queue.task",
+    ));
+    match tx_run_job(current_job, sctx).await {
+        Ok(()) => {
+            tx.set_status(sentry::protocol::SpanStatus::Ok);
+            tx.finish();
+            Ok(())
+        }
+        Err(e) => {
+            tx.set_status(sentry::protocol::SpanStatus::InternalError);
+            tx.set_data("error_msg", serde_json::Value::String(e.to_string()));
+            tx.finish();
+            Err(e)
+        }
+    }
+}
+
+#[instrument(skip(current_job, sctx))]
+async fn tx_run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    let config = sctx.config();
+    let pending = pending_avatar_dir(&config);
+    if !pending.exists() {
+        return Ok(());
+    }
+    let mut client = sctx.client();
+    let mut entries = tokio::fs::read_dir(&pending).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(PROCESSING_SUFFIX) {
+            // taken by another run of the job
+            continue;
+        }
+        // move the upload out of the way first, so a new upload of the user isn't deleted
+        // with this one and concurrent runs don't process it twice
+        let processing = pending.join(format!(
+            "{}.{}{}",
+            name,
+            current_job.id(),
+            PROCESSING_SUFFIX
+        ));
+        match tokio::fs::rename(&path, &processing).await {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+        if let Err(e) = process_avatar(&mut client, &config, &processing).await {
+            warn!(
+                "Job {}: Could not process avatar {}: {}",
+                current_job.id(),
+                path.display(),
+                e
+            );
+        }
+        // failed uploads are dropped, the user can simply upload again
+        tokio::fs::remove_file(&processing).await?;
+    }
+    Ok(())
+}
+
+/// Crops the pending upload to a square, scales it down and makes it the avatar of the user
+#[instrument(skip(client, config))]
+pub async fn process_avatar(
+    client: &mut Client,
+    config: &Configuration,
+    upload: &Path,
+) -> TiberiusResult<()> {
+    let user_id = match pending_avatar_user_id(upload) {
+        Some(v) => v,
+        None => {
+            warn!("Unexpected file {} in avatar uploads", upload.display());
+            return Ok(());
+        }
+    };
+    let mut user = match User::get_id(client, user_id).await? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let avatar = format!("{}/{}.png", user.id, Uuid::new_v4());
+    let target = config.avatar_base().join(&avatar);
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let upload = upload.to_path_buf();
+    tokio::task::spawn_blocking(move || -> TiberiusResult<()> {
+        let img = image::io::Reader::open(upload)?
+            .with_guessed_format()?
+            .decode()?;
+        // animations are not kept, the first frame is used
+        img.resize_to_fill(
+            AVATAR_SIZE,
+            AVATAR_SIZE,
+            image::imageops::FilterType::Lanczos3,
+        )
+        .save_with_format(target, image::ImageFormat::Png)?;
+        Ok(())
+    })
+    .await??;
+    let previous = user.avatar.clone();
+    user.set_avatar(client, Some(avatar)).await?;
+    if let Some(previous) = previous {
+        remove_avatar_file(config, &previous).await?;
+    }
+    debug!("Updated avatar of user {}", user.id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{pending_avatar_user_id, relative_avatar_path};
+
+    #[test]
+    fn test_relative_avatar_path() {
+        assert!(relative_avatar_path("12/0a1b.png").is_some());
+        assert!(relative_avatar_path("2021/1/1/avatar.png").is_some());
+        assert!(relative_avatar_path("").is_none());
+        assert!(relative_avatar_path("../config.toml").is_none());
+        assert!(relative_avatar_path("12/../../config.toml").is_none());
+        assert!(relative_avatar_path("/etc/passwd").is_none());
+        assert!(relative_avatar_path("pending/12.png").is_none());
+    }
+
+    #[test]
+    fn test_pending_avatar_user_id() {
+        assert_eq!(
+            Some(12),
+            pending_avatar_user_id(Path::new("/data/avatars/pending/12.png"))
+        );
+        assert_eq!(
+            Some(12),
+            pending_avatar_user_id(Path::new("/data/avatars/pending/12"))
+        );
+        assert_eq!(
+            Some(12),
+            pending_avatar_user_id(Path::new(
+                "/data/avatars/pending/12.1f2e3d4c-0000-0000-0000-000000000000.processing"
+            ))
+        );
+        assert_eq!(
+            None,
+            pending_avatar_user_id(Path::new("/data/avatars/pending/x.png"))
+        );
+    }
+}
@@ -1,19 +1,22 @@
 pub mod picarto;
+pub mod piczel;
+pub mod twitch;
 
 use tiberius_core::{
     config::Configuration, error::TiberiusResult, http_client, state::TiberiusState,
 };
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
 use tiberius_dependencies::prelude::*;
 use tiberius_dependencies::sentry;
 use tiberius_dependencies::serde;
 use tiberius_dependencies::serde_json;
-use tiberius_models::{Channel, Client};
+use tiberius_models::{Channel, ChannelLiveSession, Client, Notification};
 
 use crate::SharedCtx;
 use crate::scheduler::CurrentJob;
 
 #[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
-pub struct PicartoConfig {
+pub struct RefreshChannelsConfig {
     pub all_channels: Vec<Channel>,
     pub done_channels: Vec<i32>,
     pub started: bool,
@@ -45,18 +48,15 @@ pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult
 
 #[instrument(skip(current_job, sctx))]
 async fn tx_run_job(mut current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
-    let progress: PicartoConfig = current_job
-        .data()?
-        .expect("job requires configuration copy");
-    debug!("Job {}: Refreshing picarto channels", current_job.id());
+    let progress: RefreshChannelsConfig = current_job.data()?.unwrap_or_default();
+    debug!("Job {}: Refreshing channels", current_job.id());
     let mut client = sctx.client();
     let mut progress = {
         if progress.started {
             progress
         } else {
-            let all_channels =
-                Channel::get_all_channels(&mut client, Some("PicartoChannel")).await?;
-            PicartoConfig {
+            let all_channels = Channel::get_all_channels(&mut client, None::<String>).await?;
+            RefreshChannelsConfig {
                 all_channels,
                 done_channels: Vec::new(),
                 started: true,
@@ -72,15 +72,31 @@ async fn tx_run_job(mut current_job: CurrentJob, sctx: SharedCtx) -> TiberiusRes
         if progress.done_channels.contains(&channel.id) {
             continue;
         }
+        let was_live = channel.is_live;
+        let previous_fetch = channel.last_fetched_at;
         match refresh_channel(&sctx.config, &mut client, &mut channel).await {
             Ok(_) => {
                 progress.done_channels.push(channel.id);
                 debug!("Completed refresh for channel {}", channel.id);
+                if let Err(e) = record_stats(&mut client, &mut channel, previous_fetch).await {
+                    warn!(
+                        "Could not record viewer statistics of channel {}: {}",
+                        channel.id, e
+                    );
+                }
+                if !was_live && channel.is_live {
+                    if let Err(e) = notify_subscribers(&mut client, &channel).await {
+                        warn!(
+                            "Could not notify subscribers of channel {}: {}",
+                            channel.id, e
+                        );
+                    }
+                }
             }
             Err(e) => {
-                debug!(
-                    "Failed refresh on channel {} ({:?})",
-                    channel.id, channel.short_name
+                warn!(
+                    "Failed refresh on channel {} ({:?}): {}",
+                    channel.id, channel.short_name, e
                 );
             }
         };
@@ -89,6 +105,58 @@ async fn tx_run_job(mut current_job: CurrentJob, sctx: SharedCtx) -> TiberiusRes
     Ok(())
 }
 
+/// Longest time between two refreshes that is counted as watched
+///
+/// Refreshes happen every 30 minutes, a longer gap means the job was not running.
+const MAX_REFRESH_GAP_MINUTES: i64 = 30;
+
+/// Viewer minutes watched since the previous refresh, assuming the current viewer count
+/// for the whole time
+fn viewer_minutes(
+    viewers: i32,
+    previous_fetch: Option<NaiveDateTime>,
+    fetched_at: NaiveDateTime,
+) -> i32 {
+    let minutes = match previous_fetch {
+        Some(previous_fetch) => (fetched_at - previous_fetch)
+            .num_minutes()
+            .clamp(0, MAX_REFRESH_GAP_MINUTES),
+        None => 0,
+    };
+    minutes as i32 * viewers.max(0)
+}
+
+/// Adds the viewer minutes since the previous refresh and tracks the live session of the channel
+async fn record_stats(
+    client: &mut Client,
+    channel: &mut Channel,
+    previous_fetch: Option<NaiveDateTime>,
+) -> TiberiusResult<()> {
+    if channel.is_live {
+        let fetched_at = channel
+            .last_fetched_at
+            .unwrap_or_else(|| Utc::now().naive_utc());
+        let minutes = viewer_minutes(channel.viewers, previous_fetch, fetched_at);
+        channel.add_viewer_minutes(client, minutes).await?;
+        ChannelLiveSession::record_live(client, channel, minutes).await?;
+    } else {
+        ChannelLiveSession::end(client, channel.id).await?;
+    }
+    Ok(())
+}
+
+/// Notifies the subscribers of a channel that it went live
+async fn notify_subscribers(client: &mut Client, channel: &Channel) -> TiberiusResult<()> {
+    let subscribers = channel.subscriber_ids(client).await?;
+    debug!(
+        "Channel {} went live, notifying {} subscribers",
+        channel.id,
+        subscribers.len()
+    );
+    Notification::notify(client, "Channel", channel.id, "went live", &subscribers).await?;
+    Ok(())
+}
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Progress {
     all_channels: Vec<Channel>,
@@ -105,7 +173,68 @@ async fn refresh_channel(
         tiberius_models::ChannelType::PicartoChannel => {
             picarto::refresh_picarto_channel(config, client, chan).await
         }
-        tiberius_models::ChannelType::PiczelChannel => todo!(),
-        tiberius_models::ChannelType::TwitchChannel => todo!(),
+        tiberius_models::ChannelType::PiczelChannel => {
+            piczel::refresh_piczel_channel(config, client, chan).await
+        }
+        tiberius_models::ChannelType::TwitchChannel => {
+            twitch::refresh_twitch_channel(config, client, chan).await
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use tiberius_dependencies::chrono::{Duration, Utc};
+    use tiberius_models::{Channel, ChannelType};
+
+    use super::viewer_minutes;
+
+    pub(crate) fn test_channel(short_name: &str, r#type: ChannelType) -> Channel {
+        let now = Utc::now().naive_utc();
+        Channel {
+            id: 1,
+            short_name: short_name.to_string(),
+            title: String::new(),
+            description: None,
+            channel_image: None,
+            tags: None,
+            viewers: 0,
+            nsfw: false,
+            is_live: false,
+            last_fetched_at: None,
+            next_check_at: None,
+            last_live_at: None,
+            watcher_ids: Vec::new(),
+            watcher_count: 0,
+            r#type,
+            created_at: now,
+            updated_at: now,
+            associated_artist_tag_id: None,
+            viewer_minutes_today: 0,
+            viewer_minutes_thisweek: 0,
+            viewer_minutes_thismonth: 0,
+            total_viewer_minutes: 0,
+            banner_image: None,
+            remote_stream_id: None,
+            thumbnail_url: None,
+            user_id: None,
+        }
+    }
+
+    #[test]
+    fn test_viewer_minutes() {
+        let now = Utc::now().naive_utc();
+        assert_eq!(0, viewer_minutes(10, None, now));
+        assert_eq!(
+            300,
+            viewer_minutes(10, Some(now - Duration::minutes(30)), now)
+        );
+        assert_eq!(
+            50,
+            viewer_minutes(10, Some(now - Duration::minutes(5)), now)
+        );
+        // downtime of the job is not counted
+        assert_eq!(300, viewer_minutes(10, Some(now - Duration::hours(6)), now));
+        assert_eq!(0, viewer_minutes(10, Some(now + Duration::minutes(5)), now));
     }
 }
@@ -18,7 +18,7 @@ pub async fn refresh_picarto_channel(
     );
     trace!("requesting picarto channel via {}", url);
     let pic_chan: PicartoChannel = http_client.get(url).send().await?.json().await?;
-    chan.remote_stream_id = Some(pic_chan.user_id as i32);
+    chan.remote_stream_id = Some(pic_chan.user_id.to_string());
     chan.thumbnail_url = Some(pic_chan.thumbnails.web);
     chan.channel_image = Some(pic_chan.avatar);
     chan.is_live = pic_chan.online;
@@ -0,0 +1,169 @@
+use serde;
+use tiberius_core::config::Configuration;
+use tiberius_core::error::{TiberiusError, TiberiusResult};
+use tiberius_core::http_client;
+use tiberius_dependencies::chrono::Utc;
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::reqwest;
+use tiberius_models::{Channel, Client};
+
+const PICZEL_API: &str = "https://piczel.tv/api";
+
+#[instrument]
+pub async fn refresh_piczel_channel(
+    config: &Configuration,
+    client: &mut Client,
+    chan: &mut Channel,
+) -> TiberiusResult<()> {
+    let http_client = http_client(config)?;
+    fetch_piczel_channel(&http_client, PICZEL_API, chan).await?;
+    chan.update(client).await?;
+    Ok(())
+}
+
+/// Updates the live status, title, viewers and images of the channel from the Piczel API
+///
+/// The channel is not saved.
+#[instrument(skip(http_client))]
+pub async fn fetch_piczel_channel(
+    http_client: &reqwest::Client,
+    api_url: &str,
+    chan: &mut Channel,
+) -> TiberiusResult<()> {
+    let url = format!("{}/streams/{}", api_url, chan.short_name);
+    trace!("requesting piczel channel via {}", url);
+    let streams: PiczelResponse = http_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let streams = match streams {
+        PiczelResponse::Wrapped { data } => data,
+        PiczelResponse::List(data) => data,
+    };
+    let stream = streams
+        .into_iter()
+        .find(|s| s.slug.eq_ignore_ascii_case(&chan.short_name))
+        .ok_or_else(|| {
+            TiberiusError::Other(format!("piczel channel {} not found", chan.short_name))
+        })?;
+    chan.remote_stream_id = Some(stream.id.to_string());
+    if let Some(avatar) = stream.user.avatar.and_then(|x| x.avatar.url) {
+        chan.channel_image = Some(avatar.clone());
+        chan.thumbnail_url = Some(avatar);
+    }
+    chan.is_live = stream.live;
+    chan.last_fetched_at = Some(Utc::now().naive_utc());
+    if chan.is_live {
+        debug!("Channel {} is online: {}", chan.short_name, stream.title);
+        chan.last_live_at = chan.last_fetched_at;
+        chan.viewers = stream.viewers as i32;
+    } else {
+        debug!("Channel {} is offline: {}", chan.short_name, stream.title);
+        chan.viewers = 0;
+    }
+    chan.title = stream.title;
+    Ok(())
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum PiczelResponse {
+    Wrapped { data: Vec<PiczelStream> },
+    List(Vec<PiczelStream>),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde::Deserialize)]
+struct PiczelStream {
+    id: i64,
+    slug: String,
+    title: String,
+    live: bool,
+    #[serde(default)]
+    viewers: i64,
+    #[serde(default)]
+    user: PiczelUser,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde::Deserialize)]
+struct PiczelUser {
+    avatar: Option<PiczelAvatar>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde::Deserialize)]
+struct PiczelAvatar {
+    avatar: PiczelImage,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde::Deserialize)]
+struct PiczelImage {
+    url: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_core::error::TiberiusResult;
+    use tiberius_dependencies::reqwest;
+    use tiberius_dependencies::serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::fetch_piczel_channel;
+    use crate::refresh_channels::test::test_channel;
+
+    #[tokio::test]
+    async fn test_piczel_live_channel() -> TiberiusResult<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/streams/Pinkie"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{
+                    "id": 4242,
+                    "slug": "pinkie",
+                    "title": "Party planning",
+                    "live": true,
+                    "adult": false,
+                    "viewers": 17,
+                    "user": { "avatar": { "avatar": { "url": "https://piczel.tv/avatar.png" } } }
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut chan = test_channel("Pinkie", tiberius_models::ChannelType::PiczelChannel);
+        let api = format!("{}/api", server.uri());
+        fetch_piczel_channel(&reqwest::Client::new(), &api, &mut chan).await?;
+        assert!(chan.is_live);
+        assert_eq!("Party planning", chan.title);
+        assert_eq!(17, chan.viewers);
+        assert_eq!(Some("4242"), chan.remote_stream_id.as_deref());
+        assert_eq!(
+            Some("https://piczel.tv/avatar.png"),
+            chan.thumbnail_url.as_deref()
+        );
+        assert!(chan.last_live_at.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_piczel_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let mut chan = test_channel("Pinkie", tiberius_models::ChannelType::PiczelChannel);
+        let api = format!("{}/api", server.uri());
+        assert!(
+            fetch_piczel_channel(&reqwest::Client::new(), &api, &mut chan)
+                .await
+                .is_err()
+        );
+        assert!(!chan.is_live);
+        assert!(chan.last_fetched_at.is_none());
+    }
+}
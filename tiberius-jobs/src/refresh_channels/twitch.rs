@@ -0,0 +1,313 @@
+use serde;
+use tiberius_core::config::Configuration;
+use tiberius_core::error::{TiberiusError, TiberiusResult};
+use tiberius_core::http_client;
+use tiberius_dependencies::chrono::{DateTime, Duration, Utc};
+use tiberius_dependencies::once_cell::sync::OnceCell;
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::reqwest;
+use tiberius_dependencies::tokio::sync::Mutex;
+use tiberius_models::{Channel, Client};
+
+const TWITCH_API: &str = "https://api.twitch.tv/helix";
+const TWITCH_AUTH: &str = "https://id.twitch.tv/oauth2/token";
+
+/// Size of the stream thumbnail requested from Twitch
+const THUMBNAIL_SIZE: (u32, u32) = (320, 180);
+
+static TWITCH: OnceCell<TwitchApi> = OnceCell::new();
+
+#[instrument]
+pub async fn refresh_twitch_channel(
+    config: &Configuration,
+    client: &mut Client,
+    chan: &mut Channel,
+) -> TiberiusResult<()> {
+    let api = TWITCH.get_or_try_init(|| TwitchApi::from_config(config))?;
+    api.fetch_channel(chan).await?;
+    chan.update(client).await?;
+    Ok(())
+}
+
+/// Client for the Twitch Helix API using an app access token
+///
+/// The token is requested with the client credentials of the configured Twitch application
+/// and kept until shortly before it expires.
+pub struct TwitchApi {
+    http_client: reqwest::Client,
+    api_url: String,
+    auth_url: String,
+    client_id: String,
+    client_secret: String,
+    token: Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+impl TwitchApi {
+    pub fn new(
+        http_client: reqwest::Client,
+        api_url: &str,
+        auth_url: &str,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Self {
+        Self {
+            http_client,
+            api_url: api_url.to_string(),
+            auth_url: auth_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            token: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &Configuration) -> TiberiusResult<Self> {
+        let client_id = config
+            .twitch_client_id
+            .as_ref()
+            .ok_or_else(|| TiberiusError::ConfigurationUnset("TWITCH_CLIENT_ID".to_string()))?;
+        let client_secret = config
+            .twitch_client_secret
+            .as_ref()
+            .ok_or_else(|| TiberiusError::ConfigurationUnset("TWITCH_CLIENT_SECRET".to_string()))?;
+        Ok(Self::new(
+            http_client(config)?,
+            TWITCH_API,
+            TWITCH_AUTH,
+            client_id,
+            client_secret,
+        ))
+    }
+
+    async fn access_token(&self) -> TiberiusResult<String> {
+        let mut token = self.token.lock().await;
+        if let Some((token, expires_at)) = token.as_ref() {
+            if *expires_at > Utc::now() {
+                return Ok(token.clone());
+            }
+        }
+        trace!("requesting twitch app access token");
+        let resp: TwitchToken = self
+            .http_client
+            .post(&self.auth_url)
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("grant_type", "client_credentials"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // renew the token a minute early so it does not expire during a refresh
+        let expires_at = Utc::now() + Duration::seconds(resp.expires_in - 60);
+        *token = Some((resp.access_token.clone(), expires_at));
+        Ok(resp.access_token)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> TiberiusResult<Vec<T>> {
+        let token = self.access_token().await?;
+        let resp: TwitchData<T> = self
+            .http_client
+            .get(format!("{}/{}", self.api_url, endpoint))
+            .query(query)
+            .header("Client-Id", &self.client_id)
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(resp.data)
+    }
+
+    /// Updates the live status, title, viewers and images of the channel from the Twitch API
+    ///
+    /// The channel is not saved.
+    #[instrument(skip(self))]
+    pub async fn fetch_channel(&self, chan: &mut Channel) -> TiberiusResult<()> {
+        let login = chan.short_name.to_lowercase();
+        trace!("requesting twitch channel {}", login);
+        let user: TwitchUser = self
+            .get("users", &[("login", &login)])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| TiberiusError::Other(format!("twitch user {} not found", login)))?;
+        let stream: Option<TwitchStream> = self
+            .get("streams", &[("user_login", &login)])
+            .await?
+            .into_iter()
+            .find(|s: &TwitchStream| s.r#type == "live");
+        chan.remote_stream_id = Some(user.id);
+        chan.channel_image = Some(user.profile_image_url);
+        chan.last_fetched_at = Some(Utc::now().naive_utc());
+        match stream {
+            Some(stream) => {
+                debug!("Channel {} is online: {}", chan.short_name, stream.title);
+                chan.is_live = true;
+                chan.last_live_at = chan.last_fetched_at;
+                chan.viewers = stream.viewer_count as i32;
+                chan.title = stream.title;
+                chan.thumbnail_url = Some(
+                    stream
+                        .thumbnail_url
+                        .replace("{width}", &THUMBNAIL_SIZE.0.to_string())
+                        .replace("{height}", &THUMBNAIL_SIZE.1.to_string()),
+                );
+            }
+            None => {
+                debug!("Channel {} is offline", chan.short_name);
+                chan.is_live = false;
+                chan.viewers = 0;
+                if !user.offline_image_url.is_empty() {
+                    chan.thumbnail_url = Some(user.offline_image_url);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct TwitchToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct TwitchData<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct TwitchUser {
+    id: String,
+    profile_image_url: String,
+    #[serde(default)]
+    offline_image_url: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct TwitchStream {
+    r#type: String,
+    title: String,
+    viewer_count: i64,
+    thumbnail_url: String,
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_core::error::TiberiusResult;
+    use tiberius_dependencies::reqwest;
+    use tiberius_dependencies::serde_json::{json, Value};
+    use tiberius_models::ChannelType;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::TwitchApi;
+    use crate::refresh_channels::test::test_channel;
+
+    async fn mock_twitch(server: &MockServer, streams: Value) {
+        Mock::given(method("POST"))
+            .and(path("/oauth2/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "token",
+                "expires_in": 3600,
+                "token_type": "bearer"
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/helix/users"))
+            .and(query_param("login", "rarity"))
+            .and(header("Client-Id", "client"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{
+                    "id": "1234",
+                    "login": "rarity",
+                    "profile_image_url": "https://twitch.tv/profile.png",
+                    "offline_image_url": "https://twitch.tv/offline.png"
+                }]
+            })))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/helix/streams"))
+            .and(query_param("user_login", "rarity"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": streams })))
+            .mount(server)
+            .await;
+    }
+
+    fn api(server: &MockServer) -> TwitchApi {
+        TwitchApi::new(
+            reqwest::Client::new(),
+            &format!("{}/helix", server.uri()),
+            &format!("{}/oauth2/token", server.uri()),
+            "client",
+            "secret",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_twitch_live_channel() -> TiberiusResult<()> {
+        let server = MockServer::start().await;
+        mock_twitch(
+            &server,
+            json!([{
+                "id": "999",
+                "user_login": "rarity",
+                "type": "live",
+                "title": "Designing dresses",
+                "viewer_count": 42,
+                "thumbnail_url": "https://twitch.tv/live_{width}x{height}.jpg"
+            }]),
+        )
+        .await;
+
+        let api = api(&server);
+        let mut chan = test_channel("Rarity", ChannelType::TwitchChannel);
+        api.fetch_channel(&mut chan).await?;
+        assert!(chan.is_live);
+        assert_eq!("Designing dresses", chan.title);
+        assert_eq!(42, chan.viewers);
+        assert_eq!(Some("1234"), chan.remote_stream_id.as_deref());
+        assert_eq!(
+            Some("https://twitch.tv/live_320x180.jpg"),
+            chan.thumbnail_url.as_deref()
+        );
+
+        // the access token is reused for later channels
+        api.fetch_channel(&mut chan).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_twitch_offline_channel() -> TiberiusResult<()> {
+        let server = MockServer::start().await;
+        mock_twitch(&server, json!([])).await;
+
+        let mut chan = test_channel("rarity", ChannelType::TwitchChannel);
+        chan.title = "Old title".to_string();
+        chan.is_live = true;
+        chan.viewers = 10;
+        api(&server).fetch_channel(&mut chan).await?;
+        assert!(!chan.is_live);
+        assert_eq!(0, chan.viewers);
+        assert_eq!("Old title", chan.title);
+        assert_eq!(
+            Some("https://twitch.tv/offline.png"),
+            chan.thumbnail_url.as_deref()
+        );
+        assert!(chan.last_live_at.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use tiberius_core::error::TiberiusResult;
+use tiberius_dependencies::chrono::{DateTime, Datelike, Utc, Weekday};
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::sentry;
+use tiberius_dependencies::serde_json;
+use tiberius_models::Channel;
+
+use crate::scheduler::CurrentJob;
+use crate::SharedCtx;
+
+#[instrument(skip(current_job, sctx))]
+pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    sentry::configure_scope(|scope| {
+        scope.clear();
+    });
+    let tx = sentry::start_transaction(sentry::TransactionContext::new(
+        "reset_channel_stats",
+        "queue.task",
+    ));
+    match tx_run_job(current_job, sctx).await {
+        Ok(()) => {
+            tx.set_status(sentry::protocol::SpanStatus::Ok);
+            tx.finish();
+            Ok(())
+        }
+        Err(e) => {
+            tx.set_status(sentry::protocol::SpanStatus::InternalError);
+            tx.set_data("error_msg", serde_json::Value::String(e.to_string()));
+            tx.finish();
+            Err(e)
+        }
+    }
+}
+
+/// Whether a new week and a new month of viewer minutes start at the given time
+///
+/// Weeks start on Monday, days and weeks are counted in UTC.
+fn rollover(now: DateTime<Utc>) -> (bool, bool) {
+    (now.weekday() == Weekday::Mon, now.day() == 1)
+}
+
+#[instrument(skip(current_job, sctx))]
+async fn tx_run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    let mut client = sctx.client();
+    let (week, month) = rollover(Utc::now());
+    let reset = Channel::reset_viewer_minutes(&mut client, week, month).await?;
+    debug!(
+        "Job {}: Reset viewer minutes of {} channels (week: {}, month: {})",
+        current_job.id(),
+        reset,
+        week,
+        month
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::{TimeZone, Utc};
+
+    use super::rollover;
+
+    #[test]
+    fn test_rollover() {
+        // a Wednesday
+        assert_eq!(
+            (false, false),
+            rollover(Utc.with_ymd_and_hms(2026, 10, 14, 0, 0, 5).unwrap())
+        );
+        // a Monday
+        assert_eq!(
+            (true, false),
+            rollover(Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 5).unwrap())
+        );
+        // a Sunday
+        assert_eq!(
+            (false, true),
+            rollover(Utc.with_ymd_and_hms(2026, 11, 1, 0, 0, 5).unwrap())
+        );
+        // a Monday
+        assert_eq!(
+            (true, true),
+            rollover(Utc.with_ymd_and_hms(2026, 6, 1, 0, 0, 5).unwrap())
+        );
+    }
+}
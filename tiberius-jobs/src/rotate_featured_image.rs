@@ -0,0 +1,92 @@
+use tiberius_core::error::TiberiusResult;
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::sentry;
+use tiberius_dependencies::serde_json;
+use tiberius_models::ImageFeature;
+
+use crate::SharedCtx;
+use crate::scheduler::CurrentJob;
+
+/// Only images uploaded within this many days are considered for the rotation
+const CANDIDATE_MAX_AGE_DAYS: i64 = 3;
+
+#[instrument(skip(current_job, sctx))]
+pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    sentry::configure_scope(|scope| {
+        scope.clear();
+    });
+    let tx = sentry::start_transaction(sentry::TransactionContext::new(
+        "rotate_featured_image",
+        "queue.task",
+    ));
+    match tx_run_job(current_job, sctx).await {
+        Ok(()) => {
+            tx.set_status(sentry::protocol::SpanStatus::Ok);
+            tx.finish();
+            Ok(())
+        }
+        Err(e) => {
+            tx.set_status(sentry::protocol::SpanStatus::InternalError);
+            tx.set_data("error_msg", serde_json::Value::String(e.to_string()));
+            tx.finish();
+            Err(e)
+        }
+    }
+}
+
+/// Whether the current feature has been shown for the configured number of hours
+fn rotation_due(last_feature: Option<NaiveDateTime>, now: NaiveDateTime, hours: u32) -> bool {
+    match last_feature {
+        Some(last_feature) => now - last_feature >= Duration::hours(hours as i64),
+        None => true,
+    }
+}
+
+#[instrument(skip(current_job, sctx))]
+async fn tx_run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    let hours = match sctx.config().featured_image_rotation_hours {
+        Some(hours) => hours,
+        None => {
+            trace!("Featured image rotation is disabled");
+            return Ok(());
+        }
+    };
+    let mut client = sctx.client();
+    let now = Utc::now().naive_utc();
+    let latest = ImageFeature::latest(&mut client).await?;
+    if !rotation_due(latest.map(|x| x.created_at), now, hours) {
+        trace!("Current featured image is not due for rotation");
+        return Ok(());
+    }
+    let since = now - Duration::days(CANDIDATE_MAX_AGE_DAYS);
+    let mut image = match ImageFeature::rotation_candidate(&mut client, since).await? {
+        Some(image) => image,
+        None => {
+            info!("No image to feature, keeping the current featured image");
+            return Ok(());
+        }
+    };
+    ImageFeature::feature(&mut client, &mut image, None).await?;
+    debug!("Job {}: Featured image {}", current_job.id(), image.id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::{Duration, NaiveDate};
+
+    use super::rotation_due;
+
+    #[test]
+    fn test_rotation_due() {
+        let now = NaiveDate::from_ymd_opt(2026, 10, 19)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert!(rotation_due(None, now, 24));
+        assert!(!rotation_due(Some(now - Duration::hours(23)), now, 24));
+        assert!(rotation_due(Some(now - Duration::hours(24)), now, 24));
+        assert!(rotation_due(Some(now - Duration::hours(2)), now, 1));
+    }
+}
@@ -0,0 +1,166 @@
+use tiberius_core::error::{TiberiusError, TiberiusResult};
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::sentry;
+use tiberius_dependencies::serde_json;
+use tiberius_mail::EmailService;
+use tiberius_models::{Client, QueuedMail};
+
+use crate::scheduler::CurrentJob;
+use crate::SharedCtx;
+
+/// Number of mails delivered per run of the job
+const BATCH_SIZE: i64 = 50;
+
+#[instrument(skip(current_job, sctx))]
+pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    sentry::configure_scope(|scope| {
+        scope.clear();
+    });
+    let tx = sentry::start_transaction(sentry::TransactionContext::new("send_mail", "queue.task"));
+    match tx_run_job(current_job, sctx).await {
+        Ok(()) => {
+            tx.set_status(sentry::protocol::SpanStatus::Ok);
+            tx.finish();
+            Ok(())
+        }
+        Err(e) => {
+            tx.set_status(sentry::protocol::SpanStatus::InternalError);
+            tx.set_data("error_msg", serde_json::Value::String(e.to_string()));
+            tx.finish();
+            Err(e)
+        }
+    }
+}
+
+#[instrument(skip(current_job, sctx))]
+async fn tx_run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    let transport = match tiberius_mail::transport_from_config(&sctx.config) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            debug!(
+                "Job {}: No mail transport configured, leaving mail queued",
+                current_job.id()
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(TiberiusError::Other(format!(
+                "Mail configuration is invalid: {}",
+                e
+            )))
+        }
+    };
+    let mut client = sctx.client();
+    let delivered = deliver_due(&mut client, transport.as_ref()).await?;
+    debug!("Job {}: Delivered {} mails", current_job.id(), delivered);
+    let purged = QueuedMail::purge_failed(&mut client).await?;
+    if purged > 0 {
+        info!(
+            "Job {}: Purged {} undeliverable mails",
+            current_job.id(),
+            purged
+        );
+    }
+    Ok(())
+}
+
+/// Delivers the mails that are due with the given transport, returns the number of delivered mails
+///
+/// Failed deliveries are retried later with an increasing delay.
+pub async fn deliver_due(client: &mut Client, transport: &dyn EmailService) -> TiberiusResult<u64> {
+    let mut delivered = 0;
+    for mut mail in QueuedMail::claim_due(client, BATCH_SIZE).await? {
+        match tiberius_mail::deliver_queued(transport, &mail).await {
+            Ok(()) => {
+                mail.delivered(client).await?;
+                delivered += 1;
+            }
+            Err(e) => {
+                if mail.delivery_failed(client, &e.to_string()).await? {
+                    warn!(
+                        "Could not deliver mail {}, retrying at {}: {}",
+                        mail.id, mail.next_attempt_at, e
+                    );
+                } else {
+                    error!(
+                        "Could not deliver mail {} after {} attempts, giving up: {}",
+                        mail.id, mail.attempts, e
+                    );
+                }
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_core::{config::Configuration, error::TiberiusResult};
+    use tiberius_mail::{EmailService, FileMailer, MailQueue, Sender};
+    use tiberius_models::{Client, QueuedMail, User, UserToken, UserTokenContext};
+
+    use super::deliver_due;
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_queued_mail_delivery() -> TiberiusResult<()> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let sender = Sender::from_config(&Configuration::default()).unwrap();
+        let queue = MailQueue::new(client.clone(), sender.clone());
+        queue
+            .send_email_reset(&user, "https://example.com/reset-pw/012456869")
+            .await
+            .expect("mail can be queued");
+
+        let dir = tiberius_dependencies::tempfile::tempdir()?;
+        let transport = FileMailer::new(dir.path(), sender).expect("mail directory can be created");
+        assert_eq!(1, deliver_due(&mut client, &transport).await?);
+        assert_eq!(0, deliver_due(&mut client, &transport).await?);
+        assert!(QueuedMail::claim_due(&mut client, 10).await?.is_empty());
+
+        let files = std::fs::read_dir(dir.path())?.collect::<std::io::Result<Vec<_>>>()?;
+        assert_eq!(1, files.len());
+        let content = std::fs::read_to_string(files[0].path())?;
+        assert!(content.contains(&format!("<{}>", user.email)));
+        Ok(())
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_password_reset_mail() -> TiberiusResult<()> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let config = Configuration::default();
+        let sender = Sender::from_config(&config).unwrap();
+
+        // the request queues the mail the same way the forgot password page does
+        let token = UserToken::issue(
+            &mut client,
+            user.id as i64,
+            UserTokenContext::ResetPassword,
+            Some(user.email.as_ref()),
+        )
+        .await?;
+        let reset_url = config.absolute_url(format!("/session/reset_pw/{token}"));
+        MailQueue::new(client.clone(), sender.clone())
+            .send_email_reset(&user, &reset_url)
+            .await
+            .expect("mail can be queued");
+
+        // and the job delivers it later
+        let dir = tiberius_dependencies::tempfile::tempdir()?;
+        let transport = FileMailer::new(dir.path(), sender).expect("mail directory can be created");
+        assert_eq!(1, deliver_due(&mut client, &transport).await?);
+        let files = std::fs::read_dir(dir.path())?.collect::<std::io::Result<Vec<_>>>()?;
+        assert_eq!(1, files.len());
+        // undo the soft line breaks of quoted-printable parts
+        let content = std::fs::read_to_string(files[0].path())?.replace("=\r\n", "");
+        assert!(content.contains(&reset_url));
+
+        // the link in the mail resets the password of the user
+        let peeked = UserToken::peek(&mut client, &token, UserTokenContext::ResetPassword)
+            .await?
+            .expect("token from the mail is valid");
+        assert_eq!(user.id as i64, peeked.user_id);
+        Ok(())
+    }
+}
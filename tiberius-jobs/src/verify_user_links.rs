@@ -0,0 +1,131 @@
+use tiberius_core::config::Configuration;
+use tiberius_core::error::TiberiusResult;
+use tiberius_core::remote::{http_client_for_url, read_body_limited};
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+use tiberius_dependencies::prelude::*;
+use tiberius_dependencies::reqwest;
+use tiberius_dependencies::sentry;
+use tiberius_dependencies::serde_json;
+use tiberius_models::{UserLink, UserLinkState};
+
+use crate::SharedCtx;
+use crate::scheduler::CurrentJob;
+
+/// How many hours pass between two checks of a page that did not show the verification code
+const CHECK_INTERVAL_HOURS: i64 = 2;
+/// Pages are only checked this many days after the link was added, staff can still verify it later
+const CHECK_MAX_AGE_DAYS: i64 = 7;
+/// Pages larger than this many bytes are not searched for the verification code
+const PAGE_MAX_SIZE: u64 = 2 * 1024 * 1024;
+
+#[instrument(skip(current_job, sctx))]
+pub async fn run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    sentry::configure_scope(|scope| {
+        scope.clear();
+    });
+    let tx = sentry::start_transaction(sentry::TransactionContext::new(
+        "verify_user_links",
+        "queue.task",
+    ));
+    match tx_run_job(current_job, sctx).await {
+        Ok(()) => {
+            tx.set_status(sentry::protocol::SpanStatus::Ok);
+            tx.finish();
+            Ok(())
+        }
+        Err(e) => {
+            tx.set_status(sentry::protocol::SpanStatus::InternalError);
+            tx.set_data("error_msg", serde_json::Value::String(e.to_string()));
+            tx.finish();
+            Err(e)
+        }
+    }
+}
+
+/// When a page that did not show the verification code is checked again, if at all
+fn next_check_at(created_at: NaiveDateTime, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let next = now + Duration::hours(CHECK_INTERVAL_HOURS);
+    if next - created_at > Duration::days(CHECK_MAX_AGE_DAYS) {
+        None
+    } else {
+        Some(next)
+    }
+}
+
+#[instrument(skip(current_job, sctx))]
+async fn tx_run_job(current_job: CurrentJob, sctx: SharedCtx) -> TiberiusResult<()> {
+    let mut client = sctx.client();
+    let config = sctx.config();
+    for mut link in UserLink::get_due_for_check(&mut client).await? {
+        match page_shows_code(&config, &link).await {
+            Ok(true) => {
+                link.set_state(&mut client, UserLinkState::LinkVerified, None)
+                    .await?;
+                debug!(
+                    "Job {}: Found code of user link {}",
+                    current_job.id(),
+                    link.id
+                );
+            }
+            Ok(false) => {
+                let next = next_check_at(link.created_at, Utc::now().naive_utc());
+                link.set_next_check(&mut client, next).await?;
+            }
+            Err(e) => {
+                warn!(
+                    "Job {}: Could not check user link {}: {}",
+                    current_job.id(),
+                    link.id,
+                    e
+                );
+                let next = next_check_at(link.created_at, Utc::now().naive_utc());
+                link.set_next_check(&mut client, next).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the linked page and looks for the verification code of the link
+///
+/// Without a proxy, pages on non-public addresses are not fetched. Pages larger than
+/// [PAGE_MAX_SIZE] are treated as not showing the code.
+async fn page_shows_code(config: &Configuration, link: &UserLink) -> TiberiusResult<bool> {
+    let url = reqwest::Url::parse(&link.uri)?;
+    let http = http_client_for_url(config, &url).await?;
+    let resp = http.get(url).send().await?.error_for_status()?;
+    Ok(match read_body_limited(resp, PAGE_MAX_SIZE).await? {
+        Some(page) => String::from_utf8_lossy(&page).contains(&link.verification_code),
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::{Duration, NaiveDate};
+
+    use super::next_check_at;
+
+    #[test]
+    fn test_next_check_at() {
+        let created_at = NaiveDate::from_ymd_opt(2026, 10, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(
+            Some(created_at + Duration::hours(3)),
+            next_check_at(created_at, created_at + Duration::hours(1))
+        );
+        assert_eq!(
+            Some(created_at + Duration::days(7)),
+            next_check_at(
+                created_at,
+                created_at + Duration::days(7) - Duration::hours(2)
+            )
+        );
+        assert_eq!(
+            None,
+            next_check_at(created_at, created_at + Duration::days(7))
+        );
+    }
+}
@@ -1,6 +1,11 @@
+use std::path::Path;
+
+use lettre::address::Envelope;
 use lettre::AsyncTransport;
 use lettre::{message::Mailbox, Message};
-use tiberius_models::User;
+use tiberius_core::config::{Configuration, MailTransportKind};
+use tiberius_dependencies::prelude::*;
+use tiberius_models::{Client, PhilomenaModelError, QueuedMail, User};
 
 #[derive(Debug, thiserror::Error)]
 pub enum TiberiusMailError {
@@ -8,24 +13,159 @@ pub enum TiberiusMailError {
     Lettre(#[from] lettre::error::Error),
     #[error("SMTP Error: {0}")]
     SMTPError(#[from] lettre::transport::smtp::Error),
+    #[error("File Transport Error: {0}")]
+    FileError(#[from] lettre::transport::file::Error),
     #[error("Address Error: {0}")]
     AddrError(#[from] lettre::address::AddressError),
+    #[error("IO Error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Database Error: {0}")]
+    ModelError(#[from] PhilomenaModelError),
+    #[error("BASE_URL must be set to send mail, links in mails are built from it")]
+    BaseUrlUnset,
 }
 
 pub type Result<T> = std::result::Result<T, TiberiusMailError>;
 
+/// The addresses outgoing mail is sent from
+#[derive(Clone, Debug)]
+pub struct Sender {
+    pub from: Mailbox,
+    pub reply_to: Mailbox,
+}
+
+impl Sender {
+    /// Uses the sender address of the configuration, or noreply@localhost if not set
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        let from: Mailbox = match &config.mail_from {
+            Some(v) => v.parse()?,
+            None => Mailbox::new(None, "noreply@localhost".parse()?),
+        };
+        Ok(Self {
+            reply_to: from.clone(),
+            from,
+        })
+    }
+
+    /// Builds a mail to the user with a plain text and a HTML body
+    pub fn build(
+        &self,
+        user: &User,
+        subject: String,
+        body_txt: String,
+        body_html: String,
+    ) -> Result<Message> {
+        let body = lettre::message::MultiPart::alternative_plain_html(body_txt, body_html);
+        Ok(Message::builder()
+            .from(self.from.clone())
+            .reply_to(self.reply_to.clone())
+            .to(Mailbox::new(
+                Some(user.name.clone()),
+                user.email.to_string().parse()?,
+            ))
+            .date_now()
+            .subject(subject)
+            .multipart(body)?)
+    }
+}
+
+/// Delivers the mail sent by the site
+///
+/// Implementations only need to deliver formatted messages, the account mails are built from
+/// the templates in [tiberius_common_html::email].
 #[async_trait::async_trait]
-pub trait EmailService {
-    async fn send_signup_welcome(&self, user: &User, register_url: &str) -> Result<()>;
-    async fn send_email_reset(&self, user: &User, reset_url: &str) -> Result<()>;
-    async fn send_unlock_account(&self, user: &User, unlock_url: &str) -> Result<()>;
-    async fn send_update_email(&self, user: &User, update_url: &str) -> Result<()>;
+pub trait EmailService: Send + Sync {
+    fn sender(&self) -> &Sender;
+
+    /// Delivers a message formatted with [Message::formatted]
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<()>;
+
+    async fn send(&self, email: Message) -> Result<()> {
+        self.send_raw(email.envelope(), &email.formatted()).await
+    }
+
+    async fn send_signup_welcome(&self, user: &User, register_url: &str) -> Result<()> {
+        use tiberius_common_html::email::confirm_account;
+        let email = self.sender().build(
+            user,
+            confirm_account::subject(&user.name),
+            confirm_account::build_txt(&user.name, register_url),
+            confirm_account::build(&user.name, register_url).into_string(),
+        )?;
+        self.send(email).await
+    }
+
+    async fn send_email_reset(&self, user: &User, reset_url: &str) -> Result<()> {
+        use tiberius_common_html::email::password_reset;
+        let email = self.sender().build(
+            user,
+            password_reset::subject(&user.name),
+            password_reset::build_txt(&user.name, reset_url),
+            password_reset::build(&user.name, reset_url).into_string(),
+        )?;
+        self.send(email).await
+    }
+
+    async fn send_unlock_account(&self, user: &User, unlock_url: &str) -> Result<()> {
+        use tiberius_common_html::email::unlock_account;
+        let email = self.sender().build(
+            user,
+            unlock_account::subject(&user.name),
+            unlock_account::build_txt(&user.name, unlock_url),
+            unlock_account::build(&user.name, unlock_url).into_string(),
+        )?;
+        self.send(email).await
+    }
+
+    async fn send_update_email(&self, user: &User, update_url: &str) -> Result<()> {
+        use tiberius_common_html::email::update_email;
+        let email = self.sender().build(
+            user,
+            update_email::subject(&user.name),
+            update_email::build_txt(&user.name, update_url),
+            update_email::build(&user.name, update_url).into_string(),
+        )?;
+        self.send(email).await
+    }
+}
+
+/// Returns true if the configuration allows mail to be delivered
+pub fn mail_enabled(config: &Configuration) -> bool {
+    match config.mail_transport {
+        MailTransportKind::Smtp => config.mail_smtp_url.is_some(),
+        MailTransportKind::File | MailTransportKind::Stdout => true,
+    }
+}
+
+/// Returns an error if mail is enabled but the links in it cannot be built
+pub fn check_config(config: &Configuration) -> Result<()> {
+    if mail_enabled(config) && config.base_url.is_none() {
+        return Err(TiberiusMailError::BaseUrlUnset);
+    }
+    Ok(())
+}
+
+/// Builds the transport selected in the configuration
+///
+/// Returns None if the SMTP transport is selected but no SMTP server has been configured.
+pub fn transport_from_config(config: &Configuration) -> Result<Option<Box<dyn EmailService>>> {
+    Ok(match config.mail_transport {
+        MailTransportKind::Smtp => {
+            SMTP::from_config(config)?.map(|x| Box::new(x) as Box<dyn EmailService>)
+        }
+        MailTransportKind::File => Some(Box::new(FileMailer::new(
+            &config.mail_file_dir,
+            Sender::from_config(config)?,
+        )?)),
+        MailTransportKind::Stdout => {
+            Some(Box::new(StdoutMailer::new(Sender::from_config(config)?)))
+        }
+    })
 }
 
 pub struct SMTP {
     transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
-    from: lettre::message::Mailbox,
-    reply_to: lettre::message::Mailbox,
+    sender: Sender,
 }
 
 impl SMTP {
@@ -36,183 +176,238 @@ impl SMTP {
     ) -> Self {
         Self {
             transport,
-            from,
-            reply_to,
+            sender: Sender { from, reply_to },
         }
     }
-    /// Builds a new SMTP Connection against a default MailHog instance
-    #[cfg(test)]
-    pub fn new_mailhog() -> Self {
-        use lettre::Tokio1Executor;
-
+    /// Builds the SMTP transport from the mail settings of the configuration
+    ///
+    /// Returns None if no SMTP server has been configured.
+    pub fn from_config(config: &Configuration) -> Result<Option<Self>> {
+        let url = match &config.mail_smtp_url {
+            Some(v) => v,
+            None => return Ok(None),
+        };
         let transport =
-            lettre::AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("localhost")
-                .port(1025)
-                .build();
-        let from = lettre::message::Mailbox::new(
-            Some("MailHog SMTP".to_string()),
-            "noreply@example.com".parse().unwrap(),
-        );
-        let reply_to = lettre::message::Mailbox::new(
-            Some("MailHog SMTP".to_string()),
-            "noreply@example.com".parse().unwrap(),
-        );
-        Self::new(transport, from, reply_to)
+            lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::from_url(url.as_str())?.build();
+        let sender = Sender::from_config(config)?;
+        Ok(Some(Self::new(transport, sender.from, sender.reply_to)))
     }
 }
 
 #[async_trait::async_trait]
 impl EmailService for SMTP {
-    async fn send_signup_welcome(&self, user: &User, register_url: &str) -> Result<()> {
-        let body: String =
-            tiberius_common_html::email::confirm_account::build(&user.name, register_url)
-                .into_string();
-        let body_txt: String =
-            tiberius_common_html::email::confirm_account::build_txt(&user.name, register_url);
-        let body = lettre::message::MultiPart::alternative_plain_html(body_txt, body);
-        let email = Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
-            .to(Mailbox::new(
-                Some(user.name.clone()),
-                user.email.to_string().parse()?,
-            ))
-            .date_now()
-            .subject(tiberius_common_html::email::confirm_account::subject(
-                &user.name,
-            ))
-            .multipart(body)?;
-        self.transport.send(email).await?;
+    fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<()> {
+        self.transport.send_raw(envelope, email).await?;
         Ok(())
     }
+}
 
-    async fn send_email_reset(&self, user: &User, reset_url: &str) -> Result<()> {
-        let body: String =
-            tiberius_common_html::email::password_reset::build(&user.name, reset_url).into_string();
-        let body_txt: String =
-            tiberius_common_html::email::password_reset::build_txt(&user.name, reset_url);
-        let body = lettre::message::MultiPart::alternative_plain_html(body_txt, body);
-        let email = Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
-            .to(Mailbox::new(
-                Some(user.name.clone()),
-                user.email.to_string().parse()?,
-            ))
-            .date_now()
-            .subject(tiberius_common_html::email::password_reset::subject(
-                &user.name,
-            ))
-            .multipart(body)?;
-        self.transport.send(email).await?;
+/// Writes mail as .eml files into a directory, for development and tests
+pub struct FileMailer {
+    transport: lettre::AsyncFileTransport<lettre::Tokio1Executor>,
+    sender: Sender,
+}
+
+impl FileMailer {
+    /// Creates the directory if it does not exist yet
+    pub fn new<P: AsRef<Path>>(dir: P, sender: Sender) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            transport: lettre::AsyncFileTransport::new(dir),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailService for FileMailer {
+    fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<()> {
+        let id = self.transport.send_raw(envelope, email).await?;
+        debug!("Wrote mail {}.eml", id);
         Ok(())
     }
-    async fn send_unlock_account(&self, user: &User, unlock_url: &str) -> Result<()> {
-        let body: String =
-            tiberius_common_html::email::unlock_account::build(&user.name, unlock_url)
-                .into_string();
-        let body_txt: String =
-            tiberius_common_html::email::unlock_account::build_txt(&user.name, unlock_url);
-        let body = lettre::message::MultiPart::alternative_plain_html(body_txt, body);
-        let email = Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
-            .to(Mailbox::new(
-                Some(user.name.clone()),
-                user.email.to_string().parse()?,
-            ))
-            .date_now()
-            .subject(tiberius_common_html::email::unlock_account::subject(
-                &user.name,
-            ))
-            .multipart(body)?;
-        self.transport.send(email).await?;
+}
+
+/// Prints mail to the standard output, for development
+pub struct StdoutMailer {
+    sender: Sender,
+}
+
+impl StdoutMailer {
+    pub fn new(sender: Sender) -> Self {
+        Self { sender }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailService for StdoutMailer {
+    fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<()> {
+        use tiberius_dependencies::tokio::io::AsyncWriteExt;
+        let to = envelope
+            .to()
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stdout = tiberius_dependencies::tokio::io::stdout();
+        stdout
+            .write_all(format!("----- Mail to {} -----\n", to).as_bytes())
+            .await?;
+        stdout.write_all(email).await?;
+        stdout.write_all(b"\n----- End of mail -----\n").await?;
+        stdout.flush().await?;
         Ok(())
     }
-    async fn send_update_email(&self, user: &User, update_url: &str) -> Result<()> {
-        let body: String =
-            tiberius_common_html::email::update_email::build(&user.name, update_url).into_string();
-        let body_txt: String =
-            tiberius_common_html::email::update_email::build_txt(&user.name, update_url);
-        let body = lettre::message::MultiPart::alternative_plain_html(body_txt, body);
-        let email = Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
-            .to(Mailbox::new(
-                Some(user.name.clone()),
-                user.email.to_string().parse()?,
-            ))
-            .date_now()
-            .subject(tiberius_common_html::email::update_email::subject(
-                &user.name,
-            ))
-            .multipart(body)?;
-        self.transport.send(email).await?;
+}
+
+/// Queues mail in the database for delivery by the send_mail job
+///
+/// This keeps requests from waiting on the mail server and retries failed deliveries.
+pub struct MailQueue {
+    client: Client,
+    sender: Sender,
+}
+
+impl MailQueue {
+    pub fn new(client: Client, sender: Sender) -> Self {
+        Self { client, sender }
+    }
+
+    /// Returns None if the configuration does not allow mail to be delivered
+    pub fn from_config(client: Client, config: &Configuration) -> Result<Option<Self>> {
+        if !mail_enabled(config) {
+            return Ok(None);
+        }
+        check_config(config)?;
+        Ok(Some(Self::new(client, Sender::from_config(config)?)))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailService for MailQueue {
+    fn sender(&self) -> &Sender {
+        &self.sender
+    }
+
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<()> {
+        let from = envelope.from().map(|x| x.to_string());
+        let to: Vec<String> = envelope.to().iter().map(|x| x.to_string()).collect();
+        QueuedMail::enqueue(&mut self.client.clone(), from.as_deref(), &to, email).await?;
         Ok(())
     }
 }
 
+/// Delivers a queued mail with the given transport
+pub async fn deliver_queued(transport: &dyn EmailService, mail: &QueuedMail) -> Result<()> {
+    let from = match &mail.envelope_from {
+        Some(v) => Some(v.parse()?),
+        None => None,
+    };
+    let to = mail
+        .envelope_to
+        .iter()
+        .map(|x| x.parse())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    transport
+        .send_raw(&Envelope::new(from, to)?, &mail.message)
+        .await
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{EmailService, Result, SMTP};
+    use tiberius_core::config::{Configuration, MailTransportKind};
 
-    #[tokio::test]
-    pub async fn test_email_reset_delivery() -> Result<()> {
-        let mail: Box<dyn EmailService> = Box::new(SMTP::new_mailhog());
-        mail.send_email_reset(
-            &tiberius_models::User {
-                name: "Example User".to_string(),
-                email: "test@example.com".to_string().into(),
-                ..Default::default()
-            },
-            "https://example.com/reset-pw/012456869",
-        )
-        .await?;
-        Ok(())
+    use crate::{check_config, EmailService, FileMailer, Result, Sender};
+
+    fn example_user() -> tiberius_models::User {
+        tiberius_models::User {
+            name: "Example User".to_string(),
+            email: "test@example.com".to_string().into(),
+            ..Default::default()
+        }
     }
 
-    #[tokio::test]
-    pub async fn test_email_update_email() -> Result<()> {
-        let mail: Box<dyn EmailService> = Box::new(SMTP::new_mailhog());
-        mail.send_update_email(
-            &tiberius_models::User {
-                name: "Example User".to_string(),
-                email: "test@example.com".to_string().into(),
-                ..Default::default()
-            },
-            "https://example.com/update-email/012456869",
-        )
-        .await?;
-        Ok(())
+    /// Returns the contents of the mails written to the directory, with the soft line breaks of
+    /// quoted-printable parts removed so links can be searched for
+    fn written_mails(dir: &std::path::Path) -> Result<Vec<String>> {
+        let files = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        let mut mails = Vec::new();
+        for file in files {
+            assert_eq!(
+                Some("eml"),
+                file.path().extension().and_then(|x| x.to_str())
+            );
+            mails.push(std::fs::read_to_string(file.path())?.replace("=\r\n", ""));
+        }
+        Ok(mails)
     }
 
     #[tokio::test]
-    pub async fn test_email_welcome() -> Result<()> {
-        let mail: Box<dyn EmailService> = Box::new(SMTP::new_mailhog());
-        mail.send_signup_welcome(
-            &tiberius_models::User {
-                name: "Example User".to_string(),
-                email: "test@example.com".to_string().into(),
-                ..Default::default()
-            },
-            "https://example.com/welcome/012456869",
-        )
-        .await?;
+    pub async fn test_file_mailer() -> Result<()> {
+        let dir = tiberius_dependencies::tempfile::tempdir()?;
+        let mail: Box<dyn EmailService> = Box::new(FileMailer::new(
+            dir.path(),
+            Sender::from_config(&Configuration::default())?,
+        )?);
+        mail.send_unlock_account(&example_user(), "https://example.com/unlock/012456869")
+            .await?;
+        let mails = written_mails(dir.path())?;
+        assert_eq!(1, mails.len());
+        assert!(mails[0].contains("To: \"Example User\" <test@example.com>"));
+        assert!(mails[0].contains("From: noreply@localhost"));
         Ok(())
     }
 
     #[tokio::test]
-    pub async fn test_email_unlock() -> Result<()> {
-        let mail: Box<dyn EmailService> = Box::new(SMTP::new_mailhog());
-        mail.send_unlock_account(
-            &tiberius_models::User {
-                name: "Example User".to_string(),
-                email: "test@example.com".to_string().into(),
-                ..Default::default()
-            },
-            "https://example.com/unlock/012456869",
-        )
-        .await?;
+    pub async fn test_account_mails() -> Result<()> {
+        let dir = tiberius_dependencies::tempfile::tempdir()?;
+        let mail: Box<dyn EmailService> = Box::new(FileMailer::new(
+            dir.path(),
+            Sender::from_config(&Configuration::default())?,
+        )?);
+        let user = example_user();
+        mail.send_email_reset(&user, "https://example.com/reset-pw/012456869")
+            .await?;
+        mail.send_update_email(&user, "https://example.com/update-email/012456869")
+            .await?;
+        mail.send_signup_welcome(&user, "https://example.com/welcome/012456869")
+            .await?;
+        mail.send_unlock_account(&user, "https://example.com/unlock/012456869")
+            .await?;
+        let mails = written_mails(dir.path())?;
+        assert_eq!(4, mails.len());
+        for path in ["reset-pw", "update-email", "welcome", "unlock"] {
+            let link = format!("https://example.com/{path}/012456869");
+            assert!(
+                mails.iter().any(|x| x.contains(&link)),
+                "no mail contains {link}"
+            );
+        }
         Ok(())
     }
+
+    #[test]
+    fn test_check_config() {
+        // no mail is sent without a SMTP server
+        assert!(check_config(&Configuration::default()).is_ok());
+        let mut config = Configuration {
+            mail_transport: MailTransportKind::File,
+            ..Default::default()
+        };
+        assert!(check_config(&config).is_err());
+        config.base_url = Some("https://example.com".parse().unwrap());
+        assert!(check_config(&config).is_ok());
+    }
 }
@@ -147,6 +147,10 @@ impl Client {
             cache_tag_assoc: Cache::new(1000),
         }
     }
+    /// The connection pool of the client, for libraries that manage their own tables
+    pub fn pool(&self) -> &PgPool {
+        &self.db
+    }
     #[deprecated(note = "Use Client directly since it implements the necessary interface")]
     pub(crate) async fn db(&self) -> Result<PoolConnection<Postgres>, PhilomenaModelError> {
         Ok(self.db.acquire().await?)
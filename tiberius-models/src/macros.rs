@@ -60,6 +60,19 @@ macro_rules! tantivy_u64_field {
     };
 }
 
+#[macro_export]
+macro_rules! tantivy_i64_field {
+    ($builder:ident, $name:ident) => {
+        $builder.add_i64_field(
+            stringify!($name),
+            NumericOptions::default()
+                .set_indexed()
+                .set_stored()
+                .set_fast()
+        )
+    };
+}
+
 #[macro_export]
 macro_rules! tantivy_date_field {
     ($builder:ident, $name:ident) => {
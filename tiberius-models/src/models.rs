@@ -5,14 +5,28 @@
 mod channel;
 pub mod comment;
 pub use channel::*;
+mod channel_live_session;
+pub use channel_live_session::*;
 mod user_staff_entry;
 pub use user_staff_entry::*;
 mod staff_category;
 pub use staff_category::*;
 mod user_token;
 pub use user_token::*;
+mod queued_mail;
+pub use queued_mail::*;
+mod user_session;
+pub use user_session::*;
 mod user;
 pub use user::*;
+mod user_link;
+pub use user_link::*;
+mod user_name_change;
+pub use user_name_change::*;
+mod user_statistic;
+pub use user_statistic::*;
+mod webauthn_credential;
+pub use webauthn_credential::*;
 mod filter;
 pub use filter::*;
 mod image;
@@ -25,12 +39,16 @@ mod conversation;
 pub use conversation::*;
 mod forum;
 pub use forum::*;
+mod topic;
+pub use topic::*;
 mod site_notice;
 pub use site_notice::*;
 mod image_tagging;
 pub use image_tagging::*;
 mod image_feature;
 pub use image_feature::*;
+mod image_interaction;
+pub use image_interaction::*;
 mod badge;
 pub use badge::*;
 mod badge_award;
@@ -39,3 +57,7 @@ mod audit;
 pub use audit::*;
 mod api_key;
 pub use api_key::*;
+mod dnp_entry;
+pub use dnp_entry::*;
+mod used_captcha_check;
+pub use used_captcha_check::*;
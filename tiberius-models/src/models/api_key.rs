@@ -1,40 +1,87 @@
 use std::ops::DerefMut;
 
 use ring::rand::SecureRandom;
-use sqlx::{query, query_as, types::Uuid};
-use std::convert::TryInto;
+use sqlx::{query, query_as, types::ipnetwork::IpNetwork, types::Uuid};
+use std::{convert::TryInto, str::FromStr};
 use tiberius_dependencies::base64;
 use tiberius_dependencies::chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
 
 use crate::{Client, PhilomenaModelError, User};
 
+/// A permission granted to an API Key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Read access to the API
+    Read,
+    /// Upload new images
+    Upload,
+}
+
+impl ApiKeyScope {
+    pub const ALL: [ApiKeyScope; 2] = [ApiKeyScope::Read, ApiKeyScope::Upload];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Upload => "upload",
+        }
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = PhilomenaModelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "read" => ApiKeyScope::Read,
+            "upload" => ApiKeyScope::Upload,
+            v => {
+                return Err(PhilomenaModelError::NotFoundInSequence(
+                    "api key scopes".to_string(),
+                    v.to_string(),
+                ))
+            }
+        })
+    }
+}
+
 #[derive(sqlx::FromRow, Debug, Clone, serde::Serialize)]
 pub struct ApiKey {
     id: Uuid,
     user_id: i64,
     private: String,
-    valid_until: DateTime<Utc>,
+    /// The key does not expire if unset
+    valid_until: Option<DateTime<Utc>>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
+    scopes: Vec<String>,
+    last_used_at: Option<DateTime<Utc>>,
+    last_used_ip: Option<IpNetwork>,
+}
+
+fn new_key_secret() -> Result<String, PhilomenaModelError> {
+    let mut data = [0u8; 64];
+    ring::rand::SystemRandom::new().fill(&mut data)?;
+    Ok(base64::encode(data))
 }
 
 impl ApiKey {
-    pub fn new(user: &User) -> Result<ApiKey, PhilomenaModelError> {
+    pub fn new(
+        user: &User,
+        scopes: &[ApiKeyScope],
+        valid_until: Option<DateTime<Utc>>,
+    ) -> Result<ApiKey, PhilomenaModelError> {
         let id = Uuid::new_v4();
-        let key_data: String = {
-            let mut data = [0u8; 64];
-            ring::rand::SystemRandom::new().fill(&mut data)?;
-            base64::encode(data)
-        };
         Ok(ApiKey {
             id,
             user_id: user.id(),
-            private: key_data,
-            valid_until: Utc::now()
-                .checked_add_signed(Duration::weeks(52 * 5))
-                .expect("should not overflow"),
+            private: new_key_secret()?,
+            valid_until,
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            scopes: scopes.iter().map(|x| x.as_str().to_string()).collect(),
+            last_used_at: None,
+            last_used_ip: None,
         })
     }
     pub async fn get_id(
@@ -46,6 +93,20 @@ impl ApiKey {
             .await?;
         Ok(api_key)
     }
+    /// Returns the key matching the given secret, if it exists and has not expired yet
+    pub async fn get_by_secret(
+        client: &mut Client,
+        secret: &str,
+    ) -> Result<Option<Self>, PhilomenaModelError> {
+        let api_key = query_as!(
+            ApiKey,
+            "SELECT * FROM user_api_keys WHERE private = $1 AND (valid_until IS NULL OR valid_until > NOW())",
+            secret
+        )
+        .fetch_optional(client)
+        .await?;
+        Ok(api_key)
+    }
     pub async fn get_all(
         client: &mut Client,
         offset: Option<u64>,
@@ -88,16 +149,50 @@ impl ApiKey {
         }
         let uuid = query_as!(
             UuidW,
-            "INSERT INTO user_api_keys (id, user_id, private, valid_until, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            "INSERT INTO user_api_keys (id, user_id, private, valid_until, created_at, updated_at, scopes) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
             self.id,
             self.user_id,
             self.private,
             self.valid_until,
             self.created_at,
             self.updated_at,
+            &self.scopes,
         ).fetch_one(client).await?;
         Ok(uuid.id)
     }
+    /// Replaces the secret of the key, the old secret stops working immediately
+    pub async fn rotate_secret(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        let private = new_key_secret()?;
+        query!(
+            "UPDATE user_api_keys SET private = $2, updated_at = NOW() WHERE id = $1",
+            self.id,
+            private,
+        )
+        .execute(client)
+        .await?;
+        self.private = private;
+        self.updated_at = Utc::now();
+        Ok(())
+    }
+    /// Records that the key was used just now from the given address
+    pub async fn touch(
+        &mut self,
+        client: &mut Client,
+        ip: Option<IpNetwork>,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now();
+        query!(
+            "UPDATE user_api_keys SET last_used_at = $2, last_used_ip = $3 WHERE id = $1",
+            self.id,
+            now,
+            ip,
+        )
+        .execute(client)
+        .await?;
+        self.last_used_at = Some(now);
+        self.last_used_ip = ip;
+        Ok(())
+    }
     pub async fn delete(self, client: &mut Client) -> Result<Uuid, PhilomenaModelError> {
         struct UuidW {
             id: Uuid,
@@ -123,4 +218,23 @@ impl ApiKey {
     pub fn user_id(&self) -> i64 {
         self.user_id
     }
+    pub fn valid_until(&self) -> Option<DateTime<Utc>> {
+        self.valid_until
+    }
+    pub fn last_used_at(&self) -> Option<DateTime<Utc>> {
+        self.last_used_at
+    }
+    pub fn last_used_ip(&self) -> Option<IpNetwork> {
+        self.last_used_ip
+    }
+    /// Returns the scopes of the key, unknown scopes are ignored
+    pub fn scopes(&self) -> Vec<ApiKeyScope> {
+        self.scopes
+            .iter()
+            .filter_map(|x| ApiKeyScope::from_str(x).ok())
+            .collect()
+    }
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        self.scopes.iter().any(|x| x == scope.as_str())
+    }
 }
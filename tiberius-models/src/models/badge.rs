@@ -1,14 +1,20 @@
-use tiberius_dependencies::chrono::NaiveDateTime;
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
 
 #[derive(sqlx::FromRow, Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Badge {
     pub id: i32,
     pub title: String,
     pub description: String,
+    /// Filename of the badge image in the badge directory of the data root
     pub image: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Badges that can no longer be awarded, existing awards are kept
     pub disable_award: bool,
+    /// Priority badges are shown first next to the user name
     pub priority: bool,
 }
 
@@ -16,4 +22,71 @@ impl Badge {
     pub fn title(&self) -> String {
         format!("{} - {}", self.title, self.description)
     }
+
+    pub async fn get_id(client: &mut Client, id: i32) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(query_as!(Badge, "SELECT * FROM badges WHERE id = $1", id)
+            .fetch_optional(client)
+            .await?)
+    }
+
+    /// Returns all badges, priority badges first
+    pub async fn get_all(client: &mut Client) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(
+            query_as!(Badge, "SELECT * FROM badges ORDER BY priority DESC, title")
+                .fetch_all(client)
+                .await?,
+        )
+    }
+
+    pub async fn create(
+        client: &mut Client,
+        title: &str,
+        description: &str,
+        image: Option<String>,
+        priority: bool,
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        Ok(query_as!(
+            Badge,
+            "INSERT INTO badges (title, description, image, priority, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $5) RETURNING *",
+            title,
+            description,
+            image,
+            priority,
+            now,
+        )
+        .fetch_one(client)
+        .await?)
+    }
+
+    pub async fn update(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        self.updated_at = Utc::now().naive_utc();
+        query!(
+            "UPDATE badges
+                SET title = $2, description = $3, image = $4, priority = $5, disable_award = $6, updated_at = $7
+                WHERE id = $1",
+            self.id,
+            self.title,
+            self.description,
+            self.image,
+            self.priority,
+            self.disable_award,
+            self.updated_at,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the number of times this badge was awarded
+    pub async fn award_count(&self, client: &mut Client) -> Result<i64, PhilomenaModelError> {
+        Ok(query!(
+            r#"SELECT COUNT(*) AS "count!" FROM badge_awards WHERE badge_id = $1"#,
+            self.id
+        )
+        .fetch_one(client)
+        .await?
+        .count)
+    }
 }
@@ -1,8 +1,12 @@
-use tiberius_dependencies::chrono::NaiveDateTime;
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Badge, Client, PhilomenaModelError};
 
 #[derive(sqlx::FromRow, Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct BadgeAward {
     pub id: i32,
+    /// Shown instead of the badge title on the profile of the user
     pub label: Option<String>,
     pub awarded_on: NaiveDateTime,
     pub created_at: NaiveDateTime,
@@ -11,5 +15,110 @@ pub struct BadgeAward {
     pub badge_id: i32,
     pub awarded_by_id: i32,
     pub reason: Option<String>,
+    /// Title of the badge at the time of the award
     pub badge_name: Option<String>,
 }
+
+impl BadgeAward {
+    /// Awards the badge to the user
+    pub async fn award(
+        client: &mut Client,
+        badge: &Badge,
+        user_id: i32,
+        awarded_by_id: i32,
+        label: Option<String>,
+        reason: Option<String>,
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        Ok(query_as!(
+            BadgeAward,
+            "INSERT INTO badge_awards
+                (label, awarded_on, created_at, updated_at, user_id, badge_id, awarded_by_id, reason, badge_name)
+                VALUES ($1, $2, $2, $2, $3, $4, $5, $6, $7) RETURNING *",
+            label,
+            now,
+            user_id,
+            badge.id,
+            awarded_by_id,
+            reason,
+            badge.title,
+        )
+        .fetch_one(client)
+        .await?)
+    }
+
+    pub async fn get_id(client: &mut Client, id: i32) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(
+            query_as!(BadgeAward, "SELECT * FROM badge_awards WHERE id = $1", id)
+                .fetch_optional(client)
+                .await?,
+        )
+    }
+
+    /// Returns the awards of the badge, newest first
+    pub async fn get_for_badge(
+        client: &mut Client,
+        badge_id: i32,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            BadgeAward,
+            "SELECT * FROM badge_awards WHERE badge_id = $1 ORDER BY awarded_on DESC, id DESC",
+            badge_id
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Revokes the award
+    pub async fn delete(self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        query!("DELETE FROM badge_awards WHERE id = $1", self.id)
+            .execute(client)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Badge, BadgeAward, Client, PhilomenaModelError, User};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_award_badge() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let artist = Badge::create(&mut client, "Artist", "Verified artist", None, false).await?;
+        let staff = Badge::create(&mut client, "Staff", "Site staff", None, true).await?;
+        assert!(user.badges(&mut client).await?.is_empty());
+
+        BadgeAward::award(&mut client, &artist, user.id, user.id, None, None).await?;
+        BadgeAward::award(
+            &mut client,
+            &artist,
+            user.id,
+            user.id,
+            Some("Artist of the month".to_string()),
+            Some("Most favourites in October".to_string()),
+        )
+        .await?;
+        let award = BadgeAward::award(&mut client, &staff, user.id, user.id, None, None).await?;
+        assert_eq!(Some("Staff".to_string()), award.badge_name);
+
+        // badges awarded twice are listed once, priority badges first
+        let badges: Vec<i32> = user
+            .badges(&mut client)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(vec![staff.id, artist.id], badges);
+        assert_eq!(3, user.badge_awards(&mut client).await?.len());
+        assert_eq!(2, artist.award_count(&mut client).await?);
+
+        award.delete(&mut client).await?;
+        assert_eq!(1, user.badges(&mut client).await?.len());
+        assert!(BadgeAward::get_for_badge(&mut client, staff.id)
+            .await?
+            .is_empty());
+        Ok(())
+    }
+}
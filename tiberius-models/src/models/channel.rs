@@ -2,7 +2,7 @@ use std::{io::Write, ops::DerefMut, str::FromStr};
 
 use crate::{Client, PhilomenaModelError, Tag};
 use itertools::Itertools;
-use sqlx::{query_as, Postgres};
+use sqlx::{query, query_as, Postgres};
 use tiberius_dependencies::chrono::NaiveDateTime;
 use tracing::trace;
 
@@ -31,8 +31,10 @@ pub struct Channel {
     pub viewer_minutes_thismonth: i32,
     pub total_viewer_minutes: i32,
     pub banner_image: Option<String>,
-    pub remote_stream_id: Option<i32>,
+    pub remote_stream_id: Option<String>,
     pub thumbnail_url: Option<String>,
+    /// User who registered the channel and may edit or delete it
+    pub user_id: Option<i32>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, sqlx::Type)]
@@ -78,7 +80,7 @@ impl Channel {
                 last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
                 created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
                 viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
-                banner_image, remote_Stream_id, thumbnail_url
+                banner_image, remote_Stream_id, thumbnail_url, user_id
                 FROM channels WHERE nsfw = false AND last_fetched_at is not null ORDER BY short_name"
         )
         .fetch_all(client.db().await?.deref_mut())
@@ -99,7 +101,7 @@ impl Channel {
                     last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
                     created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
                     viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
-                    banner_image, remote_Stream_id, thumbnail_url FROM channels WHERE type = $1 ORDER BY short_name",
+                    banner_image, remote_Stream_id, thumbnail_url, user_id FROM channels WHERE type = $1 ORDER BY short_name",
                     channel_type
                 )
                 .fetch_all(client.db().await?.deref_mut())
@@ -113,7 +115,7 @@ impl Channel {
                 last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
                 created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
                 viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
-                banner_image, remote_Stream_id, thumbnail_url FROM channels ORDER BY short_name",
+                banner_image, remote_Stream_id, thumbnail_url, user_id FROM channels ORDER BY short_name",
                 )
                 .fetch_all(client.db().await?.deref_mut())
                 .await?
@@ -139,6 +141,10 @@ impl Channel {
         Ok(count)
     }
 
+    /// Stores the channel
+    ///
+    /// Viewer minutes and watchers are not written here, they are maintained atomically by
+    /// [Channel::add_viewer_minutes], [Channel::subscribe] and [Channel::unsubscribe].
     pub async fn update(&self, client: &mut Client) -> Result<(), PhilomenaModelError> {
         trace!("updating channel {}", self.id);
         query_as!(
@@ -155,17 +161,12 @@ impl Channel {
                     is_live = $9,
                     last_fetched_at = $10,
                     last_live_at = $11,
-                    watcher_ids = $12,
-                    watcher_count = $13,
-                    type = $14,
-                    associated_artist_tag_id = $15,
-                    viewer_minutes_today = $16,
-                    viewer_minutes_thisweek = $17,
-                    viewer_minutes_thismonth = $18,
-                    total_viewer_minutes = $19,
-                    banner_image = $20,
-                    remote_stream_id = $21,
-                    updated_at = $22
+                    type = $12,
+                    associated_artist_tag_id = $13,
+                    banner_image = $14,
+                    remote_stream_id = $15,
+                    updated_at = $16,
+                    thumbnail_url = $17
                 WHERE id = $1"#,
             self.id,
             self.short_name,
@@ -178,22 +179,223 @@ impl Channel {
             self.is_live,
             self.last_fetched_at,
             self.last_live_at,
-            &self.watcher_ids,
-            self.watcher_count,
             self.r#type.to_string(),
             self.associated_artist_tag_id,
-            self.viewer_minutes_today,
-            self.viewer_minutes_thisweek,
-            self.viewer_minutes_thismonth,
-            self.total_viewer_minutes,
             self.banner_image,
             self.remote_stream_id,
-            tiberius_dependencies::chrono::Utc::now().naive_utc()
+            tiberius_dependencies::chrono::Utc::now().naive_utc(),
+            self.thumbnail_url,
         )
         .execute(client.db().await?.deref_mut())
         .await?;
         Ok(())
     }
+    pub async fn get_by_id(
+        client: &mut Client,
+        id: i32,
+    ) -> Result<Option<Channel>, PhilomenaModelError> {
+        Ok(query_as!(
+            Channel,
+            "SELECT id, short_name, title, description, channel_image,
+                tags, viewers, nsfw, is_live, last_fetched_at, next_check_at,
+                last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
+                created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
+                viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
+                banner_image, remote_Stream_id, thumbnail_url, user_id FROM channels WHERE id = $1",
+            id
+        )
+        .fetch_optional(client.db().await?.deref_mut())
+        .await?)
+    }
+
+    /// Registers a new channel, the title and images are filled in by the next refresh
+    pub async fn create(
+        client: &mut Client,
+        r#type: ChannelType,
+        short_name: &str,
+        description: Option<String>,
+        associated_artist_tag_id: Option<i32>,
+        nsfw: bool,
+        user_id: Option<i32>,
+    ) -> Result<Channel, PhilomenaModelError> {
+        trace!("creating {:?} channel {}", r#type, short_name);
+        let now = tiberius_dependencies::chrono::Utc::now().naive_utc();
+        Ok(query_as!(
+            Channel,
+            "INSERT INTO channels (short_name, title, description, type, associated_artist_tag_id, nsfw, created_at, updated_at, user_id)
+                VALUES ($1, '', $2, $3, $4, $5, $6, $6, $7)
+                RETURNING id, short_name, title, description, channel_image,
+                tags, viewers, nsfw, is_live, last_fetched_at, next_check_at,
+                last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
+                created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
+                viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
+                banner_image, remote_Stream_id, thumbnail_url, user_id",
+            short_name,
+            description,
+            r#type.to_string(),
+            associated_artist_tag_id,
+            nsfw,
+            now,
+            user_id,
+        )
+        .fetch_one(client.db().await?.deref_mut())
+        .await?)
+    }
+
+    /// Deletes the channel, subscriptions are removed with it
+    pub async fn delete(self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        trace!("deleting channel {}", self.id);
+        query!("DELETE FROM channels WHERE id = $1", self.id)
+            .execute(client.db().await?.deref_mut())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn is_subscribed(
+        &self,
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT 1 AS one FROM channel_subscriptions WHERE channel_id = $1 AND user_id = $2",
+            self.id,
+            user_id
+        )
+        .fetch_optional(client.db().await?.deref_mut())
+        .await?
+        .is_some())
+    }
+
+    pub async fn subscriber_ids(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<i32>, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT user_id FROM channel_subscriptions WHERE channel_id = $1",
+            self.id
+        )
+        .fetch_all(client.db().await?.deref_mut())
+        .await?
+        .into_iter()
+        .map(|x| x.user_id)
+        .collect())
+    }
+
+    /// Subscribes the user to live notifications of this channel
+    ///
+    /// The `watcher_ids` and `watcher_count` of the channel are kept in sync with the subscriptions.
+    pub async fn subscribe(
+        &mut self,
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            "INSERT INTO channel_subscriptions (channel_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            self.id,
+            user_id
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?;
+        self.sync_watchers(client).await
+    }
+
+    pub async fn unsubscribe(
+        &mut self,
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            "DELETE FROM channel_subscriptions WHERE channel_id = $1 AND user_id = $2",
+            self.id,
+            user_id
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?;
+        self.sync_watchers(client).await
+    }
+
+    async fn sync_watchers(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        let watchers = query!(
+            r#"UPDATE channels SET
+                watcher_ids = ARRAY(SELECT user_id FROM channel_subscriptions WHERE channel_id = $1 ORDER BY user_id),
+                watcher_count = (SELECT COUNT(*) FROM channel_subscriptions WHERE channel_id = $1)
+            WHERE id = $1
+            RETURNING watcher_ids, watcher_count"#,
+            self.id
+        )
+        .fetch_one(client.db().await?.deref_mut())
+        .await?;
+        self.watcher_ids = watchers.watcher_ids;
+        self.watcher_count = watchers.watcher_count;
+        Ok(())
+    }
+
+    /// Adds viewer minutes to the daily, weekly, monthly and total counters
+    pub async fn add_viewer_minutes(
+        &mut self,
+        client: &mut Client,
+        minutes: i32,
+    ) -> Result<(), PhilomenaModelError> {
+        let counters = query!(
+            "UPDATE channels SET
+                viewer_minutes_today = viewer_minutes_today + $2,
+                viewer_minutes_thisweek = viewer_minutes_thisweek + $2,
+                viewer_minutes_thismonth = viewer_minutes_thismonth + $2,
+                total_viewer_minutes = total_viewer_minutes + $2
+            WHERE id = $1
+            RETURNING viewer_minutes_today, viewer_minutes_thisweek,
+                viewer_minutes_thismonth, total_viewer_minutes",
+            self.id,
+            minutes
+        )
+        .fetch_one(client.db().await?.deref_mut())
+        .await?;
+        self.viewer_minutes_today = counters.viewer_minutes_today;
+        self.viewer_minutes_thisweek = counters.viewer_minutes_thisweek;
+        self.viewer_minutes_thismonth = counters.viewer_minutes_thismonth;
+        self.total_viewer_minutes = counters.total_viewer_minutes;
+        Ok(())
+    }
+
+    /// Starts a new day of viewer minutes for all channels, and a new week or month if given
+    pub async fn reset_viewer_minutes(
+        client: &mut Client,
+        week: bool,
+        month: bool,
+    ) -> Result<u64, PhilomenaModelError> {
+        Ok(query!(
+            "UPDATE channels SET
+                viewer_minutes_today = 0,
+                viewer_minutes_thisweek = CASE WHEN $1 THEN 0 ELSE viewer_minutes_thisweek END,
+                viewer_minutes_thismonth = CASE WHEN $2 THEN 0 ELSE viewer_minutes_thismonth END",
+            week,
+            month
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?
+        .rows_affected())
+    }
+
+    /// The channels with the most viewer minutes of all time
+    pub async fn get_top_by_viewer_minutes(
+        client: &mut Client,
+        limit: i64,
+    ) -> Result<Vec<Channel>, PhilomenaModelError> {
+        Ok(query_as!(
+            Channel,
+            "SELECT id, short_name, title, description, channel_image,
+                tags, viewers, nsfw, is_live, last_fetched_at, next_check_at,
+                last_live_at, watcher_ids, watcher_count, type as \"type: ChannelType\",
+                created_at, updated_at, associated_artist_tag_id, viewer_minutes_today,
+                viewer_minutes_thisweek, viewer_minutes_thismonth, total_viewer_minutes,
+                banner_image, remote_Stream_id, thumbnail_url, user_id
+                FROM channels ORDER BY total_viewer_minutes DESC, short_name LIMIT $1",
+            limit
+        )
+        .fetch_all(client.db().await?.deref_mut())
+        .await?)
+    }
+
     pub fn title(&self) -> String {
         if self.title.is_empty() {
             self.short_name.clone()
@@ -201,12 +403,9 @@ impl Channel {
             self.title.clone()
         }
     }
-    pub fn image(&self) -> &String {
-        self.banner_image.as_ref().unwrap_or(
-            self.channel_image
-                .as_ref()
-                .expect("must have iether channel or banner"),
-        )
+    /// The banner or channel image, channels that were not refreshed yet have neither
+    pub fn image(&self) -> Option<&String> {
+        self.banner_image.as_ref().or(self.channel_image.as_ref())
     }
     pub async fn associated_artist_tag(
         &self,
@@ -251,3 +450,80 @@ impl Channel {
         Ok(cnt as u64)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{Channel, ChannelType, Client, Notification, PhilomenaModelError, User};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_channel_subscriptions() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let mut channel = Channel::create(
+            &mut client,
+            ChannelType::TwitchChannel,
+            "rarity",
+            None,
+            None,
+            false,
+            Some(user.id),
+        )
+        .await?;
+        assert_eq!(Some(user.id), channel.user_id);
+        assert!(channel.image().is_none());
+        assert!(!channel.is_subscribed(&mut client, user.id).await?);
+        let stale = Channel::get_by_id(&mut client, channel.id).await?.unwrap();
+
+        channel.subscribe(&mut client, user.id).await?;
+        channel.subscribe(&mut client, user.id).await?;
+        assert!(channel.is_subscribed(&mut client, user.id).await?);
+        assert_eq!(vec![user.id], channel.subscriber_ids(&mut client).await?);
+        assert_eq!(1, channel.watcher_count);
+        assert_eq!(vec![user.id], channel.watcher_ids);
+
+        let subscribers = channel.subscriber_ids(&mut client).await?;
+        Notification::notify(
+            &mut client,
+            "Channel",
+            channel.id,
+            "is now live",
+            &subscribers,
+        )
+        .await?;
+        let unread = Notification::unread_for_user(&mut client, user.id).await?;
+        assert_eq!(1, unread.len());
+        assert_eq!(channel.id, unread[0].actor_id);
+        assert_eq!(
+            1,
+            Notification::mark_read(&mut client, "Channel", user.id).await?
+        );
+        assert!(Notification::unread_for_user(&mut client, user.id)
+            .await?
+            .is_empty());
+
+        channel.add_viewer_minutes(&mut client, 90).await?;
+        channel.add_viewer_minutes(&mut client, 30).await?;
+        assert_eq!(120, channel.viewer_minutes_today);
+        assert_eq!(120, channel.total_viewer_minutes);
+        // updating an outdated copy keeps the counters
+        stale.update(&mut client).await?;
+        let updated = Channel::get_by_id(&mut client, channel.id).await?.unwrap();
+        assert_eq!(120, updated.total_viewer_minutes);
+        assert_eq!(vec![user.id], updated.watcher_ids);
+        Channel::reset_viewer_minutes(&mut client, true, false).await?;
+        let reset = Channel::get_by_id(&mut client, channel.id).await?.unwrap();
+        assert_eq!(0, reset.viewer_minutes_today);
+        assert_eq!(0, reset.viewer_minutes_thisweek);
+        assert_eq!(120, reset.viewer_minutes_thismonth);
+        assert_eq!(120, reset.total_viewer_minutes);
+
+        channel.unsubscribe(&mut client, user.id).await?;
+        assert_eq!(0, channel.watcher_count);
+        assert!(channel.subscriber_ids(&mut client).await?.is_empty());
+
+        let id = channel.id;
+        channel.delete(&mut client).await?;
+        assert!(Channel::get_by_id(&mut client, id).await?.is_none());
+        Ok(())
+    }
+}
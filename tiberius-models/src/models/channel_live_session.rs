@@ -0,0 +1,127 @@
+use std::ops::DerefMut;
+
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::{Channel, Client, PhilomenaModelError};
+
+/// A period during which a channel was seen live by the refresh job
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct ChannelLiveSession {
+    pub id: i64,
+    pub channel_id: i32,
+    /// The last title seen during the session
+    pub title: String,
+    pub started_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    /// Not set while the channel is still live
+    pub ended_at: Option<NaiveDateTime>,
+    pub peak_viewers: i32,
+    pub viewer_minutes: i64,
+}
+
+impl ChannelLiveSession {
+    /// Records that the channel is live, continuing its open session or starting a new one
+    pub async fn record_live(
+        client: &mut Client,
+        channel: &Channel,
+        viewer_minutes: i32,
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        Ok(query_as!(
+            ChannelLiveSession,
+            "INSERT INTO channel_live_sessions (channel_id, title, started_at, last_seen_at, peak_viewers, viewer_minutes)
+                VALUES ($1, $2, $3, $3, $4, $5)
+                ON CONFLICT (channel_id) WHERE ended_at IS NULL DO UPDATE SET
+                    title = excluded.title,
+                    last_seen_at = excluded.last_seen_at,
+                    peak_viewers = GREATEST(channel_live_sessions.peak_viewers, excluded.peak_viewers),
+                    viewer_minutes = channel_live_sessions.viewer_minutes + excluded.viewer_minutes
+                RETURNING *",
+            channel.id,
+            channel.title,
+            now,
+            channel.viewers,
+            viewer_minutes as i64,
+        )
+        .fetch_one(client.db().await?.deref_mut())
+        .await?)
+    }
+
+    /// Ends the open session of the channel at the time it was last seen live
+    ///
+    /// Returns false if the channel had no open session.
+    pub async fn end(client: &mut Client, channel_id: i32) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "UPDATE channel_live_sessions SET ended_at = last_seen_at
+                WHERE channel_id = $1 AND ended_at IS NULL",
+            channel_id
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// The latest sessions of the channel, newest first
+    pub async fn get_for_channel(
+        client: &mut Client,
+        channel_id: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            ChannelLiveSession,
+            "SELECT * FROM channel_live_sessions WHERE channel_id = $1
+                ORDER BY started_at DESC LIMIT $2",
+            channel_id,
+            limit
+        )
+        .fetch_all(client.db().await?.deref_mut())
+        .await?)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.ended_at.unwrap_or(self.last_seen_at) - self.started_at
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Channel, ChannelLiveSession, ChannelType, Client, PhilomenaModelError};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_channel_live_sessions() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let mut channel = Channel::create(
+            &mut client,
+            ChannelType::PicartoChannel,
+            "fluttershy",
+            None,
+            None,
+            false,
+            None,
+        )
+        .await?;
+        assert!(!ChannelLiveSession::end(&mut client, channel.id).await?);
+
+        channel.is_live = true;
+        channel.viewers = 10;
+        let first = ChannelLiveSession::record_live(&mut client, &channel, 300).await?;
+        channel.viewers = 4;
+        channel.title = "Animal care".to_string();
+        let second = ChannelLiveSession::record_live(&mut client, &channel, 120).await?;
+        assert_eq!(first.id, second.id);
+        assert_eq!(10, second.peak_viewers);
+        assert_eq!(420, second.viewer_minutes);
+        assert_eq!("Animal care", second.title);
+        assert!(ChannelLiveSession::end(&mut client, channel.id).await?);
+
+        let third = ChannelLiveSession::record_live(&mut client, &channel, 0).await?;
+        assert_ne!(first.id, third.id);
+        let sessions = ChannelLiveSession::get_for_channel(&mut client, channel.id, 10).await?;
+        assert_eq!(2, sessions.len());
+        assert_eq!(third.id, sessions[0].id);
+        assert!(sessions[1].ended_at.is_some());
+        Ok(())
+    }
+}
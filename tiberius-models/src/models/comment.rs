@@ -47,6 +47,37 @@ impl Comment {
                 .await?,
         )
     }
+    /// Returns the newest comments on images that are neither hidden by the filter tags nor by the user
+    ///
+    /// If an author is given, only their comments that were not posted anonymously are returned.
+    pub async fn recent_visible(
+        client: &mut Client,
+        hidden_tag_ids: &[i32],
+        hiding_user_id: Option<i64>,
+        author_id: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<Comment>, PhilomenaModelError> {
+        let hidden_tag_ids: Vec<i64> = hidden_tag_ids.iter().map(|x| *x as i64).collect();
+        Ok(query_as!(
+            Comment,
+            r#"SELECT c.* FROM comments c
+                INNER JOIN images i ON i.id = c.image_id
+                WHERE NOT c.hidden_from_users
+                    AND NOT i.hidden_from_users
+                    AND i.deleted_by_id IS NULL
+                    AND NOT EXISTS (SELECT 1 FROM image_taggings t WHERE t.image_id = i.id AND t.tag_id = ANY($1))
+                    AND NOT EXISTS (SELECT 1 FROM image_hides h WHERE h.image_id = i.id AND h.user_id = $2)
+                    AND ($3::int4 IS NULL OR (c.user_id = $3 AND NOT COALESCE(c.anonymous, false)))
+                ORDER BY c.created_at DESC
+                LIMIT $4"#,
+            &hidden_tag_ids,
+            hiding_user_id,
+            author_id,
+            limit,
+        )
+        .fetch_all(client)
+        .await?)
+    }
 }
 
 impl Identifiable for &Comment {
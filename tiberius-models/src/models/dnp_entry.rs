@@ -0,0 +1,50 @@
+use sqlx::query_as;
+use tiberius_dependencies::chrono::NaiveDateTime;
+
+use crate::{Client, PhilomenaModelError, User};
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DnpEntry {
+    pub id: i32,
+    pub requesting_user_id: i32,
+    pub modifying_user_id: Option<i32>,
+    pub tag_id: i32,
+    pub aasm_state: String,
+    pub dnp_type: String,
+    pub conditions: String,
+    pub reason: String,
+    pub hide_reason: bool,
+    pub instructions: String,
+    pub feedback: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl DnpEntry {
+    /// Returns all listed DNP entries that apply to any of the given tags
+    pub async fn get_listed_for_tags(
+        client: &mut Client,
+        tag_ids: &[i32],
+    ) -> Result<Vec<DnpEntry>, PhilomenaModelError> {
+        Ok(query_as!(
+            DnpEntry,
+            "SELECT * FROM dnp_entries WHERE tag_id = ANY($1) AND aasm_state = 'listed'",
+            tag_ids
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Returns true if the entry forbids the given user from uploading content with the tag
+    ///
+    /// Only the artist who requested the entry may upload under "Artist Upload Only" and
+    /// "With Permission Only", other DNP types are informational and do not block uploads.
+    pub fn blocks_upload(&self, user: &User) -> bool {
+        match self.dnp_type.as_str() {
+            "Artist Upload Only" | "With Permission Only" => {
+                self.requesting_user_id != user.id
+            }
+            _ => false,
+        }
+    }
+}
@@ -4,7 +4,7 @@ use maud::{html, PreEscaped};
 use sqlx::query_as;
 use tiberius_dependencies::chrono::NaiveDateTime;
 
-use crate::{Client, PhilomenaModelError, User};
+use crate::{Client, Image, PhilomenaModelError, Tag, TagLike, User};
 
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct Filter {
@@ -64,4 +64,44 @@ impl Filter {
     pub fn name(&self) -> &str {
         self.name.as_ref()
     }
+    /// Returns search queries that exclude everything hidden by this filter
+    ///
+    /// Deleted and hidden images are always excluded, regardless of the filter.
+    pub async fn negative_queries(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<String>, PhilomenaModelError> {
+        let mut queries = vec!["deleted.eq:true".to_string(), "hidden.eq:true".to_string()];
+        let hidden_tags = self.hidden_tag_ids.iter().map(|x| *x as i64).collect();
+        queries.extend(
+            Tag::get_many(client, hidden_tags)
+                .await?
+                .into_iter()
+                .map(|x| x.full_name()),
+        );
+        if let Some(complex) = &self.hidden_complex_str {
+            if !complex.trim().is_empty() {
+                queries.push(complex.clone());
+            }
+        }
+        Ok(queries)
+    }
+    /// Returns true if any tag of the image is hidden by this filter
+    ///
+    /// Complex filters are not considered yet.
+    pub fn hides_image(&self, image: &Image) -> bool {
+        image
+            .tag_ids
+            .iter()
+            .any(|x| self.hidden_tag_ids.contains(x))
+    }
+    /// Returns true if any tag of the image is spoilered by this filter
+    ///
+    /// Complex filters are not considered yet.
+    pub fn spoilers_image(&self, image: &Image) -> bool {
+        image
+            .tag_ids
+            .iter()
+            .any(|x| self.spoilered_tag_ids.contains(x))
+    }
 }
@@ -738,13 +738,13 @@ impl Image {
                 image_size, image_format, image_mime_type, ip,
                 fingerprint, user_agent, referrer, anonymous,
                 source_url, description, tag_ids, is_animated,
-                created_at, updated_at, first_seen_at
+                created_at, updated_at, first_seen_at, user_id
              ) VALUES (
                 $1, $2, $3, $4,
                 $5, $6, $7, $8,
                 $9, $10, $11, $12,
                 $13, $14, $15, $16,
-                $17, $18, $19
+                $17, $18, $19, $20
             ) RETURNING id",
             self.image,
             self.image_name,
@@ -765,6 +765,7 @@ impl Image {
             self.created_at,
             self.updated_at,
             self.first_seen_at,
+            self.user_id,
         )
         .fetch_one(&mut client.clone())
         .await?;
@@ -948,6 +949,22 @@ impl Image {
         .fetch_optional(client)
         .await?)
     }
+    /// Counts the images the user has uploaded since the given point in time
+    pub async fn count_uploads_since(
+        client: &mut Client,
+        user_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<u64, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT COUNT(*) AS cnt FROM images WHERE user_id = $1 AND created_at > $2",
+            user_id as i32,
+            since
+        )
+        .fetch_one(client)
+        .await?
+        .cnt
+        .unwrap_or_default() as u64)
+    }
     pub async fn get_newest(client: &mut Client) -> Result<Option<Self>, PhilomenaModelError> {
         let id = query!("SELECT id FROM images ORDER BY created_at DESC LIMIT 1",)
             .fetch_one(&mut *client)
@@ -980,13 +997,7 @@ impl Image {
     }
 
     pub async fn get_featured(client: &mut Client) -> Result<Option<Self>, PhilomenaModelError> {
-        let feature: Option<ImageFeature> = sqlx::query_as!(
-            ImageFeature,
-            "SELECT * FROM image_features ORDER BY created_at DESC LIMIT 1"
-        )
-        .fetch_optional(&mut client.clone())
-        .await?;
-        if let Some(feature) = feature {
+        if let Some(feature) = ImageFeature::latest(client).await? {
             Ok(Self::get_id(client, feature.image_id).await?)
         } else {
             Ok(None)
@@ -1023,6 +1034,23 @@ impl Image {
         };
         Ok((total as u64, Self::get_many(client, ids, sort_by).await?))
     }
+    /// Returns true if the image may be shown to regular users, ie it is neither hidden, deleted
+    /// nor had its content destroyed
+    pub fn publicly_visible(&self) -> bool {
+        !self.hidden_from_users && self.deleted_by_id.is_none() && !self.destroyed_content
+    }
+    /// Returns the IDs of all users that have favourited this image
+    pub async fn faved_by(&self, client: &mut Client) -> Result<Vec<i64>, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT user_id FROM image_faves WHERE image_id = $1",
+            self.id as i64
+        )
+        .fetch_all(client)
+        .await?
+        .into_iter()
+        .map(|x| x.user_id)
+        .collect())
+    }
     pub fn hidden(&self, _client: &mut Client) -> Result<bool, PhilomenaModelError> {
         //TODO: check if hidden properly, trust staff for now
         Ok(false)
@@ -1320,6 +1348,9 @@ impl Queryable for Image {
         tantivy_text_field!(builder, description);
         tantivy_bool_text_field!(builder, processed);
         tantivy_bool_text_field!(builder, deleted);
+        tantivy_bool_text_field!(builder, hidden);
+        tantivy_i64_field!(builder, uploader_id);
+        tantivy_i64_field!(builder, faved_by);
         builder.build()
     }
 
@@ -1368,6 +1399,18 @@ impl Queryable for Image {
             schema.get_field("deleted").unwrap(),
             self.deleted_by_id.is_some().to_string(),
         );
+        doc.add_text(
+            schema.get_field("hidden").unwrap(),
+            (!self.publicly_visible()).to_string(),
+        );
+        // anonymous uploads must not be found by searching for the uploader
+        if let (Some(user_id), false) = (self.user_id, self.anonymous.unwrap_or(false)) {
+            doc.add_i64(schema.get_field("uploader_id").unwrap(), user_id as i64);
+        }
+        let faved_by_field = schema.get_field("faved_by").unwrap();
+        for user_id in self.faved_by(client).await? {
+            doc.add_i64(faved_by_field, user_id);
+        }
         let tag_field = schema.get_field("tag").unwrap();
         for tag in self.tags(client).await? {
             doc.add_text(tag_field, tag.full_name());
@@ -1685,6 +1728,47 @@ mod test {
     use super::*;
     use tiberius_dependencies::chrono::TimeZone;
 
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_anonymous_uploader_not_indexed() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let image = Image::new_test_image(&mut client).await?;
+        let named = Image {
+            user_id: Some(5),
+            ..image.clone()
+        };
+        let anonymous = Image {
+            id: image.id + 1,
+            user_id: Some(5),
+            anonymous: Some(true),
+            ..image
+        };
+        let index = tantivy::Index::create_in_ram(Image::schema());
+        let mut writer = index.writer(15_000_000).unwrap();
+        writer
+            .add_document(named.get_doc(&mut client, false).await?)
+            .unwrap();
+        writer
+            .add_document(anonymous.get_doc(&mut client, false).await?)
+            .unwrap();
+        writer.commit().unwrap();
+        let (total, ids) = Image::search_item_with_str(
+            &index.reader().unwrap(),
+            "uploader_id.eq:5",
+            Vec::<String>::new(),
+            Vec::<String>::new(),
+            10,
+            0,
+            ImageSortBy::CreatedAt(SortDirection::Descending),
+        )
+        .unwrap();
+        assert_eq!(1, total);
+        assert_eq!(
+            vec![named.id as u64],
+            ids.into_iter().map(|x| x.1).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
     /// Test Philo Compat based on image 4020561 image file
     #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
     async fn test_long_filename_ex4020561() -> Result<(), PhilomenaModelError> {
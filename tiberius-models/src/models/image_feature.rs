@@ -1,10 +1,180 @@
-use tiberius_dependencies::chrono::NaiveDateTime;
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Client, Image, Notification, PhilomenaModelError};
+
+/// Actor type of the notification sent to the uploader of a featured image
+///
+/// Notifications are unique per actor, so this must differ from the "Image" actor type used for
+/// comment notifications of the image.
+pub const IMAGE_FEATURE_NOTIFICATION_ACTOR: &str = "ImageFeature";
 
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct ImageFeature {
     pub id: i64,
     pub image_id: i64,
-    pub user_id: i64,
+    /// The staff member who featured the image, not set if the rotation job picked it
+    pub user_id: Option<i64>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
+
+impl ImageFeature {
+    /// Features the image on the front page and notifies the uploader
+    pub async fn feature(
+        client: &mut Client,
+        image: &mut Image,
+        user_id: Option<i64>,
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        let feature = query_as!(
+            ImageFeature,
+            "INSERT INTO image_features (image_id, user_id, created_at, updated_at)
+                VALUES ($1, $2, $3, $3) RETURNING *",
+            image.id as i64,
+            user_id,
+            now,
+        )
+        .fetch_one(&mut *client)
+        .await?;
+        query!(
+            "UPDATE images SET featured_on = $2 WHERE id = $1",
+            image.id,
+            now
+        )
+        .execute(&mut *client)
+        .await?;
+        image.featured_on = Some(now);
+        if let Some(uploader) = image.user_id {
+            Notification::notify(
+                client,
+                IMAGE_FEATURE_NOTIFICATION_ACTOR,
+                image.id,
+                "was featured on the front page",
+                &[uploader],
+            )
+            .await?;
+        }
+        Ok(feature)
+    }
+
+    /// Returns the current feature
+    pub async fn latest(client: &mut Client) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            ImageFeature,
+            "SELECT * FROM image_features ORDER BY created_at DESC LIMIT 1"
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    /// Returns the past features, newest first
+    pub async fn history(
+        client: &mut Client,
+        limit: i64,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            ImageFeature,
+            "SELECT * FROM image_features ORDER BY created_at DESC LIMIT $1",
+            limit
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Picks the best scoring safe image uploaded since the given time that was never featured
+    pub async fn rotation_candidate(
+        client: &mut Client,
+        uploaded_since: NaiveDateTime,
+    ) -> Result<Option<Image>, PhilomenaModelError> {
+        Ok(query_as!(
+            Image,
+            "SELECT * FROM images
+                WHERE featured_on IS NULL
+                    AND created_at >= $1
+                    AND processed
+                    AND NOT hidden_from_users
+                    AND deleted_by_id IS NULL
+                    AND duplicate_id IS NULL
+                    AND EXISTS (SELECT 1 FROM tags WHERE name = 'safe' AND id = ANY(images.tag_ids))
+                ORDER BY score DESC, created_at DESC
+                LIMIT 1",
+            uploaded_since
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    pub async fn image(&self, client: &mut Client) -> Result<Option<Image>, PhilomenaModelError> {
+        Image::get_id(client, self.image_id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::query;
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use crate::{
+        Client, Image, ImageFeature, Notification, PhilomenaModelError, Tag, User,
+        IMAGE_FEATURE_NOTIFICATION_ACTOR,
+    };
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_feature_image() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let uploader = User::new_test_user(&mut client).await?;
+        let mut image = Image::new_test_image(&mut client).await?;
+        let safe = Tag::create_for_test(&mut client, "safe").await?;
+        query!(
+            "UPDATE images SET user_id = $2, tag_ids = $3, processed = true, hidden_from_users = false
+                WHERE id = $1",
+            image.id,
+            uploader.id,
+            &[safe.id][..],
+        )
+        .execute(&mut client)
+        .await?;
+        image.user_id = Some(uploader.id);
+        assert!(ImageFeature::latest(&mut client).await?.is_none());
+
+        // the rotation picks safe images that were never featured
+        let since = Utc::now().naive_utc() - Duration::days(1);
+        let candidate = ImageFeature::rotation_candidate(&mut client, since).await?;
+        assert_eq!(Some(image.id), candidate.map(|x| x.id));
+
+        let feature = ImageFeature::feature(&mut client, &mut image, Some(1)).await?;
+        assert_eq!(image.id as i64, feature.image_id);
+        assert!(image.featured_on.is_some());
+        let featured = Image::get_featured(&mut client)
+            .await?
+            .expect("featured image not found");
+        assert_eq!(image.id, featured.id);
+        assert_eq!(Some(feature.created_at), featured.featured_on);
+
+        let notifications = Notification::unread_for_user(&mut client, uploader.id).await?;
+        assert_eq!(1, notifications.len());
+        assert_eq!(image.id, notifications[0].actor_id);
+        assert_eq!(
+            1,
+            Notification::mark_read_for_actor(
+                &mut client,
+                IMAGE_FEATURE_NOTIFICATION_ACTOR,
+                image.id,
+                uploader.id
+            )
+            .await?
+        );
+        assert!(Notification::unread_for_user(&mut client, uploader.id)
+            .await?
+            .is_empty());
+
+        // already featured images are not picked by the rotation
+        assert!(ImageFeature::rotation_candidate(&mut client, since)
+            .await?
+            .is_none());
+        ImageFeature::feature(&mut client, &mut image, None).await?;
+        assert_eq!(2, ImageFeature::history(&mut client, 10).await?.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use sqlx::query;
+
+use crate::{Client, PhilomenaModelError};
+
+/// A fave, hide or vote of a user on an image
+///
+/// This is the shape the frontend expects in `window.booru.interactions`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ImageInteraction {
+    pub image_id: i64,
+    pub user_id: i64,
+    /// One of "faved", "hidden" or "voted"
+    pub interaction_type: String,
+    /// "up" or "down" for votes, empty otherwise
+    pub value: String,
+}
+
+impl ImageInteraction {
+    /// Returns the faves, hides and votes of the user on the given images
+    pub async fn for_images(
+        client: &mut Client,
+        user_id: i64,
+        image_ids: &[i64],
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        if image_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(query!(
+            r#"SELECT image_id AS "image_id!", interaction_type AS "interaction_type!", value AS "value!" FROM (
+                SELECT image_id, 'faved' AS interaction_type, '' AS value
+                    FROM image_faves WHERE user_id = $1 AND image_id = ANY($2)
+                UNION ALL
+                SELECT image_id, 'hidden' AS interaction_type, '' AS value
+                    FROM image_hides WHERE user_id = $1 AND image_id = ANY($2)
+                UNION ALL
+                SELECT image_id, 'voted' AS interaction_type, CASE WHEN up THEN 'up' ELSE 'down' END AS value
+                    FROM image_votes WHERE user_id = $1 AND image_id = ANY($2)
+            ) AS interactions ORDER BY image_id, interaction_type"#,
+            user_id,
+            image_ids,
+        )
+        .fetch_all(client)
+        .await?
+        .into_iter()
+        .map(|x| ImageInteraction {
+            image_id: x.image_id,
+            user_id,
+            interaction_type: x.interaction_type,
+            value: x.value,
+        })
+        .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::query;
+    use tiberius_dependencies::chrono::Utc;
+
+    use crate::{Client, Image, ImageInteraction, PhilomenaModelError, User};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_image_interactions() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let faved = Image::new_test_image(&mut client).await?;
+        let voted = Image::new_test_image(&mut client).await?;
+        let other = Image::new_test_image(&mut client).await?;
+        let now = Utc::now().naive_utc();
+        query!(
+            "INSERT INTO image_faves (image_id, user_id, created_at) VALUES ($1, $2, $3)",
+            faved.id as i64,
+            user.id as i64,
+            now,
+        )
+        .execute(&mut client)
+        .await?;
+        query!(
+            "INSERT INTO image_votes (image_id, user_id, created_at, up) VALUES ($1, $2, $3, false), ($4, $2, $3, true)",
+            voted.id as i64,
+            user.id as i64,
+            now,
+            other.id as i64,
+        )
+        .execute(&mut client)
+        .await?;
+
+        assert!(
+            ImageInteraction::for_images(&mut client, user.id as i64, &[])
+                .await?
+                .is_empty()
+        );
+        let interactions = ImageInteraction::for_images(
+            &mut client,
+            user.id as i64,
+            &[faved.id as i64, voted.id as i64],
+        )
+        .await?;
+        let interactions = interactions
+            .into_iter()
+            .map(|x| (x.image_id, x.interaction_type, x.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (faved.id as i64, "faved".to_string(), "".to_string()),
+                (voted.id as i64, "voted".to_string(), "down".to_string()),
+            ],
+            interactions
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-use tiberius_dependencies::chrono::NaiveDateTime;
+use std::ops::DerefMut;
+
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
 
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct Notification {
@@ -12,3 +17,97 @@ pub struct Notification {
     pub actor_child_id: Option<i32>,
     pub actor_child_type: Option<String>,
 }
+
+impl Notification {
+    /// Notifies the given users about an action of the actor
+    ///
+    /// There is only one notification per actor, it is updated with the latest action and
+    /// marked as unread again for every notified user.
+    pub async fn notify(
+        client: &mut Client,
+        actor_type: &str,
+        actor_id: i32,
+        action: &str,
+        user_ids: &[i32],
+    ) -> Result<Option<Notification>, PhilomenaModelError> {
+        if user_ids.is_empty() {
+            return Ok(None);
+        }
+        let now = Utc::now().naive_utc();
+        let notification = query_as!(
+            Notification,
+            "INSERT INTO notifications (action, actor_id, actor_type, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $4)
+                ON CONFLICT (actor_id, actor_type) DO UPDATE
+                    SET action = excluded.action, updated_at = excluded.updated_at
+                RETURNING *",
+            action,
+            actor_id,
+            actor_type,
+            now,
+        )
+        .fetch_one(client.db().await?.deref_mut())
+        .await?;
+        query!(
+            "INSERT INTO unread_notifications (notification_id, user_id)
+                SELECT $1, user_id FROM UNNEST($2::integer[]) AS user_id
+                ON CONFLICT DO NOTHING",
+            notification.id,
+            user_ids,
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?;
+        Ok(Some(notification))
+    }
+
+    pub async fn unread_for_user(
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<Vec<Notification>, PhilomenaModelError> {
+        Ok(query_as!(
+            Notification,
+            "SELECT n.* FROM notifications n
+                INNER JOIN unread_notifications u ON u.notification_id = n.id
+                WHERE u.user_id = $1 ORDER BY n.updated_at DESC",
+            user_id
+        )
+        .fetch_all(client.db().await?.deref_mut())
+        .await?)
+    }
+
+    /// Marks the notifications of all actors of the given type as read for the user
+    pub async fn mark_read(
+        client: &mut Client,
+        actor_type: &str,
+        user_id: i32,
+    ) -> Result<u64, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM unread_notifications u USING notifications n
+                WHERE u.notification_id = n.id AND n.actor_type = $1 AND u.user_id = $2",
+            actor_type,
+            user_id
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?
+        .rows_affected())
+    }
+
+    /// Marks the notifications about a single object as read for the user
+    pub async fn mark_read_for_actor(
+        client: &mut Client,
+        actor_type: &str,
+        actor_id: i32,
+        user_id: i32,
+    ) -> Result<u64, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM unread_notifications u USING notifications n
+                WHERE u.notification_id = n.id AND n.actor_type = $1 AND n.actor_id = $2 AND u.user_id = $3",
+            actor_type,
+            actor_id,
+            user_id
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?
+        .rows_affected())
+    }
+}
@@ -0,0 +1,210 @@
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
+
+/// An outgoing mail waiting for delivery
+///
+/// The message is stored fully formatted, delivery is retried with an exponential backoff
+/// until it succeeds or [QueuedMail::MAX_ATTEMPTS] attempts have failed. Mails often carry
+/// account tokens, so the message of a mail that has been given up is discarded and the
+/// remaining record is purged after [QueuedMail::failed_retention].
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct QueuedMail {
+    pub id: i64,
+    pub envelope_from: Option<String>,
+    pub envelope_to: Vec<String>,
+    /// The RFC 5322 formatted message, empty once the delivery has been given up
+    pub message: Vec<u8>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub next_attempt_at: NaiveDateTime,
+    /// Set once the delivery has been given up
+    pub failed_at: Option<NaiveDateTime>,
+}
+
+impl QueuedMail {
+    pub const MAX_ATTEMPTS: i32 = 8;
+
+    /// Time after which a claimed mail is considered abandoned and will be delivered again
+    pub fn claim_timeout() -> Duration {
+        Duration::minutes(10)
+    }
+
+    /// Time for which mails that could not be delivered are kept for inspection
+    pub fn failed_retention() -> Duration {
+        Duration::days(30)
+    }
+
+    /// Delay before the next delivery attempt after the given number of failed attempts
+    pub fn backoff(attempts: i32) -> Duration {
+        Duration::minutes(1 << (attempts - 1).clamp(0, 8))
+    }
+
+    pub async fn enqueue(
+        client: &mut Client,
+        envelope_from: Option<&str>,
+        envelope_to: &[String],
+        message: &[u8],
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        Ok(query_as!(
+            QueuedMail,
+            "INSERT INTO mail_queue (envelope_from, envelope_to, message, created_at, next_attempt_at)
+             VALUES ($1, $2, $3, $4, $4) RETURNING *",
+            envelope_from,
+            envelope_to,
+            message,
+            now,
+        )
+        .fetch_one(client)
+        .await?)
+    }
+
+    /// Claims up to `limit` mails that are due for delivery
+    ///
+    /// Claimed mails are not returned again until the [QueuedMail::claim_timeout] has passed,
+    /// so several workers can deliver mail at the same time.
+    pub async fn claim_due(
+        client: &mut Client,
+        limit: i64,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        Ok(query_as!(
+            QueuedMail,
+            "UPDATE mail_queue SET next_attempt_at = $2
+             WHERE id IN (
+                SELECT id FROM mail_queue WHERE failed_at IS NULL AND next_attempt_at <= $1
+                ORDER BY next_attempt_at LIMIT $3 FOR UPDATE SKIP LOCKED
+             ) RETURNING *",
+            now,
+            now + Self::claim_timeout(),
+            limit,
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Removes the mail from the queue after it has been delivered
+    pub async fn delivered(self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        query!("DELETE FROM mail_queue WHERE id = $1", self.id)
+            .execute(client)
+            .await?;
+        Ok(())
+    }
+
+    /// Records a failed delivery attempt and schedules the next one
+    ///
+    /// Returns false if the delivery has been given up.
+    pub async fn delivery_failed(
+        &mut self,
+        client: &mut Client,
+        error: &str,
+    ) -> Result<bool, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        self.attempts += 1;
+        self.last_error = Some(error.to_string());
+        if self.attempts >= Self::MAX_ATTEMPTS {
+            self.failed_at = Some(now);
+            self.message = Vec::new();
+        } else {
+            self.next_attempt_at = now + Self::backoff(self.attempts);
+        }
+        query!(
+            "UPDATE mail_queue SET attempts = $2, last_error = $3, next_attempt_at = $4, failed_at = $5,
+                message = $6
+             WHERE id = $1",
+            self.id,
+            self.attempts,
+            self.last_error,
+            self.next_attempt_at,
+            self.failed_at,
+            self.message,
+        )
+        .execute(client)
+        .await?;
+        Ok(self.failed_at.is_none())
+    }
+
+    /// Deletes the mails that have been given up longer than [QueuedMail::failed_retention] ago
+    ///
+    /// Returns the number of deleted mails.
+    pub async fn purge_failed(client: &mut Client) -> Result<u64, PhilomenaModelError> {
+        let cutoff = Utc::now().naive_utc() - Self::failed_retention();
+        Ok(query!(
+            "DELETE FROM mail_queue WHERE failed_at IS NOT NULL AND failed_at < $1",
+            cutoff,
+        )
+        .execute(client)
+        .await?
+        .rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::query;
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use crate::{Client, PhilomenaModelError, QueuedMail};
+
+    #[test]
+    fn test_mail_backoff() {
+        assert_eq!(Duration::minutes(1), QueuedMail::backoff(1));
+        assert_eq!(Duration::minutes(2), QueuedMail::backoff(2));
+        assert_eq!(Duration::minutes(64), QueuedMail::backoff(7));
+        assert_eq!(Duration::minutes(256), QueuedMail::backoff(20));
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_mail_queue() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let to = vec!["user@example.com".to_string()];
+        QueuedMail::enqueue(&mut client, Some("noreply@example.com"), &to, b"first").await?;
+        QueuedMail::enqueue(&mut client, None, &to, b"second").await?;
+
+        let mut claimed = QueuedMail::claim_due(&mut client, 10).await?;
+        assert_eq!(2, claimed.len());
+        assert!(QueuedMail::claim_due(&mut client, 10).await?.is_empty());
+
+        let mut failed = claimed.pop().unwrap();
+        claimed.pop().unwrap().delivered(&mut client).await?;
+        assert!(
+            failed
+                .delivery_failed(&mut client, "connection refused")
+                .await?
+        );
+        assert_eq!(1, failed.attempts);
+        assert!(failed.next_attempt_at > Utc::now().naive_utc());
+
+        failed.attempts = QueuedMail::MAX_ATTEMPTS - 1;
+        assert!(
+            !failed
+                .delivery_failed(&mut client, "connection refused")
+                .await?
+        );
+        assert!(failed.failed_at.is_some());
+        assert!(failed.message.is_empty());
+        let stored = query!("SELECT message FROM mail_queue WHERE id = $1", failed.id)
+            .fetch_one(&mut client)
+            .await?;
+        assert!(
+            stored.message.is_empty(),
+            "failed mails must not keep their body"
+        );
+
+        // recently failed mails are kept, older ones are purged
+        assert_eq!(0, QueuedMail::purge_failed(&mut client).await?);
+        query!(
+            "UPDATE mail_queue SET failed_at = $2 WHERE id = $1",
+            failed.id,
+            Utc::now().naive_utc() - QueuedMail::failed_retention() - Duration::days(1),
+        )
+        .execute(&mut client)
+        .await?;
+        assert_eq!(1, QueuedMail::purge_failed(&mut client).await?);
+        assert_eq!(0, QueuedMail::purge_failed(&mut client).await?);
+        Ok(())
+    }
+}
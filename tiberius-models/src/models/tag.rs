@@ -279,6 +279,27 @@ impl Tag {
                 .await?,
         )
     }
+    /// Changes the descriptions shown on the tag page and in the tag tooltip
+    pub async fn set_description(
+        &mut self,
+        client: &mut Client,
+        description: Option<String>,
+        short_description: Option<String>,
+    ) -> Result<(), PhilomenaModelError> {
+        self.description = description;
+        self.short_description = short_description;
+        self.updated_at = Utc::now().naive_utc();
+        sqlx::query!(
+            "UPDATE tags SET description = $2, short_description = $3, updated_at = $4 WHERE id = $1",
+            self.id,
+            self.description,
+            self.short_description,
+            self.updated_at,
+        )
+        .execute(client.db().await?.deref_mut())
+        .await?;
+        Ok(())
+    }
     pub async fn get_by_name(
         client: &mut Client,
         namespace: Option<String>,
@@ -0,0 +1,86 @@
+use sqlx::query_as;
+use tiberius_dependencies::chrono::NaiveDateTime;
+
+use crate::{Client, PhilomenaModelError};
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct Topic {
+    pub id: i32,
+    pub title: String,
+    pub post_count: i32,
+    pub view_count: i32,
+    pub sticky: bool,
+    pub last_replied_to_at: Option<NaiveDateTime>,
+    pub locked_at: Option<NaiveDateTime>,
+    pub deletion_reason: Option<String>,
+    pub lock_reason: Option<String>,
+    pub slug: String,
+    pub anonymous: Option<bool>,
+    pub watcher_ids: Vec<i32>,
+    pub watcher_count: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub forum_id: i32,
+    pub user_id: Option<i32>,
+    pub deleted_by_id: Option<i32>,
+    pub locked_by_id: Option<i32>,
+    pub last_post_id: Option<i32>,
+    pub hidden_from_users: bool,
+}
+
+impl Topic {
+    /// Returns the most recently active topics of the forums open to everyone
+    pub async fn recent(
+        client: &mut Client,
+        limit: i64,
+    ) -> Result<Vec<Topic>, PhilomenaModelError> {
+        //TODO: include restricted forums for users with the matching role
+        Ok(query_as!(
+            Topic,
+            r#"SELECT t.* FROM topics t
+                INNER JOIN forums f ON f.id = t.forum_id
+                WHERE f.access_level = 'normal' AND NOT t.hidden_from_users
+                ORDER BY t.last_replied_to_at DESC NULLS LAST, t.created_at DESC
+                LIMIT $1"#,
+            limit,
+        )
+        .fetch_all(client)
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::query;
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use crate::{Client, PhilomenaModelError, Topic};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_recent_topics() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let now = Utc::now().naive_utc();
+        for (title, forum_id, hidden, replied) in [
+            ("Old news", 1, false, now - Duration::hours(2)),
+            ("Tag aliases", 2, false, now),
+            ("Staff only", 5, false, now),
+            ("Spam", 1, true, now),
+        ] {
+            query!(
+                "INSERT INTO topics (title, slug, forum_id, hidden_from_users, last_replied_to_at, created_at, updated_at)
+                    VALUES ($1, $1, $2, $3, $4, $4, $4)",
+                title,
+                forum_id,
+                hidden,
+                replied,
+            )
+            .execute(&mut client)
+            .await?;
+        }
+        let topics = Topic::recent(&mut client, 10).await?;
+        let titles: Vec<&str> = topics.iter().map(|x| x.title.as_str()).collect();
+        assert_eq!(vec!["Tag aliases", "Old news"], titles);
+        assert_eq!(1, Topic::recent(&mut client, 1).await?.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use sqlx::query;
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
+
+/// The check code of a captcha that was already submitted
+///
+/// Check codes are recorded until they expire, so each captcha can only be answered once even
+/// when several server instances verify captchas.
+pub struct UsedCaptchaCheck;
+
+impl UsedCaptchaCheck {
+    /// Marks the check code as used until it expires, expired check codes are purged first
+    ///
+    /// Returns false if the check code was used before.
+    pub async fn consume(
+        client: &mut Client,
+        id: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<bool, PhilomenaModelError> {
+        query!(
+            "DELETE FROM used_captcha_checks WHERE expires_at < $1",
+            Utc::now().naive_utc(),
+        )
+        .execute(&mut *client)
+        .await?;
+        let inserted = query!(
+            "INSERT INTO used_captcha_checks (id, expires_at) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
+            id,
+            expires_at,
+        )
+        .execute(client)
+        .await?
+        .rows_affected();
+        Ok(inserted == 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::query;
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use crate::{Client, PhilomenaModelError, UsedCaptchaCheck};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_used_captcha_check() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let expires_at = Utc::now().naive_utc() + Duration::minutes(10);
+        assert!(UsedCaptchaCheck::consume(&mut client, "first", expires_at).await?);
+        assert!(!UsedCaptchaCheck::consume(&mut client, "first", expires_at).await?);
+        assert!(UsedCaptchaCheck::consume(&mut client, "second", expires_at).await?);
+
+        // expired check codes are forgotten
+        let expired = Utc::now().naive_utc() - Duration::minutes(1);
+        assert!(UsedCaptchaCheck::consume(&mut client, "expired", expired).await?);
+        assert!(UsedCaptchaCheck::consume(&mut client, "third", expires_at).await?);
+        let remaining = query!("SELECT id FROM used_captcha_checks ORDER BY id")
+            .fetch_all(&mut client)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["first", "second", "third"], remaining);
+        Ok(())
+    }
+}
@@ -6,12 +6,11 @@ use either::Either;
 use maud::Markup;
 use sqlx::Executor;
 use sqlx::{query, query_as, types::ipnetwork::IpNetwork, PgPool};
-use tiberius_dependencies::base32;
 use tiberius_dependencies::base64;
 use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
 use tiberius_dependencies::{
     axum_sessions_auth::{Authentication, HasPermission},
-    hex, sentry, totp_rs,
+    hex, sentry,
 };
 use tracing::trace;
 
@@ -19,10 +18,12 @@ pub mod history;
 pub mod otp;
 pub mod settings;
 pub use history::UserHistory;
-pub use otp::OTPSecret;
+pub use otp::{OTPEncryptionKey, OTPSecret};
 pub use settings::UserSettings;
 
-use crate::{Badge, BadgeAward, Client, Filter, PhilomenaModelError, UserToken};
+use crate::{
+    Badge, BadgeAward, Client, Filter, PhilomenaModelError, UserToken, WebauthnCredential,
+};
 
 #[derive(
     sqlx::Type, Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default,
@@ -105,6 +106,48 @@ impl Default for User {
         }
     }
 }
+/// Number of backup codes generated when enabling two factor authentication
+pub const TOTP_BACKUP_CODES: usize = 10;
+/// Backup codes carry 64 bits of randomness, so they are hashed with a lower cost than passwords
+const BACKUP_CODE_COST: u32 = 10;
+/// Number of consecutive failed logins after which an account is locked
+pub const MAX_FAILED_LOGINS: i32 = 10;
+/// Minimum length of a new password
+pub const PASSWORD_MIN_LENGTH: usize = 12;
+/// BCrypt only considers the first 72 bytes of a password, the pepper included
+pub const PASSWORD_MAX_BYTES: usize = 72;
+
+/// Checks a new password against the password policy
+///
+/// The pepper is appended to the password before hashing, so it counts towards
+/// [PASSWORD_MAX_BYTES]. Returns the reason if the password is rejected, suitable for showing
+/// to the user.
+pub fn check_password_policy(
+    password: &str,
+    pepper: Option<&str>,
+    name: &str,
+    email: &str,
+) -> Result<(), &'static str> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err("Password must be at least 12 characters long");
+    }
+    if password.len() + pepper.map(str::len).unwrap_or(0) > PASSWORD_MAX_BYTES {
+        return Err("Password is too long");
+    }
+    let lower = password.to_lowercase();
+    let email_local = email.split('@').next().unwrap_or("").to_lowercase();
+    if (!name.is_empty() && lower.contains(&name.to_lowercase()))
+        || (!email_local.is_empty() && lower.contains(&email_local))
+    {
+        return Err("Password must not contain your username or email");
+    }
+    let distinct = password.chars().collect::<std::collections::BTreeSet<_>>();
+    if distinct.len() < 5 {
+        return Err("Password must not consist of only a few repeated characters");
+    }
+    Ok(())
+}
+
 pub enum UserLoginResult {
     // Password, User or TOTP invalid
     Invalid,
@@ -133,79 +176,131 @@ impl User {
             None => Either::Right(tiberius_common_html::no_avatar_svg()),
         }
     }
-    pub fn validate_login<S: Into<String>>(
+    /// Checks the first step of a login, the password
+    ///
+    /// If the user requires a second factor, it must be checked with [User::verify_second_factor]
+    /// before the login is complete.
+    pub fn validate_login(
         &self,
         pepper: Option<&str>,
-        otp_secret: &[u8],
         username: &str,
         password: &str,
-        totp: Option<S>,
     ) -> Result<UserLoginResult, PhilomenaModelError> {
-        let totp: Option<String> = totp.map(|x| x.into());
-        if totp.is_none() && self.otp_secret.otp_required_for_login == Some(true) {
-            return Ok(UserLoginResult::RetryWithTOTP);
-        }
         if username != self.name && username != self.email.as_ref() {
             // Sanity check this but we shouldn't ever hit this code point
             return Ok(UserLoginResult::Invalid);
         }
-        let password = format!("{}{}", password, pepper.unwrap_or(""));
-        let valid_pw =
-            bcrypt::verify(password, &self.encrypted_password).context("BCrypt Verify")?;
-
+        if !self.verify_password(pepper, password)? {
+            return Ok(UserLoginResult::Invalid);
+        }
         if self.otp_secret.otp_required_for_login() {
-            let dotp = self
-                .otp_secret
-                .decrypt_otp(otp_secret)
-                .context("TOTP decrypt")?;
-            if let Some(totp) = totp {
-                if let Some(dotp) = dotp {
-                    //debug!("TOTP secret = {:?}", String::from_utf8_lossy(&dotp));
-                    let dotp = base32::decode(
-                        base32::Alphabet::RFC4648 { padding: false },
-                        &String::from_utf8_lossy(&dotp),
-                    );
-                    let dotp = match dotp {
-                        None => {
-                            return Err(PhilomenaModelError::Context(anyhow::format_err!(
-                                "Decode failure on TOTP secret"
-                            )))
-                        }
-                        Some(v) => v,
-                    };
-                    //assert!(dotp.len() >= 120 / 8, "TOTP Secret Insufficient Size");
-                    let time = tiberius_dependencies::chrono::Utc::now().timestamp();
-                    assert!(time > 0, "We don't run before 1970");
-                    let time = time as u64;
-                    use totp_rs::{Algorithm, TOTP};
-                    let totpi = TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, dotp);
-                    if totpi.check(&totp, time) {
-                        Ok(UserLoginResult::Valid)
-                    } else {
+            Ok(UserLoginResult::RetryWithTOTP)
+        } else {
+            Ok(UserLoginResult::Valid)
+        }
+    }
+
+    /// Checks the second step of a login, either a TOTP code or one of the backup codes
+    ///
+    /// Successfully used codes are consumed, a TOTP code cannot be used twice and neither
+    /// can a TOTP code older than the last one used.
+    pub async fn verify_second_factor(
+        &mut self,
+        client: &mut Client,
+        pepper: Option<&str>,
+        otp_secret: &[u8],
+        code: &str,
+    ) -> Result<bool, PhilomenaModelError> {
+        let code: String = code.chars().filter(|x| !x.is_whitespace()).collect();
+        if code.len() == 6 && code.chars().all(|x| x.is_ascii_digit()) {
+            let time = tiberius_dependencies::chrono::Utc::now().timestamp();
+            assert!(time > 0, "We don't run before 1970");
+            let timestep =
+                match self
+                    .otp_secret
+                    .matching_timestep(otp_secret, &code, time as u64)?
+                {
+                    Some(v) => v,
+                    None => {
                         debug!("Invalid TOTP, stopping login session");
-                        // TODO: retry with backup codes if not [0-9]{6} format
-                        Ok(UserLoginResult::Invalid)
+                        return Ok(false);
                     }
-                } else {
-                    // User has required TOTP but no TOTP setup, so reject the attempt
-                    Ok(UserLoginResult::Invalid)
-                }
+                };
+            // only accept the code if no later one has been used in the meantime
+            let consumed = query!(
+                r#"UPDATE users SET consumed_timestep = $2
+                WHERE id = $1 AND (consumed_timestep IS NULL OR consumed_timestep < $2)"#,
+                self.id,
+                timestep,
+            )
+            .execute(&mut *client)
+            .await?
+            .rows_affected()
+                == 1;
+            client.cache_users.invalidate(&self.id()).await;
+            if consumed {
+                self.otp_secret.consumed_timestep = Some(timestep);
             } else {
-                // User did not supply any TOTP
-                Ok(UserLoginResult::RetryWithTOTP)
+                debug!("TOTP replayed, stopping login session");
             }
+            Ok(consumed)
         } else {
-            Ok(UserLoginResult::Valid)
+            let codes = self.otp_secret.otp_backup_codes.clone().unwrap_or_default();
+            let peppered = format!("{}{}", code, pepper.unwrap_or(""));
+            let used = codes
+                .iter()
+                .find(|hash| bcrypt::verify(&peppered, hash).unwrap_or(false));
+            let used = match used {
+                Some(v) => v,
+                None => {
+                    debug!("Invalid backup code, stopping login session");
+                    return Ok(false);
+                }
+            };
+            let consumed = query!(
+                r#"UPDATE users SET otp_backup_codes = array_remove(otp_backup_codes, $2)
+                WHERE id = $1 AND $2 = ANY(otp_backup_codes)"#,
+                self.id,
+                used,
+            )
+            .execute(&mut *client)
+            .await?
+            .rows_affected()
+                == 1;
+            client.cache_users.invalidate(&self.id()).await;
+            if consumed {
+                self.otp_secret.otp_backup_codes =
+                    Some(codes.iter().filter(|x| *x != used).cloned().collect());
+            }
+            Ok(consumed)
         }
     }
+    /// Returns the badges awarded to the user, oldest first
     pub async fn badge_awards(
         &self,
-        _client: &mut Client,
+        client: &mut Client,
     ) -> Result<Vec<BadgeAward>, PhilomenaModelError> {
-        todo!("user awards")
+        Ok(query_as!(
+            BadgeAward,
+            "SELECT * FROM badge_awards WHERE user_id = $1 ORDER BY awarded_on, id",
+            self.id
+        )
+        .fetch_all(client)
+        .await?)
     }
-    pub async fn badges(&self, _client: &mut Client) -> Result<Vec<Badge>, PhilomenaModelError> {
-        todo!("user badges")
+    /// Returns the badges awarded to the user, priority badges first
+    ///
+    /// A badge awarded multiple times is only returned once.
+    pub async fn badges(&self, client: &mut Client) -> Result<Vec<Badge>, PhilomenaModelError> {
+        Ok(query_as!(
+            Badge,
+            "SELECT * FROM badges
+                WHERE id IN (SELECT badge_id FROM badge_awards WHERE user_id = $1)
+                ORDER BY priority DESC, title",
+            self.id
+        )
+        .fetch_all(client)
+        .await?)
     }
     pub async fn get_user_for_session<'a>(
         client: &mut Client,
@@ -347,11 +442,505 @@ impl User {
         }
     }
 
+    /// Hashes a password for storage in encrypted_password
+    pub fn hash_password(
+        pepper: Option<&str>,
+        password: &str,
+    ) -> Result<String, PhilomenaModelError> {
+        let password = format!("{}{}", password, pepper.unwrap_or(""));
+        Ok(bcrypt::hash(password, bcrypt::DEFAULT_COST)?)
+    }
+
+    /// Returns true if the user holds a staff role
+    pub fn is_staff(&self) -> bool {
+        matches!(self.role.as_str(), "admin" | "moderator" | "assistant")
+    }
+
+    /// Returns true if the user must provide a second factor when logging in
+    pub fn totp_enabled(&self) -> bool {
+        self.otp_secret.otp_required_for_login()
+    }
+
+    /// Returns true if the user has enabled TOTP or registered a passkey
+    pub async fn has_second_factor(
+        &self,
+        client: &mut Client,
+    ) -> Result<bool, PhilomenaModelError> {
+        if self.totp_enabled() {
+            return Ok(true);
+        }
+        WebauthnCredential::has_credentials(client, self.id()).await
+    }
+
+    /// Stores a new TOTP secret that is not yet required for logging in and returns it
+    ///
+    /// The secret only becomes active once a first code is verified with [User::enable_totp].
+    pub async fn begin_totp_enrollment(
+        &mut self,
+        client: &mut Client,
+        key: &OTPEncryptionKey,
+    ) -> Result<String, PhilomenaModelError> {
+        let secret = OTPSecret::generate_secret()?;
+        let mut otp_secret = OTPSecret::default();
+        otp_secret.set_secret(key, &secret)?;
+        query!(
+            r#"UPDATE users SET
+                encrypted_otp_secret = $2, encrypted_otp_secret_iv = $3, encrypted_otp_secret_salt = $4,
+                otp_required_for_login = false, consumed_timestep = NULL, otp_backup_codes = NULL
+            WHERE id = $1"#,
+            self.id,
+            otp_secret.encrypted_otp_secret,
+            otp_secret.encrypted_otp_secret_iv,
+            otp_secret.encrypted_otp_secret_salt,
+        )
+        .execute(&mut *client)
+        .await?;
+        self.otp_secret = otp_secret;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(secret)
+    }
+
+    /// Requires the pending TOTP secret for logging in, if the code matches it
+    ///
+    /// Returns the plaintext backup codes, which must be shown to the user once, or None
+    /// if the code was incorrect.
+    pub async fn enable_totp(
+        &mut self,
+        client: &mut Client,
+        pepper: Option<&str>,
+        key: &OTPEncryptionKey,
+        code: &str,
+    ) -> Result<Option<Vec<String>>, PhilomenaModelError> {
+        let code: String = code.chars().filter(|x| !x.is_whitespace()).collect();
+        let time = tiberius_dependencies::chrono::Utc::now().timestamp();
+        assert!(time > 0, "We don't run before 1970");
+        let timestep = match self
+            .otp_secret
+            .matching_timestep(key.as_ref(), &code, time as u64)?
+        {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let mut codes = Vec::with_capacity(TOTP_BACKUP_CODES);
+        let mut hashes = Vec::with_capacity(TOTP_BACKUP_CODES);
+        for _ in 0..TOTP_BACKUP_CODES {
+            let code: [u8; 8] = ring::rand::generate(&ring::rand::SystemRandom::new())?.expose();
+            let code = hex::encode(code);
+            hashes.push(bcrypt::hash(
+                format!("{}{}", code, pepper.unwrap_or("")),
+                BACKUP_CODE_COST,
+            )?);
+            codes.push(code);
+        }
+        query!(
+            r#"UPDATE users SET
+                otp_required_for_login = true, consumed_timestep = $2, otp_backup_codes = $3
+            WHERE id = $1"#,
+            self.id,
+            timestep,
+            &hashes[..],
+        )
+        .execute(&mut *client)
+        .await?;
+        self.otp_secret.otp_required_for_login = Some(true);
+        self.otp_secret.consumed_timestep = Some(timestep);
+        self.otp_secret.otp_backup_codes = Some(hashes);
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(Some(codes))
+    }
+
+    /// Removes the TOTP secret and backup codes of the user
+    pub async fn disable_totp(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        query!(
+            r#"UPDATE users SET
+                encrypted_otp_secret = NULL, encrypted_otp_secret_iv = NULL, encrypted_otp_secret_salt = NULL,
+                otp_required_for_login = false, consumed_timestep = NULL, otp_backup_codes = NULL
+            WHERE id = $1"#,
+            self.id,
+        )
+        .execute(&mut *client)
+        .await?;
+        self.otp_secret = OTPSecret::default();
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Checks the password alone, for confirming sensitive changes of a logged in user
+    pub fn verify_password(
+        &self,
+        pepper: Option<&str>,
+        password: &str,
+    ) -> Result<bool, PhilomenaModelError> {
+        let password = format!("{}{}", password, pepper.unwrap_or(""));
+        Ok(bcrypt::verify(password, &self.encrypted_password).context("BCrypt Verify")?)
+    }
+
+    /// Returns true if the name or its slug is already used by another account
+    ///
+    /// The account `except_user_id` is ignored, so users can change the capitalization of their name.
+    pub async fn name_taken(
+        client: &mut Client,
+        name: &str,
+        except_user_id: Option<i32>,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT EXISTS(
+                SELECT 1 FROM users WHERE (LOWER(name) = LOWER($1) OR slug = $2) AND id IS DISTINCT FROM $3
+            ) AS \"taken!\"",
+            name,
+            crate::slug::sluggify(name),
+            except_user_id,
+        )
+        .fetch_one(client)
+        .await?
+        .taken)
+    }
+
+    /// Renames the user and records the previous name
+    pub async fn change_name(
+        &mut self,
+        client: &mut Client,
+        name: &str,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        let slug = crate::slug::sluggify(name);
+        query!(
+            "WITH change AS (
+                INSERT INTO user_name_changes (user_id, name, created_at, updated_at) VALUES ($2, $3, $6, $6)
+            )
+            UPDATE users SET name = $4, slug = $5, updated_at = $6 WHERE id = $1",
+            self.id,
+            self.id as i64,
+            self.name,
+            name,
+            slug,
+            now,
+        )
+        .execute(&mut *client)
+        .await?;
+        self.name = name.to_string();
+        self.slug = slug;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Creates a new, unconfirmed user account
+    pub async fn create(
+        client: &mut Client,
+        name: &str,
+        email: &str,
+        encrypted_password: &str,
+    ) -> Result<User, PhilomenaModelError> {
+        let mut authentication_token = [0u8; 32];
+        ring::rand::SecureRandom::fill(
+            &ring::rand::SystemRandom::new(),
+            &mut authentication_token,
+        )?;
+        let now = Utc::now().naive_utc();
+        let id = query!(
+            r#"INSERT INTO users (
+                email, encrypted_password, name, slug, created_at, updated_at,
+                authentication_token, role
+            ) VALUES ($1, $2, $3, $4, $5, $5, $6, 'user') RETURNING id"#,
+            email.to_lowercase() as _,
+            encrypted_password,
+            name,
+            crate::slug::sluggify(name),
+            now,
+            base64::encode(authentication_token),
+        )
+        .fetch_one(&mut *client)
+        .await?
+        .id;
+        Self::get_id(client, id as i64)
+            .await?
+            .ok_or_else(|| PhilomenaModelError::Other("created user could not be read".to_string()))
+    }
+
+    /// Returns true once the user has confirmed their email address
+    pub fn confirmed(&self) -> bool {
+        self.user_history.confirmed_at.is_some()
+    }
+
+    /// Marks the email address of the user as confirmed
+    pub async fn confirm(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "UPDATE users SET confirmed_at = $2, updated_at = $2 WHERE id = $1",
+            self.id,
+            now
+        )
+        .execute(&mut *client)
+        .await?;
+        self.user_history.confirmed_at = Some(now);
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Returns true if logins from the address have been locked after too many failed attempts
+    ///
+    /// Failed logins are counted per address, so others cannot lock the owner out of the account.
+    pub async fn locked_from(
+        &self,
+        client: &mut Client,
+        ip: IpNetwork,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT 1 AS locked FROM user_login_failures
+                WHERE user_id = $1 AND ip = $2 AND locked_at IS NOT NULL",
+            self.id,
+            ip,
+        )
+        .fetch_optional(client)
+        .await?
+        .is_some())
+    }
+
+    /// Returns true if a session that logged in at the given time is still valid
+    pub fn session_valid(&self, logged_in_at: NaiveDateTime) -> bool {
+        match self.user_history.sessions_valid_since {
+            Some(since) => logged_in_at >= since,
+            None => true,
+        }
+    }
+
+    /// Counts a failed login attempt from the address, locking the address out of the account
+    /// once [MAX_FAILED_LOGINS] is reached
+    ///
+    /// Returns true if this attempt locked the address out.
+    pub async fn record_failed_login(
+        &self,
+        client: &mut Client,
+        ip: IpNetwork,
+    ) -> Result<bool, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        let r = query!(
+            r#"INSERT INTO user_login_failures (user_id, ip, failed_attempts, locked_at, updated_at)
+                VALUES ($1, $2, 1, CASE WHEN 1 >= $3 THEN $4::timestamp END, $4)
+            ON CONFLICT (user_id, ip) DO UPDATE SET
+                failed_attempts = user_login_failures.failed_attempts + 1,
+                locked_at = CASE
+                    WHEN user_login_failures.locked_at IS NULL
+                        AND user_login_failures.failed_attempts + 1 >= $3 THEN $4
+                    ELSE user_login_failures.locked_at
+                END,
+                updated_at = $4
+            RETURNING COALESCE(locked_at = $4, false) AS "newly_locked!""#,
+            self.id,
+            ip,
+            MAX_FAILED_LOGINS,
+            now,
+        )
+        .fetch_one(client)
+        .await?;
+        Ok(r.newly_locked)
+    }
+
+    /// Forgets the failed logins from the address after the user logged in from it
+    pub async fn clear_failed_logins(
+        &self,
+        client: &mut Client,
+        ip: IpNetwork,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            "DELETE FROM user_login_failures WHERE user_id = $1 AND ip = $2",
+            self.id,
+            ip,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Clears the failed login counters and unlocks the account for all addresses
+    pub async fn unlock(&mut self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        query!(
+            "DELETE FROM user_login_failures WHERE user_id = $1",
+            self.id
+        )
+        .execute(&mut *client)
+        .await?;
+        query!(
+            "UPDATE users SET failed_attempts = 0, locked_at = NULL, unlock_token = NULL WHERE id = $1",
+            self.id
+        )
+        .execute(&mut *client)
+        .await?;
+        self.user_history.failed_attempts = Some(0);
+        self.user_history.locked_at = None;
+        self.unlock_token = None;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Invalidates all sessions of the user that logged in before now
+    pub async fn revoke_sessions(
+        &mut self,
+        client: &mut Client,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "UPDATE users SET sessions_valid_since = $2 WHERE id = $1",
+            self.id,
+            now
+        )
+        .execute(&mut *client)
+        .await?;
+        self.user_history.sessions_valid_since = Some(now);
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Replaces the password of the user
+    ///
+    /// All sessions are logged out and the account is unlocked, since setting a new password
+    /// requires either the old password or control over the email address.
+    pub async fn set_password(
+        &mut self,
+        client: &mut Client,
+        encrypted_password: String,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        query!(
+            r#"UPDATE users SET
+                encrypted_password = $2, sessions_valid_since = $3, updated_at = $3,
+                failed_attempts = 0, locked_at = NULL, unlock_token = NULL,
+                reset_password_token = NULL, reset_password_sent_at = NULL
+            WHERE id = $1"#,
+            self.id,
+            encrypted_password,
+            now,
+        )
+        .execute(&mut *client)
+        .await?;
+        query!(
+            "DELETE FROM user_login_failures WHERE user_id = $1",
+            self.id
+        )
+        .execute(&mut *client)
+        .await?;
+        self.encrypted_password = encrypted_password;
+        self.user_history.sessions_valid_since = Some(now);
+        self.user_history.failed_attempts = Some(0);
+        self.user_history.locked_at = None;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Replaces the email address of the user with a confirmed new address
+    pub async fn set_email(
+        &mut self,
+        client: &mut Client,
+        email: &str,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        let email = email.to_lowercase();
+        query!(
+            "UPDATE users SET email = $2, confirmed_at = $3, updated_at = $3 WHERE id = $1",
+            self.id,
+            email.clone() as _,
+            now,
+        )
+        .execute(&mut *client)
+        .await?;
+        self.email = email.into();
+        self.user_history.confirmed_at = Some(now);
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Saves the user editable settings, filter selection is managed by the filter pages
+    pub async fn update_settings(
+        &mut self,
+        client: &mut Client,
+        settings: UserSettings,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            r#"UPDATE users SET
+                spoiler_type = $2, theme = $3, images_per_page = $4,
+                show_large_thumbnails = $5, show_sidebar_and_watched_images = $6,
+                show_sidebar_trending_images = $7, show_sidebar_streams = $8,
+                show_sidebar_forum_activity = $9, show_sidebar_recent_comments = $10,
+                fancy_tag_field_on_upload = $11, fancy_tag_field_on_edit = $12,
+                fancy_tag_field_in_settings = $13, autorefresh_by_default = $14,
+                anonymous_by_default = $15, scale_large_images = $16,
+                comments_newest_first = $17, comments_always_jump_to_last = $18,
+                comments_per_page = $19, watch_on_reply = $20, watch_on_new_topic = $21,
+                watch_on_upload = $22, messages_newest_first = $23, serve_webm = $24,
+                no_spoilered_in_watched = $25, watched_images_query_str = $26,
+                watched_images_exclude_str = $27, watched_tag_ids = $28,
+                use_centered_layout = $29, show_hidden_items = $30, hide_vote_counts = $31,
+                hide_advertisements = $32, hide_default_role = $33, autoplay = $34,
+                updated_at = $35
+            WHERE id = $1"#,
+            self.id,
+            settings.spoiler_type,
+            settings.theme,
+            settings.images_per_page,
+            settings.show_large_thumbnails,
+            settings.show_sidebar_and_watched_images,
+            settings.show_sidebar_trending_images,
+            settings.show_sidebar_streams,
+            settings.show_sidebar_forum_activity,
+            settings.show_sidebar_recent_comments,
+            settings.fancy_tag_field_on_upload,
+            settings.fancy_tag_field_on_edit,
+            settings.fancy_tag_field_in_settings,
+            settings.autorefresh_by_default,
+            settings.anonymous_by_default,
+            settings.scale_large_images,
+            settings.comments_newest_first,
+            settings.comments_always_jump_to_last,
+            settings.comments_per_page,
+            settings.watch_on_reply,
+            settings.watch_on_new_topic,
+            settings.watch_on_upload,
+            settings.messages_newest_first,
+            settings.serve_webm,
+            settings.no_spoilered_in_watched,
+            settings.watched_images_query_str,
+            settings.watched_images_exclude_str,
+            &settings.watched_tag_ids,
+            settings.use_centered_layout,
+            settings.show_hidden_items,
+            settings.hide_vote_counts,
+            settings.hide_advertisements,
+            settings.hide_default_role,
+            settings.autoplay,
+            Utc::now().naive_utc(),
+        )
+        .execute(&mut *client)
+        .await?;
+        self.user_settings = settings;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
+    /// Sets the avatar, a path relative to the avatar directory of the data root
+    pub async fn set_avatar(
+        &mut self,
+        client: &mut Client,
+        avatar: Option<String>,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            "UPDATE users SET avatar = $2, updated_at = $3 WHERE id = $1",
+            self.id,
+            avatar,
+            Utc::now().naive_utc(),
+        )
+        .execute(&mut *client)
+        .await?;
+        self.avatar = avatar;
+        client.cache_users.invalidate(&self.id()).await;
+        Ok(())
+    }
+
     pub async fn get_by_email(
         client: &mut Client,
         email: &str,
     ) -> Result<Option<User>, PhilomenaModelError> {
-        let user = query!("SELECT id FROM users WHERE email::TEXT = $1", email)
+        // compare as citext so the lookup ignores the case of the address
+        let user = query!("SELECT id FROM users WHERE email = $1::TEXT::citext", email)
             .fetch_optional(client.db().await?.deref_mut())
             .await?;
         if let Some(user) = user {
@@ -485,6 +1074,206 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_password_policy() {
+        assert!(check_password_policy("short", None, "user", "user@example.com").is_err());
+        assert!(
+            check_password_policy("aaaaaaaaaaaaaaaa", None, "user", "user@example.com").is_err()
+        );
+        assert!(
+            check_password_policy("my name is SomeUser!", None, "someuser", "x@example.com")
+                .is_err()
+        );
+        assert!(
+            check_password_policy(&"a1b2c3".repeat(13), None, "user", "x@example.com").is_err()
+        );
+        assert!(
+            check_password_policy("correct horse battery", None, "user", "x@example.com").is_ok()
+        );
+        // the pepper counts towards the bcrypt length limit
+        let long = "a1b2c3d4".repeat(8);
+        assert!(check_password_policy(&long, None, "user", "x@example.com").is_ok());
+        assert!(
+            check_password_policy(&long, Some("pepperpepper"), "user", "x@example.com").is_err()
+        );
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_register_and_confirm() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let hash = User::hash_password(Some("pepper"), "correct horse battery")?;
+        let mut user = User::create(&mut client, "New User", "New@Example.com", &hash).await?;
+        assert_eq!("new+user", user.slug);
+        assert_eq!("new@example.com", user.email.as_ref());
+        assert!(!user.confirmed());
+        assert!(User::name_taken(&mut client, "new user").await?);
+
+        let token = UserToken::issue(
+            &mut client,
+            user.id(),
+            crate::UserTokenContext::Confirm,
+            Some("new@example.com"),
+        )
+        .await?;
+        let consumed =
+            UserToken::consume(&mut client, &token, crate::UserTokenContext::Confirm).await?;
+        assert_eq!(Some(user.id()), consumed.map(|x| x.user_id));
+        assert!(
+            UserToken::consume(&mut client, &token, crate::UserTokenContext::Confirm)
+                .await?
+                .is_none(),
+            "tokens must be single-use"
+        );
+
+        user.confirm(&mut client).await?;
+        let user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert!(user.confirmed());
+        Ok(())
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_get_by_email_ignores_case() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let hash = User::hash_password(None, "correct horse battery")?;
+        let user = User::create(&mut client, "Mixed Case", "Mixed.Case@Example.com", &hash).await?;
+        for email in [
+            "mixed.case@example.com",
+            "Mixed.Case@Example.com",
+            "MIXED.CASE@EXAMPLE.COM",
+        ] {
+            let found = User::get_by_email(&mut client, email).await?;
+            assert_eq!(Some(user.id()), found.map(|x| x.id()), "{}", email);
+        }
+        assert!(User::get_by_email(&mut client, "other@example.com")
+            .await?
+            .is_none());
+        Ok(())
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_lock_and_password_reset() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let mut user = User::new_test_user(&mut client).await?;
+        let attacker: IpNetwork = "192.0.2.1".parse().unwrap();
+        let owner: IpNetwork = "2001:db8::1".parse().unwrap();
+        for _ in 1..MAX_FAILED_LOGINS {
+            assert!(!user.record_failed_login(&mut client, attacker).await?);
+        }
+        assert!(user.record_failed_login(&mut client, attacker).await?);
+        assert!(
+            !user.record_failed_login(&mut client, attacker).await?,
+            "lock only once"
+        );
+        assert!(user.locked_from(&mut client, attacker).await?);
+        // other addresses can still log in
+        assert!(!user.locked_from(&mut client, owner).await?);
+        assert!(!user.record_failed_login(&mut client, owner).await?);
+        user.clear_failed_logins(&mut client, owner).await?;
+        assert!(user.locked_from(&mut client, attacker).await?);
+
+        let logged_in_at =
+            Utc::now().naive_utc() - tiberius_dependencies::chrono::Duration::seconds(1);
+        assert!(user.session_valid(logged_in_at));
+        let hash = User::hash_password(None, "correct horse battery")?;
+        user.set_password(&mut client, hash).await?;
+        let user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert!(!user.locked_from(&mut client, attacker).await?);
+        assert!(!user.session_valid(logged_in_at));
+        assert!(user.session_valid(Utc::now().naive_utc()));
+        assert!(matches!(
+            user.validate_login(None, &user.name, "wrong horse battery")?,
+            UserLoginResult::Invalid
+        ));
+        assert!(matches!(
+            user.validate_login(None, &user.name, "correct horse battery")?,
+            UserLoginResult::Valid
+        ));
+        Ok(())
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_backup_code_single_use() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let code = bcrypt::hash("0123456789abcdef", 4)?;
+        query!(
+            "UPDATE users SET otp_backup_codes = $2, otp_required_for_login = true WHERE id = $1",
+            user.id,
+            &[code][..],
+        )
+        .execute(&mut client)
+        .await?;
+        client.cache_users.invalidate(&user.id()).await;
+        let mut user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert!(
+            !user
+                .verify_second_factor(&mut client, None, &[], "wrong code")
+                .await?
+        );
+        assert!(
+            user.verify_second_factor(&mut client, None, &[], "0123 4567 89ab cdef")
+                .await?
+        );
+        let mut user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert_eq!(Some(Vec::new()), user.otp_secret.otp_backup_codes);
+        assert!(
+            !user
+                .verify_second_factor(&mut client, None, &[], "0123456789abcdef")
+                .await?,
+            "backup codes must be single-use"
+        );
+        Ok(())
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_totp_enrollment() -> Result<(), PhilomenaModelError> {
+        use tiberius_dependencies::totp_rs::{Algorithm, TOTP};
+        let mut client = Client::new(pool, None);
+        let key = OTPEncryptionKey::new(b"test otp encryption key".to_vec());
+        let mut user = User::new_test_user(&mut client).await?;
+        let secret = user.begin_totp_enrollment(&mut client, &key).await?;
+        assert!(!user.totp_enabled());
+        assert!(!user.has_second_factor(&mut client).await?);
+        assert_eq!(Some(secret.clone()), user.otp_secret.secret(&key)?);
+
+        assert_eq!(
+            None,
+            user.enable_totp(&mut client, None, &key, "abcdef").await?
+        );
+        let secret = tiberius_dependencies::base32::decode(
+            tiberius_dependencies::base32::Alphabet::RFC4648 { padding: false },
+            &secret,
+        )
+        .unwrap();
+        let code = TOTP::new_unchecked(Algorithm::SHA1, 6, 0, 30, secret)
+            .generate(Utc::now().timestamp() as u64);
+        let backup_codes = user
+            .enable_totp(&mut client, None, &key, &code)
+            .await?
+            .expect("code must be accepted");
+        assert_eq!(TOTP_BACKUP_CODES, backup_codes.len());
+
+        let mut user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert!(user.totp_enabled());
+        assert!(user.has_second_factor(&mut client).await?);
+        assert!(
+            !user
+                .verify_second_factor(&mut client, None, key.as_ref(), &code)
+                .await?,
+            "the enrollment code must not be usable for logging in"
+        );
+        assert!(
+            user.verify_second_factor(&mut client, None, key.as_ref(), &backup_codes[3])
+                .await?
+        );
+
+        user.disable_totp(&mut client).await?;
+        let user = User::get_id(&mut client, user.id()).await?.unwrap();
+        assert!(!user.totp_enabled());
+        assert_eq!(None, user.otp_secret.secret(&key)?);
+        Ok(())
+    }
+
     #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
     async fn test_user_create_and_fetch() -> Result<(), PhilomenaModelError> {
         let mut client = Client::new(pool, None);
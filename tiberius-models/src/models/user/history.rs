@@ -28,6 +28,8 @@ pub struct UserHistory {
     pub last_renamed_at: NaiveDateTime,
     pub confirmed_at: Option<NaiveDateTime>,
     pub failed_attempts: Option<i32>,
+    /// Sessions logged in before this time are no longer valid
+    pub sessions_valid_since: Option<NaiveDateTime>,
 }
 
 impl Default for UserHistory {
@@ -59,6 +61,7 @@ impl Default for UserHistory {
             last_renamed_at: time,
             confirmed_at: None,
             failed_attempts: None,
+            sessions_valid_since: None,
         }
     }
 }
@@ -1,10 +1,14 @@
 use crate::PhilomenaModelError;
 use anyhow::Context;
 use std::num::NonZeroU32;
+use tiberius_dependencies::base32;
 use tiberius_dependencies::base64;
 use tiberius_dependencies::base64::engine::Engine;
 use tiberius_dependencies::totp_rs::{Algorithm, TOTP};
 
+/// Seconds each TOTP code is valid for
+pub const TOTP_PERIOD: u64 = 30;
+
 #[derive(sqlx::FromRow, Debug, Clone, PartialEq, Default)]
 pub struct OTPSecret {
     pub encrypted_otp_secret: Option<String>,
@@ -12,6 +16,9 @@ pub struct OTPSecret {
     pub encrypted_otp_secret_salt: Option<String>,
     /// The last time we generated a TOTP. This should be updated in the database
     /// after a successfull login, not a failed login!
+    ///
+    /// Logins store the time step (unix time divided by the 30 second period) of the
+    /// accepted code, like Philomena does.
     pub consumed_timestep: Option<i32>,
     pub otp_required_for_login: Option<bool>,
     pub otp_backup_codes: Option<Vec<String>>,
@@ -21,6 +28,18 @@ pub struct OTPSecret {
 #[repr(transparent)]
 pub struct OTPEncryptionKey(Vec<u8>);
 
+impl OTPEncryptionKey {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self(key)
+    }
+}
+
+impl AsRef<[u8]> for OTPEncryptionKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 const fn b64c_default() -> base64::engine::general_purpose::GeneralPurpose {
     base64::engine::general_purpose::GeneralPurpose::new(
         &base64::alphabet::STANDARD,
@@ -55,6 +74,66 @@ impl OTPSecret {
         })
     }
 
+    /// Generates a new random secret, base32 encoded like the secrets Philomena generates
+    pub fn generate_secret() -> Result<String, PhilomenaModelError> {
+        let secret: [u8; 20] = ring::rand::generate(&ring::rand::SystemRandom::new())?.expose();
+        Ok(base32::encode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &secret,
+        ))
+    }
+
+    /// Encrypts and stores a base32 encoded secret
+    pub fn set_secret(
+        &mut self,
+        key: &OTPEncryptionKey,
+        secret: &str,
+    ) -> Result<(), PhilomenaModelError> {
+        self.encrypt_otp(&key.0, secret.as_bytes())
+    }
+
+    /// Returns the base32 encoded secret, if any is stored
+    pub fn secret(&self, key: &OTPEncryptionKey) -> Result<Option<String>, PhilomenaModelError> {
+        Ok(self
+            .decrypt_otp(&key.0)?
+            .map(|x| String::from_utf8_lossy(&x).trim().to_string()))
+    }
+
+    /// Returns the time step the code is valid for, allowing one step of clock drift in each direction
+    ///
+    /// The secret is stored base32 encoded, as generated by Philomena.
+    pub fn matching_timestep(
+        &self,
+        otp_secret: &[u8],
+        code: &str,
+        time: u64,
+    ) -> Result<Option<i32>, PhilomenaModelError> {
+        let dotp = match self.decrypt_otp(otp_secret).context("TOTP decrypt")? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let dotp = match base32::decode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &String::from_utf8_lossy(&dotp),
+        ) {
+            Some(v) => v,
+            None => {
+                return Err(PhilomenaModelError::Context(anyhow::format_err!(
+                    "Decode failure on TOTP secret"
+                )))
+            }
+        };
+        let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 0, TOTP_PERIOD, dotp);
+        let step = time / TOTP_PERIOD;
+        for step in [step.saturating_sub(1), step, step + 1] {
+            if totp.generate(step * TOTP_PERIOD) == code {
+                // TODO: update to i64 to prevent 2038 problem
+                return Ok(Some(step as i32));
+            }
+        }
+        Ok(None)
+    }
+
     fn algo(&self, key: &OTPEncryptionKey) -> Result<Option<TOTP>, PhilomenaModelError> {
         let dotp = match self.decrypt_otp(&key.0)? {
             Some(d) => d,
@@ -262,4 +341,31 @@ mod test {
         assert_eq!(Some(1676617192), s.consumed_timestep);
         Ok(())
     }
+
+    #[test]
+    pub fn test_totp_matching_timestep() -> Result<(), PhilomenaModelError> {
+        let key = "xZYTon09JNRrj8snd7KL31wya4x71jmo5aaSSRmw1dGjWLRmEwWMTccwxgsGFGjM".as_bytes();
+        let secret = b"0123456789abcdef0123";
+        let encoded = base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret);
+        let mut s = OTPSecret::default();
+        s.encrypt_otp(key, encoded.as_bytes())?;
+        let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 0, TOTP_PERIOD, secret.to_vec());
+
+        let time = 1676616112;
+        let step = (time / TOTP_PERIOD) as i32;
+        let code = totp.generate(time);
+        assert_eq!(Some(step), s.matching_timestep(key, &code, time)?);
+        // the previous code is still accepted to allow for clock drift
+        assert_eq!(
+            Some(step),
+            s.matching_timestep(key, &code, time + TOTP_PERIOD)?
+        );
+        assert_eq!(
+            None,
+            s.matching_timestep(key, &code, time + 3 * TOTP_PERIOD)?
+        );
+        let wrong = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+        assert_eq!(None, s.matching_timestep(key, &wrong, time)?);
+        Ok(())
+    }
 }
@@ -1,3 +1,5 @@
+use std::ops::RangeInclusive;
+
 #[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
 pub struct UserSettings {
     pub spoiler_type: String,
@@ -6,6 +8,10 @@ pub struct UserSettings {
 
     pub show_large_thumbnails: bool,
     pub show_sidebar_and_watched_images: bool,
+    pub show_sidebar_trending_images: bool,
+    pub show_sidebar_streams: bool,
+    pub show_sidebar_forum_activity: bool,
+    pub show_sidebar_recent_comments: bool,
 
     pub fancy_tag_field_on_upload: bool,
     pub fancy_tag_field_on_edit: bool,
@@ -26,6 +32,8 @@ pub struct UserSettings {
     pub messages_newest_first: bool,
 
     pub serve_webm: bool,
+    /// Play animated thumbnails and videos without hovering or clicking them
+    pub autoplay: bool,
     pub no_spoilered_in_watched: bool,
     pub watched_images_query_str: String,
     pub watched_images_exclude_str: String,
@@ -44,12 +52,16 @@ pub struct UserSettings {
 impl Default for UserSettings {
     fn default() -> Self {
         Self {
-            spoiler_type: String::default(),
-            theme: String::default(),
-            images_per_page: 20,
+            spoiler_type: "static".to_string(),
+            theme: "default".to_string(),
+            images_per_page: 25,
 
             show_large_thumbnails: false,
-            show_sidebar_and_watched_images: false,
+            show_sidebar_and_watched_images: true,
+            show_sidebar_trending_images: true,
+            show_sidebar_streams: true,
+            show_sidebar_forum_activity: true,
+            show_sidebar_recent_comments: true,
 
             fancy_tag_field_on_upload: true,
             fancy_tag_field_on_edit: true,
@@ -70,6 +82,7 @@ impl Default for UserSettings {
             messages_newest_first: true,
 
             serve_webm: true,
+            autoplay: true,
             no_spoilered_in_watched: true,
             watched_images_query_str: String::default(),
             watched_images_exclude_str: String::default(),
@@ -86,3 +99,64 @@ impl Default for UserSettings {
         }
     }
 }
+
+impl UserSettings {
+    /// Themes a user can pick, each has a stylesheet of the same name
+    pub const THEMES: &'static [&'static str] =
+        &["default", "dark", "light", "fuchsia", "green", "orange"];
+    /// How images hit by the spoiler tags of the filter are shown
+    pub const SPOILER_TYPES: &'static [&'static str] = &["click", "hover", "static", "off"];
+    /// Number of images per page a user can choose from
+    pub const IMAGES_PER_PAGE: RangeInclusive<i32> = 15..=50;
+
+    /// Returns the theme of the user, themes that no longer exist fall back to the default theme
+    pub fn theme(&self) -> &str {
+        if Self::THEMES.contains(&self.theme.as_str()) {
+            &self.theme
+        } else {
+            Self::THEMES[0]
+        }
+    }
+
+    /// Returns the spoiler type of the user, unknown types are shown as static spoilers
+    pub fn spoiler_type(&self) -> &str {
+        if Self::SPOILER_TYPES.contains(&self.spoiler_type.as_str()) {
+            &self.spoiler_type
+        } else {
+            "static"
+        }
+    }
+
+    /// Returns the page size of image listings, clamped to the sizes the settings page offers
+    pub fn images_per_page(&self) -> u64 {
+        self.images_per_page
+            .clamp(*Self::IMAGES_PER_PAGE.start(), *Self::IMAGES_PER_PAGE.end()) as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UserSettings;
+
+    #[test]
+    fn test_validated_settings() {
+        let settings = UserSettings {
+            theme: "../../secret".to_string(),
+            spoiler_type: "".to_string(),
+            images_per_page: 1000,
+            ..Default::default()
+        };
+        assert_eq!("default", settings.theme());
+        assert_eq!("static", settings.spoiler_type());
+        assert_eq!(50, settings.images_per_page());
+        let settings = UserSettings {
+            theme: "dark".to_string(),
+            spoiler_type: "hover".to_string(),
+            images_per_page: 0,
+            ..Default::default()
+        };
+        assert_eq!("dark", settings.theme());
+        assert_eq!("hover", settings.spoiler_type());
+        assert_eq!(15, settings.images_per_page());
+    }
+}
@@ -0,0 +1,322 @@
+use std::str::FromStr;
+
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+use tiberius_dependencies::uuid::Uuid;
+
+use crate::{Client, PhilomenaModelError};
+
+/// A link from a user to their presence on another site, usually to verify them as an artist
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct UserLink {
+    pub id: i32,
+    /// See [UserLinkState]
+    pub aasm_state: String,
+    pub uri: String,
+    pub hostname: Option<String>,
+    pub path: Option<String>,
+    pub verification_code: String,
+    pub public: bool,
+    pub next_check_at: Option<NaiveDateTime>,
+    pub contacted_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user_id: i32,
+    pub verified_by_user_id: Option<i32>,
+    pub contacted_by_user_id: Option<i32>,
+    /// The artist tag the user is verified for
+    pub tag_id: Option<i32>,
+}
+
+/// Prefix of the code users place on the linked page to show they control it
+pub const USER_LINK_VERIFICATION_PREFIX: &str = "TIBERIUS-LINKVALIDATION-";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UserLinkState {
+    /// The verification code was not found on the linked page yet
+    Unverified,
+    /// The verification code was found on the linked page, staff still has to approve the link
+    LinkVerified,
+    /// Staff approved the link
+    Verified,
+    Contacted,
+    Rejected,
+}
+
+impl ToString for UserLinkState {
+    fn to_string(&self) -> String {
+        match self {
+            UserLinkState::Unverified => "unverified",
+            UserLinkState::LinkVerified => "link_verified",
+            UserLinkState::Verified => "verified",
+            UserLinkState::Contacted => "contacted",
+            UserLinkState::Rejected => "rejected",
+        }
+        .to_string()
+    }
+}
+
+impl FromStr for UserLinkState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "unverified" => Self::Unverified,
+            "link_verified" => Self::LinkVerified,
+            "verified" => Self::Verified,
+            "contacted" => Self::Contacted,
+            "rejected" => Self::Rejected,
+            v => anyhow::bail!("Invalid user link state: {:?}", v),
+        })
+    }
+}
+
+impl UserLink {
+    pub fn state(&self) -> Result<UserLinkState, PhilomenaModelError> {
+        Ok(UserLinkState::from_str(&self.aasm_state)?)
+    }
+
+    pub fn is_verified(&self) -> bool {
+        matches!(self.state(), Ok(UserLinkState::Verified))
+    }
+
+    /// Returns the links of the user, only the public ones unless `include_private` is set
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i32,
+        include_private: bool,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserLink,
+            "SELECT * FROM user_links WHERE user_id = $1 AND (public OR $2) ORDER BY created_at",
+            user_id,
+            include_private
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    pub async fn get_id(client: &mut Client, id: i32) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(
+            query_as!(UserLink, "SELECT * FROM user_links WHERE id = $1", id)
+                .fetch_optional(client)
+                .await?,
+        )
+    }
+
+    /// Adds a new unverified link, the linked page is checked for the verification code soon after
+    pub async fn create(
+        client: &mut Client,
+        user_id: i32,
+        uri: &url::Url,
+        tag_id: Option<i32>,
+        public: bool,
+    ) -> Result<Self, PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        let verification_code = format!(
+            "{}{}",
+            USER_LINK_VERIFICATION_PREFIX,
+            Uuid::new_v4().simple()
+        );
+        Ok(query_as!(
+            UserLink,
+            "INSERT INTO user_links
+                (aasm_state, uri, hostname, path, verification_code, public, next_check_at, created_at, updated_at, user_id, tag_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $7, $8, $9) RETURNING *",
+            UserLinkState::Unverified.to_string(),
+            uri.as_str(),
+            uri.host_str(),
+            uri.path(),
+            verification_code,
+            public,
+            now,
+            user_id,
+            tag_id,
+        )
+        .fetch_one(client)
+        .await?)
+    }
+
+    /// Returns the links staff has not approved or rejected yet, oldest first
+    pub async fn get_pending(client: &mut Client) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserLink,
+            "SELECT * FROM user_links
+                WHERE aasm_state IN ('unverified', 'link_verified', 'contacted')
+                ORDER BY created_at"
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Returns the unverified links whose page is due to be checked for the verification code
+    pub async fn get_due_for_check(client: &mut Client) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserLink,
+            "SELECT * FROM user_links
+                WHERE aasm_state = 'unverified' AND next_check_at <= $1
+                ORDER BY next_check_at",
+            Utc::now().naive_utc()
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Sets when the linked page is checked next, `None` stops the automatic checks
+    pub async fn set_next_check(
+        &mut self,
+        client: &mut Client,
+        next_check_at: Option<NaiveDateTime>,
+    ) -> Result<(), PhilomenaModelError> {
+        self.next_check_at = next_check_at;
+        self.updated_at = Utc::now().naive_utc();
+        query!(
+            "UPDATE user_links SET next_check_at = $2, updated_at = $3 WHERE id = $1",
+            self.id,
+            self.next_check_at,
+            self.updated_at,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Moves the link into the given state
+    ///
+    /// `staff_user_id` is recorded as the user who verified or contacted the link owner.
+    pub async fn set_state(
+        &mut self,
+        client: &mut Client,
+        state: UserLinkState,
+        staff_user_id: Option<i32>,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        match state {
+            UserLinkState::Verified => self.verified_by_user_id = staff_user_id,
+            UserLinkState::Contacted => {
+                self.contacted_by_user_id = staff_user_id;
+                self.contacted_at = Some(now);
+            }
+            _ => (),
+        }
+        if state != UserLinkState::Unverified {
+            self.next_check_at = None;
+        }
+        self.aasm_state = state.to_string();
+        self.updated_at = now;
+        query!(
+            "UPDATE user_links
+                SET aasm_state = $2, verified_by_user_id = $3, contacted_by_user_id = $4,
+                    contacted_at = $5, next_check_at = $6, updated_at = $7
+                WHERE id = $1",
+            self.id,
+            self.aasm_state,
+            self.verified_by_user_id,
+            self.contacted_by_user_id,
+            self.contacted_at,
+            self.next_check_at,
+            self.updated_at,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns true if the user has a verified link for the artist tag
+    pub async fn is_verified_artist(
+        client: &mut Client,
+        user_id: i32,
+        tag_id: i32,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM user_links WHERE user_id = $1 AND tag_id = $2 AND aasm_state = 'verified'
+            ) AS "exists!""#,
+            user_id,
+            tag_id
+        )
+        .fetch_one(client)
+        .await?
+        .exists)
+    }
+
+    /// Returns the verified public links for any of the artist tags
+    ///
+    /// Links the user did not make public are left out, they still count for
+    /// [UserLink::is_verified_artist].
+    pub async fn get_verified_for_tags(
+        client: &mut Client,
+        tag_ids: &[i32],
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserLink,
+            "SELECT * FROM user_links WHERE tag_id = ANY($1) AND aasm_state = 'verified' AND public
+             ORDER BY user_id",
+            tag_ids
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    pub async fn delete(self, client: &mut Client) -> Result<(), PhilomenaModelError> {
+        query!("DELETE FROM user_links WHERE id = $1", self.id)
+            .execute(client)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Client, PhilomenaModelError, Tag, User, UserLink, UserLinkState};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_verify_user_link() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let tag = Tag::create_for_test(&mut client, "artist:tester").await?;
+        let uri = url::Url::parse("https://example.com/tester").unwrap();
+        let mut link = UserLink::create(&mut client, user.id, &uri, Some(tag.id), true).await?;
+        assert_eq!(UserLinkState::Unverified, link.state()?);
+        assert_eq!(Some("example.com".to_string()), link.hostname);
+        assert_eq!(1, UserLink::get_due_for_check(&mut client).await?.len());
+        assert!(!UserLink::is_verified_artist(&mut client, user.id, tag.id).await?);
+
+        link.set_state(&mut client, UserLinkState::LinkVerified, None)
+            .await?;
+        assert!(UserLink::get_due_for_check(&mut client).await?.is_empty());
+        assert_eq!(1, UserLink::get_pending(&mut client).await?.len());
+
+        link.set_state(&mut client, UserLinkState::Verified, Some(user.id))
+            .await?;
+        assert!(UserLink::is_verified_artist(&mut client, user.id, tag.id).await?);
+        assert!(UserLink::get_pending(&mut client).await?.is_empty());
+        let verified = UserLink::get_verified_for_tags(&mut client, &[tag.id]).await?;
+        assert_eq!(
+            Some(user.id),
+            verified.first().and_then(|x| x.verified_by_user_id)
+        );
+
+        // private links verify the user but are not shown
+        let hidden_tag = Tag::create_for_test(&mut client, "artist:hidden tester").await?;
+        let uri = url::Url::parse("https://example.com/hidden").unwrap();
+        let mut hidden =
+            UserLink::create(&mut client, user.id, &uri, Some(hidden_tag.id), false).await?;
+        hidden
+            .set_state(&mut client, UserLinkState::Verified, Some(user.id))
+            .await?;
+        assert!(UserLink::is_verified_artist(&mut client, user.id, hidden_tag.id).await?);
+        assert!(
+            UserLink::get_verified_for_tags(&mut client, &[hidden_tag.id])
+                .await?
+                .is_empty()
+        );
+        let verified =
+            UserLink::get_verified_for_tags(&mut client, &[tag.id, hidden_tag.id]).await?;
+        assert_eq!(
+            vec![link.id],
+            verified.iter().map(|x| x.id).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
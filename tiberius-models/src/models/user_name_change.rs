@@ -0,0 +1,89 @@
+use sqlx::query_as;
+use tiberius_dependencies::chrono::NaiveDateTime;
+
+use crate::{Client, PhilomenaModelError};
+
+/// A previous name of a user
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct UserNameChange {
+    pub id: i32,
+    pub user_id: i64,
+    /// The name the user had before the change
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl UserNameChange {
+    /// Returns the previous names of the user, newest first
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserNameChange,
+            "SELECT * FROM user_name_changes WHERE user_id = $1 ORDER BY created_at DESC",
+            user_id as i64
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Returns the most recent name change of the user
+    pub async fn latest_for_user(
+        client: &mut Client,
+        user_id: i32,
+    ) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserNameChange,
+            "SELECT * FROM user_name_changes WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1",
+            user_id as i64
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    /// Returns the most recent change away from the name, to find who used it last
+    pub async fn get_by_name(
+        client: &mut Client,
+        name: &str,
+    ) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserNameChange,
+            "SELECT * FROM user_name_changes WHERE name = $1 ORDER BY created_at DESC LIMIT 1",
+            name
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Client, PhilomenaModelError, User, UserNameChange};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_change_name() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let mut user = User::new_test_user(&mut client).await?;
+        let old_name = user.name.clone();
+        assert!(UserNameChange::latest_for_user(&mut client, user.id)
+            .await?
+            .is_none());
+        assert!(!User::name_taken(&mut client, &old_name.to_uppercase(), Some(user.id)).await?);
+        assert!(User::name_taken(&mut client, &old_name.to_uppercase(), None).await?);
+
+        user.change_name(&mut client, "Renamed User").await?;
+        assert_eq!("renamed+user", user.slug);
+        let change = UserNameChange::get_by_name(&mut client, &old_name)
+            .await?
+            .expect("name change was not recorded");
+        assert_eq!(user.id as i64, change.user_id);
+        let renamed = User::get_by_name(&mut client, "Renamed User")
+            .await?
+            .expect("user was not renamed");
+        assert_eq!(user.id, renamed.id);
+        assert!(User::get_by_name(&mut client, &old_name).await?.is_none());
+        Ok(())
+    }
+}
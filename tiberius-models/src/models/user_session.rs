@@ -0,0 +1,204 @@
+use sqlx::{query, query_as, types::ipnetwork::IpNetwork, types::Uuid};
+use tiberius_dependencies::chrono::{DateTime, Duration, NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
+
+/// The record of a logged in session, used to show users where they are logged in
+/// and to revoke individual sessions
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct UserSession {
+    pub id: Uuid,
+    pub user_id: Option<i32>,
+    pub user_agent: Option<String>,
+    pub ip: Option<IpNetwork>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl UserSession {
+    /// Minimum time between updates of the last seen time, to avoid a write on every request
+    pub fn touch_interval() -> Duration {
+        Duration::minutes(5)
+    }
+
+    pub async fn get(client: &mut Client, id: Uuid) -> Result<Option<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserSession,
+            "SELECT id, user_id, user_agent, ip, created_at, last_seen_at, expires
+             FROM user_sessions WHERE id = $1",
+            id
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    /// Returns the unexpired sessions of the user, most recently seen first
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i64,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserSession,
+            "SELECT id, user_id, user_agent, ip, created_at, last_seen_at, expires
+             FROM user_sessions WHERE user_id = $1 AND (expires IS NULL OR expires > $2)
+             ORDER BY last_seen_at DESC",
+            user_id as i32,
+            Utc::now(),
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Records a new login on the session, replacing any previous record of it
+    pub async fn create(
+        client: &mut Client,
+        id: Uuid,
+        user_id: i64,
+        user_agent: Option<&str>,
+        ip: Option<IpNetwork>,
+        expires: DateTime<Utc>,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "INSERT INTO user_sessions (id, user_id, user_agent, ip, created_at, last_seen_at, expires)
+             VALUES ($1, $2, $3, $4, $5, $5, $6)
+             ON CONFLICT (id) DO UPDATE SET user_id = $2, user_agent = $3, ip = $4,
+                created_at = $5, last_seen_at = $5, expires = $6",
+            id,
+            user_id as i32,
+            user_agent,
+            ip,
+            now,
+            expires,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Updates the last seen time, user agent and IP of the session
+    pub async fn touch(
+        &mut self,
+        client: &mut Client,
+        user_agent: Option<&str>,
+        ip: Option<IpNetwork>,
+    ) -> Result<(), PhilomenaModelError> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "UPDATE user_sessions SET last_seen_at = $2, user_agent = $3, ip = $4 WHERE id = $1",
+            self.id,
+            now,
+            user_agent,
+            ip,
+        )
+        .execute(client)
+        .await?;
+        self.last_seen_at = now;
+        self.user_agent = user_agent.map(|x| x.to_string());
+        self.ip = ip;
+        Ok(())
+    }
+
+    /// Deletes the session of the user, logging it out on it's next request
+    ///
+    /// Returns false if the user has no such session.
+    pub async fn revoke(
+        client: &mut Client,
+        user_id: i64,
+        id: Uuid,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM user_sessions WHERE user_id = $1 AND id = $2",
+            user_id as i32,
+            id
+        )
+        .execute(client)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Deletes all sessions of the user except the given one, returns the number of revoked sessions
+    pub async fn revoke_all(
+        client: &mut Client,
+        user_id: i64,
+        except: Option<Uuid>,
+    ) -> Result<u64, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM user_sessions WHERE user_id = $1 AND id IS DISTINCT FROM $2",
+            user_id as i32,
+            except
+        )
+        .execute(client)
+        .await?
+        .rows_affected())
+    }
+
+    /// Deletes records of expired sessions
+    pub async fn cleanup(client: &mut Client) -> Result<u64, PhilomenaModelError> {
+        Ok(
+            query!("DELETE FROM user_sessions WHERE expires < $1", Utc::now())
+                .execute(client)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::types::Uuid;
+    use tiberius_dependencies::chrono::{Duration, Utc};
+
+    use crate::{Client, PhilomenaModelError, User, UserSession};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_session_revocation() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let expires = Utc::now() + Duration::days(1);
+        let (current, other) = (Uuid::new_v4(), Uuid::new_v4());
+        UserSession::create(
+            &mut client,
+            current,
+            user.id(),
+            Some("Firefox"),
+            None,
+            expires,
+        )
+        .await?;
+        UserSession::create(&mut client, other, user.id(), Some("Chrome"), None, expires).await?;
+        assert_eq!(
+            2,
+            UserSession::get_for_user(&mut client, user.id())
+                .await?
+                .len()
+        );
+
+        assert!(!UserSession::revoke(&mut client, user.id() + 1, other).await?);
+        assert_eq!(
+            1,
+            UserSession::revoke_all(&mut client, user.id(), Some(current)).await?
+        );
+        let sessions = UserSession::get_for_user(&mut client, user.id()).await?;
+        assert_eq!(
+            vec![current],
+            sessions.iter().map(|x| x.id).collect::<Vec<_>>()
+        );
+
+        UserSession::create(
+            &mut client,
+            other,
+            user.id(),
+            None,
+            None,
+            Utc::now() - Duration::seconds(1),
+        )
+        .await?;
+        assert_eq!(1, UserSession::cleanup(&mut client).await?);
+        assert!(UserSession::revoke(&mut client, user.id(), current).await?);
+        assert!(UserSession::get(&mut client, current).await?.is_none());
+        Ok(())
+    }
+}
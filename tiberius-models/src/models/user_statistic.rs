@@ -0,0 +1,157 @@
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+
+use crate::{Client, PhilomenaModelError};
+
+/// Activity of a user on a single day
+///
+/// Days are counted in UTC since the unix epoch.
+#[derive(sqlx::FromRow, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStatistic {
+    pub id: i32,
+    pub user_id: i32,
+    pub day: i32,
+    pub uploads: i32,
+    pub votes_cast: i32,
+    pub comments_posted: i32,
+    pub metadata_updates: i32,
+    pub images_favourited: i32,
+    pub forum_posts: i32,
+}
+
+/// The counters of [UserStatistic]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserStatisticKind {
+    Uploads,
+    VotesCast,
+    CommentsPosted,
+    MetadataUpdates,
+    ImagesFavourited,
+    ForumPosts,
+}
+
+impl UserStatistic {
+    /// Number of the day the given time falls on
+    pub fn day_of(time: NaiveDateTime) -> i32 {
+        (time.timestamp().div_euclid(86400)) as i32
+    }
+
+    pub fn today() -> i32 {
+        Self::day_of(Utc::now().naive_utc())
+    }
+
+    /// Returns the statistics of the user starting from the given day, oldest first
+    ///
+    /// Days without activity have no entry.
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i32,
+        since_day: i32,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            UserStatistic,
+            "SELECT * FROM user_statistics WHERE user_id = $1 AND day >= $2 ORDER BY day",
+            user_id,
+            since_day
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Adds one to the counter of the user for today
+    pub async fn increment(
+        client: &mut Client,
+        user_id: i32,
+        kind: UserStatisticKind,
+    ) -> Result<(), PhilomenaModelError> {
+        let (uploads, votes, comments, metadata, faves, posts) = match kind {
+            UserStatisticKind::Uploads => (1, 0, 0, 0, 0, 0),
+            UserStatisticKind::VotesCast => (0, 1, 0, 0, 0, 0),
+            UserStatisticKind::CommentsPosted => (0, 0, 1, 0, 0, 0),
+            UserStatisticKind::MetadataUpdates => (0, 0, 0, 1, 0, 0),
+            UserStatisticKind::ImagesFavourited => (0, 0, 0, 0, 1, 0),
+            UserStatisticKind::ForumPosts => (0, 0, 0, 0, 0, 1),
+        };
+        query!(
+            "INSERT INTO user_statistics
+                (user_id, day, uploads, votes_cast, comments_posted, metadata_updates, images_favourited, forum_posts)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (user_id, day) DO UPDATE SET
+                    uploads = user_statistics.uploads + excluded.uploads,
+                    votes_cast = user_statistics.votes_cast + excluded.votes_cast,
+                    comments_posted = user_statistics.comments_posted + excluded.comments_posted,
+                    metadata_updates = user_statistics.metadata_updates + excluded.metadata_updates,
+                    images_favourited = user_statistics.images_favourited + excluded.images_favourited,
+                    forum_posts = user_statistics.forum_posts + excluded.forum_posts",
+            user_id,
+            Self::today(),
+            uploads,
+            votes,
+            comments,
+            metadata,
+            faves,
+            posts,
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
+
+    /// Sums up the counters of the given days
+    pub fn total(stats: &[UserStatistic]) -> UserStatistic {
+        stats
+            .iter()
+            .fold(UserStatistic::default(), |acc, x| UserStatistic {
+                uploads: acc.uploads + x.uploads,
+                votes_cast: acc.votes_cast + x.votes_cast,
+                comments_posted: acc.comments_posted + x.comments_posted,
+                metadata_updates: acc.metadata_updates + x.metadata_updates,
+                images_favourited: acc.images_favourited + x.images_favourited,
+                forum_posts: acc.forum_posts + x.forum_posts,
+                ..acc
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::chrono::NaiveDate;
+
+    use crate::{Client, PhilomenaModelError, User, UserStatistic, UserStatisticKind};
+
+    #[test]
+    fn test_day_of() {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        assert_eq!(
+            0,
+            UserStatistic::day_of(epoch.and_hms_opt(23, 59, 59).unwrap())
+        );
+        assert_eq!(
+            20745,
+            UserStatistic::day_of(
+                NaiveDate::from_ymd_opt(2026, 10, 19)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+            )
+        );
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_user_statistics() -> Result<(), PhilomenaModelError> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        UserStatistic::increment(&mut client, user.id, UserStatisticKind::Uploads).await?;
+        UserStatistic::increment(&mut client, user.id, UserStatisticKind::Uploads).await?;
+        UserStatistic::increment(&mut client, user.id, UserStatisticKind::CommentsPosted).await?;
+
+        let stats =
+            UserStatistic::get_for_user(&mut client, user.id, UserStatistic::today()).await?;
+        assert_eq!(1, stats.len());
+        let total = UserStatistic::total(&stats);
+        assert_eq!(2, total.uploads);
+        assert_eq!(1, total.comments_posted);
+        assert_eq!(0, total.votes_cast);
+        Ok(())
+    }
+}
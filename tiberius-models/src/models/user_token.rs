@@ -1,12 +1,56 @@
 use std::ops::DerefMut;
 
-use sqlx::query_as;
-use tiberius_dependencies::chrono::NaiveDateTime;
+use ring::rand::SecureRandom;
+use sqlx::{query, query_as};
+use tiberius_dependencies::base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
 use tiberius_dependencies::hex;
+use tiberius_dependencies::sha2::{Digest, Sha256};
 use tracing::trace;
 
 use crate::{Client, PhilomenaModelError};
 
+/// The purpose a token was issued for, stored in the context column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserTokenContext {
+    /// Login session, the raw session ID is stored
+    Session,
+    /// Confirmation of the email address given at registration
+    Confirm,
+    /// Password reset requested via the forgotten password form
+    ResetPassword,
+    /// Unlocking an account locked after too many failed logins
+    Unlock,
+    /// Confirmation of a new email address, stored in sent_to
+    ChangeEmail,
+}
+
+impl UserTokenContext {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserTokenContext::Session => "session",
+            UserTokenContext::Confirm => "confirm",
+            UserTokenContext::ResetPassword => "reset_password",
+            UserTokenContext::Unlock => "unlock",
+            UserTokenContext::ChangeEmail => "change_email",
+        }
+    }
+    /// How long a token of this context remains valid after being issued
+    pub fn max_age(&self) -> Duration {
+        match self {
+            UserTokenContext::Session => Duration::days(365),
+            UserTokenContext::Confirm => Duration::days(7),
+            UserTokenContext::ResetPassword => Duration::hours(6),
+            UserTokenContext::Unlock => Duration::days(1),
+            UserTokenContext::ChangeEmail => Duration::days(7),
+        }
+    }
+}
+
+fn hash_token(token: &[u8]) -> Vec<u8> {
+    Sha256::digest(token).to_vec()
+}
+
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct UserToken {
     pub id: i64,
@@ -27,7 +71,7 @@ impl UserToken {
             UserToken,
             "SELECT * FROM user_tokens WHERE token = $1 AND context = $2",
             user_token,
-            "session"
+            UserTokenContext::Session.as_str()
         )
         .fetch_optional(client.db().await?.deref_mut())
         .await?;
@@ -42,4 +86,128 @@ impl UserToken {
         );
         Ok(Some(user_token))
     }
+
+    /// Issues a new single-use token for the user and returns it encoded for use in URLs
+    ///
+    /// Only the hash of the token is stored, so a leaked database does not allow using
+    /// outstanding tokens.
+    pub async fn issue(
+        client: &mut Client,
+        user_id: i64,
+        context: UserTokenContext,
+        sent_to: Option<&str>,
+    ) -> Result<String, PhilomenaModelError> {
+        let mut token = [0u8; 32];
+        ring::rand::SystemRandom::new().fill(&mut token)?;
+        query!(
+            "INSERT INTO user_tokens (user_id, token, context, sent_to, created_at) VALUES ($1, $2, $3, $4, $5)",
+            user_id,
+            hash_token(&token),
+            context.as_str(),
+            sent_to,
+            Utc::now().naive_utc(),
+        )
+        .execute(client)
+        .await?;
+        Ok(URL_SAFE_NO_PAD.encode(token))
+    }
+
+    /// Looks up a token issued with [UserToken::issue] without consuming it
+    ///
+    /// Returns None if the token is unknown, was issued for a different context or has expired.
+    pub async fn peek(
+        client: &mut Client,
+        token: &str,
+        context: UserTokenContext,
+    ) -> Result<Option<UserToken>, PhilomenaModelError> {
+        let token = match URL_SAFE_NO_PAD.decode(token) {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        };
+        let not_before = Utc::now().naive_utc() - context.max_age();
+        Ok(query_as!(
+            UserToken,
+            "SELECT * FROM user_tokens WHERE token = $1 AND context = $2 AND created_at > $3",
+            hash_token(&token),
+            context.as_str(),
+            not_before,
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    /// Looks up and deletes a token issued with [UserToken::issue]
+    ///
+    /// Returns None if the token is unknown, was issued for a different context or has expired.
+    pub async fn consume(
+        client: &mut Client,
+        token: &str,
+        context: UserTokenContext,
+    ) -> Result<Option<UserToken>, PhilomenaModelError> {
+        let token = match URL_SAFE_NO_PAD.decode(token) {
+            Ok(v) => v,
+            Err(_) => return Ok(None),
+        };
+        let not_before = Utc::now().naive_utc() - context.max_age();
+        Ok(query_as!(
+            UserToken,
+            "DELETE FROM user_tokens WHERE token = $1 AND context = $2 AND created_at > $3 RETURNING *",
+            hash_token(&token),
+            context.as_str(),
+            not_before,
+        )
+        .fetch_optional(client)
+        .await?)
+    }
+
+    /// Returns the unexpired tokens of the user for the given context, newest first
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i64,
+        context: UserTokenContext,
+    ) -> Result<Vec<UserToken>, PhilomenaModelError> {
+        let not_before = Utc::now().naive_utc() - context.max_age();
+        Ok(query_as!(
+            UserToken,
+            "SELECT * FROM user_tokens WHERE user_id = $1 AND context = $2 AND created_at > $3 ORDER BY created_at DESC",
+            user_id,
+            context.as_str(),
+            not_before,
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Deletes a single token of the user, returns false if the user has no such token
+    pub async fn revoke(
+        client: &mut Client,
+        user_id: i64,
+        id: i64,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM user_tokens WHERE user_id = $1 AND id = $2",
+            user_id,
+            id
+        )
+        .execute(client)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Deletes all tokens of the user for the given context
+    pub async fn revoke_all(
+        client: &mut Client,
+        user_id: i64,
+        context: UserTokenContext,
+    ) -> Result<(), PhilomenaModelError> {
+        query!(
+            "DELETE FROM user_tokens WHERE user_id = $1 AND context = $2",
+            user_id,
+            context.as_str()
+        )
+        .execute(client)
+        .await?;
+        Ok(())
+    }
 }
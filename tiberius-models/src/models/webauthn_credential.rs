@@ -0,0 +1,133 @@
+use sqlx::{query, query_as};
+use tiberius_dependencies::chrono::{NaiveDateTime, Utc};
+use tiberius_dependencies::webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+use crate::{Client, PhilomenaModelError};
+
+/// A WebAuthn credential (passkey or security key) registered by a user
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct WebauthnCredential {
+    pub id: i64,
+    pub user_id: i32,
+    /// Name given by the user to tell their credentials apart
+    pub name: String,
+    pub credential_id: Vec<u8>,
+    /// The serialized passkey, including public key and signature counter
+    pub passkey: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl WebauthnCredential {
+    pub fn passkey(&self) -> Result<Passkey, PhilomenaModelError> {
+        Ok(serde_json::from_value(self.passkey.clone())?)
+    }
+
+    pub async fn get_for_user(
+        client: &mut Client,
+        user_id: i64,
+    ) -> Result<Vec<Self>, PhilomenaModelError> {
+        Ok(query_as!(
+            WebauthnCredential,
+            "SELECT * FROM user_webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+            user_id as i32,
+        )
+        .fetch_all(client)
+        .await?)
+    }
+
+    /// Returns the passkeys of the user for starting an authentication
+    pub async fn passkeys_for_user(
+        client: &mut Client,
+        user_id: i64,
+    ) -> Result<Vec<Passkey>, PhilomenaModelError> {
+        Self::get_for_user(client, user_id)
+            .await?
+            .iter()
+            .map(|x| x.passkey())
+            .collect()
+    }
+
+    pub async fn has_credentials(
+        client: &mut Client,
+        user_id: i64,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "SELECT EXISTS(SELECT 1 FROM user_webauthn_credentials WHERE user_id = $1) AS \"exists!\"",
+            user_id as i32,
+        )
+        .fetch_one(client)
+        .await?
+        .exists)
+    }
+
+    pub async fn create(
+        client: &mut Client,
+        user_id: i64,
+        name: &str,
+        passkey: &Passkey,
+    ) -> Result<Self, PhilomenaModelError> {
+        Ok(query_as!(
+            WebauthnCredential,
+            "INSERT INTO user_webauthn_credentials (user_id, name, credential_id, passkey, created_at)
+             VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            user_id as i32,
+            name,
+            passkey.cred_id().0.clone(),
+            serde_json::to_value(passkey)?,
+            Utc::now().naive_utc(),
+        )
+        .fetch_one(client)
+        .await?)
+    }
+
+    /// Stores the updated signature counter of a credential after a successful authentication
+    ///
+    /// Returns false if the credential does not belong to the given user.
+    pub async fn record_use(
+        client: &mut Client,
+        user_id: i64,
+        result: &AuthenticationResult,
+    ) -> Result<bool, PhilomenaModelError> {
+        let credential = query_as!(
+            WebauthnCredential,
+            "SELECT * FROM user_webauthn_credentials WHERE user_id = $1 AND credential_id = $2",
+            user_id as i32,
+            &result.cred_id().0,
+        )
+        .fetch_optional(&mut *client)
+        .await?;
+        let credential = match credential {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let mut passkey = credential.passkey()?;
+        passkey.update_credential(result);
+        query!(
+            "UPDATE user_webauthn_credentials SET passkey = $2, last_used_at = $3 WHERE id = $1",
+            credential.id,
+            serde_json::to_value(&passkey)?,
+            Utc::now().naive_utc(),
+        )
+        .execute(client)
+        .await?;
+        Ok(true)
+    }
+
+    /// Deletes a credential of the user, returns false if no such credential exists
+    pub async fn delete(
+        client: &mut Client,
+        user_id: i64,
+        id: i64,
+    ) -> Result<bool, PhilomenaModelError> {
+        Ok(query!(
+            "DELETE FROM user_webauthn_credentials WHERE user_id = $1 AND id = $2",
+            user_id as i32,
+            id,
+        )
+        .execute(client)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+}
@@ -1,13 +1,241 @@
-use axum::Json;
+use axum::{
+    extract::{Query, State},
+    headers::{ContentType, HeaderMapExt},
+    http::{HeaderMap, StatusCode},
+};
 use axum_extra::routing::TypedPath;
-use serde::Deserialize;
-use tiberius_core::error::TiberiusResult;
+use maud::html;
+use serde::{Deserialize, Serialize};
+use tiberius_core::{
+    error::TiberiusResult,
+    request_helper::{CustomResponse, TiberiusResponse},
+    session::Unauthenticated,
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::mime;
+use tiberius_models::{Filter, Image, ImageThumbType, TagLike};
+
+use crate::templates::{
+    common::{frontmatter::artist_tags, routes::absolute_static_host},
+    images::PathShowImage,
+};
 
 #[derive(TypedPath, Deserialize)]
 #[typed_path("/oembed")]
 pub struct PathOembed {}
 
-#[instrument]
-pub async fn fetch(_: PathOembed) -> TiberiusResult<Json<()>> {
-    todo!()
+#[derive(Deserialize, Debug)]
+pub struct QueryOembed {
+    pub url: String,
+    pub format: Option<String>,
+    pub maxwidth: Option<u32>,
+    pub maxheight: Option<u32>,
+}
+
+/// oEmbed response of type "photo", see <https://oembed.com/>
+#[derive(Serialize, Debug)]
+pub struct OembedResponse {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub version: &'static str,
+    pub title: String,
+    pub author_name: String,
+    pub author_url: String,
+    pub provider_name: String,
+    pub provider_url: String,
+    pub cache_age: u32,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail_url: String,
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    pub tiberius_id: i32,
+    pub tiberius_score: i32,
+    pub tiberius_comments: i32,
+    pub tiberius_tags: Vec<String>,
+}
+
+impl OembedResponse {
+    fn to_xml(&self) -> String {
+        let fields: [(&str, String); 14] = [
+            ("type", self.kind.to_string()),
+            ("version", self.version.to_string()),
+            ("title", self.title.clone()),
+            ("author_name", self.author_name.clone()),
+            ("author_url", self.author_url.clone()),
+            ("provider_name", self.provider_name.clone()),
+            ("provider_url", self.provider_url.clone()),
+            ("cache_age", self.cache_age.to_string()),
+            ("url", self.url.clone()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("thumbnail_url", self.thumbnail_url.clone()),
+            ("thumbnail_width", self.thumbnail_width.to_string()),
+            ("thumbnail_height", self.thumbnail_height.to_string()),
+        ];
+        let mut xml =
+            r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?><oembed>"#.to_string();
+        for (name, value) in fields {
+            xml.push_str(&format!(
+                "<{name}>{}</{name}>",
+                html! { (value) }.into_string()
+            ));
+        }
+        xml.push_str("</oembed>");
+        xml
+    }
+}
+
+enum OembedFormat {
+    Json,
+    Xml,
+}
+
+/// Extracts the image ID from any of the URLs an image can be linked by
+///
+/// Accepts `/:id`, `/images/:id` and `/embed/:id[/:flag]`, query and fragment are ignored.
+pub fn image_id_from_url(url: &str) -> Option<u64> {
+    let path = match url::Url::parse(url) {
+        Ok(v) => v.path().to_string(),
+        Err(_) => url.split(['?', '#']).next().unwrap_or("").to_string(),
+    };
+    let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+    match segments.as_slice() {
+        [id] => id.parse().ok(),
+        ["images", id] => id.parse().ok(),
+        ["embed", id] | ["embed", id, _] => id.parse().ok(),
+        _ => None,
+    }
+}
+
+fn oembed_error(status: StatusCode) -> TiberiusResult<TiberiusResponse<(StatusCode, String)>> {
+    Ok(TiberiusResponse::Custom(CustomResponse {
+        content: (status, status.canonical_reason().unwrap_or("").to_string()),
+        headers: HeaderMap::new(),
+    }))
+}
+
+/// Returns the oEmbed representation of an image
+///
+/// Images that are hidden, deleted or filtered by the default filter of the site are not
+/// returned, since the consumer will show them without any filter applied.
+#[instrument(skip(state, rstate))]
+pub async fn fetch(
+    _: PathOembed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    Query(query): Query<QueryOembed>,
+) -> TiberiusResult<TiberiusResponse<(StatusCode, String)>> {
+    let format = match query.format.as_deref() {
+        None | Some("json") => OembedFormat::Json,
+        Some("xml") => OembedFormat::Xml,
+        Some(_) => return oembed_error(StatusCode::NOT_IMPLEMENTED),
+    };
+    let id = match image_id_from_url(&query.url) {
+        Some(v) => v,
+        None => return oembed_error(StatusCode::NOT_FOUND),
+    };
+    let mut client = state.get_db_client();
+    let image = match Image::get_id(&mut client, id as i64).await? {
+        Some(v) if v.publicly_visible() => v,
+        _ => return oembed_error(StatusCode::NOT_FOUND),
+    };
+    let filter = Filter::default_filter(&mut client).await?;
+    if filter.hides_image(&image) || filter.spoilers_image(&image) {
+        return oembed_error(StatusCode::UNAUTHORIZED);
+    }
+    let tags = image.tags(&mut client).await?;
+    let artists = artist_tags(&tags);
+    let urls = image
+        .image_thumb_urls()
+        .await?
+        .with_host(Some(absolute_static_host(&state, &rstate)));
+    let (height, width) = (
+        image.image_height.unwrap_or(1).max(1) as u32,
+        image.image_width.unwrap_or(1).max(1) as u32,
+    );
+    let limit = ImageThumbType::Large
+        .to_resolution_limit()
+        .expect("large thumbnails have a resolution limit");
+    let limit = tiberius_models::ResolutionLimit::new(
+        query.maxheight.unwrap_or(limit.height).min(limit.height),
+        query.maxwidth.unwrap_or(limit.width).min(limit.width),
+    );
+    let size = limit.clamp_resolution(height, width);
+    let thumb_size = ImageThumbType::Thumb
+        .to_resolution_limit()
+        .expect("thumbnails have a resolution limit")
+        .clamp_resolution(height, width);
+    let response = OembedResponse {
+        kind: "photo",
+        version: "1.0",
+        title: format!("#{} - {}", image.id, state.site_config().site_name()),
+        author_name: artists
+            .iter()
+            .map(|x| {
+                x.name_in_namespace
+                    .clone()
+                    .unwrap_or_else(|| x.name.clone())
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        author_url: image.source_url.clone().unwrap_or_else(|| {
+            state.config().absolute_url(
+                PathShowImage {
+                    image: image.id as u64,
+                }
+                .to_uri(),
+            )
+        }),
+        provider_name: state.site_config().site_name().clone(),
+        provider_url: state.config().absolute_url(""),
+        cache_age: 7200,
+        url: urls.large.to_string(),
+        width: size.width,
+        height: size.height,
+        thumbnail_url: urls.thumb.to_string(),
+        thumbnail_width: thumb_size.width,
+        thumbnail_height: thumb_size.height,
+        tiberius_id: image.id,
+        tiberius_score: image.score,
+        tiberius_comments: image.comments_count,
+        tiberius_tags: tags.iter().map(|x| x.full_name()).collect(),
+    };
+    let mut headers = HeaderMap::new();
+    let content = match format {
+        OembedFormat::Json => {
+            headers.typed_insert(ContentType::json());
+            serde_json::to_string(&response)?
+        }
+        OembedFormat::Xml => {
+            headers.typed_insert(ContentType::from(mime::TEXT_XML));
+            response.to_xml()
+        }
+    };
+    Ok(TiberiusResponse::Custom(CustomResponse {
+        content: (StatusCode::OK, content),
+        headers,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::image_id_from_url;
+
+    #[test]
+    fn test_image_id_from_url() {
+        assert_eq!(Some(42), image_id_from_url("https://example.com/42"));
+        assert_eq!(
+            Some(42),
+            image_id_from_url("https://example.com/images/42?q=safe")
+        );
+        assert_eq!(
+            Some(42),
+            image_id_from_url("https://example.com/embed/42/small")
+        );
+        assert_eq!(Some(42), image_id_from_url("/42#comments"));
+        assert_eq!(None, image_id_from_url("https://example.com/tags/42"));
+        assert_eq!(None, image_id_from_url("https://example.com/images/abc"));
+    }
 }
@@ -11,6 +11,8 @@ use tiberius_core::{
 };
 use tiberius_models::{Client, Image, User};
 
+use crate::templates::session::require_step_up;
+
 #[derive(serde::Serialize, Debug)]
 pub struct ChangeUploader {
     new_uploader: String,
@@ -71,6 +73,10 @@ pub async fn change_image_uploader(
     if !verify_acl {
         return Err(TiberiusError::AccessDenied);
     }
+    let form_uri = PathApiV3ImageChangeUploader { image }.to_uri().to_string();
+    if let Some(step_up) = require_step_up(&rstate, &form_uri) {
+        return Ok(TiberiusResponse::Redirect(step_up));
+    }
     let new_uploader = User::get_by_name(&mut client, &change_uploader.new_uploader).await?;
     let new_uploader = match new_uploader {
         Some(v) => v,
@@ -1,13 +1,13 @@
-use axum::{extract::State, Extension};
+use axum::extract::State;
 use axum_extra::routing::TypedPath;
 use serde::Deserialize;
 use tiberius_core::{
     error::{TiberiusError, TiberiusResult},
-    request_helper::{SafeJsonResponse, TiberiusResponse},
-    session::{SessionMode, Unauthenticated},
-    state::{TiberiusRequestState, TiberiusState},
+    request_helper::SafeJsonResponse,
+    session::{ApiKeyAuth, ScopeRead},
+    state::TiberiusState,
 };
-use tiberius_models::{Image, User};
+use tiberius_models::Image;
 
 #[derive(TypedPath, Deserialize)]
 #[typed_path("/api/v3/images/:image")]
@@ -15,16 +15,16 @@ pub struct ApiV3ImageGetImageData {
     image: u64,
 }
 
-#[instrument(skip(state, rstate))]
+#[instrument(skip(state, _auth))]
 pub async fn get_image_data(
+    ApiV3ImageGetImageData { image }: ApiV3ImageGetImageData,
     State(state): State<TiberiusState>,
-    Extension(rstate): Extension<TiberiusRequestState<Unauthenticated>>,
-    image: u64,
+    _auth: ApiKeyAuth<ScopeRead>,
 ) -> TiberiusResult<SafeJsonResponse> {
     let mut client = state.get_db_client();
     let image = Image::get_id(&mut client, image as i64).await?;
     match image {
-        Some(image) => Ok(SafeJsonResponse::safe_serialize(image)?),
-        None => Err(TiberiusError::PageNotFound("image".to_string())),
+        Some(image) if image.publicly_visible() => Ok(SafeJsonResponse::safe_serialize(image)?),
+        _ => Err(TiberiusError::PageNotFound("image".to_string())),
     }
 }
@@ -1,4 +1,6 @@
 mod change_uploader;
 mod get;
+mod upload;
 pub use change_uploader::*;
 pub use get::*;
+pub use upload::*;
@@ -0,0 +1,221 @@
+use std::str::FromStr;
+
+use axum::{
+    body::Body,
+    extract::{FromRequest, Multipart, State},
+    headers::{ContentType, HeaderMapExt},
+    http::{HeaderMap, Request, StatusCode},
+    Json,
+};
+use axum_extra::routing::TypedPath;
+use serde::{Deserialize, Serialize};
+use tiberius_core::{
+    error::{TiberiusError, TiberiusResult},
+    remote::http_client_for_url,
+    request_helper::{CustomResponse, JsonResponse, TiberiusResponse},
+    session::{ApiKeyAuth, ScopeUpload},
+    state::TiberiusState,
+};
+use tiberius_dependencies::{mime, reqwest};
+use tokio::io::AsyncWriteExt;
+
+use crate::templates::images::{check_upload, spool_multipart, store_upload, ImageUpload};
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/v3/images")]
+pub struct PathApiV3ImageUpload {}
+
+/// Image metadata of an upload, shared by JSON and multipart uploads
+#[derive(Deserialize, Debug, Default)]
+pub struct ApiV3ImageUploadMeta {
+    #[serde(default)]
+    pub tag_input: String,
+    pub source_url: Option<String>,
+    pub description: Option<String>,
+    /// Defaults to the anonymous upload setting of the user
+    pub anonymous: Option<bool>,
+}
+
+/// JSON variant of an upload, the image is fetched from the given URL
+#[derive(Deserialize, Debug)]
+pub struct ApiV3ImageUploadJson {
+    pub url: String,
+    pub image: ApiV3ImageUploadMeta,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiV3ImageStatus {
+    Processing,
+    Processed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiV3ImageUploadResponse {
+    pub id: u64,
+    pub status: ApiV3ImageStatus,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiV3Error {
+    pub error: String,
+}
+
+fn json_headers() -> HeaderMap {
+    let mut hm = HeaderMap::new();
+    hm.typed_insert(ContentType::json());
+    hm
+}
+
+fn api_error(
+    status: StatusCode,
+    error: String,
+) -> TiberiusResult<TiberiusResponse<(StatusCode, String)>> {
+    Ok(TiberiusResponse::Custom(CustomResponse {
+        content: (status, serde_json::to_string(&ApiV3Error { error })?),
+        headers: json_headers(),
+    }))
+}
+
+/// Downloads the image at the given URL into the upload, using the configured proxy
+///
+/// Without a proxy, URLs pointing at loopback, private or link local addresses are refused.
+/// Downloads larger than the configured upload limit are rejected.
+#[instrument(skip(state, upload))]
+async fn fetch_upload_url(
+    state: &TiberiusState,
+    url: &str,
+    upload: &mut ImageUpload,
+) -> TiberiusResult<Result<(), String>> {
+    let limit = state.config().upload_max_size;
+    let url = match url::Url::parse(url) {
+        Ok(v) if v.scheme() == "http" || v.scheme() == "https" => v,
+        _ => return Ok(Err("The image URL is not a valid HTTP URL".to_string())),
+    };
+    let client = match http_client_for_url(state.config(), &url).await {
+        Ok(v) => v,
+        Err(TiberiusError::ForbiddenAddress(_)) => {
+            return Ok(Err(
+                "The image URL does not point to a public server".to_string()
+            ))
+        }
+        Err(e) => return Err(e),
+    };
+    let mut resp = client.get(url).send().await?;
+    if !resp.status().is_success() {
+        return Ok(Err(format!(
+            "Could not fetch image, remote server returned {}",
+            resp.status()
+        )));
+    }
+    if resp.content_length().unwrap_or_default() > limit {
+        return Ok(Err("The image is too large".to_string()));
+    }
+    upload.content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .map(mime::Mime::from_str)
+        .transpose()?
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let file = upload.image.as_file_mut().try_clone()?;
+    file.set_len(0)?;
+    let mut file = tokio::io::BufWriter::new(tokio::fs::File::from_std(file));
+    let mut written = 0u64;
+    while let Some(chunk) = resp.chunk().await? {
+        written += chunk.len() as u64;
+        if written > limit {
+            return Ok(Err("The image is too large".to_string()));
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    upload.scraper_url = Some(resp.url().to_string());
+    Ok(Ok(()))
+}
+
+/// Uploads a new image on behalf of the owner of the API Key
+///
+/// Accepts either a JSON body with an image URL or a multipart form with the image file,
+/// the same checks as the upload form are applied.
+#[instrument(skip(state, auth, request))]
+pub async fn upload_image(
+    State(state): State<TiberiusState>,
+    auth: ApiKeyAuth<ScopeUpload>,
+    _: PathApiV3ImageUpload,
+    request: Request<Body>,
+) -> TiberiusResult<TiberiusResponse<(StatusCode, String)>> {
+    let content_type: Option<mime::Mime> = request
+        .headers()
+        .typed_get::<ContentType>()
+        .map(|x| x.into());
+    let is_multipart = content_type
+        .as_ref()
+        .map(|x| x.type_() == mime::MULTIPART)
+        .unwrap_or(false);
+    let anonymous_by_default = auth.user.user_settings.anonymous_by_default;
+    let upload = if is_multipart {
+        let multipart = match Multipart::from_request(request, &state).await {
+            Ok(v) => v,
+            Err(e) => return api_error(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        let mut upload = spool_multipart(
+            multipart,
+            state.config().upload_max_size,
+            anonymous_by_default,
+        )
+        .await?;
+        if upload.image.as_file().metadata()?.len() == 0 {
+            match upload.scraper_url.clone() {
+                Some(url) => {
+                    if let Err(e) = fetch_upload_url(&state, &url, &mut upload).await? {
+                        return api_error(StatusCode::UNPROCESSABLE_ENTITY, e);
+                    }
+                }
+                None => {
+                    return api_error(
+                        StatusCode::BAD_REQUEST,
+                        "Either an image or an URL is required".to_string(),
+                    )
+                }
+            }
+        }
+        upload
+    } else {
+        let Json(json): Json<ApiV3ImageUploadJson> =
+            match Json::from_request(request, &state).await {
+                Ok(v) => v,
+                Err(e) => return api_error(StatusCode::BAD_REQUEST, e.to_string()),
+            };
+        let mut upload = ImageUpload {
+            anonymous: json.image.anonymous.unwrap_or(anonymous_by_default),
+            source_url: json.image.source_url,
+            tag_input: json.image.tag_input,
+            description: json.image.description,
+            scraper_url: None,
+            image: tempfile::NamedTempFile::new()?,
+            content_type: mime::APPLICATION_OCTET_STREAM,
+        };
+        if let Err(e) = fetch_upload_url(&state, &json.url, &mut upload).await? {
+            return api_error(StatusCode::UNPROCESSABLE_ENTITY, e);
+        }
+        upload
+    };
+    let mut client = state.get_db_client();
+    let checked = match check_upload(&state, &mut client, &auth.user, &upload).await? {
+        Ok(v) => v,
+        Err(e) => return api_error(StatusCode::UNPROCESSABLE_ENTITY, e),
+    };
+    let image = store_upload(&state, &mut client, &auth.user, upload, checked).await?;
+    Ok(TiberiusResponse::Json(JsonResponse {
+        content: serde_json::to_value(ApiV3ImageUploadResponse {
+            id: image.id as u64,
+            status: if image.processed {
+                ApiV3ImageStatus::Processed
+            } else {
+                ApiV3ImageStatus::Processing
+            },
+        })?,
+        headers: json_headers(),
+    }))
+}
@@ -1,2 +1,11 @@
+use axum::Router;
+use axum_extra::routing::RouterExt;
+use tiberius_core::state::TiberiusState;
+
 pub mod images;
 pub mod misc;
+
+pub fn setup_api_v3(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_post(images::upload_image)
+        .typed_get(images::get_image_data)
+}
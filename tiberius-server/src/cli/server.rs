@@ -13,7 +13,7 @@ use tiberius_core::{
     app::DBPool,
     config::Configuration,
     csp_header,
-    error::TiberiusResult,
+    error::{TiberiusError, TiberiusResult},
     session::{PostgresSessionStore, Unauthenticated},
     state::{TiberiusRequestState, TiberiusState, UrlDirections},
     CSPHeader, TIBERIUS_SESSION_CACHE_SIZE,
@@ -39,14 +39,23 @@ pub async fn run_migrations(
 
 pub fn setup_all_routes(router: Router<TiberiusState>) -> Router<TiberiusState> {
     let router = crate::api::int::setup_api_int(router);
+    let router = crate::api::v3::setup_api_v3(router);
     let router = crate::api::well_known::setup_well_known(router);
     let router = templates::activity::activity_pages(router);
     let router = templates::apikeys::api_key_pages(router);
+    let router = templates::badges::badge_pages(router);
     let router = templates::images::image_pages(router);
     let router = templates::channels::channel_pages(router);
+    let router = templates::feeds::feed_pages(router);
     let router = templates::session::session_pages(router);
+    let router = templates::settings::settings_pages(router);
     let router = templates::static_file_pages(router);
     let router = templates::tags::tags_pages(router);
+    let router = templates::two_factor::two_factor_pages(router);
+    let router = templates::webauthn::webauthn_pages(router);
+    let router = templates::user::user_pages(router);
+    let router = templates::user_links::user_link_pages(router);
+    let router = templates::user_sessions::user_session_pages(router);
     let router = templates::filters::setup_filters(router);
 
     tiberius_core::assets::embedded_file_pages(router)
@@ -119,6 +128,9 @@ pub async fn server_start(
     config: Configuration,
 ) -> TiberiusResult<()> {
     info!("Starting with config {:?}", config);
+    if let Err(e) = tiberius_mail::check_config(&config) {
+        return Err(TiberiusError::ConfigurationUnset(e.to_string()));
+    }
     let db_conn: DBPool = config.db_conn().await?;
     run_migrations(&config, db_conn.clone()).await?;
     debug!("Configuring application server");
@@ -144,7 +156,7 @@ pub async fn server_start(
     } else {
         None
     };
-    let server = axum.into_make_service();
+    let server = axum.into_make_service_with_connect_info::<std::net::SocketAddr>();
     let server = axum::Server::bind(&config.bind_to).serve(server);
     if start_job_scheduler {
         let scheduler = scheduler.unwrap();
@@ -13,6 +13,7 @@ use tracing::{error, warn};
 
 use crate::templates::session::PathSessionsLogin;
 
+pub mod captcha;
 pub mod channels;
 pub mod comment;
 pub mod filters;
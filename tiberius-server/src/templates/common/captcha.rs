@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use maud::{html, Markup};
+use ring::{hmac, rand::SecureRandom};
+use tiberius_captcha::{pony::PonyCaptcha, pow::ProofOfWorkCaptcha, AnyCaptchaProvider};
+use tiberius_core::{
+    config::{CaptchaForm, CaptchaKind, Configuration},
+    error::TiberiusResult,
+    state::TiberiusState,
+};
+use tiberius_dependencies::{
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    once_cell::sync::OnceCell,
+};
+use tiberius_models::{Client, UsedCaptchaCheck};
+
+lazy_static::lazy_static! {
+    static ref FALLBACK_CAPTCHA_KEY: [u8; 32] = {
+        let mut key = [0u8; 32];
+        ring::rand::SystemRandom::new()
+            .fill(&mut key)
+            .expect("could not generate captcha key");
+        key
+    };
+}
+
+/// Name of the form field carrying the signed check code of a captcha
+pub const CAPTCHA_CHECK_FIELD: &str = "captcha_check";
+
+/// Query of pages showing a captcha
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct CaptchaQuery {
+    /// Show a captcha that can be solved without seeing images
+    #[serde(default)]
+    pub accessible_captcha: bool,
+}
+
+static PONY_CAPTCHA: OnceCell<PonyCaptcha> = OnceCell::new();
+static POW_CAPTCHA: OnceCell<ProofOfWorkCaptcha> = OnceCell::new();
+
+fn captcha_secret(config: &Configuration) -> &[u8] {
+    match &config.captcha_secret {
+        Some(v) => v.as_bytes(),
+        None => &*FALLBACK_CAPTCHA_KEY,
+    }
+}
+
+/// Returns the kind of captcha to show, if any
+///
+/// The pony captcha cannot be solved without seeing the images, the proof of work captcha is
+/// shown instead if an accessible captcha was requested.
+fn captcha_kind(config: &Configuration, accessible: bool) -> CaptchaKind {
+    match config.captcha_provider {
+        CaptchaKind::Pony if accessible => CaptchaKind::ProofOfWork,
+        v => v,
+    }
+}
+
+fn kind_name(kind: CaptchaKind) -> &'static str {
+    match kind {
+        CaptchaKind::None => "none",
+        CaptchaKind::Pony => "pony",
+        CaptchaKind::ProofOfWork => "pow",
+    }
+}
+
+/// Returns the captcha provider of the given kind, if any
+///
+/// The provider is created once, as deriving the keys is not free.
+pub fn captcha_provider(
+    config: &Configuration,
+    kind: CaptchaKind,
+) -> Option<&'static dyn AnyCaptchaProvider> {
+    match kind {
+        CaptchaKind::None => None,
+        CaptchaKind::Pony => {
+            Some(PONY_CAPTCHA.get_or_init(|| PonyCaptcha::new(captcha_secret(config))))
+        }
+        CaptchaKind::ProofOfWork => Some(POW_CAPTCHA.get_or_init(|| {
+            ProofOfWorkCaptcha::new(captcha_secret(config), config.captcha_pow_difficulty)
+        })),
+    }
+}
+
+fn captcha_key(config: &Configuration) -> hmac::Key {
+    match &config.captcha_secret {
+        Some(v) => hmac::Key::new(hmac::HMAC_SHA256, v.as_bytes()),
+        None => hmac::Key::new(hmac::HMAC_SHA256, &*FALLBACK_CAPTCHA_KEY),
+    }
+}
+
+/// Signs the check code together with the kind of captcha it belongs to
+fn sign_check(config: &Configuration, kind: CaptchaKind, check: &str) -> String {
+    let check = format!("{}:{}", kind_name(kind), check);
+    let tag = hmac::sign(&captcha_key(config), check.as_bytes());
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(check),
+        URL_SAFE_NO_PAD.encode(tag.as_ref())
+    )
+}
+
+fn open_check(config: &Configuration, signed: &str) -> Option<(CaptchaKind, String)> {
+    let (check, tag) = signed.split_once('.')?;
+    let check = URL_SAFE_NO_PAD.decode(check).ok()?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    hmac::verify(&captcha_key(config), &check, &tag).ok()?;
+    let check = String::from_utf8(check).ok()?;
+    let (kind, check) = check.split_once(':')?;
+    Some((CaptchaKind::from_str(kind).ok()?, check.to_string()))
+}
+
+/// Renders a new captcha and it's signed check code for inclusion in the given form
+///
+/// Renders nothing if the form does not require a captcha. If `accessible` is set, a captcha
+/// that can be solved without seeing images is rendered. Otherwise captchas made of images link
+/// to the accessible variant of the current page.
+pub fn captcha_input(state: &TiberiusState, form: CaptchaForm, accessible: bool) -> Markup {
+    if !state.config.requires_captcha(form) {
+        return html! {};
+    }
+    let kind = captcha_kind(&state.config, accessible);
+    let provider = match captcha_provider(&state.config, kind) {
+        Some(v) => v,
+        None => return html! {},
+    };
+    let (check, captcha) = provider.generate();
+    html! {
+        .field {
+            input type="hidden" name=(CAPTCHA_CHECK_FIELD) value=(sign_check(&state.config, kind, &check));
+            (captcha)
+            @if kind == CaptchaKind::Pony {
+                p {
+                    "Can't see the images? "
+                    a href="?accessible_captcha=true" { "Use a check that works without images" }
+                }
+            }
+        }
+    }
+}
+
+/// Verifies the captcha submitted with an URL encoded body of the given form
+///
+/// Always succeeds if the form does not require a captcha. The check code is used up by any
+/// attempt, so the answer cannot be guessed by resubmitting the same captcha.
+pub async fn verify_captcha(
+    state: &TiberiusState,
+    client: &mut Client,
+    form: CaptchaForm,
+    form_body: &str,
+) -> TiberiusResult<bool> {
+    if !state.config.requires_captcha(form) {
+        return Ok(true);
+    }
+    if captcha_kind(&state.config, false) == CaptchaKind::None {
+        return Ok(true);
+    }
+    let signed =
+        tiberius_dependencies::serde_urlencoded::from_str::<Vec<(String, String)>>(form_body)
+            .ok()
+            .and_then(|fields| {
+                fields
+                    .into_iter()
+                    .find(|(k, _)| k == CAPTCHA_CHECK_FIELD)
+                    .map(|(_, v)| v)
+            });
+    let (kind, check) = match signed.and_then(|x| open_check(&state.config, &x)) {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    if kind != captcha_kind(&state.config, false) && kind != captcha_kind(&state.config, true) {
+        return Ok(false);
+    }
+    let verification = match captcha_provider(&state.config, kind)
+        .and_then(|provider| provider.verify(&check, form_body))
+    {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    let unused =
+        UsedCaptchaCheck::consume(client, &verification.id, verification.expires_at).await?;
+    Ok(unused && verification.solved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open_check, sign_check};
+    use tiberius_core::config::{CaptchaKind, Configuration};
+
+    #[test]
+    fn test_captcha_check_signature() {
+        let config = Configuration {
+            captcha_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let signed = sign_check(&config, CaptchaKind::ProofOfWork, "{\"check\":1}");
+        assert_eq!(
+            Some((CaptchaKind::ProofOfWork, "{\"check\":1}".to_string())),
+            open_check(&config, &signed)
+        );
+        let (check, tag) = signed.split_once('.').unwrap();
+        let tampered = format!("{}A.{}", check, tag);
+        assert_eq!(None, open_check(&config, &tampered));
+        let other = Configuration {
+            captcha_secret: Some("other".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(None, open_check(&other, &signed));
+    }
+}
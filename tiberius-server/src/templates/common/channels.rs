@@ -1,4 +1,4 @@
-use crate::templates::common::{camoed_url, pluralize};
+use crate::templates::common::{camoed_url, pluralize, streambox::channel_path};
 use crate::templates::tags::{PathTagsByNameShowTag, PathTagsShowTag};
 use axum_extra::routing::TypedPath;
 use maud::{html, Markup};
@@ -10,8 +10,9 @@ pub async fn channel_box(
     state: &TiberiusState,
     client: &mut Client,
     channel: &Channel,
+    actions: Option<Markup>,
 ) -> TiberiusResult<Markup> {
-    let channel_route = "";
+    let channel_route = channel_path(channel).to_string();
     let link_class = "media-box__header media-box__header--channel media-box__header--link";
     let artist_tag = channel.associated_artist_tag(client).await?;
     let header = html! {
@@ -37,7 +38,14 @@ pub async fn channel_box(
         .media-box__content.media-box__content--channel {
             a href=(channel_route) {
                 .image-constrained.media-box__content--channel {
-                    img src=(camoed_url(state, &url::Url::parse(channel.image())?).await) alt=(channel.title());
+                    @match channel.image() {
+                        Some(image) => {
+                            img src=(camoed_url(state, &url::Url::parse(image)?).await) alt=(channel.title());
+                        },
+                        None => {
+                            img src="/images/no_avatar.svg" alt=(channel.title());
+                        },
+                    }
                 }
             }
         }
@@ -65,6 +73,12 @@ pub async fn channel_box(
             (content)
 
             (artist)
+
+            @if let Some(actions) = actions {
+                .media-box__header.media-box__header--channel {
+                    (actions)
+                }
+            }
         }
     })
 }
@@ -9,6 +9,7 @@ use tiberius_common_html::no_avatar_svg;
 use tiberius_core::{
     app::PageTitle,
     assets::{QuickTagTableContent, SiteConfig},
+    config::Configuration,
     error::{TiberiusError, TiberiusResult},
     request_helper::FormMethod,
     session::{Session, SessionMode},
@@ -16,15 +17,25 @@ use tiberius_core::{
 };
 use tiberius_dependencies::axum_flash::{Flash, IncomingFlashes};
 use tiberius_dependencies::chrono::{DateTime, NaiveDateTime, Utc};
+use tiberius_dependencies::serde_urlencoded;
 
 use crate::{
     api::int::oembed::PathOembed,
     templates::{
-        common::routes::{cdn_host, dark_stylesheet_path, static_path, stylesheet_path},
+        common::{
+            routes::{
+                absolute_static_host, cdn_host, dark_stylesheet_path, static_path, stylesheet_path,
+            },
+            user::avatar_url,
+        },
+        feeds::{FeedFormat, PathFrontPageFeed},
+        files::PathBadgeImage,
         images::{PathSearchEmpty, PathShowImage},
         session::{PathNewSession, PathRegistration, PathSessionLogout},
+        settings::PathEditSettings,
         tags::PathTagsByNameShowTag,
-        PathImageThumbGetSimple,
+        user::PathUserProfile,
+        user_links::PathUserLinks,
     },
 };
 use either::Either;
@@ -89,67 +100,258 @@ pub fn form_submit_button(label: &str) -> Markup {
     }
 }
 
-#[instrument(skip(state, image))]
-pub async fn open_graph(state: &TiberiusState, image: Option<Image>) -> TiberiusResult<Markup> {
-    let mut client = state.get_db_client();
-    let filtered = !image
-        .as_ref()
-        .map(|x| x.thumbnails_generated)
-        .unwrap_or(false);
-    let description = image
-        .as_ref()
-        .map(|img| {
-            format!(
-                "{} - {} - Manebooru",
-                img.id,
-                img.tag_list_cache.as_deref().unwrap_or("")
-            )
-        })
-        .unwrap_or("# - # - Manebooru".to_string());
-    Ok(html! {
-        meta name="generator" content="tiberius";
-        meta name="theme-color" content="#618fc3";
-        meta name="format-detection" content="telephone=no";
-        @if let Some(image) = image {
-            meta name="keywords" content=(image.tag_list_cache.as_deref().unwrap_or(""));
-            meta name="description" content=(description);
-            meta property="og:title" content=(description);
-            meta property="og:url" content=(PathShowImage{ image: (image.id as u64) }.to_uri().to_string());
-
-            @for tag in artist_tags(&image.tags(&mut client).await?) {
-                meta property="dc:creator" content=(tag.full_name());
-            }
+/// Social and structured metadata of a page
+///
+/// Covers OpenGraph, Twitter cards and schema.org JSON-LD, all URLs are absolute since
+/// consumers of these tags do not resolve them against the page.
+#[derive(Debug, Clone, Default)]
+pub struct PageMetadata {
+    pub site_name: String,
+    pub title: String,
+    pub description: String,
+    pub url: Option<String>,
+    pub og_type: &'static str,
+    pub image_url: Option<String>,
+    pub image_width: Option<u32>,
+    pub image_height: Option<u32>,
+    pub video_url: Option<String>,
+    pub keywords: Option<String>,
+    pub creators: Vec<String>,
+    pub uploader: Option<String>,
+    pub source_url: Option<String>,
+    pub oembed_url: Option<String>,
+    pub uploaded_at: Option<NaiveDateTime>,
+}
 
-            @if let Some(source_url) = &image.source_url {
-                @if !source_url.is_empty() {
-                    meta property="foaf:primaryTopic" content=(source_url);
-                }
-            }
+impl PageMetadata {
+    /// Metadata for pages that do not show a specific image
+    pub fn for_site(site_config: &SiteConfig) -> Self {
+        let site_name = site_config.site_name().clone();
+        Self {
+            title: site_name.clone(),
+            description: format!(
+                "{site_name} is a linear imagebooru which lets you share, find and discover new art and media"
+            ),
+            site_name,
+            og_type: "website",
+            ..Default::default()
+        }
+    }
 
-            link rel="alternate" type="application/json-oembed" href=(PathOembed{}.to_uri().to_string()) title="oEmbed JSON Profile";
-            link rel="canonical" href=(PathShowImage{ image: (image.id as u64) }.to_uri().to_string());
+    /// Metadata for the page of a single image
+    ///
+    /// Images that are hidden, spoilered or filtered for the viewer do not expose their
+    /// thumbnail, a placeholder is used instead.
+    pub async fn for_image<T: SessionMode>(
+        state: &TiberiusState,
+        rstate: &TiberiusRequestState<T>,
+        client: &mut Client,
+        image: &Image,
+    ) -> TiberiusResult<Self> {
+        let filter = rstate.filter(state).await?;
+        let filtered = filter.hides_image(image) || filter.spoilers_image(image);
+        Self::for_image_with(
+            state.site_config(),
+            state.config(),
+            absolute_static_host(state, rstate),
+            client,
+            image,
+            filtered,
+        )
+        .await
+    }
 
-            @match (image.image_mime_type.as_deref(), filtered) {
-                (Some("video/webm"), false) => {
-                    meta property="og:type" content="video.other";
+    /// Metadata for the page of a single image, `filtered` is set if the filter of the viewer
+    /// hides or spoilers the image
+    ///
+    /// Hidden and filtered images only get a generic title and description, as their tags could
+    /// reveal what the image shows.
+    async fn for_image_with(
+        site_config: &SiteConfig,
+        config: &Configuration,
+        static_host: String,
+        client: &mut Client,
+        image: &Image,
+        filtered: bool,
+    ) -> TiberiusResult<Self> {
+        let mut meta = Self::for_site(site_config);
+        let filtered = filtered || !image.thumbnails_generated || !image.publicly_visible();
+        let url = config.absolute_url(
+            PathShowImage {
+                image: image.id as u64,
+            }
+            .to_uri(),
+        );
+        meta.oembed_url = Some(config.absolute_url(format!(
+            "{}?{}",
+            PathOembed {}.to_uri(),
+            serde_urlencoded::to_string([("url", url.as_str())]).unwrap_or_default()
+        )));
+        meta.url = Some(url);
+        if filtered {
+            meta.title = format!("{} - {}", image.id, meta.site_name);
+            meta.description = meta.title.clone();
+            meta.image_url = Some(format!(
+                "{}{}",
+                static_host,
+                static_path("images/tagblocked.svg").to_string_lossy()
+            ));
+            return Ok(meta);
+        }
+        meta.title = format!(
+            "{} - {} - {}",
+            image.id,
+            image.tag_list_cache.as_deref().unwrap_or(""),
+            meta.site_name
+        );
+        meta.description = meta.title.clone();
+        meta.keywords = image.tag_list_cache.clone();
+        meta.creators = artist_tags(&image.tags(client).await?)
+            .into_iter()
+            .map(|x| x.full_name())
+            .collect();
+        meta.source_url = image.source_url.clone().filter(|x| !x.is_empty());
+        meta.uploaded_at = Some(image.created_at);
+        if !image.anonymous.unwrap_or(false) {
+            if let Some(user_id) = image.user_id {
+                meta.uploader = User::get_id(client, user_id as i64).await?.map(|x| x.name);
+            }
+        }
+        let urls = image.image_thumb_urls().await?.with_host(Some(static_host));
+        let (height, width) = (
+            image.image_height.unwrap_or(1).max(1) as u32,
+            image.image_width.unwrap_or(1).max(1) as u32,
+        );
+        let size = match image.image_mime_type.as_deref() {
+            Some("video/webm") => {
+                meta.og_type = "video.other";
+                meta.image_url = Some(urls.rendered.to_string());
+                meta.video_url = Some(urls.large.to_string());
+                Some(ImageThumbType::Rendered)
+            }
+            Some("image/svg+xml") => {
+                meta.image_url = Some(urls.rendered.to_string());
+                Some(ImageThumbType::Rendered)
+            }
+            _ => {
+                meta.image_url = Some(urls.large.to_string());
+                Some(ImageThumbType::Large)
+            }
+        };
+        let size = size
+            .and_then(|x| x.to_resolution_limit())
+            .map(|x| x.clamp_resolution(height, width));
+        meta.image_width = Some(size.map(|x| x.width).unwrap_or(width));
+        meta.image_height = Some(size.map(|x| x.height).unwrap_or(height));
+        Ok(meta)
+    }
 
-                    meta property="og:image" content=(PathImageThumbGetSimple{ id: image.id as u64, thumbtype : "".to_string(), filename : image.filetypef("rendered")}.to_uri().to_string());
-                    meta property="og:video" content=(PathImageThumbGetSimple{ id: image.id as u64, thumbtype : "".to_string(), filename : image.filetypef("large")}.to_uri().to_string());
-                },
-                (Some("image/svg+xml"), false) => {
-                    meta property="og:type" content="website";
-                    meta property="og:image" content=(PathImageThumbGetSimple{ id: image.id as u64, thumbtype : "".to_string(), filename : image.filetypef("rendered")}.to_uri().to_string());
-                },
-                (_, false) => {
-                    meta property="og:type" content="website";
-                    meta property="og:image" content=(PathImageThumbGetSimple{ id: image.id as u64, thumbtype : "large".to_string(), filename : image.filename()}.to_uri().to_string());
-                },
-                _ => { meta property="og:type" content="website"; },
+    /// schema.org `ImageObject` describing the image, if the page shows one
+    pub fn json_ld(&self) -> Option<serde_json::Value> {
+        let url = self.url.as_ref()?;
+        let mut obj = serde_json::json!({
+            "@context": "https://schema.org",
+            "@type": "ImageObject",
+            "url": url,
+            "name": self.title,
+            "description": self.description,
+            "contentUrl": self.image_url,
+            "width": self.image_width,
+            "height": self.image_height,
+            "keywords": self.keywords,
+            "provider": {
+                "@type": "Organization",
+                "name": self.site_name,
+            },
+            "creator": self.creators.iter().map(|x| serde_json::json!({
+                "@type": "Person",
+                "name": x,
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(uploader) = &self.uploader {
+            obj["author"] = serde_json::json!({
+                "@type": "Person",
+                "name": uploader,
+            });
+        }
+        if let Some(uploaded_at) = &self.uploaded_at {
+            obj["uploadDate"] =
+                serde_json::json!(DateTime::<Utc>::from_utc(*uploaded_at, Utc).to_rfc3339());
+        }
+        if let Some(source_url) = &self.source_url {
+            obj["isBasedOn"] = serde_json::json!(source_url);
+        }
+        Some(obj)
+    }
+
+    pub fn render(&self) -> Markup {
+        // JSON does not escape the closing script tag on its own
+        let json_ld = self
+            .json_ld()
+            .map(|x| x.to_string().replace('<', "\\u003c"));
+        html! {
+            meta name="generator" content="tiberius";
+            meta name="theme-color" content="#618fc3";
+            meta name="format-detection" content="telephone=no";
+            meta name="description" content=(self.description);
+            @if let Some(keywords) = &self.keywords {
+                meta name="keywords" content=(keywords);
+            }
+            meta property="og:site_name" content=(self.site_name);
+            meta property="og:title" content=(self.title);
+            meta property="og:description" content=(self.description);
+            meta property="og:type" content=(self.og_type);
+            @if let Some(url) = &self.url {
+                meta property="og:url" content=(url);
+                link rel="canonical" href=(url);
+            }
+            @for creator in &self.creators {
+                meta property="dc:creator" content=(creator);
+            }
+            @if let Some(source_url) = &self.source_url {
+                meta property="foaf:primaryTopic" content=(source_url);
+            }
+            link rel="alternate" type="application/atom+xml" href=(PathFrontPageFeed{ format: FeedFormat::Atom }.to_uri().to_string()) title=(format!("{} - Recently uploaded", self.site_name));
+            @if let Some(oembed_url) = &self.oembed_url {
+                link rel="alternate" type="application/json+oembed" href=(oembed_url) title="oEmbed JSON Profile";
+            }
+            @if let Some(image_url) = &self.image_url {
+                meta property="og:image" content=(image_url);
+                @if let Some(width) = self.image_width {
+                    meta property="og:image:width" content=(width);
+                }
+                @if let Some(height) = self.image_height {
+                    meta property="og:image:height" content=(height);
+                }
+                meta name="twitter:card" content="summary_large_image";
+                meta name="twitter:title" content=(self.title);
+                meta name="twitter:description" content=(self.description);
+                meta name="twitter:image" content=(image_url);
+            }
+            @if let Some(video_url) = &self.video_url {
+                meta property="og:video" content=(video_url);
+            }
+            @if let Some(json_ld) = json_ld {
+                script type="application/ld+json" { (PreEscaped(json_ld)) }
             }
-        } @else {
-            meta name="description" content="Manebooru is a linear imagebooru which lets you share, find and discover new art and media surrounding the show My Little Pony: Friendship is Magic";
         }
-    })
+    }
+}
+
+#[instrument(skip(state, rstate, image))]
+pub async fn open_graph<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    image: Option<Image>,
+) -> TiberiusResult<Markup> {
+    let meta = match image {
+        Some(image) => {
+            let mut client = state.get_db_client();
+            PageMetadata::for_image(state, rstate, &mut client, &image).await?
+        }
+        None => PageMetadata::for_site(state.site_config()),
+    };
+    Ok(meta.render())
 }
 
 pub fn artist_tags(tags: &[Tag]) -> Vec<&Tag> {
@@ -304,7 +506,7 @@ pub async fn header<T: SessionMode>(
     state: &TiberiusState,
     rstate: &TiberiusRequestState<T>,
 ) -> TiberiusResult<Markup> {
-    let notifications = rstate.notifications().await?;
+    let notifications = rstate.notifications(state).await?;
     let mut client = state.get_db_client();
     let filter: &Filter = rstate.filter(state).await?;
     trace!("preloading data for header html");
@@ -398,8 +600,8 @@ pub async fn header<T: SessionMode>(
                                 a.header__link href="/search?q=my:uploads" { i.fa.fa-fw.fa-upload { "Uploads"; } }
                                 a.header__link href="/comments?cq=my:comments" { i.fa.fa-fw.fa-comments { "Comments"; } }
                                 a.header__link href="/posts?pq=my:watched" { i.fa.fa-fw.fa-pen-square { "Posts"; } }
-                                a.header__link href=(PathRegistration{}.to_uri().to_string()) { i.fa.fa-fw.fa-link { "Links"; } }
-                                a.header__link href="/settings/edit" { i.fa.fa-fw.fa-cogs { "Settings"; } }
+                                a.header__link href=(PathUserLinks{}.to_uri().to_string()) { i.fa.fa-fw.fa-link { "Links"; } }
+                                a.header__link href=(PathEditSettings{}.to_uri().to_string()) { i.fa.fa-fw.fa-cogs { "Settings"; } }
                                 a.header__link href="/conversations" { i.fa.fa-fw.fa-envelope { "Messages"; } }
                                 a.header__link href=(PathRegistration{}.to_uri().to_string()) { i.fa.fa-fw.fa-user { "Account"; } }
                                 a.header__link href=(PathSessionLogout{}.to_uri().to_string()) { i.fa.fa-fw.fa-sign-out-alt { "Logout"; } }
@@ -674,7 +876,7 @@ pub async fn clientside_data<'a, T: SessionMode>(
     rstate: &TiberiusRequestState<T>,
 ) -> TiberiusResult<Markup> {
     let extra = rstate.csd_extra().await?;
-    let interactions = rstate.interactions().await?;
+    let interactions = rstate.interactions(state).await?;
     let user = rstate.user(state).await?;
     let filter = rstate.filter(state).await?;
 
@@ -702,6 +904,7 @@ pub async fn clientside_data<'a, T: SessionMode>(
     );
     insert_csd!(data, user_is_signed_in, user.is_some());
     insert_csd!(data, interactions, interactions);
+    insert_csd!(data, autoplay, rstate.user_settings(state).await?.autoplay);
     if let Some(user) = user {
         insert_csd!(data, user_id, user.id);
         insert_csd!(data, user_name, user.name);
@@ -715,7 +918,7 @@ pub async fn clientside_data<'a, T: SessionMode>(
                 false
             }
         );
-        insert_csd!(data, spoiler_type, user.user_settings.spoiler_type);
+        insert_csd!(data, spoiler_type, user.user_settings.spoiler_type());
         insert_csd!(data, watched_tag_list, user.user_settings.watched_tag_ids);
         insert_csd!(
             data,
@@ -801,7 +1004,7 @@ pub async fn user_attribution<S: ToString>(
 ) -> TiberiusResult<maud::Markup> {
     Ok(html! {
         strong {
-            a href="user link" { (user.displayname()) }
+            a href=(PathUserProfile { username: user.name.clone() }.to_uri().to_string()) { (user.displayname()) }
         }
         (user_badges(user, client).await?)
     })
@@ -820,8 +1023,8 @@ pub fn user_attribution_avatar<S: ToString>(
         classes
     };
     Ok(match av {
-        Either::Left(url) => html! {
-            div class=(classes) { img src=(url) {} }
+        Either::Left(avatar) => html! {
+            div class=(classes) { img src=(avatar_url(avatar)) {} }
         },
         Either::Right(markup) => html! {
             div class=(classes) { (markup) }
@@ -837,7 +1040,14 @@ pub fn badge_image(
     height: u64,
 ) -> maud::Markup {
     html! {
-        img src=(img.unwrap_or(&"placeholder/url".to_string())) alt=(alt) title=(title) width=(width) height=(height) {}
+        @match img {
+            Some(img) => {
+                img src=(PathBadgeImage { filename: img.clone() }.to_uri().to_string()) alt=(alt) title=(title) width=(width) height=(height) {}
+            },
+            None => {
+                i.fa.fa-certificate title=(title) style=(format!("font-size: {}px", height)) {}
+            },
+        }
     }
 }
 
@@ -862,7 +1072,7 @@ pub async fn user_badges(user: &User, client: &mut Client) -> TiberiusResult<mau
                     i.fa.fa-caret-down {}
                     .dropdown__content.block__header {
                         .badges.flex--column {
-                            @for badge in badges {
+                            @for badge in overflow {
                                 .badge {
                                     (badge_image(badge.image.as_ref(), badge.title(), badge.title(), 18, 18))
                                 }
@@ -895,15 +1105,16 @@ pub async fn app<T: SessionMode>(
                 //TODO: make title customizable
                 Some(title) => {
                     let title: String = title.into();
-                    title + " - Manebooru"
+                    format!("{} - {}", title, state.site_config().site_name())
                 },
-                None => "Manebooru".to_string(),
+                None => state.site_config().site_name().clone(),
             }
         ) }
     };
     let links_and_meta = html! {
         link rel="stylesheet" href=(stylesheet_path(state, rstate).await?);
-        @if rstate.user(state).await?.is_some() {
+        // only the default theme follows the color scheme of the browser
+        @if rstate.user(state).await?.is_some() && rstate.theme_name(state).await? == "default" {
             link rel="stylesheet" href=(dark_stylesheet_path(rstate)?) media="(prefers-color-scheme: dark)";
         }
         link rel="icon" href="/favicon.ico" type="image/x-icon";
@@ -936,7 +1147,7 @@ pub async fn app<T: SessionMode>(
     let script = html! {
         script type="text/javascript" src=(static_path("js/app.js").to_string_lossy()) async="async" {}
         /*(maud::PreEscaped("</script>"));*/
-        (open_graph(state, image).await?);
+        (open_graph(state, rstate, image).await?);
     };
     Ok(html! {
         (maud::DOCTYPE)
@@ -949,3 +1160,58 @@ pub async fn app<T: SessionMode>(
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use axum_extra::routing::TypedPath;
+    use tiberius_core::{assets::SiteConfig, config::Configuration, error::TiberiusResult};
+    use tiberius_models::{Client, Image};
+
+    use super::PageMetadata;
+    use crate::templates::images::PathShowImage;
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_metadata_of_hidden_image() -> TiberiusResult<()> {
+        let mut client = Client::new(pool, None);
+        let config = Configuration {
+            base_url: Some(url::Url::parse("https://booru.example").unwrap()),
+            ..Default::default()
+        };
+        let image = Image {
+            id: 42,
+            thumbnails_generated: true,
+            hidden_from_users: true,
+            tag_list_cache: Some("secret tag".to_string()),
+            source_url: Some("https://source.example/secret".to_string()),
+            ..Default::default()
+        };
+        let meta = PageMetadata::for_image_with(
+            &SiteConfig::default(),
+            &config,
+            "https://static.example".to_string(),
+            &mut client,
+            &image,
+            false,
+        )
+        .await?;
+        assert_eq!(
+            Some(format!(
+                "https://booru.example{}",
+                PathShowImage { image: 42 }.to_uri()
+            )),
+            meta.url
+        );
+        assert!(meta
+            .oembed_url
+            .as_deref()
+            .unwrap()
+            .starts_with("https://booru.example/"));
+        assert_eq!(None, meta.keywords);
+        assert_eq!(None, meta.source_url);
+        assert!(meta.creators.is_empty());
+        let html = meta.render().into_string();
+        assert!(!html.contains("secret"), "{}", html);
+        assert!(html.contains("images/tagblocked.svg"));
+        Ok(())
+    }
+}
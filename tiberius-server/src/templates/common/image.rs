@@ -152,9 +152,10 @@ pub async fn image_block<
         "Got {total} images: {:?}",
         images.iter().map(|x| x.id).collect_vec()
     );
+    rstate.interactions_for(&images.iter().map(|x| x.id as i64).collect_vec());
     let pagination = PaginationCtl::new(
         0,
-        25,
+        page_size as u8,
         &["q", "sf", "sd"],
         total,
         "images",
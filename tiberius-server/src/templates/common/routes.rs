@@ -7,32 +7,27 @@ use tiberius_core::{
     session::SessionMode,
     state::{TiberiusRequestState, TiberiusState},
 };
-use tiberius_models::{Channel, Client, Forum, Image, ImageThumbType, ImageThumbUrl, Tag, User};
+use tiberius_models::{
+    Channel, Client, Forum, Image, ImageThumbType, ImageThumbUrl, Tag, User, UserSettings,
+};
 
 pub async fn stylesheet_path<T: SessionMode>(
     state: &TiberiusState,
     rstate: &TiberiusRequestState<T>,
 ) -> TiberiusResult<String> {
-    let user = rstate.user(state).await?;
-    Ok(if let Some(user) = user {
-        let mut path = PathBuf::from_str("css/")?;
-        assert!(
-            !user.user_settings.theme.contains('/'),
-            "User theme cannot contain path segments: {:?}",
-            user.user_settings.theme
-        );
-        path.push(format!("{}.css", user.user_settings.theme));
-        assert!(
-            path.is_relative(),
-            "user theme path ({:?}) must be relative",
-            path
-        );
-        static_path(path).to_string_lossy().to_string()
-    } else {
-        static_path(PathBuf::from_str("css/default.css")?)
-            .to_string_lossy()
-            .to_string()
-    })
+    theme_stylesheet_path(&rstate.theme_name(state).await?)
+}
+
+/// Returns the stylesheet of a theme, the theme must be one of the themes users can pick
+pub fn theme_stylesheet_path(theme: &str) -> TiberiusResult<String> {
+    assert!(
+        UserSettings::THEMES.contains(&theme),
+        "Unknown user theme: {:?}",
+        theme
+    );
+    let mut path = PathBuf::from_str("css/")?;
+    path.push(format!("{}.css", theme));
+    Ok(static_path(path).to_string_lossy().to_string())
 }
 
 pub fn dark_stylesheet_path<T: SessionMode>(
@@ -103,3 +98,24 @@ pub async fn cdn_host<T: SessionMode>(
             .unwrap_or("invalid or missing host header".to_string()),
     )
 }
+
+/// Returns the static host including a scheme, suitable for [ImageThumbUrl::with_host]
+///
+/// Protocol-relative or bare hosts are assumed to be served over HTTPS.
+pub fn absolute_static_host<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+) -> String {
+    with_scheme(state.config().static_host(Some(rstate)))
+}
+
+fn with_scheme(host: String) -> String {
+    if host.starts_with("http://") || host.starts_with("https://") {
+        host.trim_end_matches('/').to_string()
+    } else {
+        format!(
+            "https://{}",
+            host.trim_start_matches("//").trim_end_matches('/')
+        )
+    }
+}
@@ -6,7 +6,7 @@ use tiberius_core::{
     session::{SessionMode, Unauthenticated},
     state::TiberiusRequestState,
 };
-use tiberius_models::{Channel, Client};
+use tiberius_models::{Channel, ChannelType, Client};
 use url::Url;
 
 pub async fn stream_box(
@@ -58,7 +58,16 @@ pub async fn stream_box(
 }
 
 pub fn channel_path(channel: &Channel) -> Url {
-    let mut url = Url::from_str("https://picarto.tv/").unwrap();
-    url.path_segments_mut().unwrap().push(&channel.short_name);
+    let (base, prefix): (&str, &[&str]) = match channel.r#type {
+        ChannelType::PicartoChannel => ("https://picarto.tv/", &[]),
+        ChannelType::PiczelChannel => ("https://piczel.tv/", &["watch"]),
+        ChannelType::TwitchChannel => ("https://www.twitch.tv/", &[]),
+    };
+    let mut url = Url::from_str(base).unwrap();
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .extend(prefix)
+        .push(&channel.short_name);
     url
 }
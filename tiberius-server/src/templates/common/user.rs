@@ -3,11 +3,19 @@ use maud::{html, Markup, PreEscaped};
 use tiberius_common_html::no_avatar_svg;
 use tiberius_core::session::SessionMode;
 use tiberius_core::state::TiberiusRequestState;
-use tiberius_core::{error::TiberiusResult, session::Unauthenticated, state::TiberiusState};
+use tiberius_core::{error::TiberiusResult, state::TiberiusState};
 use tiberius_models::{Client, Identifiable, IdentifiesUser, User};
 
+use crate::templates::common::frontmatter::user_badges;
 use crate::templates::user::{PathUserAvatar, PathUserProfileId};
 
+/// Returns the URL an avatar is served under by [PathUserAvatar]
+///
+/// Avatars may be nested in directories, so the path is not escaped like a single segment.
+pub fn avatar_url(avatar: &str) -> String {
+    format!("/avatar/{}", avatar.trim_start_matches('/'))
+}
+
 pub fn user_attribution_avatar(
     state: &TiberiusState,
     client: &mut Client,
@@ -19,8 +27,7 @@ pub fn user_attribution_avatar(
                 Some(user) => {
                     @match &user.avatar {
                         Some(avatar) => {
-                            @let av_root = state.config.static_host::<Unauthenticated>(None);
-                                img src=(format!("{}/avatars/{}", av_root, avatar.clone()));
+                            img src=(avatar_url(avatar));
                         },
                         None => (no_avatar_svg()),
                     }
@@ -56,12 +63,12 @@ where
                                 a href=(PathUserProfileId{ user_id }.to_uri().to_string()) {
                                     @let user = User::get_id(client, user_id).await?.expect("user linked to comment does not exist");
                                     (user.displayname())
-                                    // todo render awards
                                 }
                             },
                             None => "Could not find user",
                         }
                     }
+                    (user_badges(user, client).await?)
                 } @else {
                     strong {
                         (anonymous_name(source.id(), source.best_user_identifier(client).await?, false))
@@ -3,7 +3,7 @@ use std::str::FromStr;
 use async_std::path::PathBuf;
 use axum::{
     extract::State,
-    headers::{ContentType, HeaderMapExt},
+    headers::{CacheControl, ContentType, HeaderMapExt},
     http::{HeaderMap, StatusCode},
     Extension, Router,
 };
@@ -27,10 +27,14 @@ use tiberius_models::{Client, Image, ImageThumbType, PathImageGetFull, PathImage
 use tokio::fs::File;
 use tracing::trace;
 
+/// How long browsers may cache badge images, in seconds
+const BADGE_IMAGE_MAX_AGE: u64 = 7 * 24 * 60 * 60;
+
 pub fn static_file_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     r.typed_get(image_thumb_get)
         .typed_get(image_thumb_get_simple)
         .typed_get(image_full_get)
+        .typed_get(badge_image_get)
 }
 
 #[derive(TypedPath, serde::Deserialize)]
@@ -86,6 +90,7 @@ impl From<&Image> for PathImageGetShort {
 
 lazy_static! {
     static ref FILENAME_REGEX: Regex = Regex::new(r#"(?P<id>\d+)(__.+)?(?P<ext>\.\w+)"#).unwrap();
+    static ref BADGE_FILENAME_REGEX: Regex = Regex::new(r#"^[0-9a-f\-]+\.\w+$"#).unwrap();
 }
 
 #[instrument]
@@ -147,6 +152,48 @@ pub async fn image_full_get(
     }))
 }
 
+#[derive(TypedPath, serde::Deserialize)]
+#[typed_path("/media/badges/:filename")]
+pub struct PathBadgeImage {
+    pub filename: String,
+}
+
+/// Serves a badge image, badge images are never changed in place so they are cached for long
+#[instrument]
+pub async fn badge_image_get(
+    PathBadgeImage { filename }: PathBadgeImage,
+    State(state): State<TiberiusState>,
+) -> TiberiusResult<TiberiusResponse<AsyncReadBody<File>>> {
+    if !BADGE_FILENAME_REGEX.is_match(&filename) {
+        return Err(TiberiusError::PageNotFound(format!(
+            "Could not find badge image {filename}"
+        )));
+    }
+    let path = state.config().badge_base().join(&filename);
+    trace!("badge image path: {}", path.display());
+    let mime = new_mime_guess::from_path(path.clone());
+    let mime = mime.first().unwrap_or(mime::IMAGE_PNG);
+    let file = File::open(path).await?;
+    let mut hm = HeaderMap::new();
+    hm.typed_insert(ContentType::from(mime));
+    hm.typed_insert(
+        CacheControl::new()
+            .with_public()
+            .with_max_age(std::time::Duration::from_secs(BADGE_IMAGE_MAX_AGE)),
+    );
+    // badges may be SVG, which must not run scripts when opened directly
+    hm.insert(
+        axum::http::header::CONTENT_SECURITY_POLICY,
+        axum::http::HeaderValue::from_static(
+            "default-src 'none'; style-src 'unsafe-inline'; sandbox",
+        ),
+    );
+    Ok(TiberiusResponse::Custom(CustomResponse {
+        content: AsyncReadBody::new(file),
+        headers: hm,
+    }))
+}
+
 #[instrument]
 pub async fn image_thumb_get(
     PathImageThumbGet {
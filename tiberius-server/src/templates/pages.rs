@@ -21,14 +21,21 @@ use crate::templates::session::PathSessionsLogin;
 
 pub mod activity;
 pub mod apikeys;
+pub mod badges;
 pub mod blog;
 pub mod channels;
 pub mod errors;
+pub mod feeds;
 pub mod filters;
 pub mod images;
 pub mod session;
+pub mod settings;
 pub mod tags;
+pub mod two_factor;
 pub mod user;
+pub mod user_links;
+pub mod user_sessions;
+pub mod webauthn;
 
 pub async fn todo_page<S: Into<String>>(name: S) -> TiberiusResult<Markup> {
     let name: String = name.into();
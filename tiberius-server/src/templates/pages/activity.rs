@@ -1,26 +1,36 @@
 use crate::templates::{
     blog::PathBlogPage,
     common::{
+        frontmatter::pretty_time,
         image::{
             image_block, image_block_default_sort, image_box, DisplaySize, HeaderSize,
             ImageBlockHeader, ImageSize,
         },
         streambox::stream_box,
+        user::user_attribution_main,
     },
-    images::{PathQuerySearch, PathSearchEmpty, QuerySearch},
+    images::{PathQuerySearch, PathSearchEmpty, PathShowImage, QuerySearch},
 };
 use axum::{extract::State, Extension, Router};
 use axum_extra::routing::{RouterExt, TypedPath};
 use maud::{html, Markup, PreEscaped};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use tiberius_core::{
     acl::{ACLActionImage, ACLObject, ACLSubject},
-    error::TiberiusResult,
+    error::{TiberiusError, TiberiusResult},
     request_helper::{HtmlResponse, TiberiusResponse},
     session::{SessionMode, Unauthenticated},
-    state::{TiberiusRequestState, TiberiusState},
+    state::{PageSubtextCacheTag, TiberiusRequestState, TiberiusState},
 };
-use tiberius_models::{Client, Image, ImageSortBy, SortDirection};
+use tiberius_models::{
+    comment::Comment, Client, Forum, Image, ImageSortBy, SortDirection, Topic, User,
+};
+
+/// How many comments are shown in the Recent Comments block
+const RECENT_COMMENTS: i64 = 6;
+/// How many topics are shown in the Forum Activity block
+const RECENT_TOPICS: i64 = 6;
 
 pub fn activity_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     r.typed_get(index)
@@ -37,7 +47,9 @@ pub async fn index(
     rstate: TiberiusRequestState<Unauthenticated>,
 ) -> TiberiusResult<TiberiusResponse<()>> {
     let mut client: Client = state.get_db_client();
-    let show_sidebar = true; //TODO: check setting
+    let user = rstate.user(&state).await?;
+    let settings = rstate.user_settings(&state).await?;
+    let show_sidebar = settings.show_sidebar_and_watched_images;
     let featured_image = Image::get_featured(&mut client).await?;
     let body = html! {
         div.column-layout {
@@ -46,7 +58,7 @@ pub async fn index(
                     @if let Some(featured_image) = featured_image {
                         @if !featured_image.hidden(&mut client)? {
                             .center {
-                                h4.remove-top-margin { "Manebooru Spotlight" }
+                                h4.remove-top-margin { (state.site_config().site_name()) " Spotlight" }
                                 (image_box(&state, &rstate, &mut client, featured_image, ImageSize::Medium, HeaderSize::None, DisplaySize::Featured).await?)
                             }
                         }
@@ -59,36 +71,44 @@ pub async fn index(
                         "Issues? Want to chat? "
                         a href=(PathBlogPage{page: "context".to_string()}.to_uri().to_string()) { "Contact us!" }
                     }
-                    .block.hide-mobile {
-                        a.block__header--single-item.center href=(PathQuerySearch{search: "created_at.gte:10 minutes ago".to_string(), order: Some("wilson_score".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) {
-                            "Trending Images"
-                        }
-                        .block__content.flex.flex--centered.flex--wrap.image-flex-grid {
-                            @for image in Image::search(&mut client, "created_at.gte:10 minutes ago", vec!["safe", "processed.eq:true"], vec!["deleted.eq:true"], ImageSortBy::WilsonScore(SortDirection::Descending), 0, 4).await?.1 {
-                                (image_box(&state, &rstate, &mut client, image, ImageSize::Medium, HeaderSize::ThumbSmall, DisplaySize::Normal).await?)
+                    @if settings.show_sidebar_trending_images {
+                        .block.hide-mobile {
+                            a.block__header--single-item.center href=(PathQuerySearch{search: "created_at.gte:10 minutes ago".to_string(), order: Some("wilson_score".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) {
+                                "Trending Images"
+                            }
+                            .block__content.flex.flex--centered.flex--wrap.image-flex-grid {
+                                @for image in Image::search(&mut client, "created_at.gte:10 minutes ago", vec!["safe", "processed.eq:true"], vec!["deleted.eq:true"], ImageSortBy::WilsonScore(SortDirection::Descending), 0, 4).await?.1 {
+                                    (image_box(&state, &rstate, &mut client, image, ImageSize::Medium, HeaderSize::ThumbSmall, DisplaySize::Normal).await?)
+                                }
                             }
+                            a.block__header--single-item.center href=(PathQuerySearch{search: "".to_string(), order: Some("score".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) { "All Time Top Scoring" }
                         }
-                        a.block__header--single-item.center href=(PathQuerySearch{search: "".to_string(), order: Some("score".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) { "All Time Top Scoring" }
                     }
-                    .block.hide-mobile {
-                        a.block__header--single-item.center href="/channels" { "Streams" }
-                        (stream_box(&rstate, &mut client).await?)
+                    @if settings.show_sidebar_streams {
+                        .block.hide-mobile {
+                            a.block__header--single-item.center href="/channels" { "Streams" }
+                            (stream_box(&rstate, &mut client).await?)
+                        }
                     }
-                    .block.hide-mobile {
-                        a.block__header--single-item.center href="/forums" { "Forum Activity" }
-                        //TODO: implement forum activity box
+                    @if settings.show_sidebar_forum_activity {
+                        .block.hide-mobile {
+                            a.block__header--single-item.center href="/forums" { "Forum Activity" }
+                            (forum_activity(&state, &mut client).await?)
+                        }
                     }
-                    .block.hide-mobile {
-                        a.block__header--single-item.center href="/comments" { "Recent Comments" }
-                        //TODO: show recent comments
-                        a.block__header--single-item.center href=(PathQuerySearch{search: "created_at.gte:10 minutes ago".to_string(), order: Some("comment_count".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) {
-                            "Most Commented-on Images"
+                    @if settings.show_sidebar_recent_comments {
+                        .block.hide-mobile {
+                            a.block__header--single-item.center href="/comments" { "Recent Comments" }
+                            (recent_comments(&state, &rstate, &mut client, &user).await?)
+                            a.block__header--single-item.center href=(PathQuerySearch{search: "created_at.gte:10 minutes ago".to_string(), order: Some("comment_count".to_string()), direction: Some("desc".to_string())}.try_into_uri()?.to_string()) {
+                                "Most Commented-on Images"
+                            }
                         }
                     }
                 }
-                .column-layout__main {
-                    (image_block_default_sort(&state, &rstate, &mut client, ImageBlockHeader::Default, "created_at.lte:10 minutes ago", vec!["safe", "processed.eq:true"], vec!["deleted.eq:true"], 0, 25, "recently uploaded").await?)
-                }
+            }
+            .column-layout__main {
+                (image_block_default_sort(&state, &rstate, &mut client, ImageBlockHeader::Default, "created_at.lte:10 minutes ago", vec!["safe", "processed.eq:true"], vec!["deleted.eq:true"], 0, settings.images_per_page(), "recently uploaded").await?)
             }
         }
     };
@@ -99,3 +119,105 @@ pub async fn index(
         content: page.into_string(),
     }))
 }
+
+/// Renders the newest comments, leaving out images hidden by the current filter or by the user
+///
+/// The rendered block is cached per filter and user for a short while.
+#[instrument(skip(state, rstate, client))]
+async fn recent_comments(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    user: &Option<User>,
+) -> TiberiusResult<PreEscaped<String>> {
+    let filter = rstate.filter(state).await?;
+    let hiding_user = user
+        .as_ref()
+        .filter(|user| !user.user_settings.show_hidden_items)
+        .map(|user| user.id);
+    let key = PageSubtextCacheTag::RecentComments {
+        filter: filter.id,
+        user: hiding_user.unwrap_or(0),
+    };
+    state
+        .sidebar_cache
+        .try_get_with(key, async {
+            let comments = Comment::recent_visible(
+                client,
+                &filter.hidden_tag_ids,
+                hiding_user.map(|x| x as i64),
+                None,
+                RECENT_COMMENTS,
+            )
+            .await?;
+            let mut rendered = Vec::with_capacity(comments.len());
+            for comment in &comments {
+                let author = comment.author(client).await?;
+                let attribution = user_attribution_main(client, &author, comment).await?;
+                rendered.push((comment, attribution));
+            }
+            Ok::<PreEscaped<String>, TiberiusError>(html! {
+                @if rendered.is_empty() {
+                    .block__content { "No recent comments" }
+                }
+                @for (comment, author) in rendered {
+                    @let image_id = comment.image_id().unwrap_or_default() as u64;
+                    .block__content.alternating-color {
+                        (author)
+                        " on "
+                        a href=(format!("{}#comment_{}", PathShowImage { image: image_id }.to_uri(), comment.id)) {
+                            "#" (image_id)
+                        }
+                        br;
+                        span.fine-print { (pretty_time(&comment.created_at)) }
+                    }
+                }
+            })
+        })
+        .await
+        .map_err(|e| TiberiusError::Other(e.to_string()))
+}
+
+/// Renders the most recently active topics of the public forums
+///
+/// The rendered block is the same for everyone and cached for a short while.
+#[instrument(skip(state, client))]
+async fn forum_activity(
+    state: &TiberiusState,
+    client: &mut Client,
+) -> TiberiusResult<PreEscaped<String>> {
+    state
+        .sidebar_cache
+        .try_get_with(PageSubtextCacheTag::ForumActivity, async {
+            let forums: BTreeMap<i32, Forum> = Forum::all(client)
+                .await?
+                .into_iter()
+                .map(|forum| (forum.id, forum))
+                .collect();
+            let topics = Topic::recent(client, RECENT_TOPICS).await?;
+            Ok::<PreEscaped<String>, TiberiusError>(html! {
+                @if topics.is_empty() {
+                    .block__content { "No forum activity" }
+                }
+                @for topic in topics {
+                    @if let Some(forum) = forums.get(&topic.forum_id) {
+                        .block__content.alternating-color {
+                            a href=(format!("/forums/{}/topics/{}", forum.short_name, topic.slug)) {
+                                (topic.title)
+                            }
+                            br;
+                            span.fine-print {
+                                "in "
+                                a href=(format!("/forums/{}", forum.short_name)) { (forum.name()) }
+                                @if let Some(last_replied_to_at) = topic.last_replied_to_at {
+                                    ", " (pretty_time(&last_replied_to_at))
+                                }
+                            }
+                        }
+                    }
+                }
+            })
+        })
+        .await
+        .map_err(|e| TiberiusError::Other(e.to_string()))
+}
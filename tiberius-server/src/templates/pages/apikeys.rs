@@ -1,5 +1,11 @@
-use crate::templates::common::frontmatter::{csrf_input_tag, form_method, form_submit_button};
-use axum::{extract::State, http::HeaderMap, Extension, Router};
+use crate::templates::{
+    common::{
+        frontmatter::{csrf_input_tag, form_method, form_submit_button},
+        human_date,
+    },
+    session::require_step_up,
+};
+use axum::{extract::State, http::HeaderMap, response::Redirect, Extension, Form, Router};
 use axum_extra::routing::{RouterExt, TypedPath};
 use maud::html;
 use serde::Deserialize;
@@ -7,16 +13,22 @@ use tiberius_core::{
     acl::*,
     app::PageTitle,
     error::{TiberiusError, TiberiusResult},
-    request_helper::{FormMethod, HtmlResponse, JsonResponse, TiberiusResponse},
+    request_helper::{
+        ApiFormData, ApiFormDataEmpty, FormMethod, HtmlResponse, JsonResponse, TiberiusResponse,
+    },
     session::{Authenticated, SessionMode},
     state::{TiberiusRequestState, TiberiusState},
 };
-use tiberius_models::{ApiKey, Image, User};
+use itertools::Itertools;
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_dependencies::chrono::{DateTime, Duration, Utc};
+use tiberius_models::{ApiKey, ApiKeyScope, Image, User};
 use uuid::Uuid;
 
 pub fn api_key_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     r.typed_get(manage_keys_page)
         .typed_post(create_api_key)
+        .typed_post(rotate_api_key)
         .typed_delete(delete_api_key)
 }
 
@@ -58,6 +70,9 @@ pub async fn manage_keys_page(
                 th { "User" };
                 th { "Key ID" };
                 th { "Key Secret" };
+                th { "Scopes" };
+                th { "Expires" };
+                th { "Last Used" };
                 th { "Actions" };
             }
             @for api_key in keys {
@@ -65,7 +80,29 @@ pub async fn manage_keys_page(
                     td { (api_key.user(&mut client).await?.expect("key has no user").displayname()) }
                     td { (api_key.id().to_string()) }
                     td { (api_key.secret()) }
+                    td { (api_key.scopes().iter().map(|x| x.as_str()).join(", ")) }
+                    td {
+                        @match api_key.valid_until() {
+                            Some(v) => (human_date(v.naive_utc())),
+                            None => "Never",
+                        }
+                    }
+                    td {
+                        @match api_key.last_used_at() {
+                            Some(v) => {
+                                (human_date(v.naive_utc()))
+                                @if let Some(ip) = api_key.last_used_ip() {
+                                    " from " (ip.ip().to_string())
+                                }
+                            },
+                            None => "Never",
+                        }
+                    }
                     td {
+                        form method="POST" action=(PathRotateApiKey{uuid: *api_key.id()}.to_uri().to_string()) {
+                            (csrf_input_tag(&rstate).await);
+                            (form_submit_button("Rotate Secret"));
+                        }
                         form method="POST" action=(PathDeleteApiKey{uuid: *api_key.id()}.to_uri().to_string()) {
                             (csrf_input_tag(&rstate).await);
                             (form_method(FormMethod::Delete));
@@ -76,8 +113,25 @@ pub async fn manage_keys_page(
             }
         }
         @if edit_api_key {
+            h3 { "Create new Key" }
             form method="POST" action=(PathApiCreateAPIKey{}.to_uri().to_string()) {
                 (csrf_input_tag(&rstate).await);
+                .field {
+                    @for scope in ApiKeyScope::ALL {
+                        label for=(format!("scope_{}", scope.as_str())) { (scope.as_str()) }
+                        input.checkbox type="checkbox" id=(format!("scope_{}", scope.as_str())) name=(format!("scope_{}", scope.as_str())) value="true" checked[scope == ApiKeyScope::Read] {}
+                        " "
+                    }
+                }
+                .field {
+                    label for="expires_in_days" { "Expires in " }
+                    select.input #expires_in_days name="expires_in_days" {
+                        option value="30" { "30 days" }
+                        option value="90" { "90 days" }
+                        option value="365" selected { "1 year" }
+                        option value="" { "Never" }
+                    }
+                }
                 input type="submit" value="Create new Key";
             }
         }
@@ -100,12 +154,46 @@ pub async fn manage_keys_page(
 #[typed_path("/api/v3/manage/keys/create")]
 pub struct PathApiCreateAPIKey {}
 
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKey {
+    scope_read: Option<String>,
+    scope_upload: Option<String>,
+    expires_in_days: Option<String>,
+}
+
+impl CreateApiKey {
+    fn scopes(&self) -> Vec<ApiKeyScope> {
+        [
+            (&self.scope_read, ApiKeyScope::Read),
+            (&self.scope_upload, ApiKeyScope::Upload),
+        ]
+        .into_iter()
+        .filter(|(checked, _)| checked.as_deref() == Some("true"))
+        .map(|(_, scope)| scope)
+        .collect()
+    }
+    fn valid_until(&self) -> TiberiusResult<Option<DateTime<Utc>>> {
+        match self.expires_in_days.as_deref() {
+            None | Some("") => Ok(None),
+            Some(days) => {
+                let days: i64 = days.parse()?;
+                Ok(Some(Utc::now() + Duration::days(days.clamp(1, 365 * 5))))
+            }
+        }
+    }
+}
+
 #[instrument(skip(state, rstate))]
 pub async fn create_api_key(
     _: PathApiCreateAPIKey,
     State(state): State<TiberiusState>,
+    flash: Flash,
     rstate: TiberiusRequestState<Authenticated>,
-) -> TiberiusResult<TiberiusResponse<()>> {
+    Form(form): Form<ApiFormData<CreateApiKey>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
     let edit_api_key: bool = verify_acl(
         &state,
         &rstate,
@@ -113,6 +201,9 @@ pub async fn create_api_key(
         ACLActionAPIKey::CreateDelete,
     )
     .await?;
+    if !edit_api_key {
+        return Err(TiberiusError::AccessDenied);
+    }
     let admin_api_key: bool =
         verify_acl(&state, &rstate, ACLObject::APIKey, ACLActionAPIKey::Admin).await?;
     let mut client = state.get_db_client();
@@ -121,17 +212,65 @@ pub async fn create_api_key(
         None => return Err(TiberiusError::AccessDenied),
         Some(v) => v,
     };
-    let new_key = ApiKey::new(&user)?;
+    let manage = Redirect::to(PathManageAPIKeys {}.to_uri().to_string().as_str());
+    if !user.confirmed() {
+        return Ok(TiberiusResponse::Redirect(manage).with_flash(
+            flash.error("Please confirm your email address before creating API keys"),
+        ));
+    }
+    let scopes = form.data.scopes();
+    if scopes.is_empty() {
+        return Ok(TiberiusResponse::Redirect(manage)
+            .with_flash(flash.error("Select at least one scope for the new key")));
+    }
+    let new_key = ApiKey::new(&user, &scopes, form.data.valid_until()?)?;
 
     let id = new_key.insert(&mut client).await?;
-    let key = ApiKey::get_id(&mut client, id)
-        .await?
-        .expect("we just inserted, cannot fail");
 
-    Ok(TiberiusResponse::Json(JsonResponse {
-        content: serde_json::to_value(key)?,
-        headers: HeaderMap::new(),
-    }))
+    Ok(TiberiusResponse::Redirect(manage).with_flash(flash.info(format!("Created API key {}", id))))
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/v3/manage/keys/:uuid/rotate")]
+pub struct PathRotateApiKey {
+    uuid: Uuid,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn rotate_api_key(
+    PathRotateApiKey { uuid }: PathRotateApiKey,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let admin_api_key: bool =
+        verify_acl(&state, &rstate, ACLObject::APIKey, ACLActionAPIKey::Admin).await?;
+    let mut client = state.get_db_client();
+    let mut api_key = match ApiKey::get_id(&mut client, uuid).await? {
+        None => return Err(TiberiusError::AccessDenied),
+        Some(v) => v,
+    };
+    let own_key = Some(api_key.user_id()) == rstate.user(&state).await?.as_ref().map(|x| x.id());
+    if !own_key && !admin_api_key {
+        return Err(TiberiusError::AccessDenied);
+    }
+    if !own_key {
+        let manage = PathManageAPIKeys {}.to_uri().to_string();
+        if let Some(step_up) = require_step_up(&rstate, &manage) {
+            return Ok(TiberiusResponse::Redirect(step_up).with_flash(
+                flash.warning("Confirm your identity to manage API keys of other users"),
+            ));
+        }
+    }
+    api_key.rotate_secret(&mut client).await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathManageAPIKeys {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("The secret of the API key has been replaced")))
 }
 
 #[derive(TypedPath, Deserialize)]
@@ -163,11 +302,16 @@ pub async fn delete_api_key(
         Some(v) => v,
     };
 
-    if Some(api_key.user_id()) != rstate.user(&state).await?.as_ref().map(|x| x.id())
-        && !admin_api_key
-    {
+    let own_key = Some(api_key.user_id()) == rstate.user(&state).await?.as_ref().map(|x| x.id());
+    if !own_key && !admin_api_key {
         return Err(TiberiusError::AccessDenied);
     }
+    if !own_key {
+        let manage = PathManageAPIKeys {}.to_uri().to_string();
+        if let Some(step_up) = require_step_up(&rstate, &manage) {
+            return Ok(TiberiusResponse::Redirect(step_up));
+        }
+    }
 
     let ok = api_key.clone().delete(&mut client).await?;
 
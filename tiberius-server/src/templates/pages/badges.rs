@@ -0,0 +1,609 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Multipart, State},
+    response::Redirect,
+    Form, Router,
+};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup};
+use serde::Deserialize;
+use tiberius_core::{
+    acl::{verify_acl, ACLActionBadge, ACLObject},
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, ApiFormDataEmpty, HtmlResponse, TiberiusResponse},
+    session::{Authenticated, SessionMode},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_dependencies::{mime, uuid::Uuid};
+use tiberius_models::{Badge, BadgeAward, Client, User};
+
+use crate::templates::{
+    common::frontmatter::{badge_image, csrf_input_tag, pretty_time},
+    user::PathUserProfile,
+};
+
+pub fn badge_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(list_badges)
+        .typed_post(create_badge)
+        .typed_get(show_badge)
+        .typed_post(update_badge)
+        .typed_post(award_badge)
+        .typed_post(revoke_badge_award)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges")]
+pub struct PathBadgesList {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges/create")]
+pub struct PathCreateBadge {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges/:badge_id")]
+pub struct PathShowBadge {
+    pub badge_id: i32,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges/:badge_id/update")]
+pub struct PathUpdateBadge {
+    pub badge_id: i32,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges/:badge_id/awards/create")]
+pub struct PathAwardBadge {
+    pub badge_id: i32,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/badges/:badge_id/awards/:award_id/delete")]
+pub struct PathRevokeBadgeAward {
+    pub badge_id: i32,
+    pub award_id: i32,
+}
+
+/// Largest badge image accepted, in bytes
+const BADGE_IMAGE_MAX_SIZE: usize = 512 * 1024;
+/// Longest title accepted for a badge
+const BADGE_TITLE_MAX_LENGTH: usize = 255;
+
+async fn verify_badge_acl(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+    action: ACLActionBadge,
+) -> TiberiusResult<()> {
+    if verify_acl(state, rstate, ACLObject::Badge, action).await? {
+        Ok(())
+    } else {
+        Err(TiberiusError::AccessDenied)
+    }
+}
+
+async fn get_badge(client: &mut Client, badge_id: i32) -> TiberiusResult<Badge> {
+    match Badge::get_id(client, badge_id).await? {
+        Some(v) => Ok(v),
+        None => Err(TiberiusError::ObjectNotFound(
+            "Badge".to_string(),
+            badge_id.to_string(),
+        )),
+    }
+}
+
+/// Returns the file extension under which badge images of the given content type are stored
+pub fn badge_image_extension(content_type: &mime::Mime) -> Result<&'static str, String> {
+    Ok(match content_type.essence_str() {
+        "image/png" => ".png",
+        "image/gif" => ".gif",
+        "image/jpeg" => ".jpg",
+        "image/webp" => ".webp",
+        "image/svg+xml" => ".svg",
+        q => return Err(format!("Badge images can't be of the type {}", q)),
+    })
+}
+
+/// The badge form, it is sent as multipart since it may contain the badge image
+#[derive(Debug, Default)]
+pub struct BadgeForm {
+    csrf_token: String,
+    title: String,
+    description: String,
+    priority: bool,
+    disable_award: bool,
+    image: Option<(Vec<u8>, mime::Mime)>,
+}
+
+impl BadgeForm {
+    /// Reads the form, if the image is larger than [BADGE_IMAGE_MAX_SIZE] a message for the user is returned
+    async fn from_multipart(mut multipart: Multipart) -> TiberiusResult<Result<Self, String>> {
+        let mut form = BadgeForm::default();
+        while let Some(mut field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+            match name.as_str() {
+                "_csrf_token" => form.csrf_token = field.text().await?,
+                "title" => form.title = field.text().await?.trim().to_string(),
+                "description" => form.description = field.text().await?.trim().to_string(),
+                "priority" => form.priority = field.text().await? == "true",
+                "disable_award" => form.disable_award = field.text().await? == "true",
+                "image" => {
+                    let content_type = mime::Mime::from_str(
+                        field.content_type().unwrap_or("application/octet-stream"),
+                    )?;
+                    let mut data = Vec::new();
+                    while let Some(chunk) = field.chunk().await? {
+                        data.extend_from_slice(&chunk);
+                        if data.len() > BADGE_IMAGE_MAX_SIZE {
+                            return Ok(Err(format!(
+                                "Badge images may be at most {} KiB large",
+                                BADGE_IMAGE_MAX_SIZE / 1024
+                            )));
+                        }
+                    }
+                    // browsers send an empty file field if no file was selected
+                    if !data.is_empty() {
+                        form.image = Some((data, content_type));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(Ok(form))
+    }
+
+    fn verify_csrf<T: SessionMode>(&self, rstate: &TiberiusRequestState<T>) -> bool {
+        rstate.csrf_token().verify(&self.csrf_token).is_ok()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.title.is_empty() || self.title.len() > BADGE_TITLE_MAX_LENGTH {
+            return Err(format!(
+                "The title must be between 1 and {} characters long",
+                BADGE_TITLE_MAX_LENGTH
+            ));
+        }
+        if let Some((_, content_type)) = &self.image {
+            badge_image_extension(content_type)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the badge image into the badge directory of the data root and returns the filename
+///
+/// Every image gets a new name so the long cache lifetime of badge images is not an issue.
+async fn store_badge_image(
+    state: &TiberiusState,
+    data: &[u8],
+    content_type: &mime::Mime,
+) -> TiberiusResult<String> {
+    let ext = badge_image_extension(content_type).map_err(TiberiusError::Other)?;
+    let base = state.config().badge_base();
+    tokio::fs::create_dir_all(&base).await?;
+    let filename = format!("{}{}", Uuid::new_v4(), ext);
+    tokio::fs::write(base.join(&filename), data).await?;
+    Ok(filename)
+}
+
+fn badge_form(action: String, badge: Option<&Badge>, csrf: Markup) -> Markup {
+    html! {
+        form action=(action) method="POST" enctype="multipart/form-data" {
+            (csrf)
+            .field {
+                label for="badge_title" { "Title" }
+                input.input #badge_title type="text" name="title" required
+                    value=(badge.map(|x| x.title.as_str()).unwrap_or_default());
+            }
+            .field {
+                label for="badge_description" { "Description" }
+                input.input.input--wide #badge_description type="text" name="description"
+                    value=(badge.map(|x| x.description.as_str()).unwrap_or_default());
+            }
+            .field {
+                label for="badge_image" { "Image (PNG, GIF, JPEG, WebP or SVG)" }
+                input.input #badge_image type="file" name="image" accept="image/png,image/gif,image/jpeg,image/webp,image/svg+xml";
+            }
+            .field {
+                input #badge_priority type="checkbox" name="priority" value="true" checked[badge.map(|x| x.priority).unwrap_or(false)];
+                label for="badge_priority" { "Show before other badges" }
+            }
+            @if badge.is_some() {
+                .field {
+                    input #badge_disable_award type="checkbox" name="disable_award" value="true" checked[badge.map(|x| x.disable_award).unwrap_or(false)];
+                    label for="badge_disable_award" { "Disable awarding this badge" }
+                }
+            }
+            .actions {
+                button.button type="submit" { "Save" }
+            }
+        }
+    }
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    title: &str,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let app = crate::templates::common::frontmatter::app(
+        state,
+        rstate,
+        Some(PageTitle::from(title)),
+        client,
+        body,
+        None,
+    )
+    .await?;
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: app.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn list_badges(
+    _: PathBadgesList,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let can_manage = verify_acl(&state, &rstate, ACLObject::Badge, ACLActionBadge::Manage).await?;
+    if !can_manage {
+        verify_badge_acl(&state, &rstate, ACLActionBadge::Award).await?;
+    }
+    let mut client = state.get_db_client();
+    let mut badges = Vec::new();
+    for badge in Badge::get_all(&mut client).await? {
+        let awards = badge.award_count(&mut client).await?;
+        badges.push((badge, awards));
+    }
+    let body = html! {
+        h1 { "Badges" }
+        table.table {
+            thead {
+                tr {
+                    th { "Image" }
+                    th { "Title" }
+                    th { "Description" }
+                    th { "Awards" }
+                    th { "Options" }
+                }
+            }
+            tbody {
+                @for (badge, awards) in &badges {
+                    tr {
+                        td { (badge_image(badge.image.as_ref(), badge.title(), badge.title(), 32, 32)) }
+                        td {
+                            (badge.title)
+                            @if badge.disable_award {
+                                " "
+                                span.fine-print { "(award disabled)" }
+                            }
+                        }
+                        td { (badge.description) }
+                        td { (awards) }
+                        td { a href=(PathShowBadge { badge_id: badge.id }.to_uri().to_string()) { "Awards" } }
+                    }
+                }
+            }
+        }
+        @if can_manage {
+            h2 { "New Badge" }
+            (badge_form(PathCreateBadge {}.to_uri().to_string(), None, csrf_input_tag(&rstate).await))
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Badges", body).await
+}
+
+#[instrument(skip(state, rstate, multipart))]
+pub async fn create_badge(
+    _: PathCreateBadge,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    multipart: Multipart,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let retry = Redirect::to(PathBadgesList {}.to_uri().to_string().as_str());
+    let form = match BadgeForm::from_multipart(multipart).await? {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    if !form.verify_csrf(&rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    verify_badge_acl(&state, &rstate, ACLActionBadge::Manage).await?;
+    if let Err(e) = form.validate() {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    let mut client = state.get_db_client();
+    let image = match &form.image {
+        Some((data, content_type)) => Some(store_badge_image(&state, data, content_type).await?),
+        None => None,
+    };
+    let badge = Badge::create(
+        &mut client,
+        &form.title,
+        &form.description,
+        image,
+        form.priority,
+    )
+    .await?;
+    info!(
+        "user {:?} created badge {} ({})",
+        rstate.session().raw_user(),
+        badge.id,
+        badge.title
+    );
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathShowBadge { badge_id: badge.id }
+            .to_uri()
+            .to_string()
+            .as_str(),
+    ))
+    .with_flash(flash.info(format!("Created badge {}", badge.title))))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn show_badge(
+    PathShowBadge { badge_id }: PathShowBadge,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let can_manage = verify_acl(&state, &rstate, ACLObject::Badge, ACLActionBadge::Manage).await?;
+    let can_award = verify_acl(&state, &rstate, ACLObject::Badge, ACLActionBadge::Award).await?;
+    if !can_manage && !can_award {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let badge = get_badge(&mut client, badge_id).await?;
+    let mut awards = Vec::new();
+    for award in BadgeAward::get_for_badge(&mut client, badge_id).await? {
+        let user = User::get_id(&mut client, award.user_id as i64).await?;
+        let awarded_by = User::get_id(&mut client, award.awarded_by_id as i64).await?;
+        awards.push((award, user, awarded_by));
+    }
+    let body = html! {
+        h1 {
+            (badge_image(badge.image.as_ref(), badge.title(), badge.title(), 32, 32))
+            " "
+            (badge.title)
+        }
+        p { (badge.description) }
+        @if can_award {
+            h2 { "Award Badge" }
+            @if badge.disable_award {
+                p { "This badge can no longer be awarded." }
+            } @else {
+                form action=(PathAwardBadge { badge_id }.to_uri().to_string()) method="POST" {
+                    (csrf_input_tag(&rstate).await)
+                    .field {
+                        label for="award_username" { "User name" }
+                        input.input #award_username type="text" name="username" required autocapitalize="none";
+                    }
+                    .field {
+                        label for="award_label" { "Label" }
+                        input.input #award_label type="text" name="label" placeholder="Shown instead of the badge title";
+                    }
+                    .field {
+                        label for="award_reason" { "Reason" }
+                        input.input.input--wide #award_reason type="text" name="reason";
+                    }
+                    .actions {
+                        button.button type="submit" { "Award" }
+                    }
+                }
+            }
+        }
+        h2 { "Awards" }
+        @if awards.is_empty() {
+            p { "This badge has not been awarded yet." }
+        } @else {
+            table.table {
+                thead {
+                    tr {
+                        th { "User" }
+                        th { "Label" }
+                        th { "Reason" }
+                        th { "Awarded" }
+                        th { "Awarded by" }
+                        @if can_award {
+                            th { "Options" }
+                        }
+                    }
+                }
+                tbody {
+                    @for (award, user, awarded_by) in &awards {
+                        tr {
+                            td {
+                                @match user {
+                                    Some(user) => {
+                                        a href=(PathUserProfile { username: user.name.clone() }.to_uri().to_string()) { (user.displayname()) }
+                                    },
+                                    None => { em { "deleted user" } },
+                                }
+                            }
+                            td { (award.label.as_deref().unwrap_or_default()) }
+                            td { (award.reason.as_deref().unwrap_or_default()) }
+                            td { (pretty_time(&award.awarded_on)) }
+                            td { (awarded_by.as_ref().map(|x| x.displayname()).unwrap_or_default()) }
+                            @if can_award {
+                                td {
+                                    form action=(PathRevokeBadgeAward { badge_id, award_id: award.id }.to_uri().to_string()) method="POST" {
+                                        (csrf_input_tag(&rstate).await)
+                                        button.button.button--state-danger type="submit" { "Revoke" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        @if can_manage {
+            h2 { "Edit Badge" }
+            p { "Leave the image empty to keep the current image." }
+            (badge_form(PathUpdateBadge { badge_id }.to_uri().to_string(), Some(&badge), csrf_input_tag(&rstate).await))
+        }
+        a href=(PathBadgesList {}.to_uri().to_string()) { "Back to all badges" }
+    };
+    render_page(&state, &rstate, &mut client, &badge.title, body).await
+}
+
+#[instrument(skip(state, rstate, multipart))]
+pub async fn update_badge(
+    PathUpdateBadge { badge_id }: PathUpdateBadge,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    multipart: Multipart,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let retry = Redirect::to(PathShowBadge { badge_id }.to_uri().to_string().as_str());
+    let form = match BadgeForm::from_multipart(multipart).await? {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    if !form.verify_csrf(&rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    verify_badge_acl(&state, &rstate, ACLActionBadge::Manage).await?;
+    if let Err(e) = form.validate() {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    let mut client = state.get_db_client();
+    let mut badge = get_badge(&mut client, badge_id).await?;
+    if let Some((data, content_type)) = &form.image {
+        badge.image = Some(store_badge_image(&state, data, content_type).await?);
+    }
+    badge.title = form.title;
+    badge.description = form.description;
+    badge.priority = form.priority;
+    badge.disable_award = form.disable_award;
+    badge.update(&mut client).await?;
+    Ok(TiberiusResponse::Redirect(retry)
+        .with_flash(flash.info(format!("Updated badge {}", badge.title))))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AwardForm {
+    username: String,
+    label: Option<String>,
+    reason: Option<String>,
+}
+
+fn non_empty(v: &Option<String>) -> Option<String> {
+    v.as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(str::to_string)
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn award_badge(
+    PathAwardBadge { badge_id }: PathAwardBadge,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<AwardForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    verify_badge_acl(&state, &rstate, ACLActionBadge::Award).await?;
+    let staff = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let badge = get_badge(&mut client, badge_id).await?;
+    let retry = Redirect::to(PathShowBadge { badge_id }.to_uri().to_string().as_str());
+    if badge.disable_award {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("This badge can no longer be awarded")));
+    }
+    let form = form.data;
+    let username = form.username.trim();
+    let user = match User::get_by_name(&mut client, username).await? {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error(format!("The user {:?} does not exist", username))))
+        }
+    };
+    BadgeAward::award(
+        &mut client,
+        &badge,
+        user.id,
+        staff.id,
+        non_empty(&form.label),
+        non_empty(&form.reason),
+    )
+    .await?;
+    info!(
+        "user {:?} awarded badge {} to {}",
+        rstate.session().raw_user(),
+        badge.id,
+        user.id
+    );
+    Ok(
+        TiberiusResponse::Redirect(retry).with_flash(flash.info(format!(
+            "Awarded {} to {}",
+            badge.title,
+            user.displayname()
+        ))),
+    )
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn revoke_badge_award(
+    PathRevokeBadgeAward { badge_id, award_id }: PathRevokeBadgeAward,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    verify_badge_acl(&state, &rstate, ACLActionBadge::Award).await?;
+    let mut client = state.get_db_client();
+    let award = match BadgeAward::get_id(&mut client, award_id).await? {
+        Some(v) if v.badge_id == badge_id => v,
+        _ => {
+            return Err(TiberiusError::ObjectNotFound(
+                "BadgeAward".to_string(),
+                award_id.to_string(),
+            ))
+        }
+    };
+    let user_id = award.user_id;
+    award.delete(&mut client).await?;
+    info!(
+        "user {:?} revoked award {} of badge {} from {}",
+        rstate.session().raw_user(),
+        award_id,
+        badge_id,
+        user_id
+    );
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathShowBadge { badge_id }.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("The badge award was revoked")))
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_dependencies::mime;
+
+    use super::badge_image_extension;
+
+    #[test]
+    fn test_badge_image_extension() {
+        assert_eq!(Ok(".png"), badge_image_extension(&mime::IMAGE_PNG));
+        assert_eq!(Ok(".svg"), badge_image_extension(&mime::IMAGE_SVG));
+        assert!(badge_image_extension(&mime::TEXT_PLAIN).is_err());
+        assert!(badge_image_extension(&"video/webm".parse().unwrap()).is_err());
+    }
+}
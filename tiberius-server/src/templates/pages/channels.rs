@@ -1,4 +1,5 @@
 use std::fmt;
+use std::str::FromStr;
 
 use axum::extract::State;
 use axum::Router;
@@ -9,21 +10,41 @@ use axum_extra::{extract::cookie::Cookie, routing::TypedPath};
 use maud::{html, Markup};
 use serde::Deserialize;
 use tiberius_core::{
+    acl::{verify_acl, ACLActionChannel, ACLObject},
     app::PageTitle,
-    error::TiberiusResult,
+    error::{TiberiusError, TiberiusResult},
     request_helper::{
-        ApiFormDataEmpty, FormMethod, HtmlResponse, RedirectResponse, TiberiusResponse,
+        ApiFormData, ApiFormDataEmpty, FormMethod, HtmlResponse, RedirectResponse, TiberiusResponse,
     },
-    session::{SessionMode, Unauthenticated},
+    session::{Authenticated, SessionMode, Unauthenticated},
     state::{TiberiusRequestState, TiberiusState},
 };
 use tiberius_dependencies::axum_flash::Flash;
-use tiberius_models::{Channel, Client, Image};
+use tiberius_dependencies::chrono::Duration;
+use tiberius_models::{
+    Channel, ChannelLiveSession, ChannelType, Client, Image, Notification, Tag, User,
+};
 
-use crate::templates::common::{channels::channel_box, pagination::PaginationCtl};
+use crate::templates::common::{
+    channels::channel_box,
+    frontmatter::{csrf_input_tag, form_method},
+    human_date,
+    pagination::PaginationCtl,
+    streambox::channel_path,
+};
 
 pub fn channel_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
-    r.typed_get(list_channels).typed_post(set_nsfw)
+    r.typed_get(list_channels)
+        .typed_post(set_nsfw)
+        .typed_post(read)
+        .typed_get(new_channel)
+        .typed_post(create_channel)
+        .typed_get(edit_channel)
+        .typed_post(update_channel)
+        .typed_post(delete_channel)
+        .typed_post(subscribe_channel)
+        .typed_get(channel_stats)
+        .typed_get(channel_history)
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -84,10 +105,28 @@ pub async fn set_nsfw(
 #[typed_path("/channels/read")]
 pub struct PathChannelsRead {}
 
-#[tracing::instrument]
-/// POST
-pub async fn read(_: PathChannelsRead) -> String {
-    todo!()
+/// Marks the live notifications of all channels as read
+#[instrument(skip(state, rstate))]
+pub async fn read(
+    _: PathChannelsRead,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    Notification::mark_read(&mut client, CHANNEL_NOTIFICATION_ACTOR, user.id).await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathChannelsList {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("Marked channel notifications as read")))
 }
 
 #[derive(TypedPath, Deserialize, Debug)]
@@ -113,6 +152,8 @@ pub async fn list_channels(
         "channel",
         "",
     )?;
+    let user = rstate.user(&state).await?;
+    let can_manage = can_manage_channels(&state, &rstate).await?;
     let show_hide_nsfw_uri = PathSetChannelNsfw {}.to_uri().to_string();
     let show_nsfw_state = rstate
         .cookie_jar
@@ -151,7 +192,7 @@ pub async fn list_channels(
 
             .block__content {
                 @for channel in channels.iter().filter(|x| x.nsfw == show_nsfw_state || !x.nsfw) {
-                    (channel_box(&state, &mut client, channel).await?)
+                    (channel_box(&state, &mut client, channel, channel_actions(&rstate, user.as_ref(), channel, can_manage).await).await?)
                 }
             }
 
@@ -164,15 +205,32 @@ pub async fn list_channels(
             }
         }
         br;
-        //TODO: If can create channel
+        a href=(PathChannelStats{}.to_uri().to_string()) {
+            i.fa.fa-fw.fa-chart-bar {}
+            "Statistics"
+        }
+        br;
+        @if user.is_some() {
+            a href=(PathNewChannel{}.to_uri().to_string()) {
+                i.fa.fa-fw.fa-plus {}
+                "New Channel"
+            }
+            br;
+        }
+        @if user.is_some() {
+            form action=(PathChannelsRead{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+                button.button type="submit" { "Mark live notifications as read" }
+            }
+        }
         h2 { "FAQ" }
         p {
             strong { "Q: Do you host streams?" }
-            "A: No, we cheat and just link to streams on Picarto since that's where (almost) everyone is already. This is simply a nice way to track streaming artists."
+            "A: No, we cheat and just link to streams on Picarto, Piczel and Twitch since that's where (almost) everyone is already. This is simply a nice way to track streaming artists."
         }
         p {
             strong { "Q: How do I get my stream/a friend's stream/<artist>'s stream here?" }
-            "A: Send a private message to a site administrator with a link to the stream and the artist tag if applicable."
+            "A: Log in and add it with the New Channel link above, or send a private message to a site administrator with a link to the stream and the artist tag if applicable."
         }
     };
     let app = crate::templates::common::frontmatter::app(
@@ -188,3 +246,604 @@ pub async fn list_channels(
         content: app.into_string(),
     }))
 }
+
+/// Actor type of the notifications sent when a channel goes live
+const CHANNEL_NOTIFICATION_ACTOR: &str = "Channel";
+
+async fn can_manage_channels<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+) -> TiberiusResult<bool> {
+    verify_acl(state, rstate, ACLObject::Channel, ACLActionChannel::Manage).await
+}
+
+/// Whether the channel was registered by the user
+fn owns_channel(user: Option<&User>, channel: &Channel) -> bool {
+    match (user, channel.user_id) {
+        (Some(user), Some(owner)) => user.id == owner,
+        _ => false,
+    }
+}
+
+/// Users may edit and delete the channels they registered, staff may manage all of them
+async fn verify_manage_channel(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+    channel: &Channel,
+) -> TiberiusResult<()> {
+    let user = rstate.user(state).await?;
+    if owns_channel(user.as_ref(), channel) || can_manage_channels(state, rstate).await? {
+        Ok(())
+    } else {
+        Err(TiberiusError::AccessDenied)
+    }
+}
+
+async fn get_channel(client: &mut Client, channel_id: i32) -> TiberiusResult<Channel> {
+    match Channel::get_by_id(client, channel_id).await? {
+        Some(v) => Ok(v),
+        None => Err(TiberiusError::ObjectNotFound(
+            "Channel".to_string(),
+            channel_id.to_string(),
+        )),
+    }
+}
+
+/// Subscribe button and edit link shown below a channel in the list
+async fn channel_actions<T: SessionMode>(
+    rstate: &TiberiusRequestState<T>,
+    user: Option<&User>,
+    channel: &Channel,
+    can_manage: bool,
+) -> Option<Markup> {
+    let user = user?;
+    let subscribed = channel.watcher_ids.contains(&user.id);
+    Some(html! {
+        form action=(PathChannelSubscription{ channel_id: channel.id }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(rstate).await)
+            @if subscribed {
+                (form_method(FormMethod::Delete))
+                button.button.button--link type="submit" {
+                    i.fa.fa-fw.fa-bell-slash {}
+                    "Unsubscribe"
+                }
+            } @else {
+                (form_method(FormMethod::Create))
+                button.button.button--link type="submit" {
+                    i.fa.fa-fw.fa-bell {}
+                    "Subscribe"
+                }
+            }
+        }
+        @if can_manage || owns_channel(Some(user), channel) {
+            " "
+            a href=(PathEditChannel{ channel_id: channel.id }.to_uri().to_string()) {
+                i.fa.fa-fw.fa-edit {}
+                "Edit"
+            }
+        }
+    })
+}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/new")]
+pub struct PathNewChannel {}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/create")]
+pub struct PathCreateChannel {}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/:channel_id/edit")]
+pub struct PathEditChannel {
+    channel_id: i32,
+}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/:channel_id/update")]
+pub struct PathUpdateChannel {
+    channel_id: i32,
+}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/:channel_id/delete")]
+pub struct PathDeleteChannel {
+    channel_id: i32,
+}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/:channel_id/subscription")]
+pub struct PathChannelSubscription {
+    channel_id: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChannelForm {
+    #[serde(rename = "type")]
+    channel_type: String,
+    short_name: String,
+    artist_tag: Option<String>,
+    description: Option<String>,
+    nsfw: Option<String>,
+}
+
+/// Longest short name accepted for a channel
+const SHORT_NAME_MAX_LENGTH: usize = 64;
+
+impl ChannelForm {
+    fn channel_type(&self) -> Result<ChannelType, &'static str> {
+        ChannelType::from_str(&self.channel_type).map_err(|_| "Select a valid channel type")
+    }
+
+    fn short_name(&self) -> Result<&str, &'static str> {
+        let short_name = self.short_name.trim();
+        if short_name.is_empty() || short_name.len() > SHORT_NAME_MAX_LENGTH {
+            return Err("The short name must be between 1 and 64 characters long");
+        }
+        if !short_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err("The short name may only contain letters, numbers, dashes and underscores");
+        }
+        Ok(short_name)
+    }
+
+    fn description(&self) -> Option<String> {
+        self.description
+            .as_deref()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(str::to_string)
+    }
+
+    fn nsfw(&self) -> bool {
+        self.nsfw.as_deref() == Some("true")
+    }
+
+    /// Looks up the artist tag by its full name, an empty field removes the tag
+    async fn artist_tag(&self, client: &mut Client) -> TiberiusResult<Result<Option<Tag>, String>> {
+        let name = match self.artist_tag.as_deref().map(str::trim) {
+            None | Some("") => return Ok(Ok(None)),
+            Some(v) => v,
+        };
+        let (namespace, name_in_namespace) = match name.split_once(':') {
+            Some((namespace, name)) => (Some(namespace.to_string()), name.to_string()),
+            None => (None, name.to_string()),
+        };
+        Ok(
+            match Tag::get_by_name(client, namespace, name_in_namespace).await? {
+                Some(tag) => Ok(Some(tag)),
+                None => Err(format!("The tag {:?} does not exist", name)),
+            },
+        )
+    }
+}
+
+async fn channel_form<T: SessionMode>(
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    action: String,
+    channel: Option<&Channel>,
+) -> TiberiusResult<Markup> {
+    let artist_tag = match channel {
+        Some(channel) => channel.associated_artist_tag(client).await?,
+        None => None,
+    };
+    let channel_type = channel.map(|x| x.r#type);
+    Ok(html! {
+        form action=(action) method="POST" {
+            (csrf_input_tag(rstate).await)
+            .field {
+                label for="channel_type" { "Type" }
+                select.input #channel_type name="type" {
+                    @for (value, label) in [
+                        (ChannelType::PicartoChannel, "Picarto"),
+                        (ChannelType::PiczelChannel, "Piczel"),
+                        (ChannelType::TwitchChannel, "Twitch"),
+                    ] {
+                        option value=(value.to_string()) selected[channel_type == Some(value)] { (label) }
+                    }
+                }
+            }
+            .field {
+                label for="channel_short_name" { "Short name" }
+                input.input #channel_short_name type="text" name="short_name" required
+                    value=(channel.map(|x| x.short_name.as_str()).unwrap_or_default())
+                    placeholder="Name of the channel on the streaming site";
+            }
+            .field {
+                label for="channel_artist_tag" { "Artist tag" }
+                input.input #channel_artist_tag type="text" name="artist_tag" autocapitalize="none"
+                    value=(artist_tag.map(|x| x.name).unwrap_or_default())
+                    placeholder="artist:name";
+            }
+            .field {
+                label for="channel_description" { "Description" }
+                textarea.input #channel_description name="description" {
+                    (channel.and_then(|x| x.description.as_deref()).unwrap_or_default())
+                }
+            }
+            .field {
+                input #channel_nsfw type="checkbox" name="nsfw" value="true" checked[channel.map(|x| x.nsfw).unwrap_or(false)];
+                label for="channel_nsfw" { "NSFW channel" }
+            }
+            .actions {
+                button.button type="submit" { "Save" }
+            }
+        }
+    })
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    title: &str,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let app = crate::templates::common::frontmatter::app(
+        state,
+        rstate,
+        Some(PageTitle::from(title)),
+        client,
+        body,
+        None,
+    )
+    .await?;
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: app.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn new_channel(
+    _: PathNewChannel,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    if rstate.user(&state).await?.is_none() {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let form = channel_form(
+        &rstate,
+        &mut client,
+        PathCreateChannel {}.to_uri().to_string(),
+        None,
+    )
+    .await?;
+    let body = html! {
+        h1 { "New Channel" }
+        p { "The title, images and live status are fetched from the streaming site within 30 minutes." }
+        (form)
+    };
+    render_page(&state, &rstate, &mut client, "New Channel", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn create_channel(
+    _: PathCreateChannel,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<ChannelForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let retry = Redirect::to(PathNewChannel {}.to_uri().to_string().as_str());
+    let form = form.data;
+    let (channel_type, short_name) = match (form.channel_type(), form.short_name()) {
+        (Ok(channel_type), Ok(short_name)) => (channel_type, short_name),
+        (Err(e), _) | (_, Err(e)) => {
+            return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)))
+        }
+    };
+    let artist_tag = match form.artist_tag(&mut client).await? {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    let channel = Channel::create(
+        &mut client,
+        channel_type,
+        short_name,
+        form.description(),
+        artist_tag.map(|x| x.id),
+        form.nsfw(),
+        Some(user.id),
+    )
+    .await?;
+    info!(
+        "user {:?} created channel {} ({})",
+        rstate.session().raw_user(),
+        channel.id,
+        channel.short_name
+    );
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathChannelsList {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info(format!("Created channel {}", channel.short_name))))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn edit_channel(
+    PathEditChannel { channel_id }: PathEditChannel,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client = state.get_db_client();
+    let channel = get_channel(&mut client, channel_id).await?;
+    verify_manage_channel(&state, &rstate, &channel).await?;
+    let form = channel_form(
+        &rstate,
+        &mut client,
+        PathUpdateChannel { channel_id }.to_uri().to_string(),
+        Some(&channel),
+    )
+    .await?;
+    let body = html! {
+        h1 { "Editing Channel " (channel.title()) }
+        (form)
+        h3 { "Delete channel" }
+        p { "Deleting the channel also removes all subscriptions to it." }
+        form action=(PathDeleteChannel{ channel_id }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            .actions {
+                button.button.button--state-danger type="submit" { "Delete channel" }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Edit Channel", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn update_channel(
+    PathUpdateChannel { channel_id }: PathUpdateChannel,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<ChannelForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let mut channel = get_channel(&mut client, channel_id).await?;
+    verify_manage_channel(&state, &rstate, &channel).await?;
+    let retry = Redirect::to(PathEditChannel { channel_id }.to_uri().to_string().as_str());
+    let form = form.data;
+    let (channel_type, short_name) = match (form.channel_type(), form.short_name()) {
+        (Ok(channel_type), Ok(short_name)) => (channel_type, short_name),
+        (Err(e), _) | (_, Err(e)) => {
+            return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)))
+        }
+    };
+    let artist_tag = match form.artist_tag(&mut client).await? {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    if channel.r#type != channel_type || channel.short_name != short_name {
+        // the remote data belongs to the old channel, the next refresh fetches the new one
+        channel.remote_stream_id = None;
+        channel.is_live = false;
+        channel.viewers = 0;
+        channel.last_fetched_at = None;
+    }
+    channel.r#type = channel_type;
+    channel.short_name = short_name.to_string();
+    channel.description = form.description();
+    channel.associated_artist_tag_id = artist_tag.map(|x| x.id);
+    channel.nsfw = form.nsfw();
+    channel.update(&mut client).await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathChannelsList {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info(format!("Updated channel {}", channel.short_name))))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn delete_channel(
+    PathDeleteChannel { channel_id }: PathDeleteChannel,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let channel = get_channel(&mut client, channel_id).await?;
+    verify_manage_channel(&state, &rstate, &channel).await?;
+    let short_name = channel.short_name.clone();
+    channel.delete(&mut client).await?;
+    info!(
+        "user {:?} deleted channel {} ({})",
+        rstate.session().raw_user(),
+        channel_id,
+        short_name
+    );
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathChannelsList {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info(format!("Deleted channel {}", short_name))))
+}
+
+/// Subscribes to a channel with the `create` method and unsubscribes with `delete`
+#[instrument(skip(state, rstate))]
+pub async fn subscribe_channel(
+    PathChannelSubscription { channel_id }: PathChannelSubscription,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let form = form.into_afd();
+    let method = form.method();
+    if !form.verify_csrf(method, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let mut channel = get_channel(&mut client, channel_id).await?;
+    let flash = match method {
+        Some(FormMethod::Create) => {
+            channel.subscribe(&mut client, user.id).await?;
+            flash.info(format!(
+                "You will be notified when {} goes live",
+                channel.title()
+            ))
+        }
+        Some(FormMethod::Delete) => {
+            channel.unsubscribe(&mut client, user.id).await?;
+            flash.info(format!("Unsubscribed from {}", channel.title()))
+        }
+        _ => flash.error("Unknown subscription action"),
+    };
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathChannelsList {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash))
+}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/stats")]
+pub struct PathChannelStats {}
+
+#[derive(TypedPath, Deserialize, Debug)]
+#[typed_path("/channels/:channel_id/history")]
+pub struct PathChannelHistory {
+    channel_id: i32,
+}
+
+/// Number of channels shown on the statistics page
+const TOP_CHANNELS: i64 = 25;
+/// Number of live sessions shown in the history of a channel
+const HISTORY_SESSIONS: i64 = 50;
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    }
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn channel_stats(
+    _: PathChannelStats,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client = state.get_db_client();
+    let channels = Channel::get_top_by_viewer_minutes(&mut client, TOP_CHANNELS).await?;
+    let body = html! {
+        h1 { "Livestream Statistics" }
+        p { "Viewer minutes are the minutes watched by all viewers of a stream together. Days and weeks are counted in UTC, weeks start on Monday." }
+        table.table {
+            thead {
+                tr {
+                    th { "Channel" }
+                    th { "Today" }
+                    th { "This week" }
+                    th { "This month" }
+                    th { "Total" }
+                    th { "Subscribers" }
+                }
+            }
+            tbody {
+                @for channel in &channels {
+                    tr {
+                        td {
+                            a href=(PathChannelHistory{ channel_id: channel.id }.to_uri().to_string()) { (channel.title()) }
+                            @if channel.is_live {
+                                " "
+                                span.label.label--narrow.label--success { "LIVE" }
+                            }
+                        }
+                        td { (channel.viewer_minutes_today) }
+                        td { (channel.viewer_minutes_thisweek) }
+                        td { (channel.viewer_minutes_thismonth) }
+                        td { (channel.total_viewer_minutes) }
+                        td { (channel.watcher_count) }
+                    }
+                }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Livestream Statistics", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn channel_history(
+    PathChannelHistory { channel_id }: PathChannelHistory,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client = state.get_db_client();
+    let channel = get_channel(&mut client, channel_id).await?;
+    let sessions =
+        ChannelLiveSession::get_for_channel(&mut client, channel_id, HISTORY_SESSIONS).await?;
+    let body = html! {
+        h1 { "Stream history of " (channel.title()) }
+        p {
+            a href=(channel_path(&channel).to_string()) { "Watch on the streaming site" }
+            " · "
+            a href=(PathChannelStats{}.to_uri().to_string()) { "All statistics" }
+        }
+        p {
+            (channel.total_viewer_minutes) " viewer minutes in total, "
+            (channel.viewer_minutes_thismonth) " this month."
+        }
+        @if sessions.is_empty() {
+            p { "This channel has not been seen live yet." }
+        } @else {
+            table.table {
+                thead {
+                    tr {
+                        th { "Started" }
+                        th { "Duration" }
+                        th { "Title" }
+                        th { "Peak viewers" }
+                        th { "Viewer minutes" }
+                    }
+                }
+                tbody {
+                    @for session in &sessions {
+                        tr {
+                            td { (human_date(session.started_at)) }
+                            td {
+                                (format_duration(session.duration()))
+                                @if session.ended_at.is_none() {
+                                    " "
+                                    span.label.label--narrow.label--success { "LIVE" }
+                                }
+                            }
+                            td { (session.title) }
+                            td { (session.peak_viewers) }
+                            td { (session.viewer_minutes) }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    render_page(
+        &state,
+        &rstate,
+        &mut client,
+        &format!("Stream history of {}", channel.title()),
+        body,
+    )
+    .await
+}
@@ -0,0 +1,516 @@
+use axum::{
+    extract::{Query, State},
+    headers::{ContentType, HeaderMapExt},
+    http::HeaderMap,
+    Router,
+};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+use tiberius_core::{
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{CustomResponse, TiberiusResponse},
+    session::Unauthenticated,
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::{
+    chrono::{DateTime, NaiveDateTime, Utc},
+    mime,
+};
+use tiberius_models::{Client, Filter, Image, ImageSortBy, SortDirection, Tag, TagLike, User};
+
+use crate::templates::{
+    common::{
+        renderer::markdown::render_markdown,
+        routes::{absolute_static_host, static_path},
+    },
+    images::{parse_tag_input, PathShowImage},
+    tags::PathTagsByNameShowTag,
+    user::PathUserProfile,
+};
+
+/// Number of entries in a feed
+const FEED_SIZE: u64 = 25;
+
+pub fn feed_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(search_feed)
+        .typed_get(tag_feed)
+        .typed_get(user_uploads_feed)
+        .typed_get(user_favourites_feed)
+        .typed_get(front_page_feed)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+impl std::fmt::Display for FeedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FeedFormat::Atom => "atom",
+            FeedFormat::Rss => "rss",
+        })
+    }
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/feeds/:format/search")]
+pub struct PathSearchFeed {
+    pub format: FeedFormat,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuerySearchFeed {
+    pub q: Option<String>,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/feeds/:format/tags/:tag")]
+pub struct PathTagFeed {
+    pub format: FeedFormat,
+    pub tag: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/feeds/:format/users/:username/uploads")]
+pub struct PathUserUploadsFeed {
+    pub format: FeedFormat,
+    pub username: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/feeds/:format/users/:username/favourites")]
+pub struct PathUserFavouritesFeed {
+    pub format: FeedFormat,
+    pub username: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/feeds/:format/front")]
+pub struct PathFrontPageFeed {
+    pub format: FeedFormat,
+}
+
+struct FeedEntry {
+    title: String,
+    url: String,
+    published: NaiveDateTime,
+    updated: NaiveDateTime,
+    author: Option<String>,
+    tags: Vec<String>,
+    content: Markup,
+}
+
+struct Feed {
+    title: String,
+    url: String,
+    self_url: String,
+    entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Time of the latest change to an entry, the current time if the feed is empty
+    fn updated(&self) -> NaiveDateTime {
+        self.entries
+            .iter()
+            .map(|x| x.updated)
+            .max()
+            .unwrap_or_else(|| Utc::now().naive_utc())
+    }
+
+    fn render_atom(&self) -> String {
+        let body = html! {
+            feed xmlns="http://www.w3.org/2005/Atom" {
+                title { (self.title) }
+                id { (self.self_url) }
+                link rel="self" href=(self.self_url) {}
+                link rel="alternate" type="text/html" href=(self.url) {}
+                updated { (rfc3339(self.updated())) }
+                generator { "tiberius" }
+                @for entry in &self.entries {
+                    entry {
+                        title { (entry.title) }
+                        id { (entry.url) }
+                        link rel="alternate" type="text/html" href=(entry.url) {}
+                        published { (rfc3339(entry.published)) }
+                        updated { (rfc3339(entry.updated)) }
+                        author {
+                            name { (entry.author.as_deref().unwrap_or("Anonymous")) }
+                        }
+                        @for tag in &entry.tags {
+                            category term=(tag) {}
+                        }
+                        content type="html" { (entry.content.0) }
+                    }
+                }
+            }
+        };
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>{}"#,
+            body.into_string()
+        )
+    }
+
+    fn render_rss(&self) -> String {
+        let body = html! {
+            rss version="2.0" {
+                channel {
+                    title { (self.title) }
+                    link { (self.url) }
+                    description { (self.title) }
+                    lastBuildDate { (rfc2822(self.updated())) }
+                    generator { "tiberius" }
+                    @for entry in &self.entries {
+                        item {
+                            title { (entry.title) }
+                            link { (entry.url) }
+                            guid isPermaLink="true" { (entry.url) }
+                            pubDate { (rfc2822(entry.published)) }
+                            @if let Some(author) = &entry.author {
+                                author { (author) }
+                            }
+                            @for tag in &entry.tags {
+                                category { (tag) }
+                            }
+                            description { (entry.content.0) }
+                        }
+                    }
+                }
+            }
+        };
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>{}"#,
+            body.into_string()
+        )
+    }
+
+    fn into_response(self, format: FeedFormat) -> TiberiusResult<TiberiusResponse<String>> {
+        let mut headers = HeaderMap::new();
+        let content = match format {
+            FeedFormat::Atom => {
+                headers.typed_insert(ContentType::from(
+                    "application/atom+xml".parse::<mime::Mime>()?,
+                ));
+                self.render_atom()
+            }
+            FeedFormat::Rss => {
+                headers.typed_insert(ContentType::from(
+                    "application/rss+xml".parse::<mime::Mime>()?,
+                ));
+                self.render_rss()
+            }
+        };
+        Ok(TiberiusResponse::Custom(CustomResponse {
+            content,
+            headers,
+        }))
+    }
+}
+
+fn rfc3339(v: NaiveDateTime) -> String {
+    DateTime::<Utc>::from_utc(v, Utc).to_rfc3339()
+}
+
+fn rfc2822(v: NaiveDateTime) -> String {
+    DateTime::<Utc>::from_utc(v, Utc).to_rfc2822()
+}
+
+/// Runs the search through the default filter and turns the result into feed entries
+///
+/// Feeds are fetched anonymously by feed readers, so the anonymous default filter is applied
+/// regardless of the requesting user. Spoilered images are listed without their thumbnail.
+async fn image_feed(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    title: String,
+    url: String,
+    query: &str,
+    exclude_anonymous: bool,
+) -> TiberiusResult<Feed> {
+    let filter = Filter::default_filter(client).await?;
+    let aqueries = vec!["processed.eq:true".to_string()];
+    let anqueries = filter.negative_queries(client).await?;
+    let (_, images) = Image::search(
+        client,
+        query,
+        aqueries,
+        anqueries,
+        ImageSortBy::CreatedAt(SortDirection::Descending),
+        0,
+        FEED_SIZE,
+    )
+    .await?;
+    let static_host = absolute_static_host(state, rstate);
+    let mut entries = Vec::with_capacity(images.len());
+    for image in images {
+        if !image.publicly_visible() || filter.hides_image(&image) {
+            continue;
+        }
+        let anonymous = image.anonymous.unwrap_or(false);
+        if exclude_anonymous && anonymous {
+            continue;
+        }
+        let tags: Vec<String> = image
+            .tags(client)
+            .await?
+            .into_iter()
+            .map(|x| x.full_name())
+            .collect();
+        let author = match (anonymous, image.user_id) {
+            (false, Some(user_id)) => User::get_id(client, user_id as i64).await?.map(|x| x.name),
+            _ => None,
+        };
+        let thumbnail = if filter.spoilers_image(&image) {
+            format!(
+                "{}{}",
+                static_host,
+                static_path("images/tagblocked.svg").to_string_lossy()
+            )
+        } else {
+            image
+                .image_thumb_urls()
+                .await?
+                .with_host(Some(static_host.clone()))
+                .medium
+                .to_string()
+        };
+        let entry_url = state.config().absolute_url(
+            PathShowImage {
+                image: image.id as u64,
+            }
+            .to_uri(),
+        );
+        let content = html! {
+            p {
+                a href=(entry_url) { img src=(thumbnail) alt=(tags.join(", ")); }
+            }
+            (PreEscaped(render_markdown(&image.description, None)))
+            p { "Tags: " (tags.join(", ")) }
+        };
+        entries.push(FeedEntry {
+            title: format!("#{} - {}", image.id, tags.join(", ")),
+            url: entry_url,
+            published: image.created_at,
+            updated: image.updated_at,
+            author,
+            tags,
+            content,
+        });
+    }
+    Ok(Feed {
+        title: format!("{} - {}", title, state.site_config().site_name()),
+        url: state.config().absolute_url(url),
+        self_url: state.config().absolute_url(&rstate.uri.0),
+        entries,
+    })
+}
+
+async fn user_by_name(client: &mut Client, username: &str) -> TiberiusResult<User> {
+    User::get_by_name(client, username)
+        .await?
+        .ok_or_else(|| TiberiusError::ObjectNotFound("User".to_string(), username.to_string()))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn search_feed(
+    PathSearchFeed { format }: PathSearchFeed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    Query(query): Query<QuerySearchFeed>,
+) -> TiberiusResult<TiberiusResponse<String>> {
+    let mut client = state.get_db_client();
+    let q = query.q.unwrap_or_default();
+    let url = format!(
+        "/search?{}",
+        tiberius_dependencies::serde_urlencoded::to_string([("q", q.as_str())]).unwrap_or_default()
+    );
+    image_feed(
+        &state,
+        &rstate,
+        &mut client,
+        format!("Search: {}", q),
+        url,
+        if q.trim().is_empty() {
+            state.site_config().activity_filter()
+        } else {
+            &q
+        },
+        false,
+    )
+    .await?
+    .into_response(format)
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn tag_feed(
+    PathTagFeed { format, tag }: PathTagFeed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<String>> {
+    let mut client = state.get_db_client();
+    let found = match parse_tag_input(&tag).into_iter().next() {
+        Some((name, namespace)) => Tag::get_by_name(&mut client, namespace, name).await?,
+        None => None,
+    };
+    let found = found.ok_or_else(|| TiberiusError::ObjectNotFound("Tag".to_string(), tag))?;
+    let url = PathTagsByNameShowTag {
+        tag: found.full_name(),
+    }
+    .to_uri()
+    .to_string();
+    image_feed(
+        &state,
+        &rstate,
+        &mut client,
+        format!("Tag: {}", found.full_name()),
+        url,
+        &found.full_name(),
+        false,
+    )
+    .await?
+    .into_response(format)
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn user_uploads_feed(
+    PathUserUploadsFeed { format, username }: PathUserUploadsFeed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<String>> {
+    let mut client = state.get_db_client();
+    let user = user_by_name(&mut client, &username).await?;
+    let url = PathUserProfile {
+        username: user.name.clone(),
+    }
+    .to_uri()
+    .to_string();
+    image_feed(
+        &state,
+        &rstate,
+        &mut client,
+        format!("Uploads by {}", user.name),
+        url,
+        &format!("uploader_id.eq:{}", user.id),
+        true,
+    )
+    .await?
+    .into_response(format)
+}
+
+/// Feed of the images a user has favourited
+///
+/// Images are matched by the `faved_by` field of the search index, which is only updated when
+/// the image is reindexed. A new favourite shows up in the feed once that happens.
+#[instrument(skip(state, rstate))]
+pub async fn user_favourites_feed(
+    PathUserFavouritesFeed { format, username }: PathUserFavouritesFeed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<String>> {
+    let mut client = state.get_db_client();
+    let user = user_by_name(&mut client, &username).await?;
+    let url = PathUserProfile {
+        username: user.name.clone(),
+    }
+    .to_uri()
+    .to_string();
+    image_feed(
+        &state,
+        &rstate,
+        &mut client,
+        format!("Favourites of {}", user.name),
+        url,
+        &format!("faved_by.eq:{}", user.id),
+        false,
+    )
+    .await?
+    .into_response(format)
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn front_page_feed(
+    PathFrontPageFeed { format }: PathFrontPageFeed,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<String>> {
+    let mut client = state.get_db_client();
+    image_feed(
+        &state,
+        &rstate,
+        &mut client,
+        "Recently uploaded".to_string(),
+        "/".to_string(),
+        "created_at.lte:10 minutes ago",
+        false,
+    )
+    .await?
+    .into_response(format)
+}
+
+#[cfg(test)]
+mod test {
+    use maud::html;
+    use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+
+    use super::{Feed, FeedEntry, FeedFormat, PathFrontPageFeed};
+    use axum_extra::routing::TypedPath;
+
+    fn test_feed() -> Feed {
+        let date = NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap();
+        Feed {
+            title: "Search: <script> - Booru".to_string(),
+            url: "https://example.com/search?q=safe".to_string(),
+            self_url: "https://example.com/feeds/atom/search?q=safe".to_string(),
+            entries: vec![FeedEntry {
+                title: "#1 - safe".to_string(),
+                url: "https://example.com/1".to_string(),
+                published: date,
+                updated: date,
+                author: None,
+                tags: vec!["safe".to_string()],
+                content: html! { p { "<b>description</b>" } },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_feed_path() {
+        assert_eq!(
+            "/feeds/rss/front",
+            PathFrontPageFeed {
+                format: FeedFormat::Rss
+            }
+            .to_uri()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_atom_escapes_content() {
+        let atom = test_feed().render_atom();
+        assert!(atom.starts_with(r#"<?xml version="1.0" encoding="utf-8"?><feed"#));
+        assert!(atom.contains("<title>Search: &lt;script&gt; - Booru</title>"));
+        assert!(atom.contains("&lt;p&gt;&amp;lt;b&amp;gt;description"));
+        assert!(atom.contains("<name>Anonymous</name>"));
+        assert!(atom.contains("<updated>2020-09-13T12:26:40+00:00</updated>"));
+    }
+
+    #[test]
+    fn test_rss_dates() {
+        let rss = test_feed().render_rss();
+        assert!(rss.contains("<pubDate>Sun, 13 Sep 2020 12:26:40 +0000</pubDate>"));
+        assert!(rss.contains(r#"<guid isPermaLink="true">https://example.com/1</guid>"#));
+    }
+
+    #[test]
+    fn test_empty_feed_updated() {
+        let mut feed = test_feed();
+        feed.entries.clear();
+        let age = Utc::now().naive_utc() - feed.updated();
+        assert!(age >= Duration::zero() && age < Duration::minutes(1));
+    }
+}
@@ -21,16 +21,20 @@ use tiberius_core::{
     app::PageTitle,
     error::{TiberiusError, TiberiusResult},
     path_and_query,
-    request_helper::{HtmlResponse, RedirectResponse, TiberiusResponse},
+    request_helper::{ApiFormDataEmpty, HtmlResponse, RedirectResponse, TiberiusResponse},
     session::{Authenticated, SessionMode, Unauthenticated},
     state::{TiberiusRequestState, TiberiusState},
-    PathQuery,
+    AllowFraming, PathQuery,
 };
-use tiberius_dependencies::chrono::{DateTime, Utc};
+use tiberius_dependencies::chrono::{self, DateTime, Utc};
 use tiberius_dependencies::serde_urlencoded;
 use tiberius_dependencies::{axum_flash::Flash, mime, sentry};
 use tiberius_models::PathImageGetFull;
-use tiberius_models::{comment::Comment, Image, ImageMeta};
+use tiberius_models::{
+    comment::Comment, Client, DnpEntry, Filter, Image, ImageFeature, ImageMeta, ImageThumbType,
+    Notification, Tag, TagLike, TagView, User, UserLink, UserStatistic, UserStatisticKind,
+    IMAGE_FEATURE_NOTIFICATION_ACTOR,
+};
 use tokio::{
     fs::File,
     io::{AsyncWriteExt, BufWriter},
@@ -44,13 +48,15 @@ use crate::{
         activity::PathActivityIndex,
         common::{
             comment::{comment_form, comment_view, single_comment},
-            frontmatter::{image_clientside_data, quick_tag_table, tag_editor},
+            frontmatter::{csrf_input_tag, image_clientside_data, quick_tag_table, tag_editor},
             human_date,
             image::show_vote_counts,
             renderer::{textile::render_textile, textile_extensions},
+            routes::absolute_static_host,
             tag::tag_markup,
         },
         tags::{PathTagsByNameShowTag, PathTagsShowTag},
+        user::PathUserProfile,
         PathImageGetShort, PathImageThumbGetSimple,
     },
     MAX_IMAGE_DIMENSION,
@@ -62,9 +68,14 @@ pub fn image_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     let r = r.typed_get(get_image_comment);
     let r = r.typed_get(specific_show_image);
     let r = r.typed_get(show_random_image);
+    let r = r.typed_get(embed_image);
+    let r = r.typed_get(embed_image_no_flag);
     let r = r.typed_get(show_image);
+    let r = r.typed_get(featured_images);
 
     r.typed_post(repair_image_thumbnail)
+        .typed_post(feature_image)
+        .typed_post(read_feature_notification)
 }
 
 #[derive(TypedPath, Deserialize)]
@@ -115,14 +126,96 @@ pub struct PathEmbedImageNoFlag {
     image: u64,
 }
 
-#[tracing::instrument]
-pub async fn embed_image_no_flag(_: PathEmbedImageNoFlag) -> TiberiusResult<()> {
-    todo!()
+#[instrument(skip(state, rstate))]
+pub async fn embed_image_no_flag(
+    PathEmbedImageNoFlag { image }: PathEmbedImageNoFlag,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<(Extension<AllowFraming>, TiberiusResponse<()>)> {
+    render_embed(&state, &rstate, image, ImageThumbType::Medium).await
 }
 
-#[tracing::instrument]
-pub async fn embed_image(_: PathEmbedImage) -> TiberiusResult<()> {
-    todo!()
+#[instrument(skip(state, rstate))]
+pub async fn embed_image(
+    PathEmbedImage { image, flag }: PathEmbedImage,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<(Extension<AllowFraming>, TiberiusResponse<()>)> {
+    let size = match flag.as_str() {
+        "small" => ImageThumbType::Small,
+        "medium" => ImageThumbType::Medium,
+        "large" => ImageThumbType::Large,
+        "full" => ImageThumbType::Full,
+        _ => return Err(TiberiusError::PageNotFound(flag)),
+    };
+    render_embed(&state, &rstate, image, size).await
+}
+
+/// Renders a standalone page showing a single image, meant to be framed by other sites
+///
+/// Hidden, deleted and images filtered by the default filter are replaced by a placeholder
+/// that only links back to the image page.
+async fn render_embed(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    image: u64,
+    size: ImageThumbType,
+) -> TiberiusResult<(Extension<AllowFraming>, TiberiusResponse<()>)> {
+    let mut client = state.get_db_client();
+    let image = match Image::get_id(&mut client, image as i64).await? {
+        Some(v) => v,
+        None => {
+            return Err(TiberiusError::ObjectNotFound(
+                "Image".to_string(),
+                image.to_string(),
+            ))
+        }
+    };
+    let filter = Filter::default_filter(&mut client).await?;
+    let visible =
+        image.publicly_visible() && !filter.hides_image(&image) && !filter.spoilers_image(&image);
+    let site_name = state.site_config().site_name();
+    let page_url = state.config().absolute_url(
+        PathShowImage {
+            image: image.id as u64,
+        }
+        .to_uri(),
+    );
+    let urls = image
+        .image_thumb_urls()
+        .await?
+        .with_host(Some(absolute_static_host(state, rstate)));
+    let src = match size {
+        ImageThumbType::Small => urls.small,
+        ImageThumbType::Large => urls.large,
+        ImageThumbType::Full => urls.full,
+        _ => urls.medium,
+    };
+    let caption = format!("Image #{} on {}", image.id, site_name);
+    let body = html! {
+        (maud::DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="robots" content="noindex";
+                title { (caption) }
+                style { "body{margin:0;font-family:sans-serif;font-size:12px}img{max-width:100%;height:auto;display:block}p{margin:2px 4px}" }
+            }
+            body {
+                a href=(page_url) target="_blank" rel="noopener" {
+                    @if visible {
+                        img src=(src.to_string()) alt=(image.tag_list_cache.as_deref().unwrap_or(&caption));
+                    } @else {
+                        p { "This image is not available for embedding." }
+                    }
+                }
+                p {
+                    a href=(page_url) target="_blank" rel="noopener" { (caption) }
+                }
+            }
+        }
+    };
+    Ok((Extension(AllowFraming), TiberiusResponse::Html(body.into())))
 }
 
 #[instrument(skip(state, rstate))]
@@ -232,6 +325,7 @@ pub async fn show_image(
             .with_flash(flash.warning("Image not found")));
         }
     };
+    rstate.interactions_for(&[image.id as i64]);
     let allow_merge_duplicate: bool = verify_acl(
         &state,
         &rstate,
@@ -253,9 +347,20 @@ pub async fn show_image(
         ACLActionImage::RepairImage,
     )
     .await?;
+    let allow_feature_image: bool =
+        verify_acl(&state, &rstate, ACLObject::Image, ACLActionImage::Feature).await?;
     if allow_count_view {
         image.increment_views(&mut client).await?;
     }
+    let unread_feature = match (rstate.user(&state).await?, image.featured_on) {
+        (Some(user), Some(_)) if image.user_id == Some(user.id) => {
+            Notification::unread_for_user(&mut client, user.id)
+                .await?
+                .iter()
+                .any(|x| x.actor_type == IMAGE_FEATURE_NOTIFICATION_ACTOR && x.actor_id == image.id)
+        }
+        _ => false,
+    };
     let image_meta = image.metadata(&mut client).await?;
     let image_size = human_bytes::human_bytes(image.image_size.unwrap_or(0));
     let image_meta = html! {
@@ -383,6 +488,7 @@ pub async fn show_image(
         filename: image.filename(),
     };
     let thumb_url = thumb_url.to_uri().to_string();
+    let autoplay = rstate.user_settings(&state).await?.autoplay;
     let image_target = html! {
         .block.block--fixed.block--warning.block--no-margin.image-filtered.hidden {
             strong {
@@ -405,7 +511,7 @@ pub async fn show_image(
                 a href="//TODO: raw image" title=(image.title_text(&mut client).await?) {
                     span.imgspoiler {
                         @if image.image_mime_type == Some("video/webm".to_string()) {
-                            video data-image-id=(image.id) autoplay="autoplay" loop="loop" muted="muted" playsinline="playsinline" {
+                            video data-image-id=(image.id) autoplay[autoplay] loop="loop" muted="muted" playsinline="playsinline" {
                                 source src=(thumb_url) type="video/webm";
                                 source src=(thumb_url.replace(".webm", ".mp4")) type="video/mp4";
                             }
@@ -466,6 +572,7 @@ pub async fn show_image(
         .await?
         .expect("no quicktag view available");
     let tag_data = tag_data.get_tags();
+    let artists = verified_artists(&mut client, &tag_data).await?;
     let tags = html! {
         div.tagsauce {
             div.block {}
@@ -474,6 +581,16 @@ pub async fn show_image(
                     (tag_markup(tag))
                 }
             }
+            @if !artists.is_empty() {
+                p {
+                    i.fa.fa-check-circle title="Verified artist" {}
+                    " Verified artist: "
+                    @for (i, artist) in artists.iter().enumerate() {
+                        @if i > 0 { ", " }
+                        a href=(PathUserProfile { username: artist.name.clone() }.to_uri().to_string()) { (artist.displayname()) }
+                    }
+                }
+            }
         }
     };
     let source = html! {
@@ -515,7 +632,35 @@ pub async fn show_image(
             }
         }
     };
-    let options = html! {};
+    let options = html! {
+        @if unread_feature {
+            .block.block--fixed.block--success {
+                form method="POST" action=(PathImageFeatureRead{image: image.id as i64}.to_uri().to_string()) {
+                    (csrf_input_tag(&rstate).await)
+                    "Your image was featured on the front page! "
+                    button.button.button--link autocomplete="off" type="submit" { "Mark as read" }
+                }
+            }
+        }
+        @if allow_feature_image {
+            .block {
+                .flex.flex--wrap {
+                    p {
+                        form method="POST" action=(PathFeatureImage{image: image.id as i64}.to_uri().to_string()) {
+                            (csrf_input_tag(&rstate).await)
+                            button.button autocomplete="off" type="submit" { "Feature" }
+                        }
+                    }
+                    p {
+                        @if let Some(featured_on) = image.featured_on {
+                            "Featured " (human_date(featured_on)) ". "
+                        }
+                        a href=(PathFeaturedImages{}.to_uri().to_string()) { "Feature history" }
+                    }
+                }
+            }
+        }
+    };
     let comments = html! {
         h4 { "Comments" }
         //(comment_form(&mut client, rstate.user(&state).await?, &image).await?)
@@ -554,6 +699,27 @@ pub async fn show_image(
     .with_flash(flash))
 }
 
+/// Returns the users verified as artist for one of the artist tags of the image
+async fn verified_artists(client: &mut Client, tags: &[TagView]) -> TiberiusResult<Vec<User>> {
+    let tag_ids: Vec<i32> = tags
+        .iter()
+        .filter(|x| x.namespace.as_deref() == Some("artist"))
+        .map(|x| x.id as i32)
+        .collect();
+    if tag_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut links = UserLink::get_verified_for_tags(client, &tag_ids).await?;
+    links.dedup_by_key(|x| x.user_id);
+    let mut artists = Vec::new();
+    for link in links {
+        if let Some(user) = User::get_id(client, link.user_id as i64).await? {
+            artists.push(user);
+        }
+    }
+    Ok(artists)
+}
+
 #[derive(TypedPath, Deserialize, Debug)]
 #[typed_path("/images/new")]
 pub struct PathUploadImagePage {}
@@ -630,11 +796,12 @@ pub async fn upload_image(
             }
         }
     };
+    let anonymous_by_default = rstate.user_settings(&state).await?.anonymous_by_default;
     let image_anon_form = html! {
         @if user.is_some() {
             .field {
                 label for="anonymous" { "Post anonymously" }
-                input.checkbox type="checkbox" id="anonymous" name="image.anonymous" value="true" {} //TODO: load this from server settings
+                input.checkbox type="checkbox" id="anonymous" name="image.anonymous" value="true" checked[anonymous_by_default] {}
             }
         }
     };
@@ -724,7 +891,7 @@ impl FromRequestParts<TiberiusState> for ImageUpload {
     ) -> Result<Self, Self::Rejection> {
         let limit = state.config().upload_max_size;
         let multipart = todo!();
-        Ok(spool_multipart(multipart, limit).await?)
+        Ok(spool_multipart(multipart, limit, false).await?)
     }
 }
 
@@ -732,24 +899,19 @@ impl FromRequestParts<TiberiusState> for ImageUpload {
 #[typed_path("/image")]
 pub struct PathImageUpload {}
 
-#[cfg(feature = "process-images")]
-#[instrument(skip(state, rstate))]
-pub async fn new_image(
-    State(state): State<TiberiusState>,
-    mut rstate: TiberiusRequestState<Authenticated>,
-    _: PathImageUpload,
-    image_metadata: ImageUpload,
-) -> TiberiusResult<TiberiusResponse<()>> {
-    use axum::Extension;
-    use tempfile::PersistError;
-    use tiberius_dependencies::hex;
+/// The result of an upload passing all checks in [check_upload]
+#[derive(Debug)]
+pub struct CheckedUpload {
+    pub tags: Vec<Tag>,
+    /// Location in the data root the upload is persisted to
+    pub target_path: std::path::PathBuf,
+}
 
-    tracing::debug!("got image: {:?}", image_metadata);
-    let image_path = image_metadata.image.path();
-    let content_type = image_metadata.content_type.clone();
-    debug!("Got image content_type: {:?}", content_type);
-    let content_type = content_type.to_string();
-    let ext = match content_type.as_str() {
+/// Returns the file extension under which uploads of the given content type are stored
+///
+/// Returns a message suitable for the uploader if we cannot accept the content type.
+pub fn upload_extension(content_type: &mime::Mime) -> Result<&'static str, String> {
+    Ok(match content_type.essence_str() {
         // Images
         "image/png" => ".png",
         "image/gif" => ".gif",
@@ -757,238 +919,232 @@ pub async fn new_image(
         "image/jpeg" => ".jpg",
         "image/webp" => ".webp",
         "image/avif" => ".avif",
-        "image/svg+xml" => {
-            rstate
-                .flash_mut()
-                .error("We don't support SVG uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
+        "image/svg+xml" => return Err("We don't support SVG uploads yet.".to_string()),
         "image/x-icon" => ".ico",
         "image/tiff" => ".tiff",
         // Audio
-        "audio/flac" => {
-            rstate.flash_mut().error("We don't audio uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        "audio/wav" => {
-            rstate.flash_mut().error("We don't audio uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        "audio/aac" => {
-            rstate.flash_mut().error("We don't audio uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        "audio/webm" => {
-            rstate.flash_mut().error("We don't audio uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        // Video,
-        "video/ogg" => {
-            rstate.flash_mut().error("We don't video uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        "video/webm" => {
-            rstate.flash_mut().error("We don't video uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
-        "video/mpeg" => {
-            rstate.flash_mut().error("We don't video uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
+        "audio/flac" | "audio/wav" | "audio/aac" | "audio/webm" => {
+            return Err("We don't audio uploads yet.".to_string())
         }
-        "video/mp4" => {
-            rstate.flash_mut().error("We don't video uploads yet.");
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
+        // Video
+        "video/ogg" | "video/webm" | "video/mpeg" | "video/mp4" => {
+            return Err("We don't video uploads yet.".to_string())
         }
         // Other
-        q => {
-            rstate
-                .flash_mut()
-                .error(format!("We can't process images of the type {}", q));
-            return Ok(TiberiusResponse::Redirect(Redirect::to(
-                PathUploadImagePage {}.to_uri().to_string().as_str(),
-            )));
-        }
+        q => return Err(format!("We can't process images of the type {}", q)),
+    })
+}
+
+/// Splits the comma separated tag input of an upload into name and namespace
+pub fn parse_tag_input(tag_input: &str) -> Vec<(String, Option<String>)> {
+    tag_input
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.split_once(':')
+                .map(|(x, y)| (y.to_string(), x.to_string()))
+                .map(|(x, y)| (x, Some(y)))
+                .unwrap_or((x.to_string(), None))
+        })
+        .collect()
+}
+
+/// Checks an upload against the rules shared by the upload form and the API
+///
+/// This validates file type and dimensions, the upload rate limit of the user,
+/// the tags and the DNP list. If the upload is rejected, the reason is returned
+/// as message suitable for the uploader.
+#[instrument(skip(state, client, upload))]
+pub async fn check_upload(
+    state: &TiberiusState,
+    client: &mut Client,
+    user: &User,
+    upload: &ImageUpload,
+) -> TiberiusResult<Result<CheckedUpload, String>> {
+    use tiberius_dependencies::hex;
+
+    if !user.confirmed() {
+        return Ok(Err(
+            "Please confirm your email address before uploading images".to_string(),
+        ));
+    }
+    let image_path = upload.image.path();
+    debug!("Got image content_type: {:?}", upload.content_type);
+    let ext = match upload_extension(&upload.content_type) {
+        Ok(v) => v,
+        Err(e) => return Ok(Err(e)),
     };
     {
-        let img = image::io::Reader::open(image_path)?;
+        let img = tiberius_dependencies::image::io::Reader::open(image_path)?;
         match img.with_guessed_format()?.into_dimensions() {
             Ok(v) => {
                 debug!("Image metadata: {:?}", v);
                 if v.0 > MAX_IMAGE_DIMENSION || v.1 > MAX_IMAGE_DIMENSION {
-                    rstate.flash_mut().error(format!("We can't process image: It's too large, the image is {}x{} but we only support up to {}x{}", v.0, v.1, MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION));
-                    return Ok(TiberiusResponse::Redirect(Redirect::to(
-                        PathUploadImagePage {}.to_uri().to_string().as_str(),
-                    )));
+                    return Ok(Err(format!("We can't process image: It's too large, the image is {}x{} but we only support up to {}x{}", v.0, v.1, MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
                 }
                 debug!("Image within max dimensions, proceeding");
             }
-            Err(e) => {
-                rstate
-                    .flash_mut()
-                    .error(format!("We can't process image: {}", e));
-                return Ok(TiberiusResponse::Redirect(Redirect::to(
-                    PathUploadImagePage {}.to_uri().to_string().as_str(),
-                )));
-            }
+            Err(e) => return Ok(Err(format!("We can't process image: {}", e))),
         }
     }
-    let (sha3_256_hash, sha512_hash) = {
+    let config = state.config();
+    let recent_uploads = Image::count_uploads_since(
+        client,
+        user.id(),
+        (Utc::now() - chrono::Duration::hours(1)).naive_utc(),
+    )
+    .await?;
+    if recent_uploads >= config.upload_rate_limit {
+        return Ok(Err(format!(
+            "You can only upload {} images per hour, please try again later",
+            config.upload_rate_limit
+        )));
+    }
+    //TODO: create missing tags automatically
+    let tags = Tag::get_many_by_name(client, parse_tag_input(&upload.tag_input), true).await?;
+    if tags.len() < 3 {
+        return Ok(Err(
+            "Describe the image with at least 3 existing tags, including ratings and applicable artist tags".to_string(),
+        ));
+    }
+    let tag_ids: Vec<i32> = tags.iter().map(|x| x.id).collect();
+    let dnp_entries = DnpEntry::get_listed_for_tags(client, &tag_ids).await?;
+    if let Some(dnp) = dnp_entries.iter().find(|x| x.blocks_upload(user)) {
+        let tag = tags
+            .iter()
+            .find(|x| x.id == dnp.tag_id)
+            .map(|x| x.full_name())
+            .unwrap_or_default();
+        return Ok(Err(format!(
+            "The artist {} is on the do-not-post list ({}), this image cannot be uploaded",
+            tag, dnp.dnp_type
+        )));
+    }
+    let sha3_256_hash = {
         let mut file = std::fs::File::open(image_path)?;
         let mut hasher_sha3 = sha3::Sha3_256::default();
-        let mut hasher_sha2 = sha2::Sha512::default();
-        let res = spawn_blocking(move || -> Result<(String, String), std::io::Error> {
+        spawn_blocking(move || -> Result<String, std::io::Error> {
             std::io::copy(&mut file, &mut hasher_sha3)?;
-            std::io::copy(&mut file, &mut hasher_sha2)?;
-            let sha3 = hex::encode(hasher_sha3.finalize().to_vec());
-            let sha2 = hex::encode(hasher_sha2.finalize().to_vec());
-            Ok((sha3, sha2))
+            Ok(hex::encode(hasher_sha3.finalize().to_vec()))
         })
-        .await??;
-        res
+        .await??
     };
-    let config = state.config();
     let unixts = chrono::Utc::now();
-    let mut subdir = config
+    let mut target_path = config
         .data_root
         .clone()
-        .expect("require configured data root directory");
-    subdir.push("images");
-    if !subdir.exists() {
-        std::fs::create_dir(&subdir)?;
-    }
-    subdir.push(unixts.format("%Y/%m/%d").to_string());
-    if !subdir.exists() {
-        std::fs::create_dir_all(&subdir)?;
-    }
+        .ok_or_else(|| TiberiusError::ConfigurationUnset("DATA_ROOT".to_string()))?;
+    target_path.push("images");
+    target_path.push(unixts.format("%Y/%m/%d").to_string());
     // 128 characters prevents issues with ZFS and similar filesystems with 255 max filenames
     // 128 is still enough to provide dedup over any single day unless someone uploads 2⁶⁴ files
-    subdir.push(format!("{}{}", &sha3_256_hash[0..(128 / 8)], ext));
-    let new_path = subdir;
-    if new_path.exists() {
-        //TODO: handle existing uploads instead of ignoring it
-        todo!("implement handling already uploaded images");
-    } else {
-        debug!("persisting file to {}", new_path.display());
-        todo!("persist file via image_metadata.image.persist_noclobber(&new_path)?");
-    };
-    // reprocess image to ensure it's not only valid but in a good base format with good compat in all devices
-    {
-        match content_type.as_str() {
-            "image/svg+xml" => {
-                let mut nfile = new_path.clone();
-                nfile.set_extension("svg11");
-                let inkscape = std::process::Command::new("inkscape")
-                    .arg(new_path)
-                    .arg("--export-plain-svg")
-                    .arg("--export-type=svg")
-                    .arg("--export-filename")
-                    .arg(&nfile)
-                    .output()?;
-                if !inkscape.status.success() {
-                    return Err(tiberius_core::error::TiberiusError::Other(
-                        "Inkscape could not convert to SVG1.1".to_string(),
-                    ));
-                }
-                assert!(nfile.is_file(), "inkscape must have created new file");
-                todo!("downconvert svg to svg1.1");
-            }
-            "image/png" => {
-                debug!("png needs no downconvert");
-            }
-            "image/jpeg" => {
-                debug!("jpeg needs no downconvert");
-            }
-            v => {
-                todo!("downconvert {}", v)
-            }
+    target_path.push(format!("{}{}", &sha3_256_hash[0..(128 / 8)], ext));
+    if target_path.exists() {
+        //TODO: link to the existing upload
+        return Ok(Err("This image has already been uploaded".to_string()));
+    }
+    Ok(Ok(CheckedUpload { tags, target_path }))
+}
+
+/// Persists an upload that passed [check_upload] into the data root and creates the image
+///
+/// If image processing is enabled, the image is scheduled for processing.
+#[instrument(skip(state, client, upload))]
+pub async fn store_upload(
+    state: &TiberiusState,
+    client: &mut Client,
+    user: &User,
+    upload: ImageUpload,
+    checked: CheckedUpload,
+) -> TiberiusResult<Image> {
+    let config = state.config();
+    let new_path = checked.target_path;
+    if let Some(parent) = new_path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?;
         }
     }
-    let tags: Vec<(String, Option<String>)> = image_metadata
-        .tag_input
-        .split(',')
-        .map(|x| x.trim())
-        .map(|x| {
-            x.split_once(":")
-                .map(|(x, y)| (y.to_string(), x.to_string()))
-                .map(|(x, y)| (x, Some(y)))
-                .unwrap_or((x.to_string(), None))
-        })
-        .collect();
-    let mut client = state.get_db_client();
-    //TODO: create missing tags automatically
+    let image_name = upload
+        .image
+        .path()
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string());
+    debug!("persisting file to {}", new_path.display());
+    upload.image.persist_noclobber(&new_path)?;
+    // reprocess image to ensure it's not only valid but in a good base format with good compat in all devices
+    match upload.content_type.essence_str() {
+        "image/png" => debug!("png needs no downconvert"),
+        "image/jpeg" => debug!("jpeg needs no downconvert"),
+        v => debug!("{} is left for the processing job", v),
+    }
+    let canon_path = new_path.strip_prefix(config.image_base())?;
     //TODO: rewrite image from scratch to discard metadata
-    let tags = tiberius_models::Tag::get_many_by_name(&mut client, tags, true).await?;
-    let tags = tags.into_iter().map(|x| x.id).collect();
-    let canon_path = new_path.clone();
-    let canon_path =
-        canon_path.strip_prefix(&config.data_root.as_ref().expect("require static data root"))?;
-    let canon_path = canon_path.strip_prefix("images")?;
-    //image.image.persist_to(new_path);
     let image = Image {
         image: Some(canon_path.to_string_lossy().to_string()),
-        image_name: image_metadata
-            .image
-            .path()
-            .file_name()
-            .map(|x| x.to_string_lossy().to_string()),
-        image_mime_type: Some(image_metadata.content_type.to_string()),
+        image_name,
+        image_mime_type: Some(upload.content_type.to_string()),
+        user_id: Some(user.id),
         //TODO: store IP of user
         //TODO: store fingerprint of user
-        anonymous: Some(image_metadata.anonymous),
-        source_url: image_metadata
-            .scraper_url
-            .clone()
-            .or(image_metadata.source_url.clone()),
-        tag_ids: tags,
-        description: image_metadata
-            .description
-            .clone()
-            .unwrap_or_else(String::new),
+        anonymous: Some(upload.anonymous),
+        source_url: upload.scraper_url.clone().or(upload.source_url.clone()),
+        tag_ids: checked.tags.iter().map(|x| x.id).collect(),
+        description: upload.description.clone().unwrap_or_default(),
         ..Default::default()
     };
-    let image = image.insert_new(&mut client).await?;
+    let image = image.insert_new(client).await?;
+    UserStatistic::increment(client, user.id, UserStatisticKind::Uploads).await?;
     #[cfg(feature = "process-images")]
     {
         use tiberius_jobs::process_image::ImageProcessConfig;
         debug!("Scheduling processing of image");
         tiberius_jobs::process_image::process_image(
-            &mut client,
+            &mut client.clone(),
             ImageProcessConfig {
                 image_id: image.id as u64,
             },
         )
         .await?;
     }
+    Ok(image)
+}
 
-    rstate
-        .flash_mut()
-        .info("We are processing your image, it might take a few minutes");
-    return Ok(TiberiusResponse::Redirect(Redirect::to(
+#[cfg(feature = "process-images")]
+#[instrument(skip(state, rstate))]
+pub async fn new_image(
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    _: PathImageUpload,
+    image_metadata: ImageUpload,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    tracing::debug!("got image: {:?}", image_metadata);
+    let mut client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let checked = match check_upload(&state, &mut client, &user, &image_metadata).await? {
+        Ok(v) => v,
+        Err(e) => {
+            return Ok(TiberiusResponse::Redirect(Redirect::to(
+                PathUploadImagePage {}.to_uri().to_string().as_str(),
+            ))
+            .with_flash(flash.error(e)));
+        }
+    };
+    let image = store_upload(&state, &mut client, &user, image_metadata, checked).await?;
+
+    Ok(TiberiusResponse::Redirect(Redirect::to(
         PathShowImage {
             image: image.id as u64,
         }
         .to_uri()
         .to_string()
         .as_str(),
-    )));
+    ))
+    .with_flash(flash.info("We are processing your image, it might take a few minutes")))
 }
 
 #[derive(TypedPath, Deserialize, Debug)]
@@ -1065,12 +1221,17 @@ pub async fn search(_: PathSearchEmpty, query: Query<QuerySearch>) -> TiberiusRe
 
 /// Spools a multipart of image upload type onto the disk
 ///
-/// If the upload exceeds the limit number of bytes, an error is returned
+/// If the upload exceeds the limit number of bytes, an error is returned.
+/// `anonymous` is used if the multipart has no anonymous field.
 #[tracing::instrument]
-pub async fn spool_multipart(mut multipart: Multipart, limit: u64) -> TiberiusResult<ImageUpload> {
+pub async fn spool_multipart(
+    mut multipart: Multipart,
+    limit: u64,
+    anonymous: bool,
+) -> TiberiusResult<ImageUpload> {
     let tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new()?;
     let mut upload: ImageUpload = ImageUpload {
-        anonymous: false,
+        anonymous,
         source_url: None,
         tag_input: String::new(),
         description: None,
@@ -1176,6 +1337,167 @@ pub async fn repair_image_thumbnail(
     Ok(TiberiusResponse::Other(()))
 }
 
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/images/:image/feature")]
+pub struct PathFeatureImage {
+    image: i64,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn feature_image(
+    PathFeatureImage { image }: PathFeatureImage,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    set_scope_tx!("POST /images/:image/feature");
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    if !verify_acl(&state, &rstate, ACLObject::Image, ACLActionImage::Feature).await? {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut image = match Image::get_id(&mut client, image).await? {
+        Some(image) => image,
+        None => {
+            return Err(TiberiusError::ObjectNotFound(
+                "Image".to_string(),
+                image.to_string(),
+            ))
+        }
+    };
+    let redirect = Redirect::to(
+        PathShowImage {
+            image: image.id as u64,
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    );
+    if image.hidden_from_users || image.deleted_by_id.is_some() {
+        return Ok(TiberiusResponse::Redirect(redirect)
+            .with_flash(flash.error("Deleted images cannot be featured")));
+    }
+    ImageFeature::feature(&mut client, &mut image, Some(user.id as i64)).await?;
+    info!("user {} featured image {}", user.id, image.id);
+    Ok(TiberiusResponse::Redirect(redirect)
+        .with_flash(flash.info("Image is now featured on the front page")))
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/images/:image/feature/read")]
+pub struct PathImageFeatureRead {
+    image: i64,
+}
+
+/// Marks the notification about the feature of the image as read for its uploader
+#[instrument(skip(state, rstate))]
+pub async fn read_feature_notification(
+    PathImageFeatureRead { image }: PathImageFeatureRead,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    Notification::mark_read_for_actor(
+        &mut state.get_db_client(),
+        IMAGE_FEATURE_NOTIFICATION_ACTOR,
+        image as i32,
+        user.id,
+    )
+    .await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathShowImage {
+            image: image as u64,
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    )))
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/images/featured")]
+pub struct PathFeaturedImages {}
+
+/// How many past features are listed in the feature history
+const FEATURE_HISTORY_LENGTH: i64 = 50;
+
+#[instrument(skip(state, rstate))]
+pub async fn featured_images(
+    _: PathFeaturedImages,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    if !verify_acl(&state, &rstate, ACLObject::Image, ACLActionImage::Feature).await? {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let mut features = Vec::new();
+    for feature in ImageFeature::history(&mut client, FEATURE_HISTORY_LENGTH).await? {
+        let user = match feature.user_id {
+            Some(user_id) => User::get_id(&mut client, user_id).await?,
+            None => None,
+        };
+        features.push((feature, user));
+    }
+    let body = html! {
+        h1 { "Featured Images" }
+        table.table {
+            thead {
+                tr {
+                    th { "Image" }
+                    th { "Featured by" }
+                    th { "Featured" }
+                }
+            }
+            tbody {
+                @for (feature, user) in &features {
+                    tr {
+                        td {
+                            a href=(PathShowImage{ image: feature.image_id as u64 }.to_uri().to_string()) {
+                                "#" (feature.image_id)
+                            }
+                        }
+                        td {
+                            @match (feature.user_id, user) {
+                                (_, Some(user)) => (user.displayname()),
+                                (Some(_), None) => "Unknown user",
+                                (None, None) => { em { "Automatic rotation" } },
+                            }
+                        }
+                        td { (human_date(feature.created_at)) }
+                    }
+                }
+            }
+        }
+    };
+    let app = crate::templates::common::frontmatter::app(
+        &state,
+        &rstate,
+        Some(PageTitle::from("Featured Images")),
+        &mut client,
+        body,
+        None,
+    )
+    .await?;
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: app.into_string(),
+    }))
+}
+
 #[cfg(test)]
 mod test {
     use crate::templates::images::{NavigateRelation, QueryNavigateImage, QuerySearchQuery};
@@ -1,25 +1,62 @@
-use axum::{extract::State, response::Redirect, Extension, Form, Router};
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+    Extension, Form, Router,
+};
 use axum_extra::routing::{RouterExt, TypedPath};
 use maud::{html, Markup, PreEscaped};
 use serde::Deserialize;
+use sqlx::types::ipnetwork::IpNetwork;
 use tiberius_core::{
-    error::TiberiusResult,
-    request_helper::{HtmlResponse, RedirectResponse, TiberiusResponse},
-    session::{AuthMethod, Authenticated, SessionMode, Unauthenticated},
+    config::{CaptchaForm, Configuration},
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{
+        ApiFormData, ApiFormDataEmpty, HtmlResponse, RedirectResponse, TiberiusResponse,
+    },
+    session::{AuthMethod, Authenticated, SessionMode, Unauthenticated, LOGIN_MAX_AGE_DAYS},
     state::{TiberiusRequestState, TiberiusState},
 };
-use tiberius_dependencies::{axum_flash::Flash, time};
-use tiberius_models::{Client, User, UserLoginResult};
+use tiberius_dependencies::{axum_flash::Flash, chrono::Duration, serde_urlencoded, time};
+use tiberius_mail::EmailService;
+use tiberius_models::{
+    check_password_policy, Client, User, UserLoginResult, UserToken, UserTokenContext,
+};
 
 use crate::{
-    api::v3::misc::sessho::PathApiV3MiscSessionHandover, templates::activity::PathActivityIndex,
+    api::v3::misc::sessho::PathApiV3MiscSessionHandover,
+    templates::{
+        activity::PathActivityIndex,
+        common::{
+            captcha::{captcha_input, verify_captcha, CaptchaQuery},
+            frontmatter::csrf_input_tag,
+        },
+        two_factor::PathTwoFactor,
+        webauthn::{
+            passkey_form, passkeys_enabled, start_authentication, webauthn, PathSessionPasskey,
+            PathSessionWebauthn, PathStepUpWebauthn,
+        },
+    },
 };
 
 pub fn session_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     r.typed_get(new_session)
         .typed_get(forgot_password)
+        .typed_post(post_forgot_password)
+        .typed_get(reset_password)
+        .typed_post(post_reset_password)
+        .typed_get(unlock_account)
         .typed_post(post_new_session)
+        .typed_get(session_totp)
+        .typed_post(post_session_totp)
+        .typed_get(step_up)
+        .typed_post(post_step_up)
+        .typed_get(new_registration)
         .typed_post(post_registration)
+        .typed_get(confirm_registration)
+        .typed_post(post_confirm_registration)
+        .typed_get(change_email)
+        .typed_post(post_change_email)
+        .typed_get(confirm_email_change)
         .typed_get(get_destroy_session)
 }
 
@@ -34,6 +71,7 @@ pub async fn new_session(
     rstate: TiberiusRequestState<Unauthenticated>,
 ) -> TiberiusResult<TiberiusResponse<()>> {
     let mut client: Client = state.get_db_client();
+    let passkeys = webauthn(&state)?.is_some();
     let body = html! {
         h1 { "Sign in" }
 
@@ -54,10 +92,6 @@ pub async fn new_session(
                 input.input #user_password name="password" type="password" required="true" placeholder="Password";
             }
 
-            .field {
-                input.input #user_totp name="totp" type="text" pattern="[0-9]{6}" placeholder="TOTP";
-            }
-
             /*.field { We don't implement session remembering, just let the session linger
                 input#user_remember_me name="remember_me" type="checkbox" value="true";
                 label for="user_remember_me" { "Remember me" }
@@ -68,6 +102,21 @@ pub async fn new_session(
             }
         }
 
+        @if passkeys {
+            h3 { "Sign in with a passkey" }
+            form action=(PathSessionPasskey{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+
+                .field {
+                    input.input #passkey_email name="email" type="text" required="true" placeholder="Email or name" autocomplete="username webauthn";
+                }
+
+                .actions {
+                    button.button type="submit" { "Continue with passkey" }
+                }
+            }
+        }
+
         p {
             strong {
                 "Haven't read the "
@@ -88,9 +137,309 @@ pub async fn new_session(
 #[typed_path("/session/forgot_pw")]
 pub struct PathSessionForgotPw {}
 
-#[tracing::instrument]
-pub async fn forgot_password(_: PathSessionForgotPw) -> TiberiusResult<String> {
-    todo!()
+/// Builds an absolute link for a mail from the configured base URL, never from the request
+fn mail_link<P: TypedPath>(config: &Configuration, path: P) -> String {
+    config.absolute_url(path.to_uri())
+}
+
+/// Returns the mail queue, logging why no mail can be sent otherwise
+///
+/// Mail is delivered by the send_mail job, so requests don't wait on the mail server.
+pub(crate) fn mailer(state: &TiberiusState) -> Option<tiberius_mail::MailQueue> {
+    match tiberius_mail::MailQueue::from_config(state.get_db_client(), &state.config) {
+        Ok(Some(v)) => Some(v),
+        Ok(None) => {
+            warn!("No mail transport configured, mail will not be sent");
+            None
+        }
+        Err(e) => {
+            error!("Mail configuration is invalid: {}", e);
+            None
+        }
+    }
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn forgot_password(
+    _: PathSessionForgotPw,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let body = html! {
+        h1 { "Forgot your password?" }
+
+        form action=(PathSessionForgotPw{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+
+            p { "Enter the email address of your account and we will send you a link to set a new password." }
+
+            .field {
+                input.input #user_email name="email" type="email" required="true" placeholder="Email" autofocus="true" pattern=".*@.*";
+            }
+
+            .actions {
+                button.button type="submit" { "Send password reset link" }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_forgot_password(
+    _: PathSessionForgotPw,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormData<ForgotPassword>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    if let Some(user) = User::get_by_email(&mut client, form.data.email.trim()).await? {
+        send_password_reset(&state, &mut client, &user).await?;
+    } else {
+        debug!("password reset requested for unknown email");
+    }
+    // Don't reveal whether an account exists for the address
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathSessionsLogin {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info(
+        "If your email address is registered, you will receive a link to reset your password shortly",
+    )))
+}
+
+/// Mails the user a link to set a new password, previous links are revoked
+async fn send_password_reset(
+    state: &TiberiusState,
+    client: &mut Client,
+    user: &User,
+) -> TiberiusResult<()> {
+    UserToken::revoke_all(client, user.id as i64, UserTokenContext::ResetPassword).await?;
+    let token = UserToken::issue(
+        client,
+        user.id as i64,
+        UserTokenContext::ResetPassword,
+        Some(user.email.as_ref()),
+    )
+    .await?;
+    let reset_url = mail_link(state.config(), PathResetPassword { token });
+    if let Some(mailer) = mailer(state) {
+        if let Err(e) = mailer.send_email_reset(user, &reset_url).await {
+            error!("Could not send password reset to user {}: {}", user.id, e);
+        }
+    }
+    Ok(())
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/session/reset_pw/:token")]
+pub struct PathResetPassword {
+    token: String,
+}
+
+#[instrument(skip(state, rstate, token))]
+pub async fn reset_password(
+    PathResetPassword { token }: PathResetPassword,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let body = html! {
+        h1 { "Set a new password" }
+
+        form action=(PathResetPassword{ token }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+
+            .field {
+                input.input #user_password name="password" type="password" required="true" placeholder="New password" autofocus="true" minlength=(tiberius_models::PASSWORD_MIN_LENGTH);
+            }
+
+            .field {
+                input.input #user_password_confirmation name="password_confirmation" type="password" required="true" placeholder="Confirm new password";
+            }
+
+            p { "Setting a new password will log you out everywhere." }
+
+            .actions {
+                button.button type="submit" { "Set password" }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct ResetPassword {
+    #[sensitive]
+    password: String,
+    #[sensitive]
+    password_confirmation: String,
+}
+
+#[instrument(skip(state, rstate, token, form))]
+pub async fn post_reset_password(
+    PathResetPassword { token }: PathResetPassword,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormData<ResetPassword>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let retry = Redirect::to(
+        PathResetPassword {
+            token: token.clone(),
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    );
+    if form.data.password != form.data.password_confirmation {
+        return Ok(
+            TiberiusResponse::Redirect(retry).with_flash(flash.error("Passwords do not match"))
+        );
+    }
+    // Check the policy before consuming the token so the user can retry with the same link
+    let user = match UserToken::peek(&mut client, &token, UserTokenContext::ResetPassword).await? {
+        Some(token) => User::get_id(&mut client, token.user_id).await?,
+        None => None,
+    };
+    let invalid = Redirect::to(PathSessionForgotPw {}.to_uri().to_string().as_str());
+    let user = match user {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(invalid)
+                .with_flash(flash.error("This password reset link is invalid or has expired")))
+        }
+    };
+    if let Err(e) = check_password_policy(
+        &form.data.password,
+        state.config.password_pepper(),
+        &user.name,
+        user.email.as_ref(),
+    ) {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    let mut user =
+        match UserToken::consume(&mut client, &token, UserTokenContext::ResetPassword).await? {
+            Some(_) => user,
+            None => {
+                return Ok(TiberiusResponse::Redirect(invalid)
+                    .with_flash(flash.error("This password reset link is invalid or has expired")))
+            }
+        };
+    let encrypted_password =
+        User::hash_password(state.config.password_pepper(), &form.data.password)?;
+    user.set_password(&mut client, encrypted_password).await?;
+    UserToken::revoke_all(&mut client, user.id as i64, UserTokenContext::ResetPassword).await?;
+    UserToken::revoke_all(&mut client, user.id as i64, UserTokenContext::Unlock).await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathSessionsLogin {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("Your password has been changed, please log in again")))
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/session/unlock/:token")]
+pub struct PathUnlockAccount {
+    token: String,
+}
+
+#[instrument(skip(state, token))]
+pub async fn unlock_account(
+    PathUnlockAccount { token }: PathUnlockAccount,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let mut client = state.get_db_client();
+    let login = Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str());
+    let user = match UserToken::consume(&mut client, &token, UserTokenContext::Unlock).await? {
+        Some(token) => User::get_id(&mut client, token.user_id).await?,
+        None => None,
+    };
+    match user {
+        Some(mut user) => {
+            user.unlock(&mut client).await?;
+            Ok(TiberiusResponse::Redirect(login)
+                .with_flash(flash.info("Your account has been unlocked, you can log in again")))
+        }
+        None => Ok(TiberiusResponse::Redirect(login)
+            .with_flash(flash.error("This unlock link is invalid or has expired"))),
+    }
+}
+
+/// Shown when logins from the address of the request are locked
+pub(crate) const LOCKED_OUT: &str = "Logins to this account from your network have been locked after too many failed attempts, the owner can unlock them with the link sent to their email";
+
+/// Shown when the second factor of a login was not entered in time
+pub(crate) const LOGIN_TIMED_OUT: &str = "Your login timed out, please enter your password again";
+
+/// Drops a pending login that waited too long on the second factor
+///
+/// Returns true if the user has to start over with their password.
+pub(crate) fn expire_pending_login(
+    rstate: &mut TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<bool> {
+    if !rstate.session().second_factor_expired() {
+        return Ok(false);
+    }
+    rstate.session_mut().unset_user();
+    rstate.push_session_update()?;
+    Ok(true)
+}
+
+/// The address failed logins of the request are counted for, see [User::record_failed_login]
+pub(crate) fn login_address<T: SessionMode>(rstate: &TiberiusRequestState<T>) -> IpNetwork {
+    IpNetwork::from(
+        rstate
+            .client_ip
+            .unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into()),
+    )
+}
+
+/// Sends the user a link to unlock their account
+async fn send_unlock<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    user: &User,
+) -> TiberiusResult<()> {
+    UserToken::revoke_all(client, user.id as i64, UserTokenContext::Unlock).await?;
+    let token = UserToken::issue(
+        client,
+        user.id as i64,
+        UserTokenContext::Unlock,
+        Some(user.email.as_ref()),
+    )
+    .await?;
+    let unlock_url = mail_link(state.config(), PathUnlockAccount { token });
+    if let Some(mailer) = mailer(state) {
+        if let Err(e) = mailer.send_unlock_account(user, &unlock_url).await {
+            error!("Could not send unlock mail to user {}: {}", user.id, e);
+        }
+    }
+    Ok(())
 }
 
 #[derive(TypedPath, Deserialize)]
@@ -102,8 +451,6 @@ pub struct NewSession {
     email: String,
     #[sensitive]
     password: String,
-    #[sensitive]
-    totp: Option<String>,
 }
 
 #[instrument(skip(state, rstate))]
@@ -119,40 +466,59 @@ pub async fn post_new_session(
 
     let user: Option<User> = User::get_mail_or_name(&mut client, login_data.email.as_str()).await?;
     let retry = PathSessionsLogin {}.to_uri();
-    if let Some(user) = user {
+    if let Some(mut user) = user {
+        if user
+            .locked_from(&mut client, login_address(&rstate))
+            .await?
+        {
+            debug!("login attempt on locked account");
+            return Ok((
+                flash.error(LOCKED_OUT),
+                Redirect::to(retry.to_string().as_str()),
+            ));
+        }
         let valid = user.validate_login(
             state.config.password_pepper(),
-            &state.config.otp_secret(),
             &login_data.email,
             &login_data.password,
-            login_data.totp,
         )?;
-        let home = PathActivityIndex {}.to_uri();
         match valid {
-            UserLoginResult::Valid => {
-                let session = rstate.session_mut();
-                session.set_user(&user);
-                let id = session.id();
-                debug!("Creating new session, persisting {} to DB", id);
-                rstate.db_session_mut().set_expiration_time_from_max_age(time::Duration::DAY * 365);
-                rstate.push_session_update()?;
-                Ok((
-                    flash.info("Login successfull!"),
-                    Redirect::to(PathActivityIndex {}.to_uri().to_string().as_str()),
-                ))
-            }
             UserLoginResult::Invalid => {
                 debug!("password disagree");
+                if user
+                    .record_failed_login(&mut client, login_address(&rstate))
+                    .await?
+                {
+                    info!(
+                        "locking address out of user {} after too many failed logins",
+                        user.id
+                    );
+                    send_unlock(&state, &rstate, &mut client, &user).await?;
+                }
                 Ok((
                     flash.error("User or password incorrect"),
                     Redirect::to(retry.to_string().as_str()),
                 ))
             }
-            UserLoginResult::RetryWithTOTP => {
-                debug!("password agree, TOTP missing");
+            UserLoginResult::Valid | UserLoginResult::RetryWithTOTP => {
+                let totp = matches!(valid, UserLoginResult::RetryWithTOTP);
+                let passkeys = passkeys_enabled(&state, &mut client, &user).await?;
+                rstate.session_mut().set_user(&user);
+                if !totp && !passkeys {
+                    return finish_login(&state, &mut rstate, &mut client, &mut user, flash).await;
+                }
+                debug!("password agree, waiting on second factor");
+                let session = rstate.session_mut();
+                if totp {
+                    session.set_waiting_auths(AuthMethod::TOTP);
+                }
+                if passkeys {
+                    session.set_waiting_auths(AuthMethod::WebAuthn);
+                }
+                rstate.push_session_update()?;
                 Ok((
-                    flash.error("User or password incorrect"),
-                    Redirect::to(retry.to_string().as_str()),
+                    flash,
+                    Redirect::to(PathSessionTotp {}.to_uri().to_string().as_str()),
                 ))
             }
         }
@@ -165,13 +531,750 @@ pub async fn post_new_session(
     }
 }
 
+/// Persists the session of a user that completed all login steps
+pub(crate) async fn finish_login(
+    state: &TiberiusState,
+    rstate: &mut TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    user: &mut User,
+    flash: Flash,
+) -> TiberiusResult<(Flash, Redirect)> {
+    let flash = if user.confirmed() {
+        flash
+    } else {
+        flash.warning(
+            "Please confirm your email address, some features are unavailable until you do",
+        )
+    };
+    user.clear_failed_logins(client, login_address(rstate))
+        .await?;
+    let id = rstate.session().id();
+    debug!("Creating new session, persisting {} to DB", id);
+    rstate
+        .db_session_mut()
+        .set_expiration_time_from_max_age(time::Duration::days(LOGIN_MAX_AGE_DAYS));
+    rstate.push_session_update()?;
+    if state.site_config().require_staff_two_factor()
+        && user.is_staff()
+        && !user.has_second_factor(client).await?
+    {
+        return Ok((
+            flash.warning(
+                "Staff members must enable two factor authentication to use their permissions",
+            ),
+            Redirect::to(PathTwoFactor {}.to_uri().to_string().as_str()),
+        ));
+    }
+    Ok((
+        flash.info("Login successfull!"),
+        Redirect::to(PathActivityIndex {}.to_uri().to_string().as_str()),
+    ))
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/sessions/totp")]
+pub struct PathSessionTotp {}
+
+#[instrument(skip(state, rstate))]
+pub async fn session_totp(
+    _: PathSessionTotp,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let mut client: Client = state.get_db_client();
+    if expire_pending_login(&mut rstate)? {
+        return Ok(TiberiusResponse::Redirect(Redirect::to(
+            PathSessionsLogin {}.to_uri().to_string().as_str(),
+        ))
+        .with_flash(flash.error(LOGIN_TIMED_OUT)));
+    }
+    let user = match rstate.session().pending_user(&mut client).await? {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(Redirect::to(
+                PathSessionsLogin {}.to_uri().to_string().as_str(),
+            ))
+            .with_flash(flash))
+        }
+    };
+    let totp = rstate.session().waiting_on(AuthMethod::TOTP);
+    let passkey = if rstate.session().waiting_on(AuthMethod::WebAuthn) {
+        start_authentication(&state, &mut rstate, &mut client, &user).await?
+    } else {
+        None
+    };
+    let body = html! {
+        h1 { "Two factor authentication" }
+
+        @if let Some(options) = passkey {
+            p { "Confirm it's you with one of your passkeys or security keys." }
+
+            (passkey_form(&rstate, PathSessionWebauthn{}.to_uri().to_string(), &options, html! {}).await)
+        }
+
+        @if totp {
+            form action=(PathSessionTotp{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+
+                p { "Enter the 6 digit code from your authenticator app. If you lost access to it, you can use one of your backup codes instead." }
+
+                .field {
+                    input.input #user_totp name="code" type="text" required="true" placeholder="Code" autofocus="true" autocomplete="one-time-code";
+                }
+
+                .actions {
+                    button.button type="submit" { "Sign in" }
+                }
+            }
+        }
+
+        p {
+            a href=(PathSessionLogout{}.to_uri().to_string()) { "Cancel and sign in as someone else" }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    })
+    .with_flash(flash))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct SessionTotp {
+    #[sensitive]
+    code: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_session_totp(
+    _: PathSessionTotp,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormData<SessionTotp>>,
+) -> TiberiusResult<(Flash, Redirect)> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let login = Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str());
+    if expire_pending_login(&mut rstate)? {
+        return Ok((flash.error(LOGIN_TIMED_OUT), login));
+    }
+    let mut user = match rstate.session().pending_user(&mut client).await? {
+        Some(v) if rstate.session().waiting_on(AuthMethod::TOTP) => v,
+        _ => return Ok((flash.error("Please log in again"), login)),
+    };
+    if user
+        .locked_from(&mut client, login_address(&rstate))
+        .await?
+    {
+        rstate.session_mut().unset_user();
+        rstate.push_session_update()?;
+        return Ok((flash.error(LOCKED_OUT), login));
+    }
+    let valid = user
+        .verify_second_factor(
+            &mut client,
+            state.config.password_pepper(),
+            &state.config.otp_secret(),
+            &form.data.code,
+        )
+        .await?;
+    if valid {
+        rstate.session_mut().complete_auth(AuthMethod::TOTP);
+        finish_login(&state, &mut rstate, &mut client, &mut user, flash).await
+    } else if user
+        .record_failed_login(&mut client, login_address(&rstate))
+        .await?
+    {
+        info!(
+            "locking address out of user {} after too many failed logins",
+            user.id
+        );
+        send_unlock(&state, &rstate, &mut client, &user).await?;
+        rstate.session_mut().unset_user();
+        rstate.push_session_update()?;
+        Ok((flash.error(LOCKED_OUT), login))
+    } else {
+        Ok((
+            flash.error("The code is invalid or has already been used"),
+            Redirect::to(PathSessionTotp {}.to_uri().to_string().as_str()),
+        ))
+    }
+}
+
+/// How long a step-up authentication allows sensitive actions
+pub(crate) const STEP_UP_MAX_AGE_MINUTES: i64 = 15;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/sessions/step_up")]
+pub struct PathStepUp {}
+
+/// Only allows local paths as redirect target after a step-up, to avoid open redirects
+pub(crate) fn safe_return_to(return_to: Option<&str>) -> String {
+    match return_to {
+        Some(v) if v.starts_with('/') && !v.starts_with("//") && !v.contains('\\') => v.to_string(),
+        _ => PathActivityIndex {}.to_uri().to_string(),
+    }
+}
+
+pub(crate) fn step_up_uri(return_to: &str) -> String {
+    format!(
+        "{}?{}",
+        PathStepUp {}.to_uri(),
+        serde_urlencoded::to_string([("return_to", return_to)])
+            .expect("a single pair must always encode")
+    )
+}
+
+/// Returns a redirect to the step-up page unless the user recently re-authenticated
+///
+/// Sensitive moderator actions call this after checking the ACL, the user is sent back
+/// to `return_to` afterwards.
+pub(crate) fn require_step_up<T: SessionMode>(
+    rstate: &TiberiusRequestState<T>,
+    return_to: &str,
+) -> Option<Redirect> {
+    if rstate
+        .session()
+        .stepped_up(Duration::minutes(STEP_UP_MAX_AGE_MINUTES))
+    {
+        None
+    } else {
+        Some(Redirect::to(&step_up_uri(return_to)))
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct StepUpQuery {
+    return_to: Option<String>,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn step_up(
+    _: PathStepUp,
+    State(state): State<TiberiusState>,
+    mut rstate: TiberiusRequestState<Authenticated>,
+    Query(query): Query<StepUpQuery>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let return_to = safe_return_to(query.return_to.as_deref());
+    let passkey = start_authentication(&state, &mut rstate, &mut client, &user).await?;
+    let body = html! {
+        h1 { "Confirm it's you" }
+
+        p { "This action requires you to confirm your identity again." }
+
+        @if let Some(options) = passkey {
+            (passkey_form(
+                &rstate,
+                PathStepUpWebauthn{}.to_uri().to_string(),
+                &options,
+                html! { input type="hidden" name="return_to" value=(return_to); },
+            ).await)
+        }
+
+        form action=(PathStepUp{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            input type="hidden" name="return_to" value=(return_to);
+
+            .field {
+                input.input #user_current_password name="current_password" type="password" required="true" placeholder="Current password" autofocus="true";
+            }
+
+            @if user.totp_enabled() {
+                .field {
+                    input.input #user_totp name="code" type="text" required="true" placeholder="Code or backup code" autocomplete="one-time-code";
+                }
+            }
+
+            .actions {
+                button.button type="submit" { "Confirm" }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct StepUp {
+    #[sensitive]
+    current_password: String,
+    #[sensitive]
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    return_to: Option<String>,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_step_up(
+    _: PathStepUp,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<StepUp>>,
+) -> TiberiusResult<(Flash, Redirect)> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let return_to = safe_return_to(form.data.return_to.as_deref());
+    if user
+        .locked_from(&mut client, login_address(&rstate))
+        .await?
+    {
+        rstate.session_mut().unset_user();
+        rstate.push_session_update()?;
+        return Ok((
+            flash.error(LOCKED_OUT),
+            Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str()),
+        ));
+    }
+    let mut valid =
+        user.verify_password(state.config.password_pepper(), &form.data.current_password)?;
+    if valid && user.totp_enabled() {
+        valid = match &form.data.code {
+            Some(code) => {
+                user.verify_second_factor(
+                    &mut client,
+                    state.config.password_pepper(),
+                    &state.config.otp_secret(),
+                    code,
+                )
+                .await?
+            }
+            None => false,
+        };
+    }
+    if valid {
+        rstate.session_mut().step_up();
+        rstate.push_session_update()?;
+        Ok((flash, Redirect::to(&return_to)))
+    } else if user
+        .record_failed_login(&mut client, login_address(&rstate))
+        .await?
+    {
+        info!(
+            "locking address out of user {} after too many failed logins",
+            user.id
+        );
+        send_unlock(&state, &rstate, &mut client, &user).await?;
+        rstate.session_mut().unset_user();
+        rstate.push_session_update()?;
+        Ok((
+            flash.error(LOCKED_OUT),
+            Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str()),
+        ))
+    } else {
+        Ok((
+            flash.error("Your password or code is incorrect"),
+            Redirect::to(&step_up_uri(&return_to)),
+        ))
+    }
+}
+
 #[derive(TypedPath, serde::Deserialize)]
 #[typed_path("/sessions/register")]
 pub struct PathRegistration {}
 
-#[tracing::instrument]
-pub async fn post_registration(_: PathRegistration) -> TiberiusResult<String> {
-    todo!()
+/// Longest name a new account may use
+pub const USERNAME_MAX_LENGTH: usize = 50;
+
+#[instrument(skip(state, rstate))]
+pub async fn new_registration(
+    _: PathRegistration,
+    State(state): State<TiberiusState>,
+    Query(captcha): Query<CaptchaQuery>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let body = html! {
+        h1 { "Sign up" }
+
+        form action=(PathRegistration{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+
+            .field {
+                input.input #user_name name="name" type="text" required="true" placeholder="Username" autofocus="true" maxlength=(USERNAME_MAX_LENGTH);
+            }
+
+            .field {
+                input.input #user_email name="email" type="email" required="true" placeholder="Email" pattern=".*@.*";
+            }
+
+            .field {
+                input.input #user_password name="password" type="password" required="true" placeholder="Password" minlength=(tiberius_models::PASSWORD_MIN_LENGTH);
+            }
+
+            .field {
+                input.input #user_password_confirmation name="password_confirmation" type="password" required="true" placeholder="Confirm password";
+            }
+
+            p {
+                "Your password must be at least " (tiberius_models::PASSWORD_MIN_LENGTH) " characters long and must not contain your username or email."
+            }
+
+            (captcha_input(&state, CaptchaForm::Registration, captcha.accessible_captcha))
+
+            .actions {
+                button.button type="submit" { "Sign up" }
+            }
+        }
+
+        p {
+            "We will send you an email to confirm your address. Until then, you won't be able to upload images or create API keys."
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct Registration {
+    name: String,
+    email: String,
+    #[sensitive]
+    password: String,
+    #[sensitive]
+    password_confirmation: String,
+}
+
+impl Registration {
+    /// Checks the form data for problems that don't require a database lookup
+    fn validate(&self, pepper: Option<&str>) -> Result<(), &'static str> {
+        let name = self.name.trim();
+        validate_username(name)?;
+        match self.email.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && !domain.is_empty() => (),
+            _ => return Err("Email address is invalid"),
+        }
+        if self.password != self.password_confirmation {
+            return Err("Passwords do not match");
+        }
+        check_password_policy(&self.password, pepper, name, &self.email)
+    }
+}
+
+/// Checks a new username for problems that don't require a database lookup
+pub fn validate_username(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || tiberius_models::slug::sluggify(name).is_empty() {
+        return Err("Username must contain letters or digits");
+    }
+    if name.chars().count() > USERNAME_MAX_LENGTH {
+        return Err("Username must be at most 50 characters long");
+    }
+    if name.chars().any(|x| x.is_control()) {
+        return Err("Username contains invalid characters");
+    }
+    Ok(())
+}
+
+#[instrument(skip(state, rstate, body))]
+pub async fn post_registration(
+    _: PathRegistration,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    body: String,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let form: ApiFormData<Registration> = serde_urlencoded::from_str(&body)?;
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let retry = Redirect::to(PathRegistration {}.to_uri().to_string().as_str());
+    let mut client = state.get_db_client();
+    if !verify_captcha(&state, &mut client, CaptchaForm::Registration, &body).await? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("The captcha was not solved correctly")));
+    }
+    let registration = form.data;
+    if let Err(e) = registration.validate(state.config.password_pepper()) {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    let name = registration.name.trim();
+    if User::name_taken(&mut client, name, None).await? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("This username is already taken")));
+    }
+    let encrypted_password =
+        User::hash_password(state.config.password_pepper(), &registration.password)?;
+    let done = TiberiusResponse::Redirect(Redirect::to(
+        PathSessionsLogin {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info(
+        "Check your email, we have sent you a link to confirm your address and finish the registration",
+    ));
+    // Don't reveal whether an account exists for the address, the owner gets a password reset
+    // link instead so they can get back into their account
+    if let Some(existing) = User::get_by_email(&mut client, &registration.email).await? {
+        debug!(
+            "registration attempted with the email of user {}",
+            existing.id
+        );
+        send_password_reset(&state, &mut client, &existing).await?;
+        return Ok(done);
+    }
+    let user = User::create(&mut client, name, &registration.email, &encrypted_password).await?;
+    let token = UserToken::issue(
+        &mut client,
+        user.id as i64,
+        UserTokenContext::Confirm,
+        Some(&registration.email),
+    )
+    .await?;
+    let confirm_url = mail_link(state.config(), PathConfirmRegistration { token });
+    if let Some(mailer) = mailer(&state) {
+        if let Err(e) = mailer.send_signup_welcome(&user, &confirm_url).await {
+            error!(
+                "Could not send confirmation mail to user {}: {}",
+                user.id, e
+            );
+        }
+    }
+    Ok(done)
+}
+
+#[derive(TypedPath, serde::Deserialize)]
+#[typed_path("/registrations/confirm/:token")]
+pub struct PathConfirmRegistration {
+    token: String,
+}
+
+/// Asks the user to confirm their address
+///
+/// The token is only consumed by the form submission, so link scanners opening the mail don't
+/// use it up.
+#[instrument(skip(state, rstate, token))]
+pub async fn confirm_registration(
+    PathConfirmRegistration { token }: PathConfirmRegistration,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let body = html! {
+        h1 { "Confirm your email address" }
+
+        form action=(PathConfirmRegistration{ token }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+
+            .actions {
+                button.button type="submit" { "Confirm email address" }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate, token))]
+pub async fn post_confirm_registration(
+    PathConfirmRegistration { token }: PathConfirmRegistration,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let home = Redirect::to(PathActivityIndex {}.to_uri().to_string().as_str());
+    let token = UserToken::consume(&mut client, &token, UserTokenContext::Confirm).await?;
+    let user = match token {
+        Some(token) => User::get_id(&mut client, token.user_id).await?,
+        None => None,
+    };
+    let mut user = match user {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(home)
+                .with_flash(flash.error("This confirmation link is invalid or has expired")))
+        }
+    };
+    if !user.confirmed() {
+        user.confirm(&mut client).await?;
+    }
+    Ok(TiberiusResponse::Redirect(home)
+        .with_flash(flash.info("Your email address has been confirmed")))
+}
+
+#[derive(TypedPath, serde::Deserialize)]
+#[typed_path("/registrations/email")]
+pub struct PathChangeEmail {}
+
+#[instrument(skip(state, rstate))]
+pub async fn change_email(
+    _: PathChangeEmail,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let body = html! {
+        h1 { "Change email address" }
+
+        p {
+            "Your current email address is " strong { (user.email) } ". "
+            "We will send a confirmation link to the new address, it will only be changed once you open it."
+        }
+
+        form action=(PathChangeEmail{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+
+            .field {
+                input.input #user_email name="email" type="email" required="true" placeholder="New email" autofocus="true" pattern=".*@.*";
+            }
+
+            .field {
+                input.input #user_current_password name="current_password" type="password" required="true" placeholder="Current password";
+            }
+
+            .actions {
+                button.button type="submit" { "Change email" }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, None, &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct ChangeEmail {
+    email: String,
+    #[sensitive]
+    current_password: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_change_email(
+    _: PathChangeEmail,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<ChangeEmail>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathChangeEmail {}.to_uri().to_string().as_str());
+    if !user.verify_password(state.config.password_pepper(), &form.data.current_password)? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Your current password is incorrect")));
+    }
+    let email = form.data.email.trim().to_lowercase();
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => (),
+        _ => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("Email address is invalid")))
+        }
+    }
+    if User::get_by_email(&mut client, &email).await?.is_some() {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("This email address is already in use")));
+    }
+    UserToken::revoke_all(&mut client, user.id as i64, UserTokenContext::ChangeEmail).await?;
+    let token = UserToken::issue(
+        &mut client,
+        user.id as i64,
+        UserTokenContext::ChangeEmail,
+        Some(&email),
+    )
+    .await?;
+    let update_url = mail_link(state.config(), PathConfirmEmailChange { token });
+    // The confirmation goes to the new address to prove it belongs to the user
+    let recipient = User {
+        email: email.into(),
+        ..user.clone()
+    };
+    if let Some(mailer) = mailer(&state) {
+        if let Err(e) = mailer.send_update_email(&recipient, &update_url).await {
+            error!("Could not send email change to user {}: {}", user.id, e);
+        }
+    }
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathActivityIndex {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("Check your new email address for a link to confirm the change")))
+}
+
+#[derive(TypedPath, serde::Deserialize)]
+#[typed_path("/registrations/email/confirm/:token")]
+pub struct PathConfirmEmailChange {
+    token: String,
+}
+
+#[instrument(skip(state, token))]
+pub async fn confirm_email_change(
+    PathConfirmEmailChange { token }: PathConfirmEmailChange,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let mut client = state.get_db_client();
+    let home = Redirect::to(PathActivityIndex {}.to_uri().to_string().as_str());
+    let token = UserToken::consume(&mut client, &token, UserTokenContext::ChangeEmail).await?;
+    let (token, user) = match token {
+        Some(token) => {
+            let user = User::get_id(&mut client, token.user_id).await?;
+            (token, user)
+        }
+        None => {
+            return Ok(TiberiusResponse::Redirect(home)
+                .with_flash(flash.error("This confirmation link is invalid or has expired")))
+        }
+    };
+    let (mut user, email) = match (user, token.sent_to) {
+        (Some(user), Some(email)) => (user, email),
+        _ => {
+            return Ok(TiberiusResponse::Redirect(home)
+                .with_flash(flash.error("This confirmation link is invalid or has expired")))
+        }
+    };
+    if User::get_by_email(&mut client, &email).await?.is_some() {
+        return Ok(TiberiusResponse::Redirect(home)
+            .with_flash(flash.error("This email address is already in use")));
+    }
+    user.set_email(&mut client, &email).await?;
+    Ok(TiberiusResponse::Redirect(home)
+        .with_flash(flash.info("Your email address has been changed")))
 }
 
 #[derive(TypedPath, serde::Deserialize)]
@@ -194,3 +1297,25 @@ pub async fn get_destroy_session(
         Redirect::to(PathActivityIndex {}.to_uri().to_string().as_str()),
     ))
 }
+
+#[cfg(test)]
+mod test {
+    use tiberius_core::config::Configuration;
+
+    use super::{mail_link, PathResetPassword};
+
+    #[test]
+    fn test_mail_link_ignores_host() {
+        let config = Configuration {
+            base_url: Some("https://example.com/".parse().unwrap()),
+            ..Default::default()
+        };
+        let link = mail_link(
+            &config,
+            PathResetPassword {
+                token: "abc".to_string(),
+            },
+        );
+        assert_eq!("https://example.com/session/reset_pw/abc", link);
+    }
+}
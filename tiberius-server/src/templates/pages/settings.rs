@@ -0,0 +1,597 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Multipart, State},
+    response::Redirect,
+    Form, Router,
+};
+use axum_extra::routing::{RouterExt, TypedPath};
+use itertools::Itertools;
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+use tiberius_core::{
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, ApiFormDataEmpty, HtmlResponse, TiberiusResponse},
+    session::{Authenticated, SessionMode},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_dependencies::chrono::{Duration, NaiveDateTime, Utc};
+use tiberius_dependencies::{image, mime};
+use tiberius_jobs::process_avatars::{pending_avatar_dir, remove_avatar_file};
+use tiberius_models::{Client, Tag, TagLike, User, UserNameChange, UserSettings};
+
+use crate::templates::{
+    common::{
+        frontmatter::csrf_input_tag, human_date, routes::theme_stylesheet_path,
+        user::user_attribution_avatar,
+    },
+    images::parse_tag_input,
+    session::{validate_username, PathChangeEmail, USERNAME_MAX_LENGTH},
+    two_factor::PathTwoFactor,
+    user_links::PathUserLinks,
+    user_sessions::PathUserSessions,
+    webauthn::PathWebauthn,
+};
+
+pub fn settings_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(edit_settings)
+        .typed_post(update_settings)
+        .typed_post(update_avatar)
+        .typed_post(remove_avatar)
+        .typed_post(change_username)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/edit")]
+pub struct PathEditSettings {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/update")]
+pub struct PathUpdateSettings {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/avatar")]
+pub struct PathUpdateAvatar {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/avatar/delete")]
+pub struct PathRemoveAvatar {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/name")]
+pub struct PathChangeUsername {}
+
+/// Largest width and height of an uploaded avatar
+const AVATAR_MAX_DIMENSION: u32 = 1000;
+/// How many days a user has to wait between two changes of their username
+const USERNAME_CHANGE_COOLDOWN_DAYS: i64 = 90;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct SettingsForm {
+    theme: Option<String>,
+    images_per_page: Option<String>,
+    use_centered_layout: Option<String>,
+    autoplay: Option<String>,
+    spoiler_type: Option<String>,
+    no_spoilered_in_watched: Option<String>,
+    show_hidden_items: Option<String>,
+    watched_tag_list: Option<String>,
+    anonymous_by_default: Option<String>,
+    show_sidebar_and_watched_images: Option<String>,
+    show_sidebar_trending_images: Option<String>,
+    show_sidebar_streams: Option<String>,
+    show_sidebar_forum_activity: Option<String>,
+    show_sidebar_recent_comments: Option<String>,
+}
+
+fn checked(v: &Option<String>) -> bool {
+    v.as_deref() == Some("true")
+}
+
+impl SettingsForm {
+    /// Applies the submitted values on top of the current settings of the user
+    ///
+    /// Unknown themes and spoiler types keep the current value, the page size is clamped.
+    fn apply(&self, settings: &UserSettings, watched_tag_ids: Vec<i32>) -> UserSettings {
+        let theme = self
+            .theme
+            .clone()
+            .filter(|x| UserSettings::THEMES.contains(&x.as_str()))
+            .unwrap_or_else(|| settings.theme.clone());
+        let spoiler_type = self
+            .spoiler_type
+            .clone()
+            .filter(|x| UserSettings::SPOILER_TYPES.contains(&x.as_str()))
+            .unwrap_or_else(|| settings.spoiler_type.clone());
+        let images_per_page = self
+            .images_per_page
+            .as_deref()
+            .and_then(|x| x.trim().parse::<i32>().ok())
+            .map(|x| {
+                x.clamp(
+                    *UserSettings::IMAGES_PER_PAGE.start(),
+                    *UserSettings::IMAGES_PER_PAGE.end(),
+                )
+            })
+            .unwrap_or(settings.images_per_page);
+        UserSettings {
+            theme,
+            images_per_page,
+            use_centered_layout: checked(&self.use_centered_layout),
+            autoplay: checked(&self.autoplay),
+            spoiler_type,
+            no_spoilered_in_watched: checked(&self.no_spoilered_in_watched),
+            show_hidden_items: checked(&self.show_hidden_items),
+            watched_tag_ids,
+            anonymous_by_default: checked(&self.anonymous_by_default),
+            show_sidebar_and_watched_images: checked(&self.show_sidebar_and_watched_images),
+            show_sidebar_trending_images: checked(&self.show_sidebar_trending_images),
+            show_sidebar_streams: checked(&self.show_sidebar_streams),
+            show_sidebar_forum_activity: checked(&self.show_sidebar_forum_activity),
+            show_sidebar_recent_comments: checked(&self.show_sidebar_recent_comments),
+            ..settings.clone()
+        }
+    }
+}
+
+fn checkbox(name: &str, label: &str, value: bool) -> Markup {
+    html! {
+        .field {
+            input #(format!("user_{}", name)) type="checkbox" name=(name) value="true" checked[value];
+            label for=(format!("user_{}", name)) { (label) }
+        }
+    }
+}
+
+fn theme_label(theme: &str) -> String {
+    let mut chars = theme.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn spoiler_type_label(spoiler_type: &str) -> &'static str {
+    match spoiler_type {
+        "click" => "Click to reveal",
+        "hover" => "Hover to reveal",
+        "off" => "Show them unspoilered",
+        _ => "Show the spoilered tags",
+    }
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    title: &str,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(state, rstate, Some(PageTitle::from(title)), client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn edit_settings(
+    _: PathEditSettings,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let settings = &user.user_settings;
+    let watched_tags = Tag::get_many(
+        &mut client,
+        settings.watched_tag_ids.iter().map(|x| *x as i64).collect(),
+    )
+    .await?;
+    let last_name_change = UserNameChange::latest_for_user(&mut client, user.id).await?;
+    let next_name_change = next_name_change(
+        last_name_change.map(|x| x.created_at),
+        Utc::now().naive_utc(),
+    );
+    let body = html! {
+        h1 { "Username" }
+        p {
+            "Your profile and uploads move to the new name, links to your previous name keep working. "
+            "You can change your username once every " (USERNAME_CHANGE_COOLDOWN_DAYS) " days."
+        }
+        @if let Some(next_name_change) = next_name_change {
+            p { "You can change your username again " (human_date(next_name_change)) "." }
+        } @else {
+            form action=(PathChangeUsername{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+                .field {
+                    label for="user_name" { "New username" }
+                    input.input #user_name type="text" name="name" required maxlength=(USERNAME_MAX_LENGTH) value=(user.name);
+                }
+                .actions {
+                    button.button type="submit" { "Change Username" }
+                }
+            }
+        }
+        h1 { "Avatar" }
+        .flex {
+            .flex__fixed.spacing-right {
+                (user_attribution_avatar(&state, &mut client, &Some(user.clone())))
+            }
+            .flex__grow {
+                form action=(PathUpdateAvatar{}.to_uri().to_string()) method="POST" enctype="multipart/form-data" {
+                    (csrf_input_tag(&rstate).await)
+                    p {
+                        "PNG, JPEG, GIF or WebP, at most " (AVATAR_MAX_DIMENSION) "x" (AVATAR_MAX_DIMENSION)
+                        " pixels and " (state.config().avatar_max_size / 1024) " KiB. Avatars are cropped to a square."
+                    }
+                    .field {
+                        input.input #user_avatar type="file" name="avatar" required accept="image/png,image/jpeg,image/gif,image/webp";
+                    }
+                    .actions {
+                        button.button type="submit" { "Upload Avatar" }
+                    }
+                }
+                @if user.avatar.is_some() {
+                    form action=(PathRemoveAvatar{}.to_uri().to_string()) method="POST" {
+                        (csrf_input_tag(&rstate).await)
+                        button.button.button--state-danger type="submit" { "Remove Avatar" }
+                    }
+                }
+            }
+        }
+        h1 { "Content Settings" }
+        form #js-setting-table action=(PathUpdateSettings{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            h3 { "Display" }
+            .field {
+                label for="user_theme" { "Theme" }
+                select.input #user_theme name="theme" {
+                    @for theme in UserSettings::THEMES {
+                        option value=(theme) selected[*theme == settings.theme()] data-theme-path=(theme_stylesheet_path(theme)?) { (theme_label(theme)) }
+                    }
+                }
+            }
+            .field {
+                label for="user_images_per_page" { "Images per page" }
+                input.input #user_images_per_page type="number" name="images_per_page" min=(UserSettings::IMAGES_PER_PAGE.start()) max=(UserSettings::IMAGES_PER_PAGE.end()) value=(settings.images_per_page());
+            }
+            (checkbox("use_centered_layout", "Center the page instead of using the full width of the window", settings.use_centered_layout))
+            (checkbox("autoplay", "Play animated images and videos automatically", settings.autoplay))
+            h3 { "Spoilers and hidden images" }
+            .field {
+                label for="user_spoiler_type" { "Images spoilered by your filter" }
+                select.input #user_spoiler_type name="spoiler_type" {
+                    @for spoiler_type in UserSettings::SPOILER_TYPES {
+                        option value=(spoiler_type) selected[*spoiler_type == settings.spoiler_type()] { (spoiler_type_label(spoiler_type)) }
+                    }
+                }
+            }
+            (checkbox("no_spoilered_in_watched", "Leave out spoilered images from your watched tags", settings.no_spoilered_in_watched))
+            (checkbox("show_hidden_items", "Show comments on images you have hidden", settings.show_hidden_items))
+            .field {
+                label for="user_watched_tag_list" { "Watched tags, separated by commas" }
+                textarea.input.input--wide #user_watched_tag_list name="watched_tag_list" { (watched_tags.iter().map(|x| x.full_name()).join(", ")) }
+            }
+            h3 { "Uploads and comments" }
+            (checkbox("anonymous_by_default", "Post uploads and comments anonymously by default", settings.anonymous_by_default))
+            h3 { "Front page" }
+            (checkbox("show_sidebar_and_watched_images", "Show the sidebar", settings.show_sidebar_and_watched_images))
+            (checkbox("show_sidebar_trending_images", "Show trending images in the sidebar", settings.show_sidebar_trending_images))
+            (checkbox("show_sidebar_streams", "Show live streams in the sidebar", settings.show_sidebar_streams))
+            (checkbox("show_sidebar_forum_activity", "Show forum activity in the sidebar", settings.show_sidebar_forum_activity))
+            (checkbox("show_sidebar_recent_comments", "Show recent comments in the sidebar", settings.show_sidebar_recent_comments))
+            .actions {
+                button.button type="submit" { "Save My Settings" }
+            }
+        }
+        h1 { "Account" }
+        ul {
+            li { a href=(PathChangeEmail{}.to_uri().to_string()) { "Change your email address" } }
+            li { a href=(PathTwoFactor{}.to_uri().to_string()) { "Two factor authentication" } }
+            li { a href=(PathWebauthn{}.to_uri().to_string()) { "Security keys" } }
+            li { a href=(PathUserSessions{}.to_uri().to_string()) { "Active sessions" } }
+            li { a href=(PathUserLinks{}.to_uri().to_string()) { "Artist links" } }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Settings", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn update_settings(
+    _: PathUpdateSettings,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<SettingsForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathEditSettings {}.to_uri().to_string().as_str());
+    let watched_tag_names = parse_tag_input(form.data.watched_tag_list.as_deref().unwrap_or(""));
+    let watched_tags = Tag::get_many_by_name(&mut client, watched_tag_names.clone(), true).await?;
+    if watched_tags.len() < watched_tag_names.len() {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Some of your watched tags do not exist")));
+    }
+    let watched_tag_ids = watched_tags.iter().map(|x| x.id).unique().collect();
+    let settings = form.data.apply(&user.user_settings, watched_tag_ids);
+    user.update_settings(&mut client, settings).await?;
+    Ok(TiberiusResponse::Redirect(retry).with_flash(flash.info("Your settings have been saved")))
+}
+
+/// Returns when the user may change their username again, `None` if they may do so now
+fn next_name_change(
+    last_change: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    let next = last_change? + Duration::days(USERNAME_CHANGE_COOLDOWN_DAYS);
+    (next > now).then_some(next)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UsernameForm {
+    name: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn change_username(
+    _: PathChangeUsername,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<UsernameForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathEditSettings {}.to_uri().to_string().as_str());
+    let name = form.data.name.trim().to_string();
+    if name == user.name {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("This already is your username")));
+    }
+    if let Err(e) = validate_username(&name) {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    let last_change = UserNameChange::latest_for_user(&mut client, user.id).await?;
+    if let Some(next) = next_name_change(last_change.map(|x| x.created_at), Utc::now().naive_utc())
+    {
+        return Ok(
+            TiberiusResponse::Redirect(retry).with_flash(flash.error(format!(
+                "You can change your username again {}",
+                human_date(next)
+            ))),
+        );
+    }
+    if User::name_taken(&mut client, &name, Some(user.id)).await? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("This username is already taken")));
+    }
+    let previous = user.name.clone();
+    user.change_name(&mut client, &name).await?;
+    info!(
+        "user {} changed their name from {:?} to {:?}",
+        user.id, previous, user.name
+    );
+    Ok(TiberiusResponse::Redirect(retry)
+        .with_flash(flash.info(format!("Your username is now {}", user.name))))
+}
+
+/// Checks that an avatar upload is a supported image within the size limits
+///
+/// If the upload is rejected, the reason is returned as message suitable for the user.
+fn check_avatar(data: &[u8], content_type: &mime::Mime) -> TiberiusResult<Result<(), String>> {
+    match content_type.essence_str() {
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" => (),
+        q => return Ok(Err(format!("Avatars can't be of the type {}", q))),
+    }
+    let dimensions = image::io::Reader::new(std::io::Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions();
+    Ok(match dimensions {
+        Ok((width, height)) if width > AVATAR_MAX_DIMENSION || height > AVATAR_MAX_DIMENSION => {
+            Err(format!(
+                "Your avatar is {}x{} pixels large but may be at most {}x{}",
+                width, height, AVATAR_MAX_DIMENSION, AVATAR_MAX_DIMENSION
+            ))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("We can't process your avatar: {}", e)),
+    })
+}
+
+/// Stores an avatar upload for the avatar job, which crops it and sets it as avatar of the user
+#[instrument(skip(state, rstate, multipart))]
+pub async fn update_avatar(
+    _: PathUpdateAvatar,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    mut multipart: Multipart,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    let config = state.config();
+    let retry = Redirect::to(PathEditSettings {}.to_uri().to_string().as_str());
+    let mut csrf_token = String::new();
+    let mut avatar: Option<(Vec<u8>, mime::Mime)> = None;
+    while let Some(mut field) = multipart.next_field().await? {
+        match field.name().unwrap_or_default() {
+            "_csrf_token" => csrf_token = field.text().await?,
+            "avatar" => {
+                let content_type = mime::Mime::from_str(
+                    field.content_type().unwrap_or("application/octet-stream"),
+                )?;
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    data.extend_from_slice(&chunk);
+                    if data.len() as u64 > config.avatar_max_size {
+                        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(
+                            format!(
+                                "Avatars may be at most {} KiB large",
+                                config.avatar_max_size / 1024
+                            ),
+                        )));
+                    }
+                }
+                avatar = Some((data, content_type));
+            }
+            _ => (),
+        }
+    }
+    if rstate.csrf_token().verify(&csrf_token).is_err() {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let (data, content_type) = match avatar {
+        Some(v) if !v.0.is_empty() => v,
+        _ => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("Select an image to upload")))
+        }
+    };
+    if let Err(e) = check_avatar(&data, &content_type)? {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e)));
+    }
+    // write next to the pending directory first so the job never sees a partial upload
+    let pending = pending_avatar_dir(&config);
+    tokio::fs::create_dir_all(&pending).await?;
+    let upload = config.avatar_base().join(format!("{}.upload", user.id));
+    tokio::fs::write(&upload, &data).await?;
+    tokio::fs::rename(&upload, pending.join(user.id.to_string())).await?;
+    Ok(TiberiusResponse::Redirect(retry)
+        .with_flash(flash.info("Your avatar will be updated within a minute")))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn remove_avatar(
+    _: PathRemoveAvatar,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    clear_avatar(&state, &mut client, &mut user).await?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathEditSettings {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("Your avatar has been removed")))
+}
+
+/// Removes the avatar of the user and deletes the file
+pub async fn clear_avatar(
+    state: &TiberiusState,
+    client: &mut Client,
+    user: &mut User,
+) -> TiberiusResult<()> {
+    if let Some(avatar) = user.avatar.clone() {
+        user.set_avatar(client, None).await?;
+        remove_avatar_file(&state.config(), &avatar).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tiberius_models::UserSettings;
+
+    use tiberius_dependencies::mime;
+
+    use tiberius_dependencies::chrono::{Duration, NaiveDate};
+
+    use super::{check_avatar, next_name_change, SettingsForm};
+
+    #[test]
+    fn test_apply_settings_form() {
+        let settings = UserSettings {
+            theme: "dark".to_string(),
+            show_sidebar_and_watched_images: true,
+            ..Default::default()
+        };
+        let form = SettingsForm {
+            show_sidebar_streams: Some("true".to_string()),
+            ..Default::default()
+        };
+        let settings = form.apply(&settings, vec![3]);
+        assert!(!settings.show_sidebar_and_watched_images);
+        assert!(settings.show_sidebar_streams);
+        assert!(!settings.show_sidebar_recent_comments);
+        assert!(!settings.autoplay);
+        assert_eq!("dark", settings.theme);
+        assert_eq!(vec![3], settings.watched_tag_ids);
+        let form = SettingsForm {
+            theme: Some("orange".to_string()),
+            spoiler_type: Some("hover".to_string()),
+            images_per_page: Some("40".to_string()),
+            autoplay: Some("true".to_string()),
+            ..Default::default()
+        };
+        let settings = form.apply(&settings, Vec::new());
+        assert_eq!("orange", settings.theme);
+        assert_eq!("hover", settings.spoiler_type);
+        assert_eq!(40, settings.images_per_page);
+        assert!(settings.autoplay);
+        assert!(settings.watched_tag_ids.is_empty());
+        let form = SettingsForm {
+            theme: Some("../secret".to_string()),
+            spoiler_type: Some("blur".to_string()),
+            images_per_page: Some("1000".to_string()),
+            ..Default::default()
+        };
+        let settings = form.apply(&settings, Vec::new());
+        assert_eq!("orange", settings.theme);
+        assert_eq!("hover", settings.spoiler_type);
+        assert_eq!(50, settings.images_per_page);
+    }
+
+    #[test]
+    fn test_check_avatar_type() {
+        assert!(check_avatar(b"<svg></svg>", &mime::IMAGE_SVG)
+            .unwrap()
+            .is_err());
+        assert!(check_avatar(b"not an image", &mime::IMAGE_PNG)
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_next_name_change() {
+        let now = NaiveDate::from_ymd_opt(2026, 10, 19)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(None, next_name_change(None, now));
+        assert_eq!(None, next_name_change(Some(now - Duration::days(90)), now));
+        assert_eq!(
+            Some(now + Duration::days(1)),
+            next_name_change(Some(now - Duration::days(89)), now)
+        );
+    }
+}
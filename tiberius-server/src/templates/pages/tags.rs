@@ -2,24 +2,30 @@ use crate::set_scope_tx;
 use axum::{
     extract::{Query, State},
     http::{HeaderMap, HeaderValue},
-    Extension, Router,
+    response::Redirect,
+    Extension, Form, Router,
 };
 use axum_extra::routing::{RouterExt, TypedPath};
-use maud::Markup;
+use maud::{html, Markup};
 use tiberius_core::{
-    error::TiberiusResult,
-    request_helper::{HtmlResponse, JsonResponse, TiberiusResponse},
+    acl::{verify_acl, ACLActionTag, ACLObject},
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, HtmlResponse, JsonResponse, TiberiusResponse},
+    session::Authenticated,
     state::{TiberiusRequestState, TiberiusState},
 };
-use tiberius_models::{Client, Tag, TagLike};
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_models::{Client, Tag, TagLike, UserLink};
 
-use crate::templates::todo_page;
+use crate::templates::{common::frontmatter::csrf_input_tag, todo_page};
 
 pub fn tags_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
     r.typed_get(list_tags)
         .typed_get(show_tag)
         .typed_get(show_tag_by_name)
-        .typed_post(edit_tag)
+        .typed_get(edit_tag)
+        .typed_post(update_tag)
         .typed_get(tag_changes)
         .typed_get(usage)
         .typed_post(reindex)
@@ -86,12 +92,138 @@ pub async fn show_tag_by_name(
 #[derive(TypedPath, serde::Deserialize)]
 #[typed_path("/tags/:tag_id/edit")]
 pub struct TagsByIdEditTag {
+    pub tag_id: i64,
+}
+
+#[derive(TypedPath, serde::Deserialize)]
+#[typed_path("/tags/:tag_id/update")]
+pub struct TagsByIdUpdateTag {
+    pub tag_id: i64,
+}
+
+/// Longest short description accepted, it is shown in the tag tooltip
+const SHORT_DESCRIPTION_MAX_LENGTH: usize = 255;
+
+/// Staff may edit every tag description, verified artists the description of their artist tag
+async fn can_edit_description(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+    client: &mut Client,
+    tag: &Tag,
+) -> TiberiusResult<bool> {
+    if verify_acl(state, rstate, ACLObject::Tag, ACLActionTag::EditDescription).await? {
+        return Ok(true);
+    }
+    Ok(match rstate.user(state).await? {
+        Some(user) => UserLink::is_verified_artist(client, user.id, tag.id).await?,
+        None => false,
+    })
+}
+
+async fn get_editable_tag(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+    client: &mut Client,
     tag_id: i64,
+) -> TiberiusResult<Tag> {
+    let tag = Tag::get(client, tag_id)
+        .await?
+        .ok_or_else(|| TiberiusError::ObjectNotFound("Tag".to_string(), tag_id.to_string()))?;
+    if !can_edit_description(state, rstate, client, &tag).await? {
+        return Err(TiberiusError::AccessDenied);
+    }
+    Ok(tag)
 }
 
-#[tracing::instrument]
-pub async fn edit_tag(TagsByIdEditTag { tag_id }: TagsByIdEditTag) -> TiberiusResult<String> {
-    Ok(todo_page("edit_tags").await?.into_string())
+#[instrument(skip(state, rstate))]
+pub async fn edit_tag(
+    TagsByIdEditTag { tag_id }: TagsByIdEditTag,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client = state.get_db_client();
+    let tag = get_editable_tag(&state, &rstate, &mut client, tag_id).await?;
+    let body = html! {
+        h1 { "Editing Tag " (tag.full_name()) }
+        form action=(TagsByIdUpdateTag { tag_id }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            .field {
+                label for="tag_short_description" { "Short description" }
+                input.input.input--wide #tag_short_description type="text" name="short_description"
+                    maxlength=(SHORT_DESCRIPTION_MAX_LENGTH)
+                    value=(tag.short_description.as_deref().unwrap_or_default());
+            }
+            .field {
+                label for="tag_description" { "Description" }
+                textarea.input.input--wide #tag_description name="description" rows="8" {
+                    (tag.description.as_deref().unwrap_or_default())
+                }
+            }
+            .actions {
+                button.button type="submit" { "Save Tag" }
+            }
+        }
+    };
+    let app = crate::templates::common::frontmatter::app(
+        &state,
+        &rstate,
+        Some(PageTitle::from(format!("Editing Tag {}", tag.full_name()))),
+        &mut client,
+        body,
+        None,
+    )
+    .await?;
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: app.into_string(),
+    }))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct TagDescriptionForm {
+    description: String,
+    short_description: String,
+}
+
+fn non_empty(v: &str) -> Option<String> {
+    let v = v.trim();
+    (!v.is_empty()).then(|| v.to_string())
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn update_tag(
+    TagsByIdUpdateTag { tag_id }: TagsByIdUpdateTag,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<TagDescriptionForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let mut tag = get_editable_tag(&state, &rstate, &mut client, tag_id).await?;
+    let retry = Redirect::to(TagsByIdEditTag { tag_id }.to_uri().to_string().as_str());
+    let form = form.data;
+    if form.short_description.trim().chars().count() > SHORT_DESCRIPTION_MAX_LENGTH {
+        return Ok(
+            TiberiusResponse::Redirect(retry).with_flash(flash.error(format!(
+                "The short description may be at most {} characters long",
+                SHORT_DESCRIPTION_MAX_LENGTH
+            ))),
+        );
+    }
+    tag.set_description(
+        &mut client,
+        non_empty(&form.description),
+        non_empty(&form.short_description),
+    )
+    .await?;
+    info!(
+        "user {:?} edited the description of tag {}",
+        rstate.session().raw_user(),
+        tag.id
+    );
+    Ok(TiberiusResponse::Redirect(retry).with_flash(flash.info("The tag description was saved")))
 }
 
 #[derive(TypedPath, serde::Deserialize)]
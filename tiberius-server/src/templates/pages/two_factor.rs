@@ -0,0 +1,372 @@
+use axum::{extract::State, response::Redirect, Form, Router};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+use tiberius_core::{
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, ApiFormDataEmpty, HtmlResponse, TiberiusResponse},
+    session::Authenticated,
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::{axum_flash::Flash, qrcode};
+use tiberius_models::{Client, OTPEncryptionKey, User, WebauthnCredential};
+
+use crate::templates::{common::frontmatter::csrf_input_tag, webauthn::PathWebauthn};
+
+pub fn two_factor_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(show_two_factor)
+        .typed_post(start_two_factor)
+        .typed_post(enable_two_factor)
+        .typed_post(disable_two_factor)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/totp")]
+pub struct PathTwoFactor {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/totp/start")]
+pub struct PathStartTwoFactor {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/totp/enable")]
+pub struct PathEnableTwoFactor {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/totp/disable")]
+pub struct PathDisableTwoFactor {}
+
+fn otp_key(state: &TiberiusState) -> OTPEncryptionKey {
+    OTPEncryptionKey::new(state.config.otp_secret())
+}
+
+/// Builds the otpauth URI understood by authenticator apps
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let mut uri = url::Url::parse("otpauth://totp/").expect("static URI must parse");
+    uri.path_segments_mut()
+        .expect("otpauth URIs have a path")
+        .pop_if_empty()
+        .push(&format!("{}:{}", issuer, account));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", "6")
+        .append_pair("period", "30");
+    uri.to_string()
+}
+
+fn qr_code(data: &str) -> TiberiusResult<PreEscaped<String>> {
+    let code = qrcode::QrCode::new(data.as_bytes())
+        .map_err(|e| TiberiusError::Other(format!("could not generate QR code: {}", e)))?;
+    Ok(PreEscaped(
+        code.render::<qrcode::render::svg::Color>()
+            .min_dimensions(200, 200)
+            .build(),
+    ))
+}
+
+async fn render_page(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+    client: &mut Client,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(state, rstate, None, client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn show_two_factor(
+    _: PathTwoFactor,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    // Staff members with a passkey already satisfy the requirement
+    let staff_required = state.site_config().require_staff_two_factor()
+        && user.is_staff()
+        && !WebauthnCredential::has_credentials(&mut client, user.id()).await?;
+    let body = if user.totp_enabled() {
+        let backup_codes = user
+            .otp_secret
+            .otp_backup_codes
+            .as_ref()
+            .map(|x| x.len())
+            .unwrap_or(0);
+        html! {
+            h1 { "Two factor authentication" }
+
+            p { "Two factor authentication is enabled for your account. You have " (backup_codes) " unused backup codes left." }
+
+            @if staff_required {
+                p { "Staff members are required to use two factor authentication, it cannot be disabled." }
+            } @else {
+                h3 { "Disable two factor authentication" }
+                form action=(PathDisableTwoFactor{}.to_uri().to_string()) method="POST" {
+                    (csrf_input_tag(&rstate).await)
+
+                    .field {
+                        input.input #user_current_password name="current_password" type="password" required="true" placeholder="Current password";
+                    }
+
+                    .field {
+                        input.input #user_totp name="code" type="text" required="true" placeholder="Code or backup code" autocomplete="one-time-code";
+                    }
+
+                    .actions {
+                        button.button type="submit" { "Disable" }
+                    }
+                }
+            }
+        }
+    } else if let Some(secret) = user.otp_secret.secret(&otp_key(&state)).ok().flatten() {
+        let uri = otpauth_uri(
+            state.site_config().site_name(),
+            user.email.as_ref(),
+            &secret,
+        );
+        html! {
+            h1 { "Two factor authentication" }
+
+            @if staff_required {
+                .flash.flash--warning { "Staff members are required to enable two factor authentication before they can use their permissions." }
+            }
+
+            p { "Scan the QR code below with your authenticator app, then enter the code it shows to enable two factor authentication." }
+
+            .block__content {
+                (qr_code(&uri)?)
+            }
+
+            p {
+                "If you can't scan the code, enter this secret manually: "
+                code { (secret) }
+                br;
+                a href=(uri) { "Open in authenticator app" }
+            }
+
+            form action=(PathEnableTwoFactor{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+
+                .field {
+                    input.input #user_totp name="code" type="text" required="true" placeholder="Code" pattern="[0-9 ]{6,7}" autocomplete="one-time-code";
+                }
+
+                .field {
+                    input.input #user_current_password name="current_password" type="password" required="true" placeholder="Current password";
+                }
+
+                .actions {
+                    button.button type="submit" { "Enable" }
+                }
+            }
+
+            form action=(PathStartTwoFactor{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+                p {
+                    "Lost the secret before enabling it? "
+                    button.button.button--link type="submit" { "Generate a new secret" }
+                }
+            }
+        }
+    } else {
+        html! {
+            h1 { "Two factor authentication" }
+
+            @if staff_required {
+                .flash.flash--warning { "Staff members are required to enable two factor authentication before they can use their permissions." }
+            }
+
+            p { "Two factor authentication asks for a code from an authenticator app on your phone when you log in." }
+
+            form action=(PathStartTwoFactor{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+
+                .actions {
+                    button.button type="submit" { "Set up two factor authentication" }
+                }
+            }
+        }
+    };
+    let body = html! {
+        (body)
+
+        h3 { "Passkeys" }
+        p {
+            "Passkeys and security keys can be used instead of codes. "
+            a href=(PathWebauthn{}.to_uri().to_string()) { "Manage your passkeys" }
+        }
+    };
+    render_page(&state, &rstate, &mut client, body).await
+}
+
+/// Stores a new pending TOTP secret, replacing a pending secret that was never enabled
+#[instrument(skip(state, rstate))]
+pub async fn start_two_factor(
+    _: PathStartTwoFactor,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let page = Redirect::to(PathTwoFactor {}.to_uri().to_string().as_str());
+    if user.totp_enabled() {
+        return Ok(TiberiusResponse::Redirect(page)
+            .with_flash(flash.error("Two factor authentication is already enabled")));
+    }
+    user.begin_totp_enrollment(&mut client, &otp_key(&state))
+        .await?;
+    Ok(TiberiusResponse::Redirect(page).with_flash(flash))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct TwoFactorForm {
+    #[sensitive]
+    current_password: String,
+    #[sensitive]
+    code: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn enable_two_factor(
+    _: PathEnableTwoFactor,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<TwoFactorForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathTwoFactor {}.to_uri().to_string().as_str());
+    if user.totp_enabled() {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Two factor authentication is already enabled")));
+    }
+    if !user.verify_password(state.config.password_pepper(), &form.data.current_password)? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Your current password is incorrect")));
+    }
+    let backup_codes = user
+        .enable_totp(
+            &mut client,
+            state.config.password_pepper(),
+            &otp_key(&state),
+            &form.data.code,
+        )
+        .await?;
+    let backup_codes = match backup_codes {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("The code is invalid, check the time on your device")))
+        }
+    };
+    let body = html! {
+        h1 { "Two factor authentication enabled" }
+
+        p {
+            "Write down these backup codes and keep them in a safe place. "
+            "Each can be used once to log in if you lose access to your authenticator app. "
+            strong { "They will not be shown again." }
+        }
+
+        ul {
+            @for code in &backup_codes {
+                li { code { (code) } }
+            }
+        }
+
+        p { a href=(PathTwoFactor{}.to_uri().to_string()) { "Continue" } }
+    };
+    Ok(render_page(&state, &rstate, &mut client, body)
+        .await?
+        .with_flash(flash))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn disable_two_factor(
+    _: PathDisableTwoFactor,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<TwoFactorForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user: User = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathTwoFactor {}.to_uri().to_string().as_str());
+    if state.site_config().require_staff_two_factor()
+        && user.is_staff()
+        && !WebauthnCredential::has_credentials(&mut client, user.id()).await?
+    {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(
+            flash.error("Staff members are required to use two factor authentication, register a passkey before disabling it"),
+        ));
+    }
+    if !user.totp_enabled() {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Two factor authentication is not enabled")));
+    }
+    if !user.verify_password(state.config.password_pepper(), &form.data.current_password)? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Your current password is incorrect")));
+    }
+    let valid = user
+        .verify_second_factor(
+            &mut client,
+            state.config.password_pepper(),
+            &state.config.otp_secret(),
+            &form.data.code,
+        )
+        .await?;
+    if !valid {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("The code is invalid or has already been used")));
+    }
+    user.disable_totp(&mut client).await?;
+    Ok(TiberiusResponse::Redirect(retry)
+        .with_flash(flash.info("Two factor authentication has been disabled")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::otpauth_uri;
+
+    #[test]
+    fn test_otpauth_uri() {
+        let uri = otpauth_uri("My Booru", "user@example.com", "JBSWY3DPEHPK3PXP");
+        assert!(uri.starts_with("otpauth://totp/My%20Booru:user@example.com?"));
+        let uri = url::Url::parse(&uri).unwrap();
+        let query: Vec<(String, String)> = uri.query_pairs().into_owned().collect();
+        assert!(query.contains(&("secret".to_string(), "JBSWY3DPEHPK3PXP".to_string())));
+        assert!(query.contains(&("issuer".to_string(), "My Booru".to_string())));
+    }
+}
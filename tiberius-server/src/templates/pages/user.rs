@@ -1,5 +1,69 @@
-use axum_extra::routing::TypedPath;
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::State,
+    headers::{CacheControl, ContentType, HeaderMapExt},
+    http::HeaderMap,
+    response::Redirect,
+    Form, Router,
+};
+use axum_extra::{
+    body::AsyncReadBody,
+    routing::{RouterExt, TypedPath},
+};
+use maud::{html, Markup, PreEscaped};
 use serde::Deserialize;
+use tiberius_core::{
+    acl::{verify_acl, ACLActionUser, ACLObject},
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormDataEmpty, CustomResponse, HtmlResponse, TiberiusResponse},
+    session::{Authenticated, Unauthenticated},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_dependencies::chrono::NaiveDateTime;
+use tiberius_dependencies::mime;
+use tiberius_jobs::process_avatars::avatar_file_path;
+use tiberius_models::{
+    comment::Comment, Badge, Client, Image, ImageSortBy, SortDirection, Tag, User, UserLink,
+    UserNameChange, UserStatistic,
+};
+use tokio::fs::File;
+
+use crate::templates::{
+    common::{
+        frontmatter::{badge_image, csrf_input_tag, pretty_time, tag_link},
+        human_date,
+        image::{image_box, DisplaySize, HeaderSize, ImageSize},
+        renderer::textile::render_textile,
+        user::user_attribution_avatar,
+    },
+    feeds::{FeedFormat, PathUserFavouritesFeed, PathUserUploadsFeed},
+    images::{PathQuerySearch, PathShowImage},
+    settings::clear_avatar,
+    tags::TagsByIdEditTag,
+};
+
+/// How many images are shown in the uploads and favourites blocks
+const PROFILE_IMAGES: u64 = 8;
+/// How many comments are shown on the profile
+const PROFILE_COMMENTS: i64 = 5;
+/// How many days the statistics on the profile cover
+const PROFILE_STATISTIC_DAYS: i32 = 90;
+/// How many days are listed individually in the statistics table
+const PROFILE_STATISTIC_ROWS: usize = 14;
+/// How long browsers may cache avatars, in seconds
+///
+/// Every processed avatar gets a new filename, so cached avatars never go stale.
+const AVATAR_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
+pub fn user_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(show_profile)
+        .typed_get(show_profile_by_id)
+        .typed_get(avatar_get)
+        .typed_post(remove_user_avatar)
+}
 
 #[derive(TypedPath, Deserialize)]
 #[typed_path("/user/:username")]
@@ -18,3 +82,502 @@ pub struct PathUserProfileId {
 pub struct PathUserAvatar {
     pub path: String,
 }
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/user/id/:user_id/avatar/delete")]
+pub struct PathRemoveUserAvatar {
+    pub user_id: i64,
+}
+
+#[instrument(skip(state))]
+pub async fn show_profile_by_id(
+    PathUserProfileId { user_id }: PathUserProfileId,
+    State(state): State<TiberiusState>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = User::get_id(&mut client, user_id)
+        .await?
+        .ok_or_else(|| TiberiusError::ObjectNotFound("User".to_string(), user_id.to_string()))?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathUserProfile {
+            username: user.name,
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    )))
+}
+
+/// Sends requests for a name the user no longer has to their current profile
+async fn redirect_previous_name(
+    client: &mut Client,
+    username: String,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let user = match UserNameChange::get_by_name(client, &username).await? {
+        Some(change) => User::get_id(client, change.user_id).await?,
+        None => None,
+    };
+    let user = user.ok_or_else(|| TiberiusError::ObjectNotFound("User".to_string(), username))?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathUserProfile {
+            username: user.name,
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    )))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn show_profile(
+    PathUserProfile { username }: PathUserProfile,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Unauthenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match User::get_by_name(&mut client, &username).await? {
+        Some(v) => v,
+        None => return redirect_previous_name(&mut client, username).await,
+    };
+    let viewer = rstate.user(&state).await?;
+    let own_profile = viewer.as_ref().map(|x| x.id) == Some(user.id);
+    let can_remove_avatar = user.avatar.is_some()
+        && verify_acl(
+            &state,
+            &rstate,
+            ACLObject::User,
+            ACLActionUser::RemoveAvatar,
+        )
+        .await?;
+
+    let links = verified_links(&mut client, &user, own_profile).await?;
+    let badges = user_badge_awards(&mut client, &user).await?;
+    let uploads = profile_images(
+        &state,
+        &rstate,
+        &mut client,
+        &format!("uploader_id.eq:{}", user.id),
+    )
+    .await?;
+    let favourites = profile_images(
+        &state,
+        &rstate,
+        &mut client,
+        &format!("faved_by.eq:{}", user.id),
+    )
+    .await?;
+    let comments = recent_comments(&state, &rstate, &mut client, &user, &viewer).await?;
+    let statistics = statistics(&mut client, &user).await?;
+    let name_changes = UserNameChange::get_for_user(&mut client, user.id).await?;
+
+    let body = html! {
+        .profile-top {
+            .profile-top__avatar {
+                (user_attribution_avatar(&state, &mut client, &Some(user.clone())))
+            }
+            .profile-top__name-and-links {
+                div {
+                    h1.profile-top__name-header { (user.displayname()) "'s profile" }
+                    @if let Some(title) = &user.personal_title {
+                        span.profile-top__title { (title) }
+                    }
+                    br;
+                    span { "Member since " (human_date(user.user_history.created_at)) }
+                }
+                .profile-top__options {
+                    ul.profile-top__options__column {
+                        li { a href=(PathUserUploadsFeed { format: FeedFormat::Atom, username: user.name.clone() }.to_uri().to_string()) { "Uploads feed" } }
+                        li { a href=(PathUserFavouritesFeed { format: FeedFormat::Atom, username: user.name.clone() }.to_uri().to_string()) { "Favourites feed" } }
+                    }
+                    @if can_remove_avatar {
+                        ul.profile-top__options__column {
+                            li {
+                                form action=(PathRemoveUserAvatar { user_id: user.id as i64 }.to_uri().to_string()) method="POST" {
+                                    (csrf_input_tag(&rstate).await)
+                                    button.button.button--link type="submit" { "Remove avatar" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .column-layout {
+            .column-layout__left {
+                .block {
+                    .block__header { span.block__header__title { "User Links" } }
+                    @if links.is_empty() {
+                        .block__content { "No verified links" }
+                    }
+                    @for (link, tag) in &links {
+                        .block__content.alternating-color {
+                            a href=(link.uri) rel="nofollow noopener" { (link.uri) }
+                            @if let Some(tag) = tag {
+                                br;
+                                span.fine-print { "Verified for " (tag_link(true, &tag.name, &tag.name)) }
+                                @if own_profile {
+                                    " "
+                                    a.fine-print href=(TagsByIdEditTag { tag_id: tag.id as i64 }.to_uri().to_string()) { "Edit tag description" }
+                                }
+                            }
+                        }
+                    }
+                }
+                .block {
+                    .block__header { span.block__header__title { "Badges" } }
+                    @if badges.is_empty() {
+                        .block__content { "No badges" }
+                    }
+                    @for (badge, award_label, award_reason, awarded_on) in &badges {
+                        .block__content.flex.alternating-color {
+                            .flex__fixed.spacing-right {
+                                (badge_image(badge.image.as_ref(), badge.title(), badge.title(), 32, 32))
+                            }
+                            .flex__grow {
+                                strong { (award_label.as_deref().unwrap_or(&badge.title)) }
+                                @if let Some(reason) = award_reason {
+                                    br;
+                                    (reason)
+                                }
+                                br;
+                                span.fine-print { "Awarded " (pretty_time(awarded_on)) }
+                            }
+                        }
+                    }
+                }
+                .block {
+                    .block__header { span.block__header__title { "Statistics" } }
+                    (statistics)
+                }
+                @if !name_changes.is_empty() {
+                    .block {
+                        .block__header { span.block__header__title { "Previous Names" } }
+                        @for change in &name_changes {
+                            .block__content.alternating-color {
+                                (change.name)
+                                br;
+                                span.fine-print { "until " (pretty_time(&change.created_at)) }
+                            }
+                        }
+                    }
+                }
+            }
+            .column-layout__main {
+                @if let Some(description) = &user.description {
+                    .block {
+                        .block__header { span.block__header__title { "About Me" } }
+                        .block__content { (render_textile(description)) }
+                    }
+                }
+                (image_section(&state, &rstate, &mut client, "Recent Uploads", None, uploads).await?)
+                (image_section(&state, &rstate, &mut client, "Recent Favourites", Some(&format!("faved_by.eq:{}", user.id)), favourites).await?)
+                .block {
+                    .block__header { span.block__header__title { "Recent Comments" } }
+                    (comments)
+                }
+            }
+        }
+    };
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(&state, &rstate, Some(PageTitle::from(format!("{}'s profile", user.displayname()))), &mut client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state))]
+pub async fn avatar_get(
+    PathUserAvatar { path }: PathUserAvatar,
+    State(state): State<TiberiusState>,
+) -> TiberiusResult<TiberiusResponse<AsyncReadBody<File>>> {
+    let not_found = || TiberiusError::PageNotFound(format!("Could not find avatar {path}"));
+    let file_path = avatar_file_path(&state.config(), &path).ok_or_else(not_found)?;
+    let file = match File::open(&file_path).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(e.into()),
+    };
+    let mime = new_mime_guess::from_path(&file_path);
+    let mime = mime.first().unwrap_or(mime::IMAGE_PNG);
+    let mut hm = HeaderMap::new();
+    hm.typed_insert(ContentType::from(mime));
+    hm.typed_insert(
+        CacheControl::new()
+            .with_public()
+            .with_max_age(std::time::Duration::from_secs(AVATAR_MAX_AGE)),
+    );
+    Ok(TiberiusResponse::Custom(CustomResponse {
+        content: AsyncReadBody::new(file),
+        headers: hm,
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn remove_user_avatar(
+    PathRemoveUserAvatar { user_id }: PathRemoveUserAvatar,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    if !verify_acl(
+        &state,
+        &rstate,
+        ACLObject::User,
+        ACLActionUser::RemoveAvatar,
+    )
+    .await?
+    {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = User::get_id(&mut client, user_id)
+        .await?
+        .ok_or_else(|| TiberiusError::ObjectNotFound("User".to_string(), user_id.to_string()))?;
+    clear_avatar(&state, &mut client, &mut user).await?;
+    info!(
+        "user {:?} removed the avatar of user {}",
+        rstate.session().raw_user(),
+        user.id
+    );
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathUserProfile {
+            username: user.name,
+        }
+        .to_uri()
+        .to_string()
+        .as_str(),
+    ))
+    .with_flash(flash.info("The avatar has been removed")))
+}
+
+/// Returns the verified links of the user together with the artist tag they are verified for
+///
+/// Links that are not public are only shown to the user themselves.
+async fn verified_links(
+    client: &mut Client,
+    user: &User,
+    include_private: bool,
+) -> TiberiusResult<Vec<(UserLink, Option<Tag>)>> {
+    let mut links = Vec::new();
+    for link in UserLink::get_for_user(client, user.id, include_private).await? {
+        if !link.is_verified() {
+            continue;
+        }
+        let tag = match link.tag_id {
+            Some(tag_id) => Tag::get(client, tag_id as i64).await?,
+            None => None,
+        };
+        links.push((link, tag));
+    }
+    Ok(links)
+}
+
+/// Pairs every badge award of the user with its badge
+async fn user_badge_awards(
+    client: &mut Client,
+    user: &User,
+) -> TiberiusResult<Vec<(Badge, Option<String>, Option<String>, NaiveDateTime)>> {
+    let badges: BTreeMap<i32, Badge> = user
+        .badges(client)
+        .await?
+        .into_iter()
+        .map(|badge| (badge.id, badge))
+        .collect();
+    Ok(user
+        .badge_awards(client)
+        .await?
+        .into_iter()
+        .filter_map(|award| {
+            badges.get(&award.badge_id).map(|badge| {
+                (
+                    badge.clone(),
+                    award.label.filter(|x| !x.is_empty()),
+                    award.reason.filter(|x| !x.is_empty()),
+                    award.awarded_on,
+                )
+            })
+        })
+        .collect())
+}
+
+/// Searches the newest images matching the query through the filter of the viewer
+///
+/// Anonymous uploads are not indexed with their uploader, so `uploader_id` queries never find them.
+async fn profile_images(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    query: &str,
+) -> TiberiusResult<Vec<Image>> {
+    let filter = rstate.filter(state).await?;
+    let aqueries = vec![
+        "processed.eq:true".to_string(),
+        "hidden.eq:false".to_string(),
+    ];
+    let anqueries = filter.negative_queries(client).await?;
+    let (_, images) = Image::search(
+        client,
+        query,
+        aqueries,
+        anqueries,
+        ImageSortBy::CreatedAt(SortDirection::Descending),
+        0,
+        PROFILE_IMAGES,
+    )
+    .await?;
+    Ok(images
+        .into_iter()
+        .filter(|image| image.publicly_visible() && !filter.hides_image(image))
+        .collect())
+}
+
+async fn image_section(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    title: &str,
+    query: Option<&str>,
+    images: Vec<Image>,
+) -> TiberiusResult<Markup> {
+    let search = match query {
+        Some(query) => Some(
+            PathQuerySearch {
+                search: query.to_string(),
+                order: None,
+                direction: None,
+            }
+            .try_into_uri()?
+            .to_string(),
+        ),
+        None => None,
+    };
+    Ok(html! {
+        .block {
+            @if let Some(search) = search {
+                a.block__header--single-item.center href=(search) { (title) }
+            } @else {
+                span.block__header--single-item.center { (title) }
+            }
+            .block__content.flex.flex--centered.flex--wrap.image-flex-grid {
+                @if images.is_empty() {
+                    "Nothing to show"
+                }
+                @for image in images {
+                    (image_box(state, rstate, client, image, ImageSize::Medium, HeaderSize::ThumbSmall, DisplaySize::Normal).await?)
+                }
+            }
+        }
+    })
+}
+
+/// Renders the newest comments of the user that the viewer can see
+async fn recent_comments(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Unauthenticated>,
+    client: &mut Client,
+    user: &User,
+    viewer: &Option<User>,
+) -> TiberiusResult<Markup> {
+    let filter = rstate.filter(state).await?;
+    let hiding_user = viewer
+        .as_ref()
+        .filter(|viewer| !viewer.user_settings.show_hidden_items)
+        .map(|viewer| viewer.id as i64);
+    let comments = Comment::recent_visible(
+        client,
+        &filter.hidden_tag_ids,
+        hiding_user,
+        Some(user.id),
+        PROFILE_COMMENTS,
+    )
+    .await?;
+    Ok(html! {
+        @if comments.is_empty() {
+            .block__content { "No recent comments" }
+        }
+        @for comment in comments {
+            @let image_id = comment.image_id().unwrap_or_default() as u64;
+            .block__content.alternating-color {
+                a href=(format!("{}#comment_{}", PathShowImage { image: image_id }.to_uri(), comment.id)) {
+                    "On #" (image_id)
+                }
+                " "
+                span.fine-print { (pretty_time(&comment.created_at)) }
+                .communication__body__text { (render_textile(&comment.body)) }
+            }
+        }
+    })
+}
+
+/// Renders the totals of the last days and a table of the most recent active days
+async fn statistics(client: &mut Client, user: &User) -> TiberiusResult<Markup> {
+    let since = UserStatistic::today() - PROFILE_STATISTIC_DAYS + 1;
+    let stats = UserStatistic::get_for_user(client, user.id, since).await?;
+    let total = UserStatistic::total(&stats);
+    Ok(html! {
+        .block__content {
+            "In the last " (PROFILE_STATISTIC_DAYS) " days:"
+            ul {
+                li { (total.uploads) " uploads" }
+                li { (total.votes_cast) " votes cast" }
+                li { (total.images_favourited) " favourites" }
+                li { (total.comments_posted) " comments" }
+                li { (total.metadata_updates) " metadata updates" }
+                li { (total.forum_posts) " forum posts" }
+            }
+        }
+        @if !stats.is_empty() {
+            .block__content {
+                table.table {
+                    thead {
+                        tr {
+                            th { "Day" }
+                            th { "Uploads" }
+                            th { "Votes" }
+                            th { "Faves" }
+                            th { "Comments" }
+                            th { "Tags" }
+                            th { "Posts" }
+                        }
+                    }
+                    tbody {
+                        @for stat in stats.iter().rev().take(PROFILE_STATISTIC_ROWS) {
+                            tr {
+                                td { (statistic_date(stat.day)) }
+                                td { (stat.uploads) }
+                                td { (stat.votes_cast) }
+                                td { (stat.images_favourited) }
+                                td { (stat.comments_posted) }
+                                td { (stat.metadata_updates) }
+                                td { (stat.forum_posts) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Formats the day number of a statistic as a date
+fn statistic_date(day: i32) -> String {
+    NaiveDateTime::from_timestamp_opt(day as i64 * 86400, 0)
+        .map(|x| x.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::statistic_date;
+
+    #[test]
+    fn test_statistic_date() {
+        assert_eq!("1970-01-01", statistic_date(0));
+        assert_eq!("2026-10-19", statistic_date(20745));
+    }
+}
@@ -0,0 +1,479 @@
+use std::str::FromStr;
+
+use axum::{extract::State, response::Redirect, Form, Router};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup};
+use serde::Deserialize;
+use tiberius_core::{
+    acl::{verify_acl, ACLActionUserLink, ACLObject},
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, ApiFormDataEmpty, HtmlResponse, TiberiusResponse},
+    session::{Authenticated, SessionMode},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::axum_flash::Flash;
+use tiberius_models::{Client, Tag, TagLike, User, UserLink, UserLinkState};
+
+use crate::templates::{
+    common::frontmatter::{csrf_input_tag, pretty_time, tag_link},
+    user::PathUserProfile,
+};
+
+pub fn user_link_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(list_user_links)
+        .typed_post(create_user_link)
+        .typed_post(delete_user_link)
+        .typed_get(list_pending_user_links)
+        .typed_post(transition_user_link)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/links")]
+pub struct PathUserLinks {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/links/create")]
+pub struct PathCreateUserLink {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/settings/links/:link_id/delete")]
+pub struct PathDeleteUserLink {
+    pub link_id: i32,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/user_links")]
+pub struct PathPendingUserLinks {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/user_links/:link_id/transition")]
+pub struct PathTransitionUserLink {
+    pub link_id: i32,
+}
+
+/// Namespace of the tags a link can verify a user for
+const ARTIST_NAMESPACE: &str = "artist";
+
+fn state_text(state: &str) -> &'static str {
+    match UserLinkState::from_str(state) {
+        Ok(UserLinkState::Unverified) => "Waiting for the verification code",
+        Ok(UserLinkState::LinkVerified) => "Code found, waiting for staff",
+        Ok(UserLinkState::Verified) => "Verified",
+        Ok(UserLinkState::Contacted) => "Staff contacted you",
+        Ok(UserLinkState::Rejected) => "Rejected",
+        Err(_) => "Unknown",
+    }
+}
+
+/// Checks that the submitted address is a link to a web page
+///
+/// If the address is rejected, the reason is returned as message suitable for the user.
+fn parse_link_uri(uri: &str) -> Result<url::Url, String> {
+    let uri = url::Url::parse(uri.trim()).map_err(|e| format!("The link is not valid: {}", e))?;
+    match uri.scheme() {
+        "http" | "https" => (),
+        q => return Err(format!("Links must use http or https, not {}", q)),
+    }
+    if uri.host_str().is_none() {
+        return Err("The link has no host".to_string());
+    }
+    Ok(uri)
+}
+
+async fn link_tag(client: &mut Client, link: &UserLink) -> TiberiusResult<Option<Tag>> {
+    Ok(match link.tag_id {
+        Some(tag_id) => Tag::get(client, tag_id as i64).await?,
+        None => None,
+    })
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    title: &str,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let app = crate::templates::common::frontmatter::app(
+        state,
+        rstate,
+        Some(PageTitle::from(title)),
+        client,
+        body,
+        None,
+    )
+    .await?;
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: app.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn list_user_links(
+    _: PathUserLinks,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let mut links = Vec::new();
+    for link in UserLink::get_for_user(&mut client, user.id, true).await? {
+        let tag = link_tag(&mut client, &link).await?;
+        links.push((link, tag));
+    }
+    let body = html! {
+        h1 { "Links" }
+        p {
+            "Link your profiles on other sites to show them on your profile. "
+            "Artists can get verified for their artist tag, which lets them edit the tag description "
+            "and marks them as the artist on their images."
+        }
+        p {
+            "After adding a link, place its verification code on the linked page, for example in the profile description. "
+            "The page is checked automatically for a week, staff reviews the link once the code was found."
+        }
+        @if links.is_empty() {
+            p { "You have not added any links yet." }
+        } @else {
+            table.table {
+                thead {
+                    tr {
+                        th { "Link" }
+                        th { "Tag" }
+                        th { "State" }
+                        th { "Verification code" }
+                        th { "Public" }
+                        th { "Options" }
+                    }
+                }
+                tbody {
+                    @for (link, tag) in &links {
+                        tr {
+                            td { a href=(link.uri) rel="nofollow noopener" { (link.uri) } }
+                            td {
+                                @if let Some(tag) = tag {
+                                    (tag_link(true, &tag.full_name(), &tag.full_name()))
+                                }
+                            }
+                            td { (state_text(&link.aasm_state)) }
+                            td {
+                                @if !link.is_verified() {
+                                    code { (link.verification_code) }
+                                }
+                            }
+                            td { @if link.public { "Yes" } @else { "No" } }
+                            td {
+                                form action=(PathDeleteUserLink { link_id: link.id }.to_uri().to_string()) method="POST" {
+                                    (csrf_input_tag(&rstate).await)
+                                    button.button.button--state-danger type="submit" { "Remove" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        h2 { "Add Link" }
+        form action=(PathCreateUserLink {}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            .field {
+                label for="link_uri" { "Link" }
+                input.input.input--wide #link_uri type="url" name="uri" required placeholder="https://";
+            }
+            .field {
+                label for="link_tag_name" { "Artist tag" }
+                input.input #link_tag_name type="text" name="tag_name" placeholder="artist:name" autocapitalize="none";
+            }
+            .field {
+                input #link_public type="checkbox" name="public" value="true" checked;
+                label for="link_public" { "Show this link on my profile" }
+            }
+            .actions {
+                button.button type="submit" { "Add Link" }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Links", body).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserLinkForm {
+    uri: String,
+    tag_name: Option<String>,
+    public: Option<String>,
+}
+
+impl UserLinkForm {
+    /// Looks up the artist tag by its full name, an empty field links no tag
+    async fn artist_tag(&self, client: &mut Client) -> TiberiusResult<Result<Option<Tag>, String>> {
+        let name = match self.tag_name.as_deref().map(str::trim) {
+            None | Some("") => return Ok(Ok(None)),
+            Some(v) => v,
+        };
+        let name_in_namespace = match name.split_once(':') {
+            Some((ARTIST_NAMESPACE, name)) => name.to_string(),
+            _ => return Ok(Err(format!("The tag {:?} is not an artist tag", name))),
+        };
+        Ok(
+            match Tag::get_by_name(
+                client,
+                Some(ARTIST_NAMESPACE.to_string()),
+                name_in_namespace,
+            )
+            .await?
+            {
+                Some(tag) => Ok(Some(tag)),
+                None => Err(format!("The tag {:?} does not exist", name)),
+            },
+        )
+    }
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn create_user_link(
+    _: PathCreateUserLink,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<UserLinkForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let retry = Redirect::to(PathUserLinks {}.to_uri().to_string().as_str());
+    let form = form.data;
+    let uri = match parse_link_uri(&form.uri) {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    let tag = match form.artist_tag(&mut client).await? {
+        Ok(v) => v,
+        Err(e) => return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(e))),
+    };
+    let tag_id = tag.as_ref().map(|x| x.id);
+    let existing = UserLink::get_for_user(&mut client, user.id, true).await?;
+    if existing.iter().any(|x| {
+        x.uri == uri.as_str()
+            && x.tag_id == tag_id
+            && !matches!(x.state(), Ok(UserLinkState::Rejected))
+    }) {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("You already added this link")));
+    }
+    let link = UserLink::create(
+        &mut client,
+        user.id,
+        &uri,
+        tag_id,
+        form.public.as_deref() == Some("true"),
+    )
+    .await?;
+    Ok(
+        TiberiusResponse::Redirect(retry).with_flash(flash.info(format!(
+            "Link added, place the code {} on the linked page to verify it",
+            link.verification_code
+        ))),
+    )
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn delete_user_link(
+    PathDeleteUserLink { link_id }: PathDeleteUserLink,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let mut client = state.get_db_client();
+    let link = match UserLink::get_id(&mut client, link_id).await? {
+        Some(v) if v.user_id == user.id => v,
+        _ => {
+            return Err(TiberiusError::ObjectNotFound(
+                "UserLink".to_string(),
+                link_id.to_string(),
+            ))
+        }
+    };
+    link.delete(&mut client).await?;
+    Ok(
+        TiberiusResponse::Redirect(Redirect::to(PathUserLinks {}.to_uri().to_string().as_str()))
+            .with_flash(flash.info("The link was removed")),
+    )
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn list_pending_user_links(
+    _: PathPendingUserLinks,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    if !verify_acl(
+        &state,
+        &rstate,
+        ACLObject::UserLink,
+        ACLActionUserLink::Verify,
+    )
+    .await?
+    {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let mut links = Vec::new();
+    for link in UserLink::get_pending(&mut client).await? {
+        let user = User::get_id(&mut client, link.user_id as i64).await?;
+        let tag = link_tag(&mut client, &link).await?;
+        links.push((link, user, tag));
+    }
+    let body = html! {
+        h1 { "User Links" }
+        @if links.is_empty() {
+            p { "No links are waiting for verification." }
+        } @else {
+            table.table {
+                thead {
+                    tr {
+                        th { "User" }
+                        th { "Link" }
+                        th { "Tag" }
+                        th { "State" }
+                        th { "Verification code" }
+                        th { "Added" }
+                        th { "Options" }
+                    }
+                }
+                tbody {
+                    @for (link, user, tag) in &links {
+                        tr {
+                            td {
+                                @match user {
+                                    Some(user) => {
+                                        a href=(PathUserProfile { username: user.name.clone() }.to_uri().to_string()) { (user.displayname()) }
+                                    },
+                                    None => { em { "deleted user" } },
+                                }
+                            }
+                            td { a href=(link.uri) rel="nofollow noopener" { (link.uri) } }
+                            td {
+                                @if let Some(tag) = tag {
+                                    (tag_link(true, &tag.full_name(), &tag.full_name()))
+                                }
+                            }
+                            td { (state_text(&link.aasm_state)) }
+                            td { code { (link.verification_code) } }
+                            td { (pretty_time(&link.created_at)) }
+                            td {
+                                @for (target, label) in [(UserLinkState::Verified, "Verify"), (UserLinkState::Contacted, "Contacted"), (UserLinkState::Rejected, "Reject")] {
+                                    form action=(PathTransitionUserLink { link_id: link.id }.to_uri().to_string()) method="POST" {
+                                        (csrf_input_tag(&rstate).await)
+                                        input type="hidden" name="state" value=(target.to_string());
+                                        button.button type="submit" { (label) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "User Links", body).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransitionForm {
+    state: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn transition_user_link(
+    PathTransitionUserLink { link_id }: PathTransitionUserLink,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<TransitionForm>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    if !verify_acl(
+        &state,
+        &rstate,
+        ACLObject::UserLink,
+        ACLActionUserLink::Verify,
+    )
+    .await?
+    {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let staff = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathPendingUserLinks {}.to_uri().to_string().as_str());
+    let target = match UserLinkState::from_str(&form.data.state) {
+        Ok(v @ (UserLinkState::Verified | UserLinkState::Contacted | UserLinkState::Rejected)) => v,
+        _ => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("Links can't be moved into this state")))
+        }
+    };
+    let mut client = state.get_db_client();
+    let mut link = match UserLink::get_id(&mut client, link_id).await? {
+        Some(v) => v,
+        None => {
+            return Err(TiberiusError::ObjectNotFound(
+                "UserLink".to_string(),
+                link_id.to_string(),
+            ))
+        }
+    };
+    link.set_state(&mut client, target, Some(staff.id)).await?;
+    info!(
+        "user {:?} moved user link {} of user {} into state {}",
+        rstate.session().raw_user(),
+        link.id,
+        link.user_id,
+        link.aasm_state
+    );
+    Ok(
+        TiberiusResponse::Redirect(retry).with_flash(flash.info(format!(
+            "Moved the link {} into the state {}",
+            link.uri, link.aasm_state
+        ))),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_link_uri;
+
+    #[test]
+    fn test_parse_link_uri() {
+        assert_eq!(
+            Some("example.com"),
+            parse_link_uri(" https://example.com/artist ")
+                .unwrap()
+                .host_str()
+        );
+        assert!(parse_link_uri("http://example.com").is_ok());
+        assert!(parse_link_uri("ftp://example.com/artist").is_err());
+        assert!(parse_link_uri("javascript:alert(1)").is_err());
+        assert!(parse_link_uri("example.com/artist").is_err());
+    }
+}
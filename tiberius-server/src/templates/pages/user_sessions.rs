@@ -0,0 +1,392 @@
+use axum::{extract::State, response::Redirect, Form, Router};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+use tiberius_core::{
+    acl::*,
+    app::PageTitle,
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormDataEmpty, HtmlResponse, TiberiusResponse},
+    session::{Authenticated, SessionMode},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::{axum_flash::Flash, uuid::Uuid};
+use tiberius_models::{Client, User, UserSession, UserToken, UserTokenContext};
+
+use crate::templates::{
+    common::{frontmatter::csrf_input_tag, human_date},
+    session::require_step_up,
+};
+
+pub fn user_session_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(show_sessions)
+        .typed_post(revoke_session)
+        .typed_post(revoke_philomena_session)
+        .typed_post(revoke_other_sessions)
+        .typed_get(admin_show_sessions)
+        .typed_post(admin_revoke_sessions)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/sessions")]
+pub struct PathUserSessions {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/sessions/:id/revoke")]
+pub struct PathRevokeUserSession {
+    id: Uuid,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/sessions/philomena/:id/revoke")]
+pub struct PathRevokePhilomenaSession {
+    id: i64,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/sessions/revoke_others")]
+pub struct PathRevokeOtherSessions {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/users/:user_id/sessions")]
+pub struct PathAdminUserSessions {
+    pub user_id: i64,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/users/:user_id/sessions/revoke")]
+pub struct PathAdminRevokeUserSessions {
+    user_id: i64,
+}
+
+/// Longest user agent shown in the session list
+const USER_AGENT_DISPLAY_LENGTH: usize = 80;
+
+fn user_agent(session: &UserSession) -> String {
+    match &session.user_agent {
+        None => "Unknown browser".to_string(),
+        Some(v) if v.chars().count() > USER_AGENT_DISPLAY_LENGTH => {
+            format!(
+                "{}…",
+                v.chars()
+                    .take(USER_AGENT_DISPLAY_LENGTH)
+                    .collect::<String>()
+            )
+        }
+        Some(v) => v.clone(),
+    }
+}
+
+/// Renders the sessions of a user, with a revoke button per session if `revoke` is set
+async fn session_table<T: SessionMode>(
+    rstate: &TiberiusRequestState<T>,
+    sessions: &[UserSession],
+    philomena_sessions: &[UserToken],
+    revoke: bool,
+) -> Markup {
+    let current = rstate.session().id();
+    html! {
+        table.table {
+            thead {
+                tr {
+                    th { "Browser" }
+                    th { "IP address" }
+                    th { "Signed in" }
+                    th { "Last seen" }
+                    @if revoke { th {} }
+                }
+            }
+            tbody {
+                @for session in sessions {
+                    tr {
+                        td {
+                            (user_agent(session))
+                            @if session.id == current {
+                                " "
+                                strong { "(this session)" }
+                            }
+                        }
+                        td {
+                            @match session.ip {
+                                Some(ip) => (ip.ip()),
+                                None => "Unknown",
+                            }
+                        }
+                        td { (human_date(session.created_at)) }
+                        td { (human_date(session.last_seen_at)) }
+                        @if revoke {
+                            td {
+                                @if session.id != current {
+                                    form action=(PathRevokeUserSession{ id: session.id }.to_uri().to_string()) method="POST" {
+                                        (csrf_input_tag(rstate).await)
+                                        button.button type="submit" { "Log out" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                @for token in philomena_sessions {
+                    tr {
+                        td { "Philomena session" }
+                        td { "Unknown" }
+                        td { (human_date(token.created_at)) }
+                        td { "Unknown" }
+                        @if revoke {
+                            td {
+                                form action=(PathRevokePhilomenaSession{ id: token.id }.to_uri().to_string()) method="POST" {
+                                    (csrf_input_tag(rstate).await)
+                                    button.button type="submit" { "Log out" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    title: &str,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(state, rstate, Some(PageTitle::from(title)), client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn show_sessions(
+    _: PathUserSessions,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let sessions = UserSession::get_for_user(&mut client, user.id()).await?;
+    let philomena_sessions =
+        UserToken::get_for_user(&mut client, user.id(), UserTokenContext::Session).await?;
+    let body = html! {
+        h1 { "Active sessions" }
+
+        p { "These are the browsers you are currently logged in with. If you don't recognize one, log it out and change your password." }
+
+        (session_table(&rstate, &sessions, &philomena_sessions, true).await)
+
+        form action=(PathRevokeOtherSessions{}.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            .actions {
+                button.button type="submit" { "Log out everywhere else" }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "Active sessions", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn revoke_session(
+    PathRevokeUserSession { id }: PathRevokeUserSession,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let sessions = Redirect::to(PathUserSessions {}.to_uri().to_string().as_str());
+    if id == rstate.session().id() {
+        return Ok(TiberiusResponse::Redirect(sessions)
+            .with_flash(flash.error("Use the logout button to log out of this session")));
+    }
+    if !UserSession::revoke(&mut client, user.id(), id).await? {
+        return Err(TiberiusError::ObjectNotFound(
+            "UserSession".to_string(),
+            id.to_string(),
+        ));
+    }
+    state.session_record_cache.invalidate(&id).await;
+    Ok(TiberiusResponse::Redirect(sessions)
+        .with_flash(flash.info("The session has been logged out")))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn revoke_philomena_session(
+    PathRevokePhilomenaSession { id }: PathRevokePhilomenaSession,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    if !UserToken::revoke(&mut client, user.id(), id).await? {
+        return Err(TiberiusError::ObjectNotFound(
+            "UserToken".to_string(),
+            id.to_string(),
+        ));
+    }
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathUserSessions {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("The session has been logged out")))
+}
+
+/// Logs the user out of all sessions, except the current one if given
+///
+/// Sessions that have not been recorded yet are logged out by moving the time
+/// sessions of the user are valid since.
+async fn revoke_all_sessions(
+    client: &mut Client,
+    user: &mut User,
+    except: Option<Uuid>,
+) -> TiberiusResult<u64> {
+    let revoked = UserSession::revoke_all(client, user.id(), except).await?;
+    UserToken::revoke_all(client, user.id(), UserTokenContext::Session).await?;
+    user.revoke_sessions(client).await?;
+    Ok(revoked)
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn revoke_other_sessions(
+    _: PathRevokeOtherSessions,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let current = rstate.session().id();
+    revoke_all_sessions(&mut client, &mut user, Some(current)).await?;
+    rstate.session_mut().renew_login();
+    rstate.push_session_update()?;
+    Ok(TiberiusResponse::Redirect(Redirect::to(
+        PathUserSessions {}.to_uri().to_string().as_str(),
+    ))
+    .with_flash(flash.info("You have been logged out everywhere else")))
+}
+
+async fn verify_manage_sessions(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<()> {
+    if verify_acl(
+        state,
+        rstate,
+        ACLObject::User,
+        ACLActionUser::ManageSessions,
+    )
+    .await?
+    {
+        Ok(())
+    } else {
+        Err(TiberiusError::AccessDenied)
+    }
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn admin_show_sessions(
+    PathAdminUserSessions { user_id }: PathAdminUserSessions,
+    State(state): State<TiberiusState>,
+    rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    verify_manage_sessions(&state, &rstate).await?;
+    let mut client: Client = state.get_db_client();
+    let user = match User::get_id(&mut client, user_id).await? {
+        Some(v) => v,
+        None => {
+            return Err(TiberiusError::ObjectNotFound(
+                "User".to_string(),
+                user_id.to_string(),
+            ))
+        }
+    };
+    let sessions = UserSession::get_for_user(&mut client, user.id()).await?;
+    let philomena_sessions =
+        UserToken::get_for_user(&mut client, user.id(), UserTokenContext::Session).await?;
+    let body = html! {
+        h1 { "Sessions of " (user.displayname()) }
+
+        (session_table(&rstate, &sessions, &philomena_sessions, false).await)
+
+        h3 { "Compromised account" }
+        p { "Logs the user out of every session, including Philomena sessions. The user can log in again with their password." }
+        form action=(PathAdminRevokeUserSessions{ user_id }.to_uri().to_string()) method="POST" {
+            (csrf_input_tag(&rstate).await)
+            .actions {
+                button.button type="submit" { "Log out everywhere" }
+            }
+        }
+    };
+    render_page(&state, &rstate, &mut client, "User sessions", body).await
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn admin_revoke_sessions(
+    PathAdminRevokeUserSessions { user_id }: PathAdminRevokeUserSessions,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormDataEmpty>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.into_afd().verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    verify_manage_sessions(&state, &rstate).await?;
+    let show = PathAdminUserSessions { user_id }.to_uri().to_string();
+    if let Some(step_up) = require_step_up(&rstate, &show) {
+        return Ok(TiberiusResponse::Redirect(step_up)
+            .with_flash(flash.warning("Confirm your identity to log out other users")));
+    }
+    let mut client: Client = state.get_db_client();
+    let mut user = match User::get_id(&mut client, user_id).await? {
+        Some(v) => v,
+        None => {
+            return Err(TiberiusError::ObjectNotFound(
+                "User".to_string(),
+                user_id.to_string(),
+            ))
+        }
+    };
+    let revoked = revoke_all_sessions(&mut client, &mut user, None).await?;
+    info!(
+        "user {:?} logged out all sessions of user {}",
+        rstate.session().raw_user(),
+        user.id
+    );
+    Ok(
+        TiberiusResponse::Redirect(Redirect::to(&show)).with_flash(flash.info(format!(
+            "Logged out {} sessions of {}",
+            revoked,
+            user.displayname()
+        ))),
+    )
+}
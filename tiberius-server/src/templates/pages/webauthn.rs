@@ -0,0 +1,600 @@
+use axum::{extract::State, response::Redirect, Form, Router};
+use axum_extra::routing::{RouterExt, TypedPath};
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+use tiberius_core::{
+    error::{TiberiusError, TiberiusResult},
+    request_helper::{ApiFormData, HtmlResponse, TiberiusResponse},
+    session::{AuthMethod, Authenticated, SessionMode, Unauthenticated},
+    state::{TiberiusRequestState, TiberiusState},
+};
+use tiberius_dependencies::{
+    axum_flash::Flash,
+    uuid::Uuid,
+    webauthn_rs::{
+        prelude::{
+            PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+            RegisterPublicKeyCredential,
+        },
+        Webauthn,
+    },
+};
+use tiberius_models::{Client, User, WebauthnCredential};
+
+use crate::templates::{
+    common::{frontmatter::csrf_input_tag, human_date},
+    session::{
+        expire_pending_login, finish_login, login_address, safe_return_to, PathSessionTotp,
+        PathSessionsLogin, LOCKED_OUT, LOGIN_TIMED_OUT,
+    },
+};
+
+pub fn webauthn_pages(r: Router<TiberiusState>) -> Router<TiberiusState> {
+    r.typed_get(show_webauthn)
+        .typed_post(register_webauthn)
+        .typed_post(delete_webauthn)
+        .typed_post(post_session_webauthn)
+        .typed_post(post_passkey_login)
+        .typed_post(post_step_up_webauthn)
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/webauthn")]
+pub struct PathWebauthn {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/registrations/webauthn/:id/delete")]
+pub struct PathDeleteWebauthn {
+    id: i64,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/sessions/webauthn")]
+pub struct PathSessionWebauthn {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/sessions/passkey")]
+pub struct PathSessionPasskey {}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/sessions/step_up/webauthn")]
+pub struct PathStepUpWebauthn {}
+
+/// Session key of the state of a pending credential registration
+const REGISTRATION_STATE: &str = "webauthn_registration";
+/// Session key of the state of a pending authentication
+const AUTHENTICATION_STATE: &str = "webauthn_authentication";
+/// Longest name a credential may be given
+const CREDENTIAL_NAME_MAX_LENGTH: usize = 50;
+
+/// Returns the WebAuthn relying party of the site, or None if it is not configured
+pub(crate) fn webauthn(state: &TiberiusState) -> TiberiusResult<Option<Webauthn>> {
+    state.config.webauthn(state.site_config().site_name())
+}
+
+/// The stable and opaque user handle the authenticator stores for the user
+fn user_handle(user: &User) -> Uuid {
+    Uuid::from_u64_pair(0, user.id as u64)
+}
+
+/// Returns true if the user can sign in with a passkey
+pub(crate) async fn passkeys_enabled(
+    state: &TiberiusState,
+    client: &mut Client,
+    user: &User,
+) -> TiberiusResult<bool> {
+    if webauthn(state)?.is_none() {
+        return Ok(false);
+    }
+    Ok(WebauthnCredential::has_credentials(client, user.id()).await?)
+}
+
+/// Issues a challenge for the passkeys of the user and stores its state in the session
+///
+/// Returns the options to pass to the browser, or None if the user has no passkeys.
+pub(crate) async fn start_authentication<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &mut TiberiusRequestState<T>,
+    client: &mut Client,
+    user: &User,
+) -> TiberiusResult<Option<String>> {
+    let webauthn = match webauthn(state)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let passkeys = WebauthnCredential::passkeys_for_user(client, user.id()).await?;
+    if passkeys.is_empty() {
+        return Ok(None);
+    }
+    let (challenge, auth_state) = webauthn.start_passkey_authentication(&passkeys)?;
+    rstate.session_mut().set_json_data(
+        AUTHENTICATION_STATE.to_string(),
+        serde_json::to_value(&auth_state)?,
+    );
+    rstate.push_session_update()?;
+    Ok(Some(serde_json::to_string(&challenge)?))
+}
+
+/// Verifies a credential against the challenge stored in the session
+///
+/// The challenge is removed from the session, so each challenge can only be answered once.
+async fn finish_authentication<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &mut TiberiusRequestState<T>,
+    client: &mut Client,
+    user: &User,
+    credential: &str,
+) -> TiberiusResult<bool> {
+    let webauthn = match webauthn(state)? {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    let auth_state = match rstate.session_mut().remove_data(AUTHENTICATION_STATE) {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    rstate.push_session_update()?;
+    let auth_state: PasskeyAuthentication = serde_json::from_value(auth_state)?;
+    let credential: PublicKeyCredential = match serde_json::from_str(credential) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("could not parse credential: {}", e);
+            return Ok(false);
+        }
+    };
+    let result = match webauthn.finish_passkey_authentication(&credential, &auth_state) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("passkey authentication failed: {}", e);
+            return Ok(false);
+        }
+    };
+    Ok(WebauthnCredential::record_use(client, user.id(), &result).await?)
+}
+
+/// Renders a form that signs the challenge with a passkey and submits it to the given action
+pub(crate) async fn passkey_form<T: SessionMode>(
+    rstate: &TiberiusRequestState<T>,
+    action: String,
+    options: &str,
+    extra: Markup,
+) -> Markup {
+    html! {
+        form action=(action) method="POST" {
+            (csrf_input_tag(rstate).await)
+            input type="hidden" name="credential" value="";
+            (extra)
+
+            .actions {
+                button.button.js-webauthn type="button" data-webauthn-mode="get" data-webauthn-options=(options) {
+                    i.fa.fa-key {}
+                    " Use a passkey"
+                }
+            }
+        }
+    }
+}
+
+async fn render_page<T: SessionMode>(
+    state: &TiberiusState,
+    rstate: &TiberiusRequestState<T>,
+    client: &mut Client,
+    body: Markup,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let page: PreEscaped<String> = html! {
+        (crate::templates::common::frontmatter::app(state, rstate, None, client, body, None).await?);
+    };
+    Ok(TiberiusResponse::Html(HtmlResponse {
+        content: page.into_string(),
+    }))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn show_webauthn(
+    _: PathWebauthn,
+    State(state): State<TiberiusState>,
+    mut rstate: TiberiusRequestState<Authenticated>,
+) -> TiberiusResult<TiberiusResponse<()>> {
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let credentials = WebauthnCredential::get_for_user(&mut client, user.id()).await?;
+    let registration = match webauthn(&state)? {
+        None => None,
+        Some(webauthn) => {
+            let exclude = credentials
+                .iter()
+                .map(|x| Ok(x.passkey()?.cred_id().clone()))
+                .collect::<TiberiusResult<Vec<_>>>()?;
+            let (challenge, reg_state) = webauthn.start_passkey_registration(
+                user_handle(&user),
+                &user.name,
+                user.displayname(),
+                Some(exclude),
+            )?;
+            rstate.session_mut().set_json_data(
+                REGISTRATION_STATE.to_string(),
+                serde_json::to_value(&reg_state)?,
+            );
+            rstate.push_session_update()?;
+            Some(serde_json::to_string(&challenge)?)
+        }
+    };
+    let body = html! {
+        h1 { "Passkeys and security keys" }
+
+        p { "Passkeys let you sign in without your password, or serve as second factor after entering it." }
+        p { "Your password stays valid, so you can still sign in or reset it by email if you lose your passkeys." }
+
+        @if credentials.is_empty() {
+            p { "You have not registered any passkeys yet." }
+        } @else {
+            table.table {
+                thead {
+                    tr {
+                        th { "Name" }
+                        th { "Added" }
+                        th { "Last used" }
+                        th {}
+                    }
+                }
+                tbody {
+                    @for credential in &credentials {
+                        tr {
+                            td { (credential.name) }
+                            td { (human_date(credential.created_at)) }
+                            td {
+                                @match credential.last_used_at {
+                                    Some(v) => (human_date(v)),
+                                    None => "Never",
+                                }
+                            }
+                            td {
+                                form action=(PathDeleteWebauthn{ id: credential.id }.to_uri().to_string()) method="POST" {
+                                    (csrf_input_tag(&rstate).await)
+                                    input.input name="current_password" type="password" required="true" placeholder="Current password";
+                                    button.button type="submit" { "Remove" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        @if let Some(options) = registration {
+            h3 { "Add a passkey" }
+            form action=(PathWebauthn{}.to_uri().to_string()) method="POST" {
+                (csrf_input_tag(&rstate).await)
+                input type="hidden" name="credential" value="";
+
+                .field {
+                    input.input #webauthn_name name="name" type="text" required="true" maxlength=(CREDENTIAL_NAME_MAX_LENGTH) placeholder="Name, for example \"Phone\"";
+                }
+
+                .field {
+                    input.input #user_current_password name="current_password" type="password" required="true" placeholder="Current password";
+                }
+
+                .actions {
+                    button.button.js-webauthn type="button" data-webauthn-mode="create" data-webauthn-options=(options) { "Add passkey" }
+                }
+            }
+        } @else {
+            p { "Passkeys are not available on this site." }
+        }
+    };
+    render_page(&state, &rstate, &mut client, body).await
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct RegisterWebauthn {
+    name: String,
+    #[sensitive]
+    current_password: String,
+    credential: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn register_webauthn(
+    _: PathWebauthn,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<RegisterWebauthn>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathWebauthn {}.to_uri().to_string().as_str());
+    let webauthn = match webauthn(&state)? {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("Passkeys are not available on this site")))
+        }
+    };
+    let name = form.data.name.trim();
+    if name.is_empty() || name.chars().count() > CREDENTIAL_NAME_MAX_LENGTH {
+        return Ok(
+            TiberiusResponse::Redirect(retry).with_flash(flash.error(format!(
+                "The name must be between 1 and {} characters long",
+                CREDENTIAL_NAME_MAX_LENGTH
+            ))),
+        );
+    }
+    if !user.verify_password(state.config.password_pepper(), &form.data.current_password)? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Your current password is incorrect")));
+    }
+    let reg_state = match rstate.session_mut().remove_data(REGISTRATION_STATE) {
+        Some(v) => v,
+        None => {
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("The registration expired, please try again")))
+        }
+    };
+    rstate.push_session_update()?;
+    let reg_state: PasskeyRegistration = serde_json::from_value(reg_state)?;
+    let passkey = serde_json::from_str::<RegisterPublicKeyCredential>(&form.data.credential)
+        .map_err(|e| e.to_string())
+        .and_then(|credential| {
+            webauthn
+                .finish_passkey_registration(&credential, &reg_state)
+                .map_err(|e| e.to_string())
+        });
+    let passkey = match passkey {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("passkey registration failed: {}", e);
+            return Ok(TiberiusResponse::Redirect(retry)
+                .with_flash(flash.error("The passkey could not be registered")));
+        }
+    };
+    WebauthnCredential::create(&mut client, user.id(), name, &passkey).await?;
+    Ok(TiberiusResponse::Redirect(retry).with_flash(flash.info("The passkey has been added")))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct DeleteWebauthn {
+    #[sensitive]
+    current_password: String,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn delete_webauthn(
+    PathDeleteWebauthn { id }: PathDeleteWebauthn,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<DeleteWebauthn>>,
+) -> TiberiusResult<TiberiusResponse<(Flash, TiberiusResponse<()>)>> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client: Client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let retry = Redirect::to(PathWebauthn {}.to_uri().to_string().as_str());
+    if !user.verify_password(state.config.password_pepper(), &form.data.current_password)? {
+        return Ok(TiberiusResponse::Redirect(retry)
+            .with_flash(flash.error("Your current password is incorrect")));
+    }
+    // The password verified above remains to sign in, but staff members must keep a second factor
+    let credentials = WebauthnCredential::get_for_user(&mut client, user.id()).await?;
+    let last_factor = !user.totp_enabled() && credentials.iter().all(|x| x.id == id);
+    if last_factor && state.site_config().require_staff_two_factor() && user.is_staff() {
+        return Ok(TiberiusResponse::Redirect(retry).with_flash(flash.error(
+            "Staff members are required to use two factor authentication, enable it before removing your last passkey",
+        )));
+    }
+    if !WebauthnCredential::delete(&mut client, user.id(), id).await? {
+        return Err(TiberiusError::ObjectNotFound(
+            "WebauthnCredential".to_string(),
+            id.to_string(),
+        ));
+    }
+    Ok(TiberiusResponse::Redirect(retry).with_flash(flash.info("The passkey has been removed")))
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct PasskeyAssertion {
+    credential: String,
+    #[serde(default)]
+    return_to: Option<String>,
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_session_webauthn(
+    _: PathSessionWebauthn,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormData<PasskeyAssertion>>,
+) -> TiberiusResult<(Flash, Redirect)> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let login = Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str());
+    if expire_pending_login(&mut rstate)? {
+        return Ok((flash.error(LOGIN_TIMED_OUT), login));
+    }
+    let mut user = match rstate.session().pending_user(&mut client).await? {
+        Some(v) if rstate.session().waiting_on(AuthMethod::WebAuthn) => v,
+        _ => return Ok((flash.error("Please log in again"), login)),
+    };
+    if user
+        .locked_from(&mut client, login_address(&rstate))
+        .await?
+    {
+        rstate.session_mut().unset_user();
+        rstate.push_session_update()?;
+        return Ok((flash.error(LOCKED_OUT), login));
+    }
+    if finish_authentication(
+        &state,
+        &mut rstate,
+        &mut client,
+        &user,
+        &form.data.credential,
+    )
+    .await?
+    {
+        rstate.session_mut().complete_auth(AuthMethod::WebAuthn);
+        finish_login(&state, &mut rstate, &mut client, &mut user, flash).await
+    } else {
+        Ok((
+            flash.error("The passkey could not be verified"),
+            Redirect::to(PathSessionTotp {}.to_uri().to_string().as_str()),
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, securefmt::Debug)]
+pub struct PasskeyLogin {
+    email: String,
+}
+
+/// Starts a passwordless login, the passkey is then verified on the second factor page
+#[instrument(skip(state, rstate))]
+pub async fn post_passkey_login(
+    _: PathSessionPasskey,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Unauthenticated>,
+    Form(form): Form<ApiFormData<PasskeyLogin>>,
+) -> TiberiusResult<(Flash, Redirect)> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let user = User::get_mail_or_name(&mut client, form.data.email.as_str()).await?;
+    let user = match user {
+        Some(v)
+            if !v.locked_from(&mut client, login_address(&rstate)).await?
+                && passkeys_enabled(&state, &mut client, &v).await? =>
+        {
+            v
+        }
+        _ => {
+            return Ok((
+                flash.error("Could not sign in with a passkey, please use your password"),
+                Redirect::to(PathSessionsLogin {}.to_uri().to_string().as_str()),
+            ))
+        }
+    };
+    let session = rstate.session_mut();
+    session.set_user(&user);
+    session.set_waiting_auths(AuthMethod::WebAuthn);
+    rstate.push_session_update()?;
+    Ok((
+        flash,
+        Redirect::to(PathSessionTotp {}.to_uri().to_string().as_str()),
+    ))
+}
+
+#[instrument(skip(state, rstate))]
+pub async fn post_step_up_webauthn(
+    _: PathStepUpWebauthn,
+    State(state): State<TiberiusState>,
+    flash: Flash,
+    mut rstate: TiberiusRequestState<Authenticated>,
+    Form(form): Form<ApiFormData<PasskeyAssertion>>,
+) -> TiberiusResult<(Flash, Redirect)> {
+    if !form.verify_csrf(None, &rstate) {
+        return Err(TiberiusError::AccessDenied);
+    }
+    let mut client = state.get_db_client();
+    let user = match rstate.user(&state).await? {
+        Some(v) => v,
+        None => return Err(TiberiusError::AccessDenied),
+    };
+    let return_to = safe_return_to(form.data.return_to.as_deref());
+    if finish_authentication(
+        &state,
+        &mut rstate,
+        &mut client,
+        &user,
+        &form.data.credential,
+    )
+    .await?
+    {
+        rstate.session_mut().step_up();
+        rstate.push_session_update()?;
+        Ok((flash, Redirect::to(&return_to)))
+    } else {
+        Ok((
+            flash.error("The passkey could not be verified"),
+            Redirect::to(&crate::templates::session::step_up_uri(&return_to)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::user_handle;
+    use tiberius_core::{config::Configuration, error::TiberiusResult};
+    use tiberius_models::{Client, User, WebauthnCredential};
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "../migrations"))]
+    async fn test_webauthn_software_authenticator() -> TiberiusResult<()> {
+        let mut client = Client::new(pool, None);
+        let user = User::new_test_user(&mut client).await?;
+        let origin = url::Url::parse("http://localhost:8080").unwrap();
+        let config = Configuration {
+            webauthn_rp_id: Some("localhost".to_string()),
+            webauthn_origin: Some(origin.clone()),
+            ..Default::default()
+        };
+        let webauthn = config.webauthn("Test Booru")?.unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+
+        let (challenge, reg_state) = webauthn.start_passkey_registration(
+            user_handle(&user),
+            &user.name,
+            user.displayname(),
+            None,
+        )?;
+        let credential = authenticator
+            .do_registration(origin.clone(), challenge)
+            .expect("software authenticator must register");
+        let passkey = webauthn.finish_passkey_registration(&credential, &reg_state)?;
+        assert!(!user.has_second_factor(&mut client).await?);
+        WebauthnCredential::create(&mut client, user.id(), "Soft", &passkey).await?;
+        assert!(user.has_second_factor(&mut client).await?);
+
+        let passkeys = WebauthnCredential::passkeys_for_user(&mut client, user.id()).await?;
+        assert_eq!(1, passkeys.len());
+        let (challenge, auth_state) = webauthn.start_passkey_authentication(&passkeys)?;
+        let assertion = authenticator
+            .do_authentication(origin.clone(), challenge)
+            .expect("software authenticator must sign");
+        let result = webauthn.finish_passkey_authentication(&assertion, &auth_state)?;
+        assert!(!WebauthnCredential::record_use(&mut client, user.id() + 1, &result).await?);
+        assert!(WebauthnCredential::record_use(&mut client, user.id(), &result).await?);
+        let (_, other_state) = webauthn.start_passkey_authentication(&passkeys)?;
+        assert!(
+            webauthn
+                .finish_passkey_authentication(&assertion, &other_state)
+                .is_err(),
+            "assertions must not be accepted for a different challenge"
+        );
+
+        let credentials = WebauthnCredential::get_for_user(&mut client, user.id()).await?;
+        assert!(credentials[0].last_used_at.is_some());
+        assert!(WebauthnCredential::delete(&mut client, user.id(), credentials[0].id).await?);
+        assert!(!WebauthnCredential::has_credentials(&mut client, user.id()).await?);
+        Ok(())
+    }
+}